@@ -0,0 +1,62 @@
+//! ssh-agent 转发 (auth-agent-req@openssh.com，OpenSSH 的 PROTOCOL.agent)
+//!
+//! 开启后在 exec 和 shell 通道上请求转发，之后远程的程序 (例如 git) 需要使用 agent 时，
+//! 服务端会打开 auth-agent@openssh.com 通道，每个通道单独连接一次本地的 SSH_AUTH_SOCK，
+//! 数据的转发见 forwarded_channel。没有请求过转发时服务端打开的 agent 通道会被拒绝
+
+use std::io;
+use crate::client::Client;
+use crate::constant::ssh_msg_code;
+use crate::error::SshResult;
+use crate::forwarded_channel::{ForwardedChannel, LocalStream};
+
+
+/// 本地 agent 的 unix socket 路径
+pub(crate) const SSH_AUTH_SOCK: &str = "SSH_AUTH_SOCK";
+
+
+impl Client {
+    /*
+        byte      SSH_MSG_CHANNEL_OPEN
+        string    "auth-agent@openssh.com"
+        uint32    sender channel
+        uint32    initial window size
+        uint32    maximum packet size
+    */
+    /// 只有请求过 agent 转发时才接受，连接不上本地的 agent 时回应 SSH_OPEN_CONNECT_FAILED
+    pub(crate) fn accept_agent_channel(&mut self, sender_channel: u32, window: u32, max_packet_size: u32) -> SshResult<()> {
+        if !self.agent_forwarding {
+            log::info!("reject agent channel, agent forwarding was not requested.");
+            return self.send_open_failure(sender_channel,
+                                          ssh_msg_code::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+                                          "agent forwarding not requested")
+        }
+        let stream = match connect_agent() {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("connect ssh-agent error: {}", e);
+                return self.send_open_failure(sender_channel,
+                                              ssh_msg_code::SSH_OPEN_CONNECT_FAILED,
+                                              "cannot connect to the local ssh-agent")
+            }
+        };
+        self.open_forwarded(ForwardedChannel::new("agent", sender_channel, window, max_packet_size, stream))
+    }
+}
+
+
+#[cfg(unix)]
+fn connect_agent() -> io::Result<Box<dyn LocalStream>> {
+    let path = match std::env::var_os(SSH_AUTH_SOCK) {
+        Some(v) => v,
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, "SSH_AUTH_SOCK is not set"))
+    };
+    let stream = std::os::unix::net::UnixStream::connect(path)?;
+    stream.set_nonblocking(true)?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(unix))]
+fn connect_agent() -> io::Result<Box<dyn LocalStream>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "agent forwarding is only supported on unix"))
+}
@@ -0,0 +1,191 @@
+//! 默认后端: ring + RustCrypto
+
+use aes::cipher::{NewCipher, StreamCipher, StreamCipherSeek};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::agreement::{self, EphemeralPrivateKey, PublicKey, UnparsedPublicKey};
+use ring::{constant_time, digest, hmac, signature};
+use rsa::PublicKey as _;
+use crate::algorithm::backend::{Curve, Digest, EcdsaCurve};
+use crate::{SshError, SshResult};
+
+pub(crate) use ring::aead::chacha20_poly1305_openssh::{OpeningKey, SealingKey};
+
+
+/// 后端名称
+#[cfg_attr(feature = "ring-backend", allow(dead_code))]
+pub(crate) const NAME: &str = "ring+rustcrypto";
+
+
+pub(crate) fn digest(algorithm: Digest, data: &[u8]) -> Vec<u8> {
+    let algorithm = match algorithm {
+        Digest::Sha1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        Digest::Sha256 => &digest::SHA256,
+        Digest::Sha512 => &digest::SHA512,
+    };
+    digest::digest(algorithm, data).as_ref().to_vec()
+}
+
+/// 依次对 data 中的每一段计算 HMAC
+pub(crate) fn hmac(algorithm: Digest, key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    let algorithm = match algorithm {
+        Digest::Sha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+        Digest::Sha256 => hmac::HMAC_SHA256,
+        Digest::Sha512 => hmac::HMAC_SHA512,
+    };
+    let key = hmac::Key::new(algorithm, key);
+    let mut context = hmac::Context::with_key(&key);
+    for v in data {
+        context.update(v);
+    }
+    context.sign().as_ref().to_vec()
+}
+
+/// 比较时间不依赖于内容
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    constant_time::verify_slices_are_equal(a, b).is_ok()
+}
+
+
+pub(crate) struct Aes128Ctr(aes::Aes128Ctr);
+
+impl Aes128Ctr {
+    pub(crate) fn new(key: &[u8; 16], iv: &[u8; 16]) -> Self {
+        Aes128Ctr(aes::Aes128Ctr::new(key.into(), iv.into()))
+    }
+
+    pub(crate) fn apply_keystream(&mut self, buf: &mut [u8]) {
+        self.0.apply_keystream(buf)
+    }
+
+    /// 解密但不移动密钥流的位置，用于提前读取数据包长度
+    pub(crate) fn peek(&mut self, buf: &mut [u8]) {
+        let pos: usize = self.0.current_pos();
+        self.0.apply_keystream(buf);
+        self.0.seek(pos);
+    }
+}
+
+
+/// AES-GCM, 密钥长度为 16 或 32 字节
+pub(crate) struct AesGcm(LessSafeKey);
+
+impl AesGcm {
+    pub(crate) fn new(key: &[u8]) -> SshResult<Self> {
+        let algorithm = match key.len() {
+            16 => &aead::AES_128_GCM,
+            32 => &aead::AES_256_GCM,
+            _ => return Err(SshError::from("invalid aes-gcm key length."))
+        };
+        match UnboundKey::new(algorithm, key) {
+            Ok(v) => Ok(AesGcm(LessSafeKey::new(v))),
+            Err(_) => Err(SshError::from("encryption error."))
+        }
+    }
+
+    /// 原地加密，返回认证标签
+    pub(crate) fn seal(&self, nonce: [u8; 12], aad: &[u8], buf: &mut [u8]) -> SshResult<Vec<u8>> {
+        let nonce = Nonce::assume_unique_for_key(nonce);
+        match self.0.seal_in_place_separate_tag(nonce, Aad::from(aad), buf) {
+            Ok(tag) => Ok(tag.as_ref().to_vec()),
+            Err(_) => Err(SshError::from("encryption error."))
+        }
+    }
+
+    /// buf 为密文 || 认证标签，标签校验通过后才会原地解密，返回明文的长度
+    pub(crate) fn open(&self, nonce: [u8; 12], aad: &[u8], buf: &mut [u8]) -> SshResult<usize> {
+        let nonce = Nonce::assume_unique_for_key(nonce);
+        match self.0.open_in_place(nonce, Aad::from(aad), buf) {
+            Ok(plain) => Ok(plain.len()),
+            Err(_) => Err(SshError::from("encryption error."))
+        }
+    }
+}
+
+
+/// ECDH 的临时密钥
+pub(crate) struct EphemeralKey {
+    private_key: EphemeralPrivateKey,
+    public_key: PublicKey
+}
+
+impl EphemeralKey {
+    pub(crate) fn generate(curve: Curve) -> SshResult<Self> {
+        let rng = ring::rand::SystemRandom::new();
+        let private_key = match EphemeralPrivateKey::generate(agreement_algorithm(curve), &rng) {
+            Ok(v) => v,
+            Err(_) => return Err(SshError::from("encryption error."))
+        };
+        match private_key.compute_public_key() {
+            Ok(public_key) => Ok(EphemeralKey { private_key, public_key }),
+            Err(_) => Err(SshError::from("encryption error."))
+        }
+    }
+
+    pub(crate) fn public_key(&self) -> &[u8] {
+        self.public_key.as_ref()
+    }
+
+    /// 和对方的公钥计算共享密钥
+    pub(crate) fn agree(&self, peer_public_key: &[u8]) -> SshResult<Vec<u8>> {
+        let algorithm = self.private_key.algorithm();
+        let peer_public_key = UnparsedPublicKey::new(algorithm, peer_public_key);
+        // ring 的 agree_ephemeral 会消耗私钥，每次密钥交换都会生成新的临时密钥，只会调用一次
+        let private_key = unsafe { (&self.private_key as *const EphemeralPrivateKey).read() };
+        match agreement::agree_ephemeral(
+            private_key,
+            &peer_public_key,
+            ring::error::Unspecified,
+            |key_material| Ok(key_material.to_vec())
+        ) {
+            Ok(v) => Ok(v),
+            Err(_) => Err(SshError::from("encryption error."))
+        }
+    }
+}
+
+fn agreement_algorithm(curve: Curve) -> &'static agreement::Algorithm {
+    match curve {
+        Curve::X25519 => &agreement::X25519,
+        Curve::NistP256 => &agreement::ECDH_P256,
+    }
+}
+
+
+pub(crate) fn verify_ed25519(public_key: &[u8], message: &[u8], sig: &[u8]) -> bool {
+    signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+        .verify(message, sig)
+        .is_ok()
+}
+
+/// sig 为定长的 r || s
+/// ring 会校验点是否在曲线上，无效的点和无穷远点都会验签失败
+pub(crate) fn verify_ecdsa(curve: EcdsaCurve, point: &[u8], message: &[u8], sig: &[u8]) -> bool {
+    let algorithm = match curve {
+        EcdsaCurve::NistP256 => &signature::ECDSA_P256_SHA256_FIXED,
+        EcdsaCurve::NistP384 => &signature::ECDSA_P384_SHA384_FIXED,
+    };
+    signature::UnparsedPublicKey::new(algorithm, point)
+        .verify(message, sig)
+        .is_ok()
+}
+
+/// RSASSA-PKCS1-v1_5, e 和 n 为大端字节
+#[cfg_attr(feature = "ring-backend", allow(dead_code))]
+pub(crate) fn verify_rsa(algorithm: Digest, e: &[u8], n: &[u8], message: &[u8], sig: &[u8]) -> SshResult<bool> {
+    let e = rsa::BigUint::from_bytes_be(e);
+    let n = rsa::BigUint::from_bytes_be(n);
+    let public_key = match rsa::RsaPublicKey::new(n, e) {
+        Ok(v) => v,
+        Err(e) => return Err(SshError::from(format!("invalid rsa host key: {:?}", e)))
+    };
+    let hash = match algorithm {
+        Digest::Sha1 => rsa::Hash::SHA1,
+        Digest::Sha256 => rsa::Hash::SHA2_256,
+        Digest::Sha512 => rsa::Hash::SHA2_512,
+    };
+    let scheme = rsa::PaddingScheme::PKCS1v15Sign {
+        hash: Some(hash)
+    };
+    let digest = digest(algorithm, message);
+    Ok(public_key.verify(scheme, &digest, sig).is_ok())
+}
@@ -0,0 +1,298 @@
+//! DES / 3DES (FIPS 46-3)
+//!
+//! ring 和 RustCrypto 的默认依赖中都没有 3DES，所有后端共用这里的实现。
+//! 按位查表实现，没有针对速度和侧信道做优化，只用于只支持 3des-cbc 的旧设备
+
+const BLOCK_SIZE: usize = 8;
+
+/// 初始置换
+const IP: [u8; 64] = [
+    58, 50, 42, 34, 26, 18, 10, 2, 60, 52, 44, 36, 28, 20, 12, 4,
+    62, 54, 46, 38, 30, 22, 14, 6, 64, 56, 48, 40, 32, 24, 16, 8,
+    57, 49, 41, 33, 25, 17, 9, 1, 59, 51, 43, 35, 27, 19, 11, 3,
+    61, 53, 45, 37, 29, 21, 13, 5, 63, 55, 47, 39, 31, 23, 15, 7
+];
+
+/// 逆初始置换
+const FP: [u8; 64] = [
+    40, 8, 48, 16, 56, 24, 64, 32, 39, 7, 47, 15, 55, 23, 63, 31,
+    38, 6, 46, 14, 54, 22, 62, 30, 37, 5, 45, 13, 53, 21, 61, 29,
+    36, 4, 44, 12, 52, 20, 60, 28, 35, 3, 43, 11, 51, 19, 59, 27,
+    34, 2, 42, 10, 50, 18, 58, 26, 33, 1, 41, 9, 49, 17, 57, 25
+];
+
+/// 扩展置换，32 位扩展为 48 位
+const E: [u8; 48] = [
+    32, 1, 2, 3, 4, 5, 4, 5, 6, 7, 8, 9,
+    8, 9, 10, 11, 12, 13, 12, 13, 14, 15, 16, 17,
+    16, 17, 18, 19, 20, 21, 20, 21, 22, 23, 24, 25,
+    24, 25, 26, 27, 28, 29, 28, 29, 30, 31, 32, 1
+];
+
+/// S 盒之后的置换
+const P: [u8; 32] = [
+    16, 7, 20, 21, 29, 12, 28, 17, 1, 15, 23, 26, 5, 18, 31, 10,
+    2, 8, 24, 14, 32, 27, 3, 9, 19, 13, 30, 6, 22, 11, 4, 25
+];
+
+/// 密钥置换 1，去掉校验位，64 位变为 56 位
+const PC1: [u8; 56] = [
+    57, 49, 41, 33, 25, 17, 9, 1, 58, 50, 42, 34, 26, 18,
+    10, 2, 59, 51, 43, 35, 27, 19, 11, 3, 60, 52, 44, 36,
+    63, 55, 47, 39, 31, 23, 15, 7, 62, 54, 46, 38, 30, 22,
+    14, 6, 61, 53, 45, 37, 29, 21, 13, 5, 28, 20, 12, 4
+];
+
+/// 密钥置换 2，56 位变为 48 位的子密钥
+const PC2: [u8; 48] = [
+    14, 17, 11, 24, 1, 5, 3, 28, 15, 6, 21, 10,
+    23, 19, 12, 4, 26, 8, 16, 7, 27, 20, 13, 2,
+    41, 52, 31, 37, 47, 55, 30, 40, 51, 45, 33, 48,
+    44, 49, 39, 56, 34, 53, 46, 42, 50, 36, 29, 32
+];
+
+/// 每一轮密钥循环左移的位数
+const SHIFTS: [u8; 16] = [1, 1, 2, 2, 2, 2, 2, 2, 1, 2, 2, 2, 2, 2, 2, 1];
+
+const S: [[u8; 64]; 8] = [
+    [
+        14, 4, 13, 1, 2, 15, 11, 8, 3, 10, 6, 12, 5, 9, 0, 7,
+        0, 15, 7, 4, 14, 2, 13, 1, 10, 6, 12, 11, 9, 5, 3, 8,
+        4, 1, 14, 8, 13, 6, 2, 11, 15, 12, 9, 7, 3, 10, 5, 0,
+        15, 12, 8, 2, 4, 9, 1, 7, 5, 11, 3, 14, 10, 0, 6, 13
+    ],
+    [
+        15, 1, 8, 14, 6, 11, 3, 4, 9, 7, 2, 13, 12, 0, 5, 10,
+        3, 13, 4, 7, 15, 2, 8, 14, 12, 0, 1, 10, 6, 9, 11, 5,
+        0, 14, 7, 11, 10, 4, 13, 1, 5, 8, 12, 6, 9, 3, 2, 15,
+        13, 8, 10, 1, 3, 15, 4, 2, 11, 6, 7, 12, 0, 5, 14, 9
+    ],
+    [
+        10, 0, 9, 14, 6, 3, 15, 5, 1, 13, 12, 7, 11, 4, 2, 8,
+        13, 7, 0, 9, 3, 4, 6, 10, 2, 8, 5, 14, 12, 11, 15, 1,
+        13, 6, 4, 9, 8, 15, 3, 0, 11, 1, 2, 12, 5, 10, 14, 7,
+        1, 10, 13, 0, 6, 9, 8, 7, 4, 15, 14, 3, 11, 5, 2, 12
+    ],
+    [
+        7, 13, 14, 3, 0, 6, 9, 10, 1, 2, 8, 5, 11, 12, 4, 15,
+        13, 8, 11, 5, 6, 15, 0, 3, 4, 7, 2, 12, 1, 10, 14, 9,
+        10, 6, 9, 0, 12, 11, 7, 13, 15, 1, 3, 14, 5, 2, 8, 4,
+        3, 15, 0, 6, 10, 1, 13, 8, 9, 4, 5, 11, 12, 7, 2, 14
+    ],
+    [
+        2, 12, 4, 1, 7, 10, 11, 6, 8, 5, 3, 15, 13, 0, 14, 9,
+        14, 11, 2, 12, 4, 7, 13, 1, 5, 0, 15, 10, 3, 9, 8, 6,
+        4, 2, 1, 11, 10, 13, 7, 8, 15, 9, 12, 5, 6, 3, 0, 14,
+        11, 8, 12, 7, 1, 14, 2, 13, 6, 15, 0, 9, 10, 4, 5, 3
+    ],
+    [
+        12, 1, 10, 15, 9, 2, 6, 8, 0, 13, 3, 4, 14, 7, 5, 11,
+        10, 15, 4, 2, 7, 12, 9, 5, 6, 1, 13, 14, 0, 11, 3, 8,
+        9, 14, 15, 5, 2, 8, 12, 3, 7, 0, 4, 10, 1, 13, 11, 6,
+        4, 3, 2, 12, 9, 5, 15, 10, 11, 14, 1, 7, 6, 0, 8, 13
+    ],
+    [
+        4, 11, 2, 14, 15, 0, 8, 13, 3, 12, 9, 7, 5, 10, 6, 1,
+        13, 0, 11, 7, 4, 9, 1, 10, 14, 3, 5, 12, 2, 15, 8, 6,
+        1, 4, 11, 13, 12, 3, 7, 14, 10, 15, 6, 8, 0, 5, 9, 2,
+        6, 11, 13, 8, 1, 4, 10, 7, 9, 5, 0, 15, 14, 2, 3, 12
+    ],
+    [
+        13, 2, 8, 4, 6, 15, 11, 1, 10, 9, 3, 14, 5, 0, 12, 7,
+        1, 15, 13, 8, 10, 3, 7, 4, 12, 5, 6, 11, 0, 14, 9, 2,
+        7, 11, 4, 1, 9, 12, 14, 2, 0, 6, 10, 13, 15, 3, 5, 8,
+        2, 1, 14, 7, 4, 10, 8, 13, 15, 12, 9, 0, 3, 5, 6, 11
+    ],
+];
+
+
+/// table 中的位置从 1 开始，1 是 width 位输入的最高位
+fn permute(input: u64, width: u8, table: &[u8]) -> u64 {
+    let mut out = 0_u64;
+    for v in table {
+        out = (out << 1) | ((input >> (width - v)) & 1);
+    }
+    out
+}
+
+fn feistel(r: u32, subkey: u64) -> u32 {
+    let x = permute(r as u64, 32, &E) ^ subkey;
+    let mut s = 0_u64;
+    for (i, sbox) in S.iter().enumerate() {
+        let b = ((x >> (42 - 6 * i)) & 0x3f) as usize;
+        // 第 1 位和第 6 位为行，中间 4 位为列
+        let row = ((b >> 4) & 0x2) | (b & 1);
+        let col = (b >> 1) & 0xf;
+        s = (s << 4) | sbox[row * 16 + col] as u64;
+    }
+    permute(s, 32, &P) as u32
+}
+
+
+/// 单个 DES 密钥的 16 个子密钥
+struct Des {
+    subkeys: [u64; 16],
+}
+
+impl Des {
+    fn new(key: &[u8]) -> Self {
+        let mut k = [0_u8; 8];
+        k.copy_from_slice(&key[..8]);
+        let k = permute(u64::from_be_bytes(k), 64, &PC1);
+        let mut c = (k >> 28) & 0x0fff_ffff;
+        let mut d = k & 0x0fff_ffff;
+        let mut subkeys = [0_u64; 16];
+        for (i, shift) in SHIFTS.iter().enumerate() {
+            c = ((c << shift) | (c >> (28 - shift))) & 0x0fff_ffff;
+            d = ((d << shift) | (d >> (28 - shift))) & 0x0fff_ffff;
+            subkeys[i] = permute((c << 28) | d, 56, &PC2);
+        }
+        Des { subkeys }
+    }
+
+    fn crypt(&self, block: u64, decrypt: bool) -> u64 {
+        let block = permute(block, 64, &IP);
+        let mut l = (block >> 32) as u32;
+        let mut r = block as u32;
+        for i in 0..16 {
+            let k = match decrypt {
+                true => self.subkeys[15 - i],
+                false => self.subkeys[i]
+            };
+            let next = l ^ feistel(r, k);
+            l = r;
+            r = next;
+        }
+        permute(((r as u64) << 32) | l as u64, 64, &FP)
+    }
+}
+
+
+/// 3DES-EDE CBC 模式，密钥为 24 字节 (K1 || K2 || K3)
+/// CBC 的链接值在多次调用之间保留
+pub(crate) struct Des3Cbc {
+    keys: [Des; 3],
+    iv: u64,
+}
+
+impl Des3Cbc {
+    pub(crate) fn new(key: &[u8; 24], iv: &[u8; 8]) -> Self {
+        Des3Cbc {
+            keys: [Des::new(&key[..8]), Des::new(&key[8..16]), Des::new(&key[16..])],
+            iv: u64::from_be_bytes(*iv),
+        }
+    }
+
+    fn encrypt_block(&self, v: u64) -> u64 {
+        let v = self.keys[0].crypt(v, false);
+        let v = self.keys[1].crypt(v, true);
+        self.keys[2].crypt(v, false)
+    }
+
+    fn decrypt_block(&self, v: u64) -> u64 {
+        let v = self.keys[2].crypt(v, true);
+        let v = self.keys[1].crypt(v, false);
+        self.keys[0].crypt(v, true)
+    }
+
+    /// buf 的长度必须是 8 的倍数
+    pub(crate) fn encrypt(&mut self, buf: &mut [u8]) {
+        for block in buf.chunks_exact_mut(BLOCK_SIZE) {
+            let v = u64::from_be_bytes(to_block(block)) ^ self.iv;
+            self.iv = self.encrypt_block(v);
+            block.copy_from_slice(&self.iv.to_be_bytes());
+        }
+    }
+
+    /// buf 的长度必须是 8 的倍数
+    pub(crate) fn decrypt(&mut self, buf: &mut [u8]) {
+        for block in buf.chunks_exact_mut(BLOCK_SIZE) {
+            let c = u64::from_be_bytes(to_block(block));
+            let v = self.decrypt_block(c) ^ self.iv;
+            self.iv = c;
+            block.copy_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    /// 解密第一个分组但不改变链接值，用于提前读取数据包长度
+    pub(crate) fn peek(&self, block: &[u8]) -> [u8; 8] {
+        let c = u64::from_be_bytes(to_block(block));
+        (self.decrypt_block(c) ^ self.iv).to_be_bytes()
+    }
+}
+
+fn to_block(v: &[u8]) -> [u8; 8] {
+    let mut block = [0_u8; BLOCK_SIZE];
+    block.copy_from_slice(&v[..BLOCK_SIZE]);
+    block
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Des, Des3Cbc};
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn des_known_answers() {
+        // (key, plaintext, ciphertext)
+        let vectors = [
+            (0x133457799bbcdff1_u64, 0x0123456789abcdef_u64, 0x85e813540f0ab405_u64),
+            (0x0123456789abcdef, 0x4e6f772069732074, 0x3fa40e8a984d4815),
+            // NIST SP 800-17 可变明文测试
+            (0x0101010101010101, 0x8000000000000000, 0x95f8a5e5dd31d900),
+            (0x0101010101010101, 0x0000000000000001, 0x166b40b44aba4bd6),
+        ];
+        for (key, plain, cipher) in vectors {
+            let des = Des::new(&key.to_be_bytes());
+            assert_eq!(des.crypt(plain, false), cipher);
+            assert_eq!(des.crypt(cipher, true), plain);
+        }
+    }
+
+    #[test]
+    fn triple_des_known_answer() {
+        // NIST SP 800-67 附录 B，三个不同的密钥，ECB
+        let key = hex("0123456789abcdef23456789abcdef01456789abcdef0123");
+        let mut k = [0_u8; 24];
+        k.copy_from_slice(&key);
+        let des = Des3Cbc::new(&k, &[0; 8]);
+        let vectors = [
+            (0x5468652071756663_u64, 0xa826fd8ce53b855f_u64),
+            (0x6b2062726f776e20, 0xcce21c8112256fe6),
+            (0x666f78206a756d70, 0x68d5c05dd9b6b900),
+        ];
+        for (plain, cipher) in vectors {
+            assert_eq!(des.encrypt_block(plain), cipher);
+            assert_eq!(des.decrypt_block(cipher), plain);
+        }
+    }
+
+    #[test]
+    fn cbc_chains_across_calls() {
+        // FIPS 81 的 CBC 示例，三个密钥相同时等于单 DES
+        let mut key = [0_u8; 24];
+        for k in key.chunks_exact_mut(8) {
+            k.copy_from_slice(&hex("0123456789abcdef"));
+        }
+        let mut iv = [0_u8; 8];
+        iv.copy_from_slice(&hex("1234567890abcdef"));
+        let expected = hex("e5c7cdde872bf27c43e934008c389c0f683788499a7c05f6");
+
+        let mut encryption = Des3Cbc::new(&key, &iv);
+        let mut buf = b"Now is the time for all ".to_vec();
+        let (first, second) = buf.split_at_mut(8);
+        encryption.encrypt(first);
+        encryption.encrypt(second);
+        assert_eq!(buf, expected);
+
+        let mut decryption = Des3Cbc::new(&key, &iv);
+        assert_eq!(&decryption.peek(&buf[..8]), b"Now is t");
+        decryption.decrypt(&mut buf[..16]);
+        decryption.decrypt(&mut buf[16..]);
+        assert_eq!(buf, b"Now is the time for all ");
+    }
+}
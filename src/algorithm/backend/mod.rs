@@ -0,0 +1,301 @@
+//! 加密原语的后端
+//!
+//! 协议代码只通过这里使用哈希、HMAC、AES-CTR、AES-GCM、ChaCha20Poly1305、ECDH 和验签，
+//! 具体的实现在编译时通过 feature 选择:
+//! - 默认: ring + RustCrypto (aes, rsa)
+//! - `ring-backend`: 验签也使用 ring。ring 没有提供 AES-CTR，仍然使用 aes
+//!
+//! `legacy-algorithms` 开启时的 3DES 不依赖后端，使用 `des` 中的实现
+//!
+//! 新的后端只需要提供和 `default` 相同的函数和类型，然后在这里按 feature 导出
+
+mod default;
+#[cfg(feature = "ring-backend")]
+mod ring;
+#[cfg(feature = "legacy-algorithms")]
+mod des;
+
+#[cfg(not(feature = "ring-backend"))]
+pub(crate) use self::default::*;
+#[cfg(feature = "ring-backend")]
+pub(crate) use self::ring::*;
+#[cfg(feature = "legacy-algorithms")]
+pub(crate) use self::des::Des3Cbc;
+
+
+/// 摘要算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Digest {
+    Sha1,
+    Sha256,
+    Sha512
+}
+
+/// 密钥交换使用的曲线
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Curve {
+    X25519,
+    NistP256
+}
+
+/// ECDSA 签名使用的曲线
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EcdsaCurve {
+    NistP256,
+    NistP384
+}
+
+
+/// 对两个后端使用同样的测试向量，`cargo test` 和 `cargo test --features ring-backend` 都会运行
+#[cfg(test)]
+mod tests {
+    use super::{Aes128Ctr, AesGcm, Curve, Digest, EcdsaCurve, EphemeralKey, OpeningKey, SealingKey};
+    use super::{constant_time_eq, digest, hmac, verify_ecdsa, verify_ed25519, verify_rsa, NAME};
+
+    /// packet_length 和 44 字节的数据
+    const CHACHA_PACKET: &str = concat!(
+        "0000002c00000068656c6c6f2c2063686163686132302d706f6c793133303540",
+        "6f70656e7373682e636f6d0000000000",
+    );
+
+    /// 序号为 7 时 SealingKey 的输出，不包括认证标签
+    const CHACHA_ENCRYPTED: &str = concat!(
+        "a39afc862846152b2bef4631404dd898b6ecbb4d06821d46118f44b2735a2e9f",
+        "ef23663ad724998b760d99b9acd2662e",
+    );
+
+    /// python cryptography 生成的 P-256 公钥
+    const P256_POINT: &str = concat!(
+        "049fad84aeae08bbef7f010014d82cef6a09de2b0cf871b5ce0c4f1d13a59a59",
+        "3407cb45769f1070e2c2470fe5b1bfe63133c0b0cdc64ea4bf3791a8ec2a07fd",
+        "4f",
+    );
+
+    /// 对 "message" 的签名 r || s
+    const P256_SIG: &str = concat!(
+        "87b6351ca014a7132d654ee0628483f68754c1c9bd43d95c6b6366c2a73e679d",
+        "f1c368ee2189016e84b27de06e3e38adf5de2e0d393e862fe25414fd19c3706c",
+    );
+
+    /// python cryptography 生成的 P-384 公钥
+    const P384_POINT: &str = concat!(
+        "04fbf96095915eb587390f850ec95b35f618ae121c72a70808ad8f9f4c261b60",
+        "2b63541743e395f7c778e52bb28c18dc83016a6ff3f72c9368326ad50a3bd148",
+        "a53cd2213bb480e4730ad019db87ae100995ec65df011e19811a15dc9ddfc9ab",
+        "3c",
+    );
+
+    /// 对 "message" 的签名 r || s
+    const P384_SIG: &str = concat!(
+        "122c828e3669130e77ec316bb7eb851371935c5cdf6eb334917bbefde38d985f",
+        "92962b44b80a1c197e28b7d44bab62b7e381a0afc5d7a883b8ca13c53934f549",
+        "0e32b3ef0702cde57865392c704c7c1e8ba9c2e5501386b1f00bfbfd968c23b0",
+    );
+
+    /// python cryptography 生成的 2048 位 RSA 公钥，e 为 65537
+    const RSA_N: &str = concat!(
+        "8fd56e1bd7a10e90077591b40b27af37650d4ad5af878a4271a9bd47b9d1a763",
+        "1e841435692ea926482797e994dd0b5aa51665129cb56adbb9f11172dd6380ce",
+        "5ad25f05ae2d7feb9aa489f4d3f835eb510e07e06452a254344a623f8dc4728e",
+        "3a7a468bcf58b1537ca5c6bced42f27d3e545c1a6c324ecbb4d5d0e9cff07a74",
+        "67b19ddc33d186e123216cb9e3920c27354b50afbf277706104c912eae05e066",
+        "155697e9ec56b0c7d9ffc722b2056fdc0a7c0b3d3a04e67ace94d0875cd01cd7",
+        "93218be6ed32e2c1b7dc683856377c924ea01cf915d30b1de94e2dcd151784e8",
+        "7f38f84ca642194e4230b093b6bfdea66add42b1d2da88ff22364c11cb2ef7eb",
+    );
+
+    /// 对 "message" 的 RSASSA-PKCS1-v1_5 签名
+    const RSA_SHA1_SIG: &str = concat!(
+        "240ef515692b5de9b574ac7610f47b324e7e5e4172177cd8aa0a5f28f9029326",
+        "f06c29877e36911342bb4e44b40d1eb7e7505f679528bc30f39d3c4a6651688c",
+        "9ba70c690d848ce86c0875605ef81479dec8e2d5952b38a8589fe4b57272902b",
+        "afbe03e28e5dff84a8043ef35664d23f8e71588120c776a7e8a087709dc651d1",
+        "ef55cc708672f94c2278b75a2268cc19363ae39c78b032ae103ac2b4c5fa4f2d",
+        "86db010b14d9441072baa2992f182b5e895c03fb0a4333378800ed8042183d4b",
+        "e91a2ed585a1b2d1b3914bd308602f424392fa4969f7f43be0c1423b2ca98fb9",
+        "8915156306b169901a1ca2d31841a18d1102c1375e46a7159b02f8c1b52b98db",
+    );
+
+    /// 对 "message" 的 RSASSA-PKCS1-v1_5 签名
+    const RSA_SHA256_SIG: &str = concat!(
+        "2fa4cf9067b4771d41b336d0cbc155ff92e45b4de2c91d55f554a8f5809df5e5",
+        "5a924f868719532467e3e1066250981cc078757313e3a5828fd7333eea119425",
+        "6d5b61aed27b1afe88a4c81b0ea5963d9b2066cae3c6704d1026bb55ab41fe6b",
+        "468f34007ccee31fc8aedef7e697c73833610a1c4e2c33883882bd1d42df35b1",
+        "d8268d6917bae64341d98ae980b1ff9fd38261197a5351f41e4f7afb8055f156",
+        "927b796dd67a9f0c94a9f787c2b22630bf86eb47fdcddd8df0fc030932c02d5a",
+        "eab452718139e97692e2152ccc0f4fe97fa9e56bb27c318ee836c8298d16c6c2",
+        "be923837b4cdde3fd3e19230f3fa02d00d2cbcd89459ff3ef81e32022e4adc36",
+    );
+
+    /// 对 "message" 的 RSASSA-PKCS1-v1_5 签名
+    const RSA_SHA512_SIG: &str = concat!(
+        "67d070042aef7f4b7b0d26bf9aa4f9216cbde766cb31dd06a98c3dc318516bec",
+        "cc41a63afaaa9f63d9b29d0e01235d654e0820c3dd86347aba3bf15a6bb9eea3",
+        "ff1ffe616c88a8e754f23caeb8e4092a9b4ebb61bebd271b48ed1ed5f8369c46",
+        "dc625baa75edf323d2a0a91e2331b570c200a698259dd99dff53bcd3011056c4",
+        "6f5850859f79129c9591e0186d014efd9b4222d41d2385953301681282e9ffc4",
+        "c6ed4d2415567ded3afb90286f278fe08cc855beb160d553f930d0da2f92820c",
+        "40d74ce657c6d7c2b964c88a287e43f695347d9d201082e8c748c2f77415c713",
+        "1e1edba50db69ec25a433ade91c66f19c5531b11dda8ccd11f9af1fe9de07786",
+    );
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn backend_name() {
+        let expected = if cfg!(feature = "ring-backend") { "ring" } else { "ring+rustcrypto" };
+        assert_eq!(NAME, expected);
+    }
+
+    #[test]
+    fn digest_known_answers() {
+        // FIPS 180-2 "abc"
+        assert_eq!(digest(Digest::Sha1, b"abc"), hex("a9993e364706816aba3e25717850c26c9cd0d89d"));
+        assert_eq!(digest(Digest::Sha256, b"abc"),
+                   hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+        assert_eq!(digest(Digest::Sha512, b"abc"), hex(concat!(
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a",
+            "2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        )));
+    }
+
+    #[test]
+    fn hmac_known_answers() {
+        // RFC 2202 和 RFC 4231 的第 2 组，分成多段计算结果相同
+        let data: [&[u8]; 2] = [b"what do ya want ", b"for nothing?"];
+        assert_eq!(hmac(Digest::Sha1, b"Jefe", &data), hex("effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"));
+        assert_eq!(hmac(Digest::Sha256, b"Jefe", &data),
+                   hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"));
+        assert_eq!(hmac(Digest::Sha512, b"Jefe", &data), hex(concat!(
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554",
+            "9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        )));
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+
+    #[test]
+    fn aes_ctr_known_answer() {
+        // NIST SP 800-38A F.5.1
+        let mut key = [0_u8; 16];
+        key.copy_from_slice(&hex("2b7e151628aed2a6abf7158809cf4f3c"));
+        let mut iv = [0_u8; 16];
+        iv.copy_from_slice(&hex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff"));
+        let mut buf = hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+        let mut ctr = Aes128Ctr::new(&key, &iv);
+        let mut head = buf[..16].to_vec();
+        ctr.peek(&mut head);
+        ctr.apply_keystream(&mut buf);
+        assert_eq!(buf, hex("874d6191b620e3261bef6864990db6ce9806f66b7970fdff8617187bb9fffdff"));
+        // peek 不移动密钥流的位置
+        assert_eq!(head, buf[..16]);
+    }
+
+    #[test]
+    fn aes_gcm_known_answers() {
+        // GCM 规范的测试用例 2 和 14: 全 0 的密钥、nonce 和明文
+        for (key_len, cipher, tag) in [
+            (16, "0388dace60b6a392f328c2b971b2fe78", "ab6e47d42cec13bdf53a67b21257bddf"),
+            (32, "cea7403d4d606b6e074ec5d3baf39d18", "d0d1c8a799996bf0265b98b5d48ab919"),
+        ] {
+            let gcm = AesGcm::new(&vec![0; key_len]).unwrap();
+            let mut buf = vec![0_u8; 16];
+            assert_eq!(gcm.seal([0; 12], &[], &mut buf).unwrap(), hex(tag));
+            assert_eq!(buf, hex(cipher));
+            buf.extend(hex(tag));
+            assert_eq!(gcm.open([0; 12], &[], &mut buf).unwrap(), 16);
+            assert_eq!(buf[..16], [0; 16]);
+            // 附加数据不同时认证失败
+            let mut buf = [hex(cipher), hex(tag)].concat();
+            assert!(gcm.open([0; 12], b"aad", &mut buf).is_err());
+        }
+        assert!(AesGcm::new(&[0; 24]).is_err());
+    }
+
+    #[test]
+    fn chacha20_poly1305_known_answer() {
+        // 由 python cryptography 的 ChaCha20 和 Poly1305 按 PROTOCOL.chacha20poly1305 计算
+        let mut key = [0_u8; 64];
+        key.iter_mut().enumerate().for_each(|(i, v)| *v = i as u8);
+        let packet = hex(CHACHA_PACKET);
+        let encrypted = hex(CHACHA_ENCRYPTED);
+        let tag = hex("93f0898cf496eae83544a3992992530f");
+        let mut buf = packet.clone();
+        let mut tag_out = [0_u8; 16];
+        SealingKey::new(&key).seal_in_place(7, &mut buf, &mut tag_out);
+        assert_eq!(buf, encrypted);
+        assert_eq!(tag_out.to_vec(), tag);
+        let opening_key = OpeningKey::new(&key);
+        let mut len = [0_u8; 4];
+        len.copy_from_slice(&buf[..4]);
+        assert_eq!(opening_key.decrypt_packet_length(7, len), [0, 0, 0, 44]);
+        assert_eq!(opening_key.open_in_place(7, &mut buf, &tag_out).unwrap(), &packet[4..]);
+        // 序号不同时认证失败
+        let mut buf = encrypted.clone();
+        assert!(opening_key.open_in_place(8, &mut buf, &tag_out).is_err());
+    }
+
+    #[test]
+    fn ecdh_agreement() {
+        for (curve, len) in [(Curve::X25519, 32), (Curve::NistP256, 65)] {
+            let a = EphemeralKey::generate(curve).unwrap();
+            let b = EphemeralKey::generate(curve).unwrap();
+            assert_eq!(a.public_key().len(), len);
+            let secret = a.agree(b.public_key()).unwrap();
+            assert_eq!(secret, b.agree(a.public_key()).unwrap());
+            assert_eq!(secret.len(), 32);
+        }
+        // 不在曲线上的点和 X25519 的小阶点
+        let key = EphemeralKey::generate(Curve::NistP256).unwrap();
+        let mut point = vec![4_u8];
+        point.extend([1_u8; 64]);
+        assert!(key.agree(&point).is_err());
+        assert!(EphemeralKey::generate(Curve::X25519).unwrap().agree(&[0; 32]).is_err());
+    }
+
+    #[test]
+    fn ed25519_known_answer() {
+        // RFC 8032 7.1 TEST 1
+        let public_key = hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+        let sig = hex(concat!(
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155",
+            "5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        ));
+        assert!(verify_ed25519(&public_key, b"", &sig));
+        assert!(!verify_ed25519(&public_key, b"x", &sig));
+    }
+
+    #[test]
+    fn ecdsa_known_answers() {
+        let vectors = [
+            (EcdsaCurve::NistP256, hex(P256_POINT), hex(P256_SIG)),
+            (EcdsaCurve::NistP384, hex(P384_POINT), hex(P384_SIG)),
+        ];
+        for (curve, point, sig) in vectors {
+            assert!(verify_ecdsa(curve, &point, b"message", &sig));
+            assert!(!verify_ecdsa(curve, &point, b"massage", &sig));
+        }
+    }
+
+    #[test]
+    fn rsa_known_answers() {
+        let n = hex(RSA_N);
+        let e = hex("010001");
+        let vectors = [
+            (Digest::Sha1, hex(RSA_SHA1_SIG)),
+            (Digest::Sha256, hex(RSA_SHA256_SIG)),
+            (Digest::Sha512, hex(RSA_SHA512_SIG)),
+        ];
+        for (algorithm, sig) in &vectors {
+            assert!(verify_rsa(*algorithm, &e, &n, b"message", sig).unwrap());
+            assert!(!verify_rsa(*algorithm, &e, &n, b"massage", sig).unwrap());
+        }
+        // mpint 格式的 n 有前导 0
+        let n = [vec![0], n].concat();
+        assert!(verify_rsa(Digest::Sha256, &e, &n, b"message", &vectors[1].1).unwrap());
+    }
+}
@@ -0,0 +1,46 @@
+//! ring 后端
+//!
+//! 和默认后端的区别是 RSA 验签也使用 ring，ring 要求 RSA 密钥至少 2048 位。
+//! ring 没有提供 AES-CTR，仍然使用默认后端的实现。
+
+use ring::signature;
+use crate::algorithm::backend::Digest;
+use crate::SshResult;
+
+pub(crate) use super::default::{
+    digest,
+    hmac,
+    constant_time_eq,
+    Aes128Ctr,
+    AesGcm,
+    OpeningKey,
+    SealingKey,
+    EphemeralKey,
+    verify_ed25519,
+    verify_ecdsa,
+};
+
+
+/// 后端名称
+pub(crate) const NAME: &str = "ring";
+
+
+/// RSASSA-PKCS1-v1_5, e 和 n 为大端字节
+pub(crate) fn verify_rsa(algorithm: Digest, e: &[u8], n: &[u8], message: &[u8], sig: &[u8]) -> SshResult<bool> {
+    let parameters = match algorithm {
+        Digest::Sha1 => &signature::RSA_PKCS1_2048_8192_SHA1_FOR_LEGACY_USE_ONLY,
+        Digest::Sha256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+        Digest::Sha512 => &signature::RSA_PKCS1_2048_8192_SHA512,
+    };
+    // mpint 最高位为 1 时会有前导 0, ring 不接受
+    let public_key = signature::RsaPublicKeyComponents {
+        n: strip_leading_zeros(n),
+        e: strip_leading_zeros(e)
+    };
+    Ok(public_key.verify(parameters, message, sig).is_ok())
+}
+
+fn strip_leading_zeros(v: &[u8]) -> &[u8] {
+    let start = v.iter().position(|b| *b != 0).unwrap_or(v.len());
+    &v[start..]
+}
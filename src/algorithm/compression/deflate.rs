@@ -0,0 +1,227 @@
+use crate::algorithm::compression::{
+    BitWriter,
+    DIST_BASE,
+    DIST_EXTRA,
+    LENGTH_BASE,
+    LENGTH_EXTRA,
+    MAX_MATCH,
+    MIN_MATCH,
+    WINDOW_SIZE,
+};
+
+
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+/// 查找匹配时最多比较的候选位置数量
+const MAX_CHAIN: usize = 128;
+/// 存储块的最大长度
+const MAX_STORED_LEN: usize = 65535;
+/// 空存储块的 LEN 和 NLEN
+const EMPTY_STORED_LEN: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+
+/// 一个 LZ77 符号
+enum Symbol {
+    Literal(u8),
+    Match { length: usize, distance: usize }
+}
+
+
+/// zlib 流的压缩端
+///
+/// 滑动窗口和哈希链在数据包之间保留，后面的数据包可以引用前面数据包的内容。
+/// 每个数据包的数据编码为一个固定哈夫曼块 (压缩后更大时为存储块)，
+/// 然后以 Z_SYNC_FLUSH 的空存储块结束，每个数据包在字节边界结束，对方收到一个数据包就能完整解压
+pub(crate) struct Deflate {
+    header_written: bool,
+    /// 最近的数据，最多保留两个窗口大小
+    history: Vec<u8>,
+    /// history[0] 在整个流中的位置
+    base: usize,
+    /// 已经加入哈希链的位置 (流中的位置)
+    inserted: usize,
+    /// 每个哈希值最近出现的位置 + 1，0 表示没有
+    head: Vec<usize>,
+    /// 同一个哈希值的上一个位置 + 1，按位置对窗口大小取模
+    prev: Vec<usize>,
+}
+
+impl Deflate {
+    pub(crate) fn new() -> Self {
+        Deflate {
+            header_written: false,
+            history: vec![],
+            base: 0,
+            inserted: 0,
+            head: vec![0; HASH_SIZE],
+            prev: vec![0; WINDOW_SIZE],
+        }
+    }
+
+    /// 压缩一个数据包的 payload
+    pub(crate) fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        if !self.header_written {
+            // CMF: deflate, 32K 窗口; FLG: 默认压缩级别，(CMF * 256 + FLG) 是 31 的倍数
+            out.extend([0x78, 0x9c]);
+            self.header_written = true;
+        }
+        let start = self.history.len();
+        self.history.extend_from_slice(data);
+        let symbols = self.symbols(start);
+
+        let mut fixed = BitWriter::new();
+        // BFINAL = 0, BTYPE = 01 (固定哈夫曼)
+        fixed.put_bits(0b010, 3);
+        for symbol in &symbols {
+            write_symbol(&mut fixed, symbol);
+        }
+        write_literal_length(&mut fixed, 256);
+        // Z_SYNC_FLUSH: 空的存储块，块头紧接在上一个块之后，然后对齐到字节
+        fixed.put_bits(0, 3);
+        let mut fixed = fixed.finish();
+        fixed.extend(EMPTY_STORED_LEN);
+
+        let stored_len = data.len() + (data.len() / MAX_STORED_LEN + 1) * 5;
+        if fixed.len() <= stored_len + 5 {
+            out.extend(fixed);
+        } else {
+            write_stored(&mut out, data);
+            write_stored(&mut out, &[]);
+        }
+        self.slide();
+        out
+    }
+
+    /// 对 history[start..] 查找匹配
+    fn symbols(&mut self, start: usize) -> Vec<Symbol> {
+        let end = self.history.len();
+        let mut symbols = vec![];
+        let mut i = start;
+        while i < end {
+            self.insert_until(self.base + i);
+            match self.longest_match(i, end) {
+                Some((length, distance)) => {
+                    symbols.push(Symbol::Match { length, distance });
+                    i += length;
+                }
+                None => {
+                    symbols.push(Symbol::Literal(self.history[i]));
+                    i += 1;
+                }
+            }
+        }
+        symbols
+    }
+
+    /// 加入哈希链，直到流中的位置 pos (不包括)
+    /// 一个位置需要后面有 MIN_MATCH - 1 个字节才能计算哈希，末尾的位置留到下一个数据包再加入
+    fn insert_until(&mut self, pos: usize) {
+        let end = self.base + self.history.len();
+        while self.inserted < pos && self.inserted + MIN_MATCH <= end {
+            let i = self.inserted - self.base;
+            let hash = hash(&self.history[i..(i + MIN_MATCH)]);
+            self.prev[self.inserted & WINDOW_MASK] = self.head[hash];
+            self.head[hash] = self.inserted + 1;
+            self.inserted += 1;
+        }
+    }
+
+    fn longest_match(&self, i: usize, end: usize) -> Option<(usize, usize)> {
+        if i + MIN_MATCH > end {
+            return None
+        }
+        let pos = self.base + i;
+        let max_len = MAX_MATCH.min(end - i);
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[hash(&self.history[i..(i + MIN_MATCH)])];
+        let mut chain = 0;
+        while candidate > 0 && chain < MAX_CHAIN {
+            let c = candidate - 1;
+            if c >= pos || pos - c > WINDOW_SIZE || c < self.base {
+                break
+            }
+            let ci = c - self.base;
+            let length = self.history[ci..].iter()
+                .zip(&self.history[i..(i + max_len)])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length >= MIN_MATCH && best.is_none_or(|(l, _)| length > l) {
+                best = Some((length, pos - c));
+                if length == max_len {
+                    break
+                }
+            }
+            let next = self.prev[c & WINDOW_MASK];
+            // prev 按窗口大小取模，旧的记录可能已经被覆盖
+            if next >= candidate {
+                break
+            }
+            candidate = next;
+            chain += 1;
+        }
+        best
+    }
+
+    /// 只保留最后一个窗口大小的数据
+    fn slide(&mut self) {
+        if self.history.len() <= 2 * WINDOW_SIZE {
+            return
+        }
+        let drop = self.history.len() - WINDOW_SIZE;
+        self.history.drain(..drop);
+        self.base += drop;
+    }
+}
+
+
+fn hash(v: &[u8]) -> usize {
+    let h = ((v[0] as usize) << 10) ^ ((v[1] as usize) << 5) ^ (v[2] as usize);
+    (h.wrapping_mul(2654435761) >> 7) & (HASH_SIZE - 1)
+}
+
+fn write_symbol(w: &mut BitWriter, symbol: &Symbol) {
+    match *symbol {
+        Symbol::Literal(v) => write_literal_length(w, v as u16),
+        Symbol::Match { length, distance } => {
+            let code = LENGTH_BASE.iter().rposition(|v| *v as usize <= length).unwrap();
+            write_literal_length(w, 257 + code as u16);
+            w.put_bits((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code]);
+            let code = DIST_BASE.iter().rposition(|v| *v as usize <= distance).unwrap();
+            // 固定哈夫曼的距离码都是 5 位
+            w.put_huffman(code as u32, 5);
+            w.put_bits((distance - DIST_BASE[code] as usize) as u32, DIST_EXTRA[code]);
+        }
+    }
+}
+
+/// 固定哈夫曼的字面量/长度码 (RFC 1951 3.2.6)
+fn write_literal_length(w: &mut BitWriter, v: u16) {
+    let v = v as u32;
+    match v {
+        0..=143 => w.put_huffman(0x30 + v, 8),
+        144..=255 => w.put_huffman(0x190 + v - 144, 9),
+        256..=279 => w.put_huffman(v - 256, 7),
+        _ => w.put_huffman(0xc0 + v - 280, 8),
+    }
+}
+
+/// 存储块，开始时必须已经对齐到字节
+/// 数据为空时写入一个空的存储块
+fn write_stored(out: &mut Vec<u8>, data: &[u8]) {
+    let mut rest = data;
+    loop {
+        let (chunk, remaining) = rest.split_at(rest.len().min(MAX_STORED_LEN));
+        // BFINAL = 0, BTYPE = 00, 剩下的 5 位用于对齐
+        out.push(0);
+        let len = chunk.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(chunk);
+        rest = remaining;
+        if rest.is_empty() {
+            return
+        }
+    }
+}
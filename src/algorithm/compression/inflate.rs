@@ -0,0 +1,375 @@
+use crate::algorithm::compression::{
+    DIST_BASE,
+    DIST_EXTRA,
+    LENGTH_BASE,
+    LENGTH_EXTRA,
+    WINDOW_SIZE,
+};
+use crate::{SshError, SshResult};
+
+
+const MAX_BITS: usize = 15;
+/// 码长的码长的顺序 (RFC 1951 3.2.7)
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+
+/// 范式哈夫曼码表，按码长依次解码
+struct Huffman {
+    /// 每个码长的符号数量
+    count: [u16; MAX_BITS + 1],
+    /// 按码的顺序排列的符号
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> SshResult<Self> {
+        let mut count = [0_u16; MAX_BITS + 1];
+        for v in lengths {
+            count[*v as usize] += 1;
+        }
+        count[0] = 0;
+        // 码不能超额分配 (不完整的码是允许的，例如只有一个距离码)
+        let mut left: i32 = 1;
+        for v in &count[1..] {
+            left = (left << 1) - *v as i32;
+            if left < 0 {
+                return Err(SshError::from("decompression error: invalid huffman code."))
+            }
+        }
+        let mut offset = [0_u16; MAX_BITS + 1];
+        for i in 1..MAX_BITS {
+            offset[i + 1] = offset[i] + count[i];
+        }
+        let mut symbol = vec![0; lengths.len()];
+        for (i, v) in lengths.iter().enumerate() {
+            if *v != 0 {
+                symbol[offset[*v as usize] as usize] = i as u16;
+                offset[*v as usize] += 1;
+            }
+        }
+        Ok(Huffman { count, symbol })
+    }
+
+    fn fixed_literal_length() -> Self {
+        let mut lengths = [0_u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        // 固定码表是完整的，不会失败
+        Huffman::new(&lengths).unwrap()
+    }
+
+    fn fixed_distance() -> Self {
+        Huffman::new(&[5; 30]).unwrap()
+    }
+}
+
+
+/// 当前正在解压的块
+enum Block {
+    /// 存储块，剩余的字节数
+    Stored(usize),
+    Huffman { literal_length: Huffman, distance: Huffman },
+}
+
+
+/// 数据不够时返回 None，由调用方回退到上一个完整的位置
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// 以位为单位的位置
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, n: u8) -> Option<u32> {
+        if self.pos + n as usize > self.data.len() * 8 {
+            return None
+        }
+        let mut v = 0_u32;
+        for i in 0..n as usize {
+            let p = self.pos + i;
+            let bit = (self.data[p / 8] >> (p % 8)) & 1;
+            v |= (bit as u32) << i;
+        }
+        self.pos += n as usize;
+        Some(v)
+    }
+
+    fn decode(&mut self, h: &Huffman) -> Option<SshResult<u16>> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS {
+            code |= self.bits(1)? as i32;
+            let count = h.count[len] as i32;
+            if code - count < first {
+                return Some(Ok(h.symbol[(index + (code - first)) as usize]))
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Some(Err(SshError::from("decompression error: invalid huffman code.")))
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8
+    }
+}
+
+
+/// zlib 流的解压端
+///
+/// 对方的数据包不一定在块的边界结束 (例如 Z_PARTIAL_FLUSH)，
+/// 没有解压完的输入和当前块的状态在数据包之间保留
+pub(crate) struct Inflate {
+    header_read: bool,
+    finished: bool,
+    /// 还没有处理的输入
+    input: Vec<u8>,
+    /// input[0] 中已经处理的位数
+    bit_offset: usize,
+    block: Option<Block>,
+    /// 最后一个块之后是否结束
+    last_block: bool,
+    /// 最近解压的数据，用于距离引用
+    window: Vec<u8>,
+}
+
+impl Inflate {
+    pub(crate) fn new() -> Self {
+        Inflate {
+            header_read: false,
+            finished: false,
+            input: vec![],
+            bit_offset: 0,
+            block: None,
+            last_block: false,
+            window: vec![],
+        }
+    }
+
+    /// 解压一个数据包的 payload，解压后的长度超过 max_len 时返回错误
+    pub(crate) fn decompress(&mut self, data: &[u8], max_len: usize) -> SshResult<Vec<u8>> {
+        // ssh 中的流不会结束
+        if self.finished {
+            return Err(SshError::from("decompression error: data after the end of stream."))
+        }
+        self.input.extend_from_slice(data);
+        let mut out = vec![];
+        let input = std::mem::take(&mut self.input);
+        let mut reader = BitReader { data: &input, pos: self.bit_offset };
+        let result = self.run(&mut reader, &mut out, max_len);
+        let consumed = reader.pos / 8;
+        self.bit_offset = reader.pos % 8;
+        self.input = input[consumed..].to_vec();
+        result?;
+
+        self.window.extend_from_slice(&out);
+        if self.window.len() > 2 * WINDOW_SIZE {
+            let drop = self.window.len() - WINDOW_SIZE;
+            self.window.drain(..drop);
+        }
+        Ok(out)
+    }
+
+    fn run(&mut self, r: &mut BitReader, out: &mut Vec<u8>, max_len: usize) -> SshResult<()> {
+        if !self.header_read {
+            let checkpoint = r.pos;
+            let (cmf, flg) = match (r.bits(8), r.bits(8)) {
+                (Some(cmf), Some(flg)) => (cmf, flg),
+                _ => {
+                    r.pos = checkpoint;
+                    return Ok(())
+                }
+            };
+            if cmf & 0x0f != 8 || (cmf << 8 | flg) % 31 != 0 || flg & 0x20 != 0 {
+                return Err(SshError::from("decompression error: invalid zlib header."))
+            }
+            self.header_read = true;
+        }
+        loop {
+            if self.finished {
+                // 最后一个块之后只有 adler32
+                r.pos = r.data.len() * 8;
+                return Ok(())
+            }
+            if self.block.is_none() {
+                let checkpoint = r.pos;
+                match self.read_block_header(r)? {
+                    Some(block) => self.block = Some(block),
+                    None => {
+                        r.pos = checkpoint;
+                        return Ok(())
+                    }
+                }
+            }
+            let done = match self.block.as_mut() {
+                Some(Block::Stored(remaining)) => {
+                    let available = r.data.len() - r.pos / 8;
+                    let n = (*remaining).min(available);
+                    out.extend_from_slice(&r.data[(r.pos / 8)..(r.pos / 8 + n)]);
+                    r.pos += n * 8;
+                    *remaining -= n;
+                    if *remaining > 0 {
+                        check_len(out, max_len)?;
+                        return Ok(())
+                    }
+                    true
+                }
+                Some(Block::Huffman { literal_length, distance }) => {
+                    match inflate_symbols(r, literal_length, distance, &self.window, out, max_len)? {
+                        true => true,
+                        false => return Ok(())
+                    }
+                }
+                None => false
+            };
+            check_len(out, max_len)?;
+            if done {
+                self.block = None;
+                if self.last_block {
+                    self.finished = true;
+                }
+            }
+        }
+    }
+
+    /// 读取块头，动态哈夫曼块包括码表
+    fn read_block_header(&mut self, r: &mut BitReader) -> SshResult<Option<Block>> {
+        let header = match r.bits(3) {
+            Some(v) => v,
+            None => return Ok(None)
+        };
+        self.last_block = header & 1 == 1;
+        match header >> 1 {
+            0 => {
+                r.align();
+                let (len, nlen) = match (r.bits(16), r.bits(16)) {
+                    (Some(len), Some(nlen)) => (len, nlen),
+                    _ => return Ok(None)
+                };
+                if len != !nlen & 0xffff {
+                    return Err(SshError::from("decompression error: invalid stored block length."))
+                }
+                Ok(Some(Block::Stored(len as usize)))
+            }
+            1 => Ok(Some(Block::Huffman {
+                literal_length: Huffman::fixed_literal_length(),
+                distance: Huffman::fixed_distance(),
+            })),
+            2 => read_dynamic(r),
+            _ => Err(SshError::from("decompression error: invalid block type."))
+        }
+    }
+}
+
+
+fn check_len(out: &[u8], max_len: usize) -> SshResult<()> {
+    if out.len() > max_len {
+        return Err(SshError::from("decompression error: payload too large."))
+    }
+    Ok(())
+}
+
+fn read_dynamic(r: &mut BitReader) -> SshResult<Option<Block>> {
+    macro_rules! need {
+        ($e: expr) => {
+            match $e {
+                Some(v) => v,
+                None => return Ok(None)
+            }
+        };
+    }
+    let hlit = need!(r.bits(5)) as usize + 257;
+    let hdist = need!(r.bits(5)) as usize + 1;
+    let hclen = need!(r.bits(4)) as usize + 4;
+    if hlit > 286 || hdist > 30 {
+        return Err(SshError::from("decompression error: too many codes."))
+    }
+    let mut lengths = [0_u8; 19];
+    for i in CODE_LENGTH_ORDER.iter().take(hclen) {
+        lengths[*i] = need!(r.bits(3)) as u8;
+    }
+    let code_length = Huffman::new(&lengths)?;
+    let mut lengths = vec![0_u8; hlit + hdist];
+    let mut i = 0;
+    while i < hlit + hdist {
+        let symbol = need!(r.decode(&code_length))?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if i == 0 {
+                    return Err(SshError::from("decompression error: repeat without a previous length."))
+                }
+                (lengths[i - 1], 3 + need!(r.bits(2)) as usize)
+            }
+            17 => (0, 3 + need!(r.bits(3)) as usize),
+            _ => (0, 11 + need!(r.bits(7)) as usize),
+        };
+        if i + repeat > hlit + hdist {
+            return Err(SshError::from("decompression error: too many lengths."))
+        }
+        lengths[i..(i + repeat)].fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(SshError::from("decompression error: missing end of block code."))
+    }
+    Ok(Some(Block::Huffman {
+        literal_length: Huffman::new(&lengths[..hlit])?,
+        distance: Huffman::new(&lengths[hlit..])?,
+    }))
+}
+
+/// 解压到块结束时返回 true，输入不够时回退到最后一个完整的符号并返回 false
+fn inflate_symbols(r: &mut BitReader,
+                   literal_length: &Huffman,
+                   distance: &Huffman,
+                   window: &[u8],
+                   out: &mut Vec<u8>,
+                   max_len: usize) -> SshResult<bool>
+{
+    loop {
+        let checkpoint = r.pos;
+        macro_rules! need {
+            ($e: expr) => {
+                match $e {
+                    Some(v) => v,
+                    None => {
+                        r.pos = checkpoint;
+                        return Ok(false)
+                    }
+                }
+            };
+        }
+        let symbol = need!(r.decode(literal_length))?;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(true),
+            257..=285 => {
+                let code = (symbol - 257) as usize;
+                let length = LENGTH_BASE[code] as usize + need!(r.bits(LENGTH_EXTRA[code])) as usize;
+                let code = need!(r.decode(distance))? as usize;
+                if code >= DIST_BASE.len() {
+                    return Err(SshError::from("decompression error: invalid distance code."))
+                }
+                let dist = DIST_BASE[code] as usize + need!(r.bits(DIST_EXTRA[code])) as usize;
+                if dist > window.len() + out.len() {
+                    return Err(SshError::from("decompression error: distance too far back."))
+                }
+                for _ in 0..length {
+                    let v = match dist > out.len() {
+                        true => window[window.len() + out.len() - dist],
+                        false => out[out.len() - dist]
+                    };
+                    out.push(v);
+                }
+            }
+            _ => return Err(SshError::from("decompression error: invalid literal/length code."))
+        }
+        check_len(out, max_len)?;
+    }
+}
@@ -0,0 +1,221 @@
+//! 压缩算法
+//!
+//! 压缩只作用于数据包的 payload，在加密之前压缩，解密之后解压。
+//! 每个方向上是一个连续的 zlib 流，不是每个数据包单独压缩，
+//! 每个数据包结束时刷新 (Z_PARTIAL_FLUSH / Z_SYNC_FLUSH)，对方收到后可以完整解压。
+//!
+//! - zlib: 密钥交换完成后开始 (RFC 4253 6.2)
+//! - zlib@openssh.com: 用户认证成功后才开始，认证之前的数据包不压缩
+
+mod deflate;
+mod inflate;
+
+use crate::algorithm::compression::deflate::Deflate;
+use crate::algorithm::compression::inflate::Inflate;
+use crate::constant::algorithms;
+use crate::limits;
+use crate::SshResult;
+
+
+/// 滑动窗口大小
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// 长度码 257 - 285 的基础长度和额外位数 (RFC 1951 3.2.5)
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+/// 距离码 0 - 29 的基础距离和额外位数
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+
+
+/// deflate 的位流，从每个字节的最低位开始写
+struct BitWriter {
+    out: Vec<u8>,
+    buf: u32,
+    len: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter { out: vec![], buf: 0, len: 0 }
+    }
+
+    /// 数值和额外位，低位在前
+    fn put_bits(&mut self, v: u32, n: u8) {
+        for i in 0..n {
+            self.buf |= ((v >> i) & 1) << self.len;
+            self.len += 1;
+            if self.len == 8 {
+                self.out.push(self.buf as u8);
+                self.buf = 0;
+                self.len = 0;
+            }
+        }
+    }
+
+    /// 哈夫曼码，高位在前
+    fn put_huffman(&mut self, code: u32, n: u8) {
+        for i in (0..n).rev() {
+            self.put_bits((code >> i) & 1, 1)
+        }
+    }
+
+    /// 不足一个字节的部分补 0
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push(self.buf as u8);
+        }
+        self.out
+    }
+}
+
+
+/// 一个方向上的压缩状态
+enum Stream<T> {
+    /// 没有协商压缩
+    None,
+    /// zlib@openssh.com, 等待用户认证成功
+    Delayed,
+    Started(T),
+}
+
+impl<T> Stream<T> {
+    /// 重新协商密钥时仍然是 zlib 的话，已经开始的流继续使用，不重新开始
+    fn negotiated(self, algorithm: &str, authenticated: bool, start: fn() -> T) -> Self {
+        match (self, algorithm) {
+            (Stream::Started(v), algorithms::COMPRESSION_ZLIB | algorithms::COMPRESSION_ZLIB_OPENSSH) => Stream::Started(v),
+            (_, algorithms::COMPRESSION_ZLIB) => Stream::Started(start()),
+            (_, algorithms::COMPRESSION_ZLIB_OPENSSH) if authenticated => Stream::Started(start()),
+            (_, algorithms::COMPRESSION_ZLIB_OPENSSH) => Stream::Delayed,
+            _ => Stream::None
+        }
+    }
+}
+
+
+/// 每个 client 两个方向上的压缩状态
+pub(crate) struct Compression {
+    client_to_server: Stream<Deflate>,
+    server_to_client: Stream<Inflate>,
+    authenticated: bool,
+}
+
+impl Compression {
+    pub(crate) fn new() -> Self {
+        Compression {
+            client_to_server: Stream::None,
+            server_to_client: Stream::None,
+            authenticated: false,
+        }
+    }
+
+    /// 密钥交换完成后使用新协商的算法
+    pub(crate) fn negotiated(&mut self, client_to_server: &str, server_to_client: &str) {
+        let authenticated = self.authenticated;
+        self.client_to_server = std::mem::replace(&mut self.client_to_server, Stream::None)
+            .negotiated(client_to_server, authenticated, Deflate::new);
+        self.server_to_client = std::mem::replace(&mut self.server_to_client, Stream::None)
+            .negotiated(server_to_client, authenticated, Inflate::new);
+    }
+
+    /// 用户认证成功，zlib@openssh.com 从下一个数据包开始压缩
+    pub(crate) fn authenticated(&mut self) {
+        self.authenticated = true;
+        if let Stream::Delayed = self.client_to_server {
+            self.client_to_server = Stream::Started(Deflate::new())
+        }
+        if let Stream::Delayed = self.server_to_client {
+            self.server_to_client = Stream::Started(Inflate::new())
+        }
+    }
+
+    /// 没有开始压缩时返回 None
+    pub(crate) fn compress(&mut self, payload: &[u8]) -> Option<Vec<u8>> {
+        match &mut self.client_to_server {
+            Stream::Started(v) => Some(v.compress(payload)),
+            _ => None
+        }
+    }
+
+    /// 没有开始压缩时返回 None
+    pub(crate) fn decompress(&mut self, payload: &[u8]) -> Option<SshResult<Vec<u8>>> {
+        match &mut self.server_to_client {
+            Stream::Started(v) => Some(v.decompress(payload, limits::MAX_DECOMPRESSED_LEN)),
+            _ => None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::constant::algorithms;
+    use crate::limits;
+    use super::{Compression, Deflate, Inflate};
+
+    /// 压缩后很难变小的数据
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x12345678_u32;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    fn round_trip(packets: &[Vec<u8>]) -> Vec<usize> {
+        let mut deflate = Deflate::new();
+        let mut inflate = Inflate::new();
+        packets.iter().map(|packet| {
+            let compressed = deflate.compress(packet);
+            assert_eq!(&inflate.decompress(&compressed, limits::MAX_DECOMPRESSED_LEN).unwrap(), packet);
+            compressed.len()
+        }).collect()
+    }
+
+    #[test]
+    fn empty_payload() {
+        round_trip(&[vec![], b"after empty".to_vec(), vec![]]);
+    }
+
+    #[test]
+    fn payload_larger_than_stored_block() {
+        // 超过 65535 字节的不可压缩数据分成多个存储块
+        round_trip(&[noise(70000), noise(100)]);
+        round_trip(&[vec![b'a'; 70000]]);
+    }
+
+    #[test]
+    fn stream_persists_across_packets() {
+        let packet = b"the quick brown fox jumps over the lazy dog, ".repeat(4);
+        let packets = vec![packet.clone(), noise(300), packet.clone(), packet];
+        let sizes = round_trip(&packets);
+        // 后面的数据包引用前面数据包的内容，比第一个数据包小得多
+        assert!(sizes[2] < sizes[0] / 2);
+        assert!(sizes[3] < sizes[0] / 2);
+    }
+
+    #[test]
+    fn openssh_zlib_starts_after_authentication() {
+        let mut compression = Compression::new();
+        compression.negotiated(algorithms::COMPRESSION_ZLIB_OPENSSH, algorithms::COMPRESSION_NONE);
+        assert!(compression.compress(b"before").is_none());
+        compression.authenticated();
+        let compressed = compression.compress(b"after").unwrap();
+        assert_eq!(Inflate::new().decompress(&compressed, limits::MAX_DECOMPRESSED_LEN).unwrap(), b"after");
+        assert!(compression.decompress(b"plain").is_none());
+    }
+}
@@ -1,8 +1,10 @@
-use aes::Aes128Ctr;
-use aes::cipher::{NewCipher, StreamCipher, StreamCipherSeek};
-use crate::algorithm::{hash, mac};
+use crate::algorithm::backend::{self, Aes128Ctr};
+use crate::algorithm::hash::{Direction, HASH};
+use crate::algorithm::mac::Mac;
+use crate::algorithm::negotiated::Integrity;
 use crate::{SshError, SshResult};
-use crate::algorithm::encryption::Encryption;
+use crate::error::SshErrorKind;
+use crate::algorithm::encryption::{Encryption, PacketLength};
 
 
 
@@ -11,85 +13,114 @@ const IV_SIZE: usize = 16;
 
 
 pub struct AesCtr128 {
-    pub(crate) client_key: Aes128Ctr,
-    pub(crate) server_key: Aes128Ctr,
+    key: Aes128Ctr,
+    /// 这个方向上协商的 mac 算法
+    mac: Box<dyn Mac>,
+    /// mac 使用的 key
+    integrity_key: Vec<u8>,
 }
 
-impl Encryption for AesCtr128 {
-    fn bsize(&self) -> usize {
-        BSIZE
-    }
-    fn iv_size(&self) -> usize {
-        IV_SIZE
-    }
-
-    fn new() -> Self {
-        let hash = hash::get();
-        let (ck, sk) = hash.extend_key(BSIZE);
-        let mut ckey = [0u8; BSIZE];
-        let mut skey = [0u8; BSIZE];
-
-        let mut civ = [0u8; BSIZE];
-        let mut siv = [0u8; BSIZE];
-
-        ckey.clone_from_slice(&ck[..BSIZE]);
-        skey.clone_from_slice(&sk[..BSIZE]);
-
-        civ.clone_from_slice(&hash.iv_c_s[..IV_SIZE]);
-        siv.clone_from_slice(&hash.iv_s_c[..IV_SIZE]);
-
-        // TODO unwrap 未处理
-        let c = Aes128Ctr::new_from_slices(&ckey, &civ).unwrap();
-        let r = Aes128Ctr::new_from_slices(&skey, &siv).unwrap();
+impl AesCtr128 {
+    pub(crate) fn new(hash: &HASH, direction: Direction, mac: Box<dyn Mac>) -> Self {
+        let keys = hash.keys(direction, BSIZE, mac.bsize());
+        let mut key = [0u8; BSIZE];
+        let mut iv = [0u8; IV_SIZE];
+        key.clone_from_slice(&keys.key);
+        iv.clone_from_slice(&keys.iv[..IV_SIZE]);
 
         AesCtr128 {
-            client_key: c,
-            server_key: r,
+            key: Aes128Ctr::new(&key, &iv),
+            integrity_key: keys.integrity_key,
+            mac,
         }
     }
+}
+
+impl Encryption for AesCtr128 {
+    fn bsize(&self) -> usize {
+        BSIZE
+    }
 
     fn encrypt(&mut self, client_sequence_num: u32, buf: &mut Vec<u8>) {
+        if self.mac.etm() {
+            return self.encrypt_then_mac(client_sequence_num, buf)
+        }
         let vec = buf.clone();
-        let mac = mac::get();
-        let tag = mac.sign(&hash::get().ik_c_s[..mac::get().bsize()], client_sequence_num, vec.as_slice());
-        self.client_key.apply_keystream(buf);
-        buf.extend(tag.as_ref())
+        let tag = self.mac.sign(&self.integrity_key, client_sequence_num, vec.as_slice());
+        self.key.apply_keystream(buf);
+        // 截断的 mac 算法只发送前 mac_len 个字节
+        let mac_len = self.mac.mac_len();
+        buf.extend(&tag[..mac_len])
     }
 
     fn decrypt(&mut self, server_sequence_number: u32, buf: &mut [u8]) -> SshResult<Vec<u8>> {
+        if self.mac.etm() {
+            return self.verify_then_decrypt(server_sequence_number, buf)
+        }
         let pl = self.packet_len(server_sequence_number, buf);
-        let data = &mut buf[..(pl + 20)];
+        let mac_len = self.mac.mac_len();
+        let data = &mut buf[..(pl + mac_len)];
         let (d, m) = data.split_at_mut(pl);
-        self.server_key.apply_keystream(d);
-        let mac = mac::get();
-        let tag = mac.sign(&hash::get().ik_s_c[..mac::get().bsize()], server_sequence_number, d);
-        let t = tag.as_ref();
-        if m != t {
-            return Err(SshError::from("encryption error."))
+        self.key.apply_keystream(d);
+        let tag = self.mac.sign(&self.integrity_key, server_sequence_number, d);
+        let t = &tag[..mac_len];
+        // 比较时间不依赖于 mac 的内容
+        if !backend::constant_time_eq(m, t) {
+            log::error!("mac verification failure.");
+            return Err(SshError::from(SshErrorKind::MacVerificationFailed))
         }
         Ok(d.to_vec())
     }
 
     fn packet_len(&mut self, _: u32, buf: &[u8]) -> usize {
+        if self.mac.etm() {
+            let mut u32_bytes = [0_u8; 4];
+            u32_bytes.clone_from_slice(&buf[..4]);
+            return u32::from_be_bytes(u32_bytes) as usize + 4
+        }
         let bsize = self.bsize();
         let mut r = vec![0_u8; bsize];
         r.clone_from_slice(&buf[..bsize]);
-        self.server_key.apply_keystream(&mut r);
-        let pos: usize = self.server_key.current_pos();
-        self.server_key.seek(pos - bsize);
+        self.key.peek(&mut r);
         let mut u32_bytes = [0_u8; 4];
         u32_bytes.clone_from_slice(&r[..4]);
         let packet_len = u32::from_be_bytes(u32_bytes);
         (packet_len + 4) as usize
     }
 
-    fn data_len(&mut self, server_sequence_number: u32, buf: &[u8]) -> usize {
-        let pl = self.packet_len(server_sequence_number, buf);
-        let bsize = mac::get().bsize();
-        pl + bsize
+    fn integrity(&self) -> Integrity {
+        Integrity::Mac(self.mac.mac_len())
     }
 
-    fn is_cp(&self) -> bool {
-        false
+    fn packet_length(&self) -> PacketLength {
+        match self.mac.etm() {
+            true => PacketLength::Aad,
+            false => PacketLength::Encrypted
+        }
+    }
+}
+
+impl AesCtr128 {
+    /// packet_length 不加密，mac 对 packet_length || 密文计算
+    fn encrypt_then_mac(&mut self, client_sequence_num: u32, buf: &mut Vec<u8>) {
+        self.key.apply_keystream(&mut buf[4..]);
+        let tag = self.mac.sign(&self.integrity_key, client_sequence_num, buf.as_slice());
+        let mac_len = self.mac.mac_len();
+        buf.extend(&tag[..mac_len])
+    }
+
+    /// 先校验 mac，通过后才解密
+    fn verify_then_decrypt(&mut self, server_sequence_number: u32, buf: &mut [u8]) -> SshResult<Vec<u8>> {
+        let pl = self.packet_len(server_sequence_number, buf);
+        let mac_len = self.mac.mac_len();
+        let data = &mut buf[..(pl + mac_len)];
+        let (d, m) = data.split_at_mut(pl);
+        let tag = self.mac.sign(&self.integrity_key, server_sequence_number, d);
+        if !backend::constant_time_eq(m, &tag[..mac_len]) {
+            log::error!("mac verification failure.");
+            return Err(SshError::from(SshErrorKind::MacVerificationFailed))
+        }
+        self.key.apply_keystream(&mut d[4..]);
+        Ok(d.to_vec())
     }
 }
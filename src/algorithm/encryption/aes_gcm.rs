@@ -0,0 +1,98 @@
+use crate::algorithm::backend;
+use crate::algorithm::encryption::{Encryption, PacketLength};
+use crate::algorithm::hash::{Direction, HASH};
+use crate::algorithm::negotiated::Integrity;
+use crate::constant::algorithms;
+use crate::{SshError, SshResult};
+use crate::error::SshErrorKind;
+
+
+const BSIZE: usize = 16;
+const IV_SIZE: usize = 12;
+const TAG_LEN: usize = 16;
+
+
+/// aes128-gcm@openssh.com / aes256-gcm@openssh.com (RFC 5647)
+///
+/// packet_length 明文传输，作为附加数据参与认证，
+/// nonce 为 4 字节固定字段 || 8 字节调用计数器，每个数据包之后计数器加一
+pub struct AesGcm {
+    key: backend::AesGcm,
+    nonce: [u8; IV_SIZE],
+}
+
+impl AesGcm {
+    /// 协商出的算法名称
+    pub(crate) fn new(algorithm: &str, hash: &HASH, direction: Direction) -> Self {
+        let key_size = match algorithm {
+            algorithms::ENCRYPTION_AES256_GCM_OPENSSH => 32,
+            _ => 16
+        };
+        let keys = hash.keys(direction, key_size, 0);
+        let mut nonce = [0_u8; IV_SIZE];
+        nonce.copy_from_slice(&keys.iv[..IV_SIZE]);
+        // 密钥是固定长度，不会失败
+        AesGcm {
+            key: backend::AesGcm::new(&keys.key).unwrap(),
+            nonce,
+        }
+    }
+}
+
+impl Encryption for AesGcm {
+    fn bsize(&self) -> usize {
+        BSIZE
+    }
+
+    fn encrypt(&mut self, _: u32, buf: &mut Vec<u8>) {
+        let (len, data) = buf.split_at_mut(4);
+        // 密钥和 nonce 都是固定长度，不会失败
+        let tag = self.key.seal(self.nonce, len, data).unwrap();
+        increment(&mut self.nonce);
+        buf.extend(tag)
+    }
+
+    fn decrypt(&mut self, sequence_number: u32, buf: &mut [u8]) -> SshResult<Vec<u8>> {
+        let pl = self.packet_len(sequence_number, buf);
+        let data = &mut buf[..(pl + TAG_LEN)];
+        let (len, data) = data.split_at_mut(4);
+        let result = self.key.open(self.nonce, len, data);
+        increment(&mut self.nonce);
+        match result {
+            Ok(n) => Ok([&len[..], &data[..n]].concat()),
+            Err(_) => {
+                log::error!("aes-gcm tag verification failure.");
+                Err(SshError::from(SshErrorKind::MacVerificationFailed))
+            }
+        }
+    }
+
+    fn packet_len(&mut self, _: u32, buf: &[u8]) -> usize {
+        let mut packet_len_slice = [0_u8; 4];
+        packet_len_slice.copy_from_slice(&buf[..4]);
+        u32::from_be_bytes(packet_len_slice) as usize + 4
+    }
+
+    fn integrity(&self) -> Integrity {
+        Integrity::Tag(TAG_LEN)
+    }
+
+    fn packet_length(&self) -> PacketLength {
+        PacketLength::Aad
+    }
+
+    /// 每个数据包使用一个新的 nonce，只受调用次数限制，
+    /// 调用计数器为 64 位，按数据包数量重新协商密钥 (limits::MAX_PACKETS_BEFORE_REKEY) 保证计数器不会回绕
+    fn max_bytes_before_rekey(&self) -> u64 {
+        u64::MAX
+    }
+}
+
+
+/// 调用计数器为 nonce 的后 8 字节，大端序
+fn increment(nonce: &mut [u8; IV_SIZE]) {
+    let mut counter = [0_u8; 8];
+    counter.copy_from_slice(&nonce[4..]);
+    let counter = u64::from_be_bytes(counter).wrapping_add(1);
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+}
@@ -1,43 +1,40 @@
-use ring::aead::chacha20_poly1305_openssh::{OpeningKey, SealingKey};
-use crate::algorithm::encryption::Encryption;
-use crate::algorithm::hash;
-use crate::error::SshError;
+use crate::algorithm::backend::{OpeningKey, SealingKey};
+use crate::algorithm::encryption::{Encryption, PacketLength};
+use crate::algorithm::hash::{Direction, HASH};
+use crate::algorithm::negotiated::Integrity;
+use crate::error::{SshError, SshErrorKind};
 
 
 const BSIZE: usize = 64;
+const TAG_LEN: usize = 16;
 
+/// 同一份密钥分别用于加密和解密，实例只会用于其中一个方向
 pub struct ChaCha20Poly1305 {
-    client_key: SealingKey,
-    server_key: OpeningKey,
+    sealing_key: SealingKey,
+    opening_key: OpeningKey,
 }
 
-impl Encryption for ChaCha20Poly1305 {
-    fn bsize(&self) -> usize {
-        BSIZE
-    }
-
-    fn iv_size(&self) -> usize {
-       0
-    }
-
-
-    fn new() -> ChaCha20Poly1305 {
-        let hash = hash::get();
-        let (ck, sk) = hash.extend_key(BSIZE);
-        let mut sealing_key = [0_u8; BSIZE];
-        let mut opening_key = [0_u8; BSIZE];
-        sealing_key.copy_from_slice(&ck);
-        opening_key.copy_from_slice(&sk);
+impl ChaCha20Poly1305 {
+    pub(crate) fn new(hash: &HASH, direction: Direction) -> ChaCha20Poly1305 {
+        let keys = hash.keys(direction, BSIZE, 0);
+        let mut key = [0_u8; BSIZE];
+        key.copy_from_slice(&keys.key);
 
         ChaCha20Poly1305 {
-            client_key: SealingKey::new(&sealing_key),
-            server_key: OpeningKey::new(&opening_key)
+            sealing_key: SealingKey::new(&key),
+            opening_key: OpeningKey::new(&key)
         }
     }
+}
+
+impl Encryption for ChaCha20Poly1305 {
+    fn bsize(&self) -> usize {
+        BSIZE
+    }
 
     fn encrypt(&mut self, sequence_number: u32, buf: &mut Vec<u8>) {
-        let mut tag = [0_u8; 16];
-        self.client_key.seal_in_place(sequence_number, buf, &mut tag);
+        let mut tag = [0_u8; TAG_LEN];
+        self.sealing_key.seal_in_place(sequence_number, buf, &mut tag);
         buf.append(&mut tag.to_vec());
     }
 
@@ -45,33 +42,35 @@ impl Encryption for ChaCha20Poly1305 {
         let mut packet_len_slice = [0_u8; 4];
         let len = &buf[..4];
         packet_len_slice.copy_from_slice(len);
-        let packet_len_slice = self.server_key.decrypt_packet_length(sequence_number, packet_len_slice);
+        let packet_len_slice = self.opening_key.decrypt_packet_length(sequence_number, packet_len_slice);
         let packet_len = u32::from_be_bytes(packet_len_slice);
         let (buf, tag_) = buf.split_at_mut((packet_len + 4) as usize);
-        let mut tag = [0_u8; 16];
-        tag.copy_from_slice(tag_);
-        match self.server_key.open_in_place(sequence_number, buf, &tag) {
+        let mut tag = [0_u8; TAG_LEN];
+        tag.copy_from_slice(&tag_[..TAG_LEN]);
+        match self.opening_key.open_in_place(sequence_number, buf, &tag) {
             Ok(result) =>  Ok([&packet_len_slice[..], result].concat()),
-            Err(_) => Err(SshError::from("encryption error."))
+            Err(_) => {
+                log::error!("chacha20-poly1305 tag verification failure.");
+                Err(SshError::from(SshErrorKind::MacVerificationFailed))
+            }
         }
     }
 
     fn packet_len(&mut self, sequence_number: u32, buf: &[u8]) -> usize {
         let mut packet_len_slice = [0_u8; 4];
         packet_len_slice.copy_from_slice(&buf[..4]);
-        let packet_len_slice = self.server_key
+        let packet_len_slice = self.opening_key
             .decrypt_packet_length(
                 sequence_number,
                 packet_len_slice);
-        u32::from_be_bytes(packet_len_slice) as usize
+        u32::from_be_bytes(packet_len_slice) as usize + 4
     }
 
-    fn data_len(&mut self, sequence_number: u32, buf: &[u8]) -> usize {
-        let packet_len = self.packet_len(sequence_number, buf);
-        packet_len + 4 + 16
+    fn integrity(&self) -> Integrity {
+        Integrity::Tag(TAG_LEN)
     }
 
-    fn is_cp(&self) -> bool {
-        true
+    fn packet_length(&self) -> PacketLength {
+        PacketLength::SeparateKey
     }
 }
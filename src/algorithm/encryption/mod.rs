@@ -1,13 +1,18 @@
 
 mod chacha20_poly1305_openssh;
 mod aes_ctr_128;
-
-use std::sync::atomic::AtomicBool;
+mod aes_gcm;
+#[cfg(feature = "legacy-algorithms")]
+mod triple_des_cbc;
 
 pub(crate) use {
     chacha20_poly1305_openssh::ChaCha20Poly1305,
-    aes_ctr_128::AesCtr128
+    aes_ctr_128::AesCtr128,
+    aes_gcm::AesGcm
 };
+#[cfg(feature = "legacy-algorithms")]
+pub(crate) use triple_des_cbc::TripleDesCbc;
+use crate::algorithm::negotiated::Integrity;
 use crate::SshResult;
 
 
@@ -21,35 +26,164 @@ use crate::SshResult;
 /// 个方向上的算法。但是，在实际使用中，推荐在两个方向上使用相同的算法。
 
 
-// 密钥是否交换完成 true 是  false 否
-pub static IS_ENCRYPT: AtomicBool = AtomicBool::new(false);
+/// 每个实例只用于一个方向，使用这个方向上的密钥和状态
+pub(crate) trait Encryption: Send {
+    fn bsize(&self) -> usize;
+    fn encrypt(&mut self, client_sequence_num: u32, buf: &mut Vec<u8>);
+    fn decrypt(&mut self, sequence_number: u32, buf: &mut [u8]) -> SshResult<Vec<u8>>;
+    /// 数据包长度，包括 packet_length 域自身，不包括 mac/tag
+    fn packet_len(&mut self, sequence_number: u32, buf: &[u8]) -> usize;
+    /// 数据包末尾的校验数据: AEAD 算法的认证标签，或者协商的 mac
+    fn integrity(&self) -> Integrity;
+    /// packet_length 域的处理方式
+    fn packet_length(&self) -> PacketLength;
+    /// 同一个密钥最多加密的字节数，达到之后重新协商密钥
+    /// 分组不小于 16 字节时为 2^(分组位数 / 4) 个分组 (RFC 4344 3.2)，否则为 1GB (和 OpenSSH 一致)
+    /// 超出 u64 时 (chacha20-poly1305 的 64 字节) 为 u64::MAX，只按用户设置的限制重新协商
+    fn max_bytes_before_rekey(&self) -> u64 {
+        let bsize = self.bsize() as u64;
+        match bsize >= 16 {
+            true => match 1_u64.checked_shl((bsize * 2) as u32) {
+                Some(v) => v.saturating_mul(bsize),
+                None => u64::MAX
+            },
+            false => 1 << 30
+        }
+    }
+}
 
-// 加密密钥
-static mut ENCRYPTION: Option<Box<dyn Encryption>> = None;
 
+/// 两个方向上各自协商的加密算法
+/// 每个 client 在密钥交换完成后持有自己的实例，重新协商密钥后替换为新的实例
+pub(crate) struct Ciphers {
+    /// 发送数据包时加密
+    pub(crate) client_to_server: Box<dyn Encryption>,
+    /// 接收数据包时解密
+    pub(crate) server_to_client: Box<dyn Encryption>,
+}
 
 
-pub(crate) fn put(v: Box<dyn Encryption>) {
-    unsafe {
-        ENCRYPTION = Some(v)
-    }
+/// packet_length 域的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PacketLength {
+    /// 和数据包的其余部分一起加密，计算填充时包括在内 (aes-ctr)
+    Encrypted,
+    /// 使用单独的密钥加密，计算填充时不包括在内 (chacha20-poly1305)
+    SeparateKey,
+    /// 明文传输，计算填充时不包括在内
+    /// aes-gcm 中作为 AEAD 的附加数据，etm 的 mac 中和密文一起计算
+    Aad,
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::config::{AlgorithmConfig, AlgorithmList};
+    use crate::constant::algorithms;
+    use crate::data::Data;
+    use crate::error::SshErrorKind;
+    use crate::mock;
+    use crate::packet::Packet;
+    use super::Ciphers;
+
+    const CIPHERS: &[&str] = &[
+        algorithms::ENCRYPTION_CHACHA20_POLY1305_OPENSSH,
+        algorithms::ENCRYPTION_AES128_CTR,
+        algorithms::ENCRYPTION_AES128_GCM_OPENSSH,
+        algorithms::ENCRYPTION_AES256_GCM_OPENSSH,
+        #[cfg(feature = "legacy-algorithms")]
+        algorithms::ENCRYPTION_3DES_CBC,
+    ];
 
-pub(crate) fn get() -> &'static mut Box<dyn Encryption> {
-    unsafe {
-        ENCRYPTION.as_mut().unwrap()
+    const MACS: &[&str] = &[
+        algorithms::MAC_HMAC_SHA1,
+        algorithms::MAC_HMAC_SHA2_256,
+        algorithms::MAC_HMAC_SHA2_512,
+        algorithms::MAC_HMAC_SHA2_256_ETM,
+        algorithms::MAC_HMAC_SHA2_512_ETM,
+    ];
+
+    /// 两端用同样的密钥得到的 Ciphers
+    fn ciphers(cipher: &str, mac: &str) -> Ciphers {
+        let mut algorithm = AlgorithmConfig::new();
+        let client = &mut algorithm.client_algorithm;
+        client.c_encryption_algorithm.0 = vec![cipher.to_string()];
+        client.s_encryption_algorithm.0 = vec![cipher.to_string()];
+        client.c_mac_algorithm.0 = vec![mac.to_string()];
+        client.s_mac_algorithm.0 = vec![mac.to_string()];
+        algorithm.server_algorithm = AlgorithmList::client_algorithm();
+        algorithm.matching_encryption_algorithm(&mock::keys()).unwrap()
     }
-}
 
+    fn encrypt(ciphers: &mut Ciphers, sequence_num: u32, payload: &[u8]) -> Vec<u8> {
+        let mut packet = Packet::from(Data::from(payload));
+        packet.build(Some(ciphers.client_to_server.as_ref()));
+        let mut buf = packet.to_vec();
+        ciphers.client_to_server.encrypt(sequence_num, &mut buf);
+        buf
+    }
 
-pub(crate) trait Encryption {
-    fn bsize(&self) -> usize;
-    fn iv_size(&self) -> usize;
-    fn new() -> Self where Self: Sized;
-    fn encrypt(&mut self, client_sequence_num: u32, buf: &mut Vec<u8>);
-    fn decrypt(&mut self, sequence_number: u32, buf: &mut [u8]) -> SshResult<Vec<u8>>;
-    fn packet_len(&mut self, sequence_number: u32, buf: &[u8]) -> usize;
-    fn data_len(&mut self, sequence_number: u32, buf: &[u8]) -> usize;
-    fn is_cp(&self) -> bool;
-}
\ No newline at end of file
+    #[test]
+    fn round_trip() {
+        for cipher in CIPHERS {
+            for mac in MACS {
+                let mut encryption = ciphers(cipher, mac);
+                let mut decryption = ciphers(cipher, mac).client_to_server;
+                for (sequence_num, len) in [0_usize, 1, 15, 16, 17, 1000].into_iter().enumerate() {
+                    let sequence_num = sequence_num as u32;
+                    let payload = vec![len as u8; len];
+                    let mut buf = encrypt(&mut encryption, sequence_num, &payload);
+                    let packet_len = decryption.packet_len(sequence_num, &buf);
+                    assert_eq!(packet_len + decryption.integrity().len(), buf.len(), "{} {}", cipher, mac);
+                    let plain = decryption.decrypt(sequence_num, &mut buf).unwrap();
+                    assert_eq!(Packet::from(plain).unpacking().unwrap().to_vec(), payload, "{} {}", cipher, mac);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_packet_is_rejected() {
+        for cipher in CIPHERS {
+            for mac in MACS {
+                let mut encryption = ciphers(cipher, mac);
+                let mut decryption = ciphers(cipher, mac).client_to_server;
+                let mut buf = encrypt(&mut encryption, 0, b"payload");
+                let last = buf.len() - 1;
+                buf[last] ^= 1;
+                let err = decryption.decrypt(0, &mut buf).unwrap_err();
+                assert_eq!(*err.kind(), SshErrorKind::MacVerificationFailed, "{} {}", cipher, mac);
+            }
+        }
+    }
+
+    #[test]
+    fn rekey_limit_of_each_cipher() {
+        for cipher in CIPHERS {
+            let limit = ciphers(cipher, algorithms::MAC_HMAC_SHA2_256).client_to_server.max_bytes_before_rekey();
+            let expected = match *cipher {
+                algorithms::ENCRYPTION_CHACHA20_POLY1305_OPENSSH
+                | algorithms::ENCRYPTION_AES128_GCM_OPENSSH
+                | algorithms::ENCRYPTION_AES256_GCM_OPENSSH => u64::MAX,
+                // 3des-cbc 的分组只有 8 字节
+                #[cfg(feature = "legacy-algorithms")]
+                algorithms::ENCRYPTION_3DES_CBC => 1 << 30,
+                _ => (1 << 32) * 16
+            };
+            assert_eq!(limit, expected, "{}", cipher);
+        }
+    }
+
+    #[test]
+    fn directions_use_different_keys() {
+        for cipher in CIPHERS {
+            let mut encryption = ciphers(cipher, algorithms::MAC_HMAC_SHA2_256);
+            let mut decryption = ciphers(cipher, algorithms::MAC_HMAC_SHA2_256).server_to_client;
+            let mut buf = encrypt(&mut encryption, 0, &[0; 32]);
+            // 用另一个方向的密钥解出的长度一般是错的，长度碰巧正确时解密也必须失败
+            let packet_len = decryption.packet_len(0, &buf);
+            if packet_len + decryption.integrity().len() == buf.len() {
+                assert!(decryption.decrypt(0, &mut buf).is_err(), "{}", cipher);
+            }
+        }
+    }
+}
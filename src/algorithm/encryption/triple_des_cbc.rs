@@ -0,0 +1,116 @@
+use crate::algorithm::backend::{self, Des3Cbc};
+use crate::algorithm::hash::{Direction, HASH};
+use crate::algorithm::mac::Mac;
+use crate::algorithm::negotiated::Integrity;
+use crate::{SshError, SshResult};
+use crate::error::SshErrorKind;
+use crate::algorithm::encryption::{Encryption, PacketLength};
+
+
+
+const BSIZE: usize = 8;
+const KEY_SIZE: usize = 24;
+const IV_SIZE: usize = 8;
+
+
+/// 3des-cbc (RFC 4253 6.3)
+///
+/// **不安全**: 分组只有 64 位 (Sweet32)，CBC 模式的填充和 mac-then-encrypt 容易受到攻击，
+/// 只在开启 `legacy-algorithms` 时提供给只支持这个算法的旧设备使用。
+///
+/// 整个数据包 (包括 packet_length) 都是 CBC 加密的，长度必须是 8 的倍数，
+/// 初始向量来自密钥交换，之后的数据包使用上一个数据包最后一个密文分组接着链接
+pub struct TripleDesCbc {
+    key: Des3Cbc,
+    /// 这个方向上协商的 mac 算法
+    mac: Box<dyn Mac>,
+    /// mac 使用的 key
+    integrity_key: Vec<u8>,
+}
+
+impl TripleDesCbc {
+    pub(crate) fn new(hash: &HASH, direction: Direction, mac: Box<dyn Mac>) -> Self {
+        let keys = hash.keys(direction, KEY_SIZE, mac.bsize());
+        let mut key = [0u8; KEY_SIZE];
+        let mut iv = [0u8; IV_SIZE];
+        key.clone_from_slice(&keys.key);
+        iv.clone_from_slice(&keys.iv[..IV_SIZE]);
+
+        TripleDesCbc {
+            key: Des3Cbc::new(&key, &iv),
+            integrity_key: keys.integrity_key,
+            mac,
+        }
+    }
+}
+
+impl Encryption for TripleDesCbc {
+    fn bsize(&self) -> usize {
+        BSIZE
+    }
+
+    fn encrypt(&mut self, client_sequence_num: u32, buf: &mut Vec<u8>) {
+        let mac_len = self.mac.mac_len();
+        if self.mac.etm() {
+            self.key.encrypt(&mut buf[4..]);
+            let tag = self.mac.sign(&self.integrity_key, client_sequence_num, buf.as_slice());
+            return buf.extend(&tag[..mac_len])
+        }
+        let tag = self.mac.sign(&self.integrity_key, client_sequence_num, buf.as_slice());
+        self.key.encrypt(buf);
+        buf.extend(&tag[..mac_len])
+    }
+
+    fn decrypt(&mut self, server_sequence_number: u32, buf: &mut [u8]) -> SshResult<Vec<u8>> {
+        let pl = self.packet_len(server_sequence_number, buf);
+        let encrypted_len = match self.mac.etm() {
+            true => pl - 4,
+            false => pl
+        };
+        if encrypted_len % BSIZE != 0 {
+            log::error!("packet length is not a multiple of the cipher block size.");
+            return Err(SshError::from("invalid packet length."))
+        }
+        let mac_len = self.mac.mac_len();
+        let data = &mut buf[..(pl + mac_len)];
+        let (d, m) = data.split_at_mut(pl);
+        if self.mac.etm() {
+            // 先校验 mac，通过后才解密
+            let tag = self.mac.sign(&self.integrity_key, server_sequence_number, d);
+            if !backend::constant_time_eq(m, &tag[..mac_len]) {
+                log::error!("mac verification failure.");
+                return Err(SshError::from(SshErrorKind::MacVerificationFailed))
+            }
+            self.key.decrypt(&mut d[4..]);
+            return Ok(d.to_vec())
+        }
+        self.key.decrypt(d);
+        let tag = self.mac.sign(&self.integrity_key, server_sequence_number, d);
+        if !backend::constant_time_eq(m, &tag[..mac_len]) {
+            log::error!("mac verification failure.");
+            return Err(SshError::from(SshErrorKind::MacVerificationFailed))
+        }
+        Ok(d.to_vec())
+    }
+
+    fn packet_len(&mut self, _: u32, buf: &[u8]) -> usize {
+        let mut u32_bytes = [0_u8; 4];
+        if self.mac.etm() {
+            u32_bytes.clone_from_slice(&buf[..4]);
+        } else {
+            u32_bytes.clone_from_slice(&self.key.peek(&buf[..BSIZE])[..4]);
+        }
+        u32::from_be_bytes(u32_bytes) as usize + 4
+    }
+
+    fn integrity(&self) -> Integrity {
+        Integrity::Mac(self.mac.mac_len())
+    }
+
+    fn packet_length(&self) -> PacketLength {
+        match self.mac.etm() {
+            true => PacketLength::Aad,
+            false => PacketLength::Encrypted
+        }
+    }
+}
@@ -1,5 +1,4 @@
-use std::borrow::BorrowMut;
-use crate::algorithm::hash;
+use crate::algorithm::hash::{self, HashType};
 use crate::data::Data;
 
 /// 密钥交换产生两个值：一个共享秘密 K，以及一个交换哈希 H。加密和验证密钥来自它们。第一
@@ -10,18 +9,7 @@ use crate::data::Data;
 ///
 /// H = hash algorithm(v_c | v_s | i_c | i_s | k_s | q_c | q_s | k)
 ///
-///
-
-
-static mut H_VAL: H = H::new();
-
-
-pub(crate) fn get() -> &'static mut H {
-    unsafe {
-        H_VAL.borrow_mut()
-    }
-}
-
+/// 每个连接各自保存，见 `Client::h`
 
 
 #[derive(Clone)]
@@ -133,8 +121,8 @@ impl H {
         v
     }
 
-    pub(crate) fn digest(&self) -> Vec<u8> {
-        hash::digest(self.as_bytes().as_slice())
+    pub(crate) fn digest(&self, hash_type: HashType) -> Vec<u8> {
+        hash::digest(hash_type, self.as_bytes().as_slice())
     }
 
 }
\ No newline at end of file
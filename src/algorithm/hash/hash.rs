@@ -1,5 +1,5 @@
-use crate::algorithm::hash::h;
-use crate::algorithm::hash;
+use crate::algorithm::hash::h::H;
+use crate::algorithm::hash::{self, HashType};
 use crate::constant;
 
 
@@ -24,22 +24,25 @@ use crate::constant;
 /// 如果 K 的熵比 HASH 的内状态（internal state）大小要大，则该过程将造成熵的丢失。
 
 
-static mut HASH_VAL: Option<HASH> = None;
-
-
-pub(crate) fn get() -> &'static HASH {
-     unsafe {
-         if HASH_VAL.is_none() {
-             HASH_VAL = Some(HASH::new())
-         }
-
-         HASH_VAL.as_ref().unwrap()
-     }
+/// 数据包的方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    /// 使用 "A" "C" "E" 生成的密钥
+    ClientToServer,
+    /// 使用 "B" "D" "F" 生成的密钥
+    ServerToClient,
 }
 
 
+/// 一个方向上的密钥，长度已经扩展到加密算法需要的长度
+pub(crate) struct DirectionKeys {
+    pub(crate) iv: Vec<u8>,
+    pub(crate) key: Vec<u8>,
+    pub(crate) integrity_key: Vec<u8>
+}
 
 
+/// 每次密钥交换完成后按这次的 K 和 H 重新生成
 pub(crate) struct HASH {
     /// IV
     pub(crate) iv_c_s          : Vec<u8>,
@@ -51,19 +54,24 @@ pub(crate) struct HASH {
 
     /// Hmac时候用到的 key
     pub(crate) ik_c_s          : Vec<u8>,
-    pub(crate) ik_s_c          : Vec<u8>
+    pub(crate) ik_s_c          : Vec<u8>,
+
+    /// 扩展密钥时用到的 K、H 和哈希算法
+    k                          : Vec<u8>,
+    h                          : Vec<u8>,
+    hash_type                  : HashType
 }
 
 
 impl HASH {
-    pub(crate) fn new() -> Self {
-        let h_val = h::get();
+    /// session_id 为第一次密钥交换的 exchange hash, 重新协商密钥时不变
+    /// h_val 为这次密钥交换的数据，hash_type 为协商的密钥交换算法使用的哈希算法
+    pub(crate) fn new(h_val: &H, hash_type: HashType, session_id: &[u8]) -> Self {
         let k = h_val.k.clone();
-        let h = h_val.digest();
-        let session_id = h.clone();
+        let h = h_val.digest(hash_type);
         let mut keys = vec![];
         for v in constant::ALPHABET {
-            keys.push(HASH::mix(&k, &h, v, &session_id));
+            keys.push(HASH::mix(hash_type, &k, &h, v, session_id));
         }
         HASH {
             iv_c_s: keys[0].clone(),
@@ -73,38 +81,51 @@ impl HASH {
             ek_s_c: keys[3].clone(),
 
             ik_c_s: keys[4].clone(),
-            ik_s_c: keys[5].clone()
+            ik_s_c: keys[5].clone(),
+
+            k,
+            h,
+            hash_type
         }
     }
 
-    fn mix(k: &[u8], h: &[u8], key_char: u8, session_id: &[u8]) -> Vec<u8> {
+    fn mix(hash_type: HashType, k: &[u8], h: &[u8], key_char: u8, session_id: &[u8]) -> Vec<u8> {
         let mut key: Vec<u8> = Vec::new();
         key.extend(k);
         key.extend(h);
         key.push(key_char);
         key.extend(session_id);
-        hash::digest(key.as_slice())
+        hash::digest(hash_type, key.as_slice())
     }
 
-    pub(crate) fn extend_key(&self, key_size: usize) -> (Vec<u8>, Vec<u8>) {
-        let mut ck = self.ek_c_s.to_vec();
-        let mut sk = self.ek_s_c.to_vec();
-        while key_size > ck.len() {
-            ck.extend(self.extend(ck.as_slice()));
-            sk.extend(self.extend(sk.as_slice()));
+    /// 一个方向上的密钥，加密密钥和完整性密钥分别扩展到 key_size 和 integrity_key_size
+    pub(crate) fn keys(&self, direction: Direction, key_size: usize, integrity_key_size: usize) -> DirectionKeys {
+        let (iv, key, integrity_key) = match direction {
+            Direction::ClientToServer => (&self.iv_c_s, &self.ek_c_s, &self.ik_c_s),
+            Direction::ServerToClient => (&self.iv_s_c, &self.ek_s_c, &self.ik_s_c),
+        };
+        DirectionKeys {
+            iv: iv.clone(),
+            key: self.extend_key(key, key_size),
+            integrity_key: self.extend_key(integrity_key, integrity_key_size)
         }
-        (ck, sk)
+    }
+
+    fn extend_key(&self, key: &[u8], key_size: usize) -> Vec<u8> {
+        let mut key = key.to_vec();
+        while key_size > key.len() {
+            key.extend(self.extend(key.as_slice()));
+        }
+        key.truncate(key_size);
+        key
     }
 
     fn extend(&self, key: &[u8]) -> Vec<u8> {
-        let h_val = h::get();
-        let k = h_val.k.clone();
-        let h = h_val.digest();
         let mut hash: Vec<u8> = Vec::new();
-        hash.extend(k);
-        hash.extend(h);
+        hash.extend(&self.k);
+        hash.extend(&self.h);
         hash.extend(key);
-        hash::digest(hash.as_slice())
+        hash::digest(self.hash_type, hash.as_slice())
     }
 
 }
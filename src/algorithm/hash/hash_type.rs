@@ -1,5 +1,6 @@
 /// 密钥交换对应的hash算法
 #[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HashType {
     SHA1,
     SHA256
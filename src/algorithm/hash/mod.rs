@@ -4,20 +4,21 @@ mod hash_type;
 
 
 pub(crate) mod h;
+pub(crate) mod md5;
 
 
 pub(crate) use hash_type::HashType;
-pub(crate) use hash::get;
+pub(crate) use hash::{Direction, HASH};
 
 
-use crate::algorithm::key_exchange;
+use crate::algorithm::backend::{self, Digest};
 
 
-pub(crate) fn digest(data: &[u8]) -> Vec<u8> {
-    let hash_type = key_exchange::get().get_hash_type();
-    let result = match hash_type {
-        HashType::SHA1 => ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, data),
-        HashType::SHA256 => ring::digest::digest(&ring::digest::SHA256, data),
+/// hash_type 为协商的密钥交换算法使用的哈希算法
+pub(crate) fn digest(hash_type: HashType, data: &[u8]) -> Vec<u8> {
+    let algorithm = match hash_type {
+        HashType::SHA1 => Digest::Sha1,
+        HashType::SHA256 => Digest::Sha256,
     };
-    result.as_ref().to_vec()
+    backend::digest(algorithm, data)
 }
\ No newline at end of file
@@ -1,44 +1,26 @@
-use ring::agreement::{EphemeralPrivateKey, PublicKey, UnparsedPublicKey, X25519};
+use crate::algorithm::backend::{Curve, EphemeralKey};
 use crate::algorithm::hash::HashType;
 use crate::algorithm::key_exchange::KeyExchange;
-use crate::error::SshError;
 use crate::SshResult;
 
 pub struct CURVE25519 {
-    pub private_key: EphemeralPrivateKey,
-    pub public_key: PublicKey
+    pub(crate) key: EphemeralKey
 }
 
 impl KeyExchange for CURVE25519 {
 
     fn new() -> SshResult<Self> {
-        let rng = ring::rand::SystemRandom::new();
-        let private_key = match EphemeralPrivateKey::generate(&X25519, &rng) {
-            Ok(v) => v,
-            Err(_) => return Err(SshError::from("encryption error."))
-        };
-        match private_key.compute_public_key() {
-            Ok(public_key) =>
-                Ok(CURVE25519 {
-                private_key,
-                public_key
-            }),
-            Err(_) => Err(SshError::from("encryption error."))
-        }
-
+        Ok(CURVE25519 {
+            key: EphemeralKey::generate(Curve::X25519)?
+        })
     }
 
     fn get_public_key(&self) -> &[u8] {
-        self.public_key.as_ref()
+        self.key.public_key()
     }
 
     fn get_shared_secret(&self, puk: Vec<u8>) -> SshResult<Vec<u8>> {
-        let mut public_key = [0u8; 32];
-        public_key.copy_from_slice(&puk);
-
-        let server_pub = UnparsedPublicKey::new(&X25519, public_key);
-        let private_key = unsafe { (&self.private_key as *const EphemeralPrivateKey).read() };
-        crate::algorithm::key_exchange::agree_ephemeral(private_key, &server_pub)
+        self.key.agree(&puk)
     }
 
     fn get_hash_type(&self) -> HashType {
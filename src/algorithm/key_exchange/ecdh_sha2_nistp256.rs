@@ -1,46 +1,30 @@
-use ring::agreement::{ECDH_P256, EphemeralPrivateKey, PublicKey, UnparsedPublicKey};
+use crate::algorithm::backend::{Curve, EphemeralKey};
 use crate::algorithm::key_exchange::KeyExchange;
 
-use crate::{SshError, SshResult};
+use crate::SshResult;
 use crate::algorithm::hash::HashType;
 
 pub struct EcdhP256 {
-    pub private_key: EphemeralPrivateKey,
-    pub public_key: PublicKey
+    pub(crate) key: EphemeralKey
 }
 
 impl KeyExchange for EcdhP256 {
     fn new() -> SshResult<Self> {
-        let rng = ring::rand::SystemRandom::new();
-        let private_key =
-            match EphemeralPrivateKey::generate(&ECDH_P256, &rng) {
-            Ok(v) => v,
-            Err(_) => return Err(SshError::from("encryption error."))
-        };
-        match private_key.compute_public_key() {
-            Ok(public_key) =>
-                Ok(EcdhP256 {
-                    private_key,
-                    public_key
-                }),
-            Err(_) => Err(SshError::from("encryption error."))
-        }
+        Ok(EcdhP256 {
+            key: EphemeralKey::generate(Curve::NistP256)?
+        })
     }
 
     fn get_public_key(&self) -> &[u8] {
-        self.public_key.as_ref()
+        self.key.public_key()
     }
 
 
     fn get_shared_secret(&self, puk: Vec<u8>) -> SshResult<Vec<u8>> {
-        let mut public_key = [0u8; 65];
-        public_key.copy_from_slice(&puk);
-        let server_pub = UnparsedPublicKey::new(&ECDH_P256, puk);
-        let private_key = unsafe { (&self.private_key as *const EphemeralPrivateKey).read() };
-        crate::algorithm::key_exchange::agree_ephemeral(private_key, &server_pub)
+        self.key.agree(&puk)
     }
 
     fn get_hash_type(&self) -> HashType {
         HashType::SHA256
     }
-}
\ No newline at end of file
+}
@@ -1,6 +1,4 @@
-use ring::agreement;
-use ring::agreement::{EphemeralPrivateKey, UnparsedPublicKey};
-use crate::{SshError, SshResult};
+use crate::SshResult;
 use crate::algorithm::hash::HashType;
 
 
@@ -14,23 +12,6 @@ pub(crate) mod curve25519;
 pub(crate) mod ecdh_sha2_nistp256;
 
 
-static mut KEY_EXCHANGE: Option<Box<dyn KeyExchange>> = None;
-
-
-pub(crate) fn put(ke: Box<dyn KeyExchange>) {
-    unsafe {
-        KEY_EXCHANGE = Some(ke)
-    }
-}
-
-pub(crate) fn get() -> &'static Box<dyn KeyExchange> {
-    unsafe {
-        KEY_EXCHANGE.as_ref().unwrap()
-    }
-}
-
-
-
 pub trait KeyExchange: Send + Sync {
     fn new() -> SshResult<Self> where Self: Sized;
     fn get_public_key(&self) -> &[u8];
@@ -38,21 +19,3 @@ pub trait KeyExchange: Send + Sync {
     fn get_hash_type(&self) -> HashType;
 }
 
-
-pub(crate) fn agree_ephemeral<B: AsRef<[u8]>>(
-    private_key: EphemeralPrivateKey,
-    peer_public_key: &UnparsedPublicKey<B>)
-    -> SshResult<Vec<u8>>
-{
-    match agreement::agree_ephemeral(
-        private_key,
-        &peer_public_key,
-        ring::error::Unspecified,
-        |_key_material| {
-            Ok(_key_material.to_vec())
-        },
-    ) {
-        Ok(o) => Ok(o),
-        Err(_) => Err(SshError::from("encryption error."))
-    }
-}
\ No newline at end of file
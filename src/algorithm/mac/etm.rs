@@ -0,0 +1,28 @@
+use crate::algorithm::mac::Mac;
+
+
+/// *-etm@openssh.com
+/// 和原来的 mac 算法相同，只是 mac 在加密之后对密文计算，packet_length 明文传输
+pub(crate) struct Etm<M: Mac>(M);
+
+impl<M: Mac> Mac for Etm<M> {
+    fn sign(&self, ik: &[u8], sequence_num: u32, buf: &[u8]) -> Vec<u8> {
+        self.0.sign(ik, sequence_num, buf)
+    }
+
+    fn new() -> Self where Self: Sized {
+        Etm(M::new())
+    }
+
+    fn bsize(&self) -> usize {
+        self.0.bsize()
+    }
+
+    fn mac_len(&self) -> usize {
+        self.0.mac_len()
+    }
+
+    fn etm(&self) -> bool {
+        true
+    }
+}
@@ -1,5 +1,4 @@
-use ring::hmac;
-use ring::hmac::{Context, Tag};
+use crate::algorithm::backend::{self, Digest};
 use crate::algorithm::mac::Mac;
 
 const BSIZE: usize = 20;
@@ -8,13 +7,9 @@ pub(crate) struct HMacSha1;
 
 impl Mac for HMacSha1 {
 
-    fn sign(&self, ik: &[u8], sequence_num: u32, buf: &[u8]) -> Tag {
+    fn sign(&self, ik: &[u8], sequence_num: u32, buf: &[u8]) -> Vec<u8> {
         let ik = &ik[..BSIZE];
-        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, ik);
-        let mut c = Context::with_key(&key);
-        c.update(sequence_num.to_be_bytes().as_slice());
-        c.update(buf);
-        c.sign()
+        backend::hmac(Digest::Sha1, ik, &[sequence_num.to_be_bytes().as_slice(), buf])
     }
 
     fn new() -> Self where Self: Sized {
@@ -24,4 +19,8 @@ impl Mac for HMacSha1 {
     fn bsize(&self) -> usize {
         BSIZE
     }
+
+    fn mac_len(&self) -> usize {
+        BSIZE
+    }
 }
\ No newline at end of file
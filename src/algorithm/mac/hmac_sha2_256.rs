@@ -0,0 +1,26 @@
+use crate::algorithm::backend::{self, Digest};
+use crate::algorithm::mac::Mac;
+
+const BSIZE: usize = 32;
+
+pub(crate) struct HMacSha2256;
+
+impl Mac for HMacSha2256 {
+
+    fn sign(&self, ik: &[u8], sequence_num: u32, buf: &[u8]) -> Vec<u8> {
+        let ik = &ik[..BSIZE];
+        backend::hmac(Digest::Sha256, ik, &[sequence_num.to_be_bytes().as_slice(), buf])
+    }
+
+    fn new() -> Self where Self: Sized {
+        HMacSha2256
+    }
+
+    fn bsize(&self) -> usize {
+        BSIZE
+    }
+
+    fn mac_len(&self) -> usize {
+        BSIZE
+    }
+}
@@ -0,0 +1,26 @@
+use crate::algorithm::backend::{self, Digest};
+use crate::algorithm::mac::Mac;
+
+const BSIZE: usize = 64;
+
+pub(crate) struct HMacSha2512;
+
+impl Mac for HMacSha2512 {
+
+    fn sign(&self, ik: &[u8], sequence_num: u32, buf: &[u8]) -> Vec<u8> {
+        let ik = &ik[..BSIZE];
+        backend::hmac(Digest::Sha512, ik, &[sequence_num.to_be_bytes().as_slice(), buf])
+    }
+
+    fn new() -> Self where Self: Sized {
+        HMacSha2512
+    }
+
+    fn bsize(&self) -> usize {
+        BSIZE
+    }
+
+    fn mac_len(&self) -> usize {
+        BSIZE
+    }
+}
@@ -1,26 +1,18 @@
-use ring::hmac::Tag;
-
+pub(crate) mod etm;
 pub(crate) mod hmac_sha1;
+pub(crate) mod hmac_sha2_256;
+pub(crate) mod hmac_sha2_512;
 
 
-static mut MAC: Option<Box<dyn Mac>> = None;
-
-
-pub(crate) fn put(m: Box<dyn Mac>) {
-    unsafe {
-        MAC = Some(m);
-    }
-}
-
-pub(crate) fn get() -> &'static mut Box<dyn Mac> {
-    unsafe {
-        MAC.as_mut().unwrap()
-    }
-}
-
-
-pub(crate) trait Mac {
-    fn sign(&self, ik: &[u8], sequence_num: u32, buf: &[u8]) -> Tag;
+/// 只用于非 AEAD 的加密算法，由对应的加密算法持有
+pub(crate) trait Mac: Send {
+    fn sign(&self, ik: &[u8], sequence_num: u32, buf: &[u8]) -> Vec<u8>;
     fn new() -> Self where Self: Sized;
     fn bsize(&self) -> usize;
+    /// mac 的输出长度
+    fn mac_len(&self) -> usize;
+    /// 为 true 时对密文计算 mac (encrypt-then-mac)，否则对明文计算
+    fn etm(&self) -> bool {
+        false
+    }
 }
\ No newline at end of file
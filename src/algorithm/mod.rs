@@ -1,5 +1,8 @@
+pub(crate) mod backend;
 pub(crate) mod key_exchange;
 pub(crate) mod hash;
 pub(crate) mod public_key;
 pub(crate) mod encryption;
 pub(crate) mod mac;
+pub(crate) mod compression;
+pub(crate) mod negotiated;
@@ -0,0 +1,81 @@
+use std::fmt::{self, Display, Formatter};
+
+
+/// 密钥交换协商出的算法
+/// 重新协商密钥后会更新
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedAlgorithms {
+    pub kex: String,
+    pub host_key: String,
+    pub cipher_client_to_server: String,
+    pub cipher_server_to_client: String,
+    pub mac_client_to_server: String,
+    pub mac_server_to_client: String,
+    pub compression_client_to_server: String,
+    pub compression_server_to_client: String,
+}
+
+/// 和 ssh -v 的输出格式一致
+impl Display for NegotiatedAlgorithms {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "kex: algorithm: {}", self.kex)?;
+        writeln!(f, "kex: host key algorithm: {}", self.host_key)?;
+        writeln!(f, "kex: server->client cipher: {} MAC: {} compression: {}",
+                 self.cipher_server_to_client,
+                 self.mac_server_to_client,
+                 self.compression_server_to_client)?;
+        write!(f, "kex: client->server cipher: {} MAC: {} compression: {}",
+               self.cipher_client_to_server,
+               self.mac_client_to_server,
+               self.compression_client_to_server)
+    }
+}
+
+
+/// 每个数据包末尾的校验数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Integrity {
+    /// 密钥交换完成之前没有校验数据
+    None,
+    /// 独立的 mac 算法，长度为 mac 的输出长度
+    Mac(usize),
+    /// AEAD 加密算法自带的认证标签，不使用协商的 mac 算法
+    Tag(usize),
+}
+
+impl Integrity {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Integrity::None => 0,
+            Integrity::Mac(len) => *len,
+            Integrity::Tag(len) => *len,
+        }
+    }
+}
+
+
+/// 协商后每个方向上的 mac/tag 长度
+/// 解包时按这里的长度切分密文后面的校验数据，不要在别处假设长度
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Negotiated {
+    /// 发送时由加密算法自己追加，目前只用于记录
+    #[allow(dead_code)]
+    pub(crate) client_to_server: Integrity,
+    pub(crate) server_to_client: Integrity,
+}
+
+impl Negotiated {
+    pub(crate) fn new(client_to_server: Integrity, server_to_client: Integrity) -> Self {
+        Negotiated {
+            client_to_server,
+            server_to_client
+        }
+    }
+}
+
+impl Default for Negotiated {
+    /// 密钥交换完成之前
+    fn default() -> Self {
+        Negotiated::new(Integrity::None, Integrity::None)
+    }
+}
@@ -0,0 +1,26 @@
+use crate::algorithm::public_key::PublicKey;
+use crate::certificate::Certificate;
+use crate::data::Data;
+use crate::SshError;
+
+
+/// 证书类型的主机密钥
+/// 交换哈希由证书中被签名的密钥签名，验签交给对应的密钥算法
+pub struct Certified(pub(crate) Box<dyn PublicKey>);
+
+impl PublicKey for Certified {
+    fn new() -> Self where Self: Sized {
+        Certified(Box::new(super::Ed25519::new()))
+    }
+
+    fn signature_algorithm(&self) -> &'static str {
+        self.0.signature_algorithm()
+    }
+
+    fn verify_signature(&self, ks: &[u8], message: &[u8], sig: &[u8]) -> Result<bool, SshError> {
+        let cert = Certificate::parse(&ks[4..])?;
+        let mut data = Data::new();
+        data.put_u8s(&cert.key);
+        self.0.verify_signature(&data, message, sig)
+    }
+}
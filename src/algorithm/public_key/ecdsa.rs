@@ -0,0 +1,75 @@
+use crate::algorithm::backend::{self, EcdsaCurve};
+use crate::algorithm::public_key::PublicKey;
+use crate::constant::algorithms;
+use crate::data::Data;
+use crate::SshError;
+
+
+/// ECDSA 主机密钥
+/// key blob:  string name, string curve, string Q (未压缩的点)
+/// 签名数据:  mpint r, mpint s
+pub struct Ecdsa {
+    algorithm: &'static str
+}
+
+impl Ecdsa {
+    pub(crate) fn nistp384() -> Self {
+        Ecdsa {
+            algorithm: algorithms::PUBLIC_KEY_ECDSA_SHA2_NISTP384
+        }
+    }
+
+    /// (曲线名称, r 和 s 的字节长度, 曲线)
+    fn params(&self) -> (&'static str, usize, EcdsaCurve) {
+        match self.algorithm {
+            algorithms::PUBLIC_KEY_ECDSA_SHA2_NISTP384 => ("nistp384", 48, EcdsaCurve::NistP384),
+            _ => ("nistp256", 32, EcdsaCurve::NistP256)
+        }
+    }
+}
+
+impl PublicKey for Ecdsa {
+    fn new() -> Self where Self: Sized {
+        Ecdsa {
+            algorithm: algorithms::PUBLIC_KEY_ECDSA_SHA2_NISTP256
+        }
+    }
+
+    fn signature_algorithm(&self) -> &'static str {
+        self.algorithm
+    }
+
+    fn verify_signature(&self, ks: &[u8], message: &[u8], sig: &[u8]) -> Result<bool, SshError> {
+        let (curve, len, ecdsa_curve) = self.params();
+
+        let mut data = Data::from(ks[4..].to_vec());
+        data.get_u8s();
+        let curve_name = data.get_u8s();
+        if curve_name != curve.as_bytes() {
+            return Err(SshError::from(format!("ecdsa curve mismatch, expected: {}", curve)))
+        }
+        let point = data.get_u8s();
+
+        let mut data = Data::from(sig.to_vec());
+        let r = data.get_mpint();
+        let s = data.get_mpint();
+        let (r, s) = match (fixed(&r, len), fixed(&s, len)) {
+            (Some(r), Some(s)) => (r, s),
+            _ => return Ok(false)
+        };
+        let sig = [r, s].concat();
+
+        Ok(backend::verify_ecdsa(ecdsa_curve, &point, message, &sig))
+    }
+}
+
+
+/// 去掉前导 0 的 mpint 转成定长的大端字节
+fn fixed(v: &[u8], len: usize) -> Option<Vec<u8>> {
+    if v.len() > len {
+        return None
+    }
+    let mut r = vec![0_u8; len - v.len()];
+    r.extend_from_slice(v);
+    Some(r)
+}
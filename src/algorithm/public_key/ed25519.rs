@@ -1,5 +1,6 @@
-use ring::signature;
+use crate::algorithm::backend;
 use crate::algorithm::public_key::PublicKey;
+use crate::constant::algorithms;
 use crate::data::Data;
 use crate::SshError;
 
@@ -10,12 +11,14 @@ impl PublicKey for Ed25519 {
         Self
     }
 
+    fn signature_algorithm(&self) -> &'static str {
+        algorithms::PUBLIC_KEY_ED25519
+    }
+
     fn verify_signature(&self, ks: &[u8], message: &[u8], sig: &[u8]) -> Result<bool, SshError> {
         let mut data = Data::from(ks[4..].to_vec());
         data.get_u8s();
         let host_key = data.get_u8s();
-        let pub_key =
-            signature::UnparsedPublicKey::new(&signature::ED25519, host_key);
-        Ok(pub_key.verify(message, sig).is_ok())
+        Ok(backend::verify_ed25519(&host_key, message, sig))
     }
 }
@@ -1,33 +1,69 @@
-use crate::SshError;
+use crate::constant::algorithms;
+use crate::data::{Data, Reader};
+use crate::known_hosts;
+use crate::{util, SshError, SshResult};
 
 mod ed25519;
 mod rsa;
+mod ecdsa;
+mod certificate;
 
 
 pub(crate) use ed25519::Ed25519;
 pub(crate) use self::rsa::RSA;
+pub(crate) use ecdsa::Ecdsa;
+pub(crate) use certificate::Certified;
 
 
 /// # 公钥算法
 /// 主要用于对服务端签名的验证
 
-static mut PUBLIC_KEY: Option<Box<dyn PublicKey>> = None;
-
-pub(crate) fn put(pk: Box<dyn PublicKey>) {
-    unsafe {
-        PUBLIC_KEY = Some(pk)
+/// 按签名算法名称创建，不包括证书类型
+pub(crate) fn from_algorithm(algorithm: &str) -> Option<Box<dyn PublicKey>> {
+    match algorithm {
+        algorithms::PUBLIC_KEY_ED25519 => Some(Box::new(Ed25519::new())),
+        algorithms::PUBLIC_KEY_ECDSA_SHA2_NISTP256 => Some(Box::new(Ecdsa::new())),
+        algorithms::PUBLIC_KEY_ECDSA_SHA2_NISTP384 => Some(Box::new(Ecdsa::nistp384())),
+        algorithms::PUBLIC_KEY_RSA_SHA2_512 => Some(Box::new(RSA::sha2_512())),
+        algorithms::PUBLIC_KEY_RSA_SHA2_256 => Some(Box::new(RSA::sha2_256())),
+        algorithms::PUBLIC_KEY_RSA => Some(Box::new(RSA::new())),
+        _ => None
     }
 }
 
 
-pub(crate) fn get() -> &'static Box<dyn PublicKey> {
-    unsafe {
-        PUBLIC_KEY.as_ref().unwrap()
+/// 校验签名
+/*
+    string    signature format identifier
+    string    signature blob
+*/
+/// key 为签名方的公钥 (key blob)，签名算法和公钥类型不一致时校验失败
+pub(crate) fn verify(key: &[u8], message: &[u8], signature: &[u8]) -> SshResult<bool> {
+    let mut reader = Reader::new(signature, "invalid signature.");
+    let algorithm = util::from_utf8(reader.string()?.to_vec())?;
+    let sig = reader.string()?;
+    // rsa-sha2-256/512 也是用 ssh-rsa 的密钥签名
+    let key_type = match algorithm.as_str() {
+        algorithms::PUBLIC_KEY_RSA_SHA2_256
+        | algorithms::PUBLIC_KEY_RSA_SHA2_512 => algorithms::PUBLIC_KEY_RSA,
+        v => v
+    };
+    if known_hosts::key_type(key).as_deref() != Some(key_type) {
+        return Ok(false)
     }
+    let public_key = match from_algorithm(&algorithm) {
+        Some(v) => v,
+        None => return Err(SshError::from(format!("unsupported signature algorithm: {}", algorithm)))
+    };
+    let mut ks = Data::new();
+    ks.put_u8s(key);
+    public_key.verify_signature(&ks, message, sig)
 }
 
 
 pub(crate) trait PublicKey: Send + Sync {
     fn new() -> Self where Self: Sized;
+    /// 签名数据中的算法名称
+    fn signature_algorithm(&self) -> &'static str;
     fn verify_signature(&self, ks: &[u8], message: &[u8], sig: &[u8]) -> Result<bool, SshError>;
 }
\ No newline at end of file
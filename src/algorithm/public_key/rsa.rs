@@ -1,14 +1,39 @@
-use rsa::PublicKey;
+use crate::algorithm::backend::{self, Digest};
 use crate::algorithm::public_key::PublicKey as PubK;
+use crate::constant::algorithms;
 use crate::data::Data;
 use crate::SshError;
 
 
-pub struct RSA;
+/// RSA 主机密钥
+/// 同一种 ssh-rsa 密钥可以使用 ssh-rsa(sha1), rsa-sha2-256, rsa-sha2-512 三种签名算法
+pub struct RSA {
+    algorithm: &'static str
+}
+
+impl RSA {
+    pub(crate) fn sha2_256() -> Self {
+        RSA {
+            algorithm: algorithms::PUBLIC_KEY_RSA_SHA2_256
+        }
+    }
+
+    pub(crate) fn sha2_512() -> Self {
+        RSA {
+            algorithm: algorithms::PUBLIC_KEY_RSA_SHA2_512
+        }
+    }
+}
 
 impl PubK for RSA {
     fn new() -> Self where Self: Sized {
-        Self
+        RSA {
+            algorithm: algorithms::PUBLIC_KEY_RSA
+        }
+    }
+
+    fn signature_algorithm(&self) -> &'static str {
+        self.algorithm
     }
 
     fn verify_signature(&self, ks: &[u8], message: &[u8], sig: &[u8]) -> Result<bool, SshError> {
@@ -16,16 +41,14 @@ impl PubK for RSA {
         let mut data = Data::from((&ks[4..]).to_vec());
         data.get_u8s();
 
-        let e = rsa::BigUint::from_bytes_be(data.get_u8s().as_slice());
-        let n = rsa::BigUint::from_bytes_be(data.get_u8s().as_slice());
-        let public_key = rsa::RsaPublicKey::new(n, e).unwrap();
-        let scheme = rsa::PaddingScheme::PKCS1v15Sign {
-            hash: Some(rsa::Hash::SHA1)
-        };
-
-        let digest = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, message);
-        let msg = digest.as_ref();
+        let e = data.get_mpint();
+        let n = data.get_mpint();
 
-        Ok(public_key.verify(scheme, msg, sig).is_ok())
+        let digest = match self.algorithm {
+            algorithms::PUBLIC_KEY_RSA_SHA2_256 => Digest::Sha256,
+            algorithms::PUBLIC_KEY_RSA_SHA2_512 => Digest::Sha512,
+            _ => Digest::Sha1
+        };
+        backend::verify_rsa(digest, &e, &n, message, sig)
     }
-}
\ No newline at end of file
+}
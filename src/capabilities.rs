@@ -0,0 +1,60 @@
+use crate::algorithm::backend;
+use crate::config::AlgorithmList;
+use crate::constant::ssh_str;
+
+
+/// 当前客户端支持的算法和功能
+/// 算法列表和密钥协商时发送给服务端的列表是同一份，按优先级排序
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// 密钥交换算法
+    pub key_exchange: Vec<String>,
+    /// 主机密钥算法
+    pub host_key: Vec<String>,
+    /// 对称加密算法
+    pub encryption: Vec<String>,
+    /// MAC 算法
+    pub mac: Vec<String>,
+    /// 压缩算法
+    pub compression: Vec<String>,
+    /// 认证方式
+    pub auth_methods: Vec<String>,
+    /// 编译时开启的 cargo feature
+    pub features: Vec<String>,
+    /// 加密原语使用的后端
+    pub crypto_backend: String,
+}
+
+impl Capabilities {
+    pub(crate) fn new() -> Self {
+        let algorithm = AlgorithmList::client_algorithm();
+        Capabilities {
+            key_exchange: algorithm.key_exchange_algorithm.0,
+            host_key: algorithm.public_key_algorithm.0,
+            encryption: algorithm.c_encryption_algorithm.0,
+            mac: algorithm.c_mac_algorithm.0,
+            compression: algorithm.c_compression_algorithm.0,
+            auth_methods: vec![
+                ssh_str::PASSWORD.to_string(),
+                ssh_str::PUBLIC_KEY.to_string()
+            ],
+            features: features(),
+            crypto_backend: backend::NAME.to_string(),
+        }
+    }
+}
+
+
+fn features() -> Vec<String> {
+    let mut v = vec![];
+    if cfg!(feature = "ctrlc") {
+        v.push("ctrlc".to_string())
+    }
+    if cfg!(feature = "ring-backend") {
+        v.push("ring-backend".to_string())
+    }
+    if cfg!(feature = "legacy-algorithms") {
+        v.push("legacy-algorithms".to_string())
+    }
+    v
+}
@@ -0,0 +1,222 @@
+use std::time::SystemTime;
+use crate::algorithm::public_key;
+use crate::constant::algorithms;
+use crate::data::{Data, Reader};
+use crate::error::{SshError, SshErrorKind, SshResult};
+use crate::known_hosts;
+use crate::util;
+
+
+/// 证书类型的主机密钥名称后缀
+const CERT_SUFFIX: &str = "-cert-v01@openssh.com";
+
+const INVALID_CERTIFICATE: &str = "invalid host certificate.";
+
+/// SSH2_CERT_TYPE_HOST
+const CERT_TYPE_HOST: u32 = 2;
+
+
+/// OpenSSH 主机证书 (PROTOCOL.certkeys)
+/*
+    string    "ssh-ed25519-cert-v01@openssh.com"
+    string    nonce
+    string    pk
+    uint64    serial
+    uint32    type
+    string    key id
+    string    valid principals
+    uint64    valid after
+    uint64    valid before
+    string    critical options
+    string    extensions
+    string    reserved
+    string    signature key
+    string    signature
+*/
+pub(crate) struct Certificate {
+    /// 被签名的主机密钥 (key blob)
+    pub(crate) key: Vec<u8>,
+    pub(crate) cert_type: u32,
+    pub(crate) key_id: String,
+    /// 为空时对所有主机名有效
+    pub(crate) principals: Vec<String>,
+    pub(crate) valid_after: u64,
+    pub(crate) valid_before: u64,
+    critical_options: Vec<u8>,
+    /// 签发证书的 CA 公钥 (key blob)
+    pub(crate) signature_key: Vec<u8>,
+    /// 签名覆盖的数据: 从开头到 signature key 为止
+    signed: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Certificate {
+    pub(crate) fn parse(blob: &[u8]) -> SshResult<Self> {
+        let mut reader = Reader::new(blob, INVALID_CERTIFICATE);
+        let key_type = util::from_utf8(reader.string()?.to_vec())?;
+        let key = match key_type.strip_suffix(CERT_SUFFIX) {
+            Some(algorithms::PUBLIC_KEY_ED25519) => {
+                // nonce
+                reader.string()?;
+                let pk = reader.string()?;
+                let mut data = Data::new();
+                data.put_str(algorithms::PUBLIC_KEY_ED25519)
+                    .put_u8s(pk);
+                data.to_vec()
+            }
+            _ => return Err(SshError::from(format!("unsupported host certificate type: {}", key_type)))
+        };
+        // serial
+        reader.u64()?;
+        let cert_type = reader.u32()?;
+        let key_id = util::from_utf8(reader.string()?.to_vec())?;
+        let mut principals = vec![];
+        let mut list = Reader::new(reader.string()?, INVALID_CERTIFICATE);
+        while !list.is_empty() {
+            principals.push(util::from_utf8(list.string()?.to_vec())?)
+        }
+        let valid_after = reader.u64()?;
+        let valid_before = reader.u64()?;
+        let critical_options = reader.string()?.to_vec();
+        // extensions
+        reader.string()?;
+        // reserved
+        reader.string()?;
+        let signature_key = reader.string()?.to_vec();
+        let signed = blob[..reader.position()].to_vec();
+        let signature = reader.string()?.to_vec();
+        Ok(Certificate {
+            key,
+            cert_type,
+            key_id,
+            principals,
+            valid_after,
+            valid_before,
+            critical_options,
+            signature_key,
+            signed,
+            signature
+        })
+    }
+
+    /// 校验证书类型、CA 签名、有效期和主机名
+    pub(crate) fn validate(&self, host: &str) -> SshResult<()> {
+        if self.cert_type != CERT_TYPE_HOST {
+            return Err(SshError::from("certificate is not a host certificate."))
+        }
+        // 主机证书目前没有定义任何 critical option
+        if !self.critical_options.is_empty() {
+            return Err(SshError::from("host certificate has unsupported critical options."))
+        }
+        if !self.verify_signature()? {
+            log::error!("host certificate signature verification failure.");
+            return Err(SshError::from("host certificate signature verification failure."))
+        }
+        let now = util::sys_time_to_secs(SystemTime::now())?;
+        if now < self.valid_after || now >= self.valid_before {
+            log::error!("host certificate [{}] is not valid at {}.", self.key_id, now);
+            return Err(SshError::from(SshErrorKind::HostCertificateExpired {
+                key_id: self.key_id.clone(),
+                valid_after: self.valid_after,
+                valid_before: self.valid_before
+            }))
+        }
+        let host = host.to_lowercase();
+        if !self.principals.is_empty() && !self.principals.iter().any(|v| v.to_lowercase() == host) {
+            log::error!("host certificate [{}] is not valid for [{}].", self.key_id, host);
+            return Err(SshError::from(SshErrorKind::HostCertificatePrincipal {
+                host,
+                principals: self.principals.clone()
+            }))
+        }
+        Ok(())
+    }
+
+    fn verify_signature(&self) -> SshResult<bool> {
+        public_key::verify(&self.signature_key, &self.signed, &self.signature)
+    }
+}
+
+
+/// 是否为证书类型的 key blob
+pub(crate) fn is_certificate(key: &[u8]) -> bool {
+    known_hosts::key_type(key)
+        .map(|v| v.ends_with(CERT_SUFFIX))
+        .unwrap_or(false)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{SshError, SshErrorKind};
+    use crate::mock::{self, blob};
+    use super::{is_certificate, Certificate};
+
+    fn validate(cert: &str, host: &str) -> SshError {
+        Certificate::parse(&blob(cert)).unwrap().validate(host).unwrap_err()
+    }
+
+    #[test]
+    fn parse_host_certificate() {
+        let cert = Certificate::parse(&blob(mock::HOST_CERT)).unwrap();
+        assert_eq!(cert.key, blob(mock::HOST_KEY));
+        assert_eq!(cert.signature_key, blob(mock::CA_KEY));
+        assert_eq!(cert.cert_type, 2);
+        assert_eq!(cert.key_id, "good");
+        assert_eq!(cert.principals, vec!["example.com".to_string()]);
+        assert_eq!((cert.valid_after, cert.valid_before), (0, u64::MAX));
+        cert.validate("example.com").unwrap();
+        cert.validate("Example.COM").unwrap();
+        assert!(is_certificate(&blob(mock::HOST_CERT)));
+        assert!(!is_certificate(&blob(mock::HOST_KEY)));
+    }
+
+    #[test]
+    fn expired_certificate() {
+        assert_eq!(validate(mock::EXPIRED_HOST_CERT, "example.com").kind(), &SshErrorKind::HostCertificateExpired {
+            key_id: "expired".to_string(),
+            // 2000-01-01 和 2001-01-01 UTC
+            valid_after: 946684800,
+            valid_before: 978307200
+        });
+    }
+
+    #[test]
+    fn wrong_principal() {
+        assert_eq!(validate(mock::OTHER_HOST_CERT, "example.com").kind(), &SshErrorKind::HostCertificatePrincipal {
+            host: "example.com".to_string(),
+            principals: vec!["other.example.com".to_string()]
+        });
+        Certificate::parse(&blob(mock::OTHER_HOST_CERT)).unwrap().validate("other.example.com").unwrap();
+    }
+
+    #[test]
+    fn user_certificate_is_rejected() {
+        assert_eq!(validate(mock::USER_CERT, "example.com").kind(),
+                   &SshErrorKind::SshError("certificate is not a host certificate.".to_string()));
+    }
+
+    #[test]
+    fn bad_signature_is_rejected() {
+        let failure = SshErrorKind::SshError("host certificate signature verification failure.".to_string());
+        // 修改签名
+        let mut cert = blob(mock::HOST_CERT);
+        *cert.last_mut().unwrap() ^= 1;
+        assert_eq!(Certificate::parse(&cert).unwrap().validate("example.com").unwrap_err().kind(), &failure);
+        // 修改签名覆盖的 key id
+        let mut cert = blob(mock::HOST_CERT);
+        let pos = cert.windows(4).position(|v| v == b"good").unwrap();
+        cert[pos + 3] = b'e';
+        let cert = Certificate::parse(&cert).unwrap();
+        assert_eq!(cert.key_id, "gooe");
+        assert_eq!(cert.validate("example.com").unwrap_err().kind(), &failure);
+    }
+
+    #[test]
+    fn truncated_certificate_is_an_error() {
+        let cert = blob(mock::HOST_CERT);
+        for len in 0..cert.len() {
+            assert!(Certificate::parse(&cert[..len]).is_err(), "{}", len);
+        }
+    }
+}
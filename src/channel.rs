@@ -1,38 +1,230 @@
+use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
-use std::sync::atomic::AtomicU32;
-use std::sync::atomic::Ordering::Relaxed;
-use crate::constant::{ssh_msg_code};
-use crate::error::{SshError, SshResult};
-use crate::data::Data;
-use crate::slog::log;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use crate::constant::{ssh_msg_code, ssh_str};
+use crate::error::{SshError, SshErrorKind, SshResult};
+use crate::data::{Data, Reader};
 use crate::channel_exec::ChannelExec;
 use crate::channel_scp::ChannelScp;
 use crate::channel_shell::ChannelShell;
-use crate::{client, config, kex};
-use crate::algorithm::hash::h;
-use crate::algorithm::{key_exchange, public_key};
+use crate::channel_subsystem::ChannelSubsystem;
+use crate::channel_state::Dynamic;
+use crate::channel_stats::{ChannelStats, ChannelStatsSnapshot};
+use crate::{agent_forward, client, kex, limits, util};
+use crate::client::Client;
 use crate::window_size::WindowSize;
 
 
-// 客户端通道编号初始值
-pub(crate) static CLIENT_CHANNEL_NO: AtomicU32 = AtomicU32::new(0);
+/// 通道由哪一方发起关闭
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseInitiator {
+    Local,
+    Remote
+}
+
+
+/// 通道当前的状态，见 `Channel::state`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelState {
+    Open,
+    /// 已经发送 CHANNEL_EOF，不能再写，仍然可以读
+    EofSent,
+    /// 服务端已经发送 CHANNEL_EOF，不会再收到数据，仍然可以写。
+    /// 双方都发送了 EOF 时也是这个状态，等待关闭
+    EofReceived,
+    /// 任意一方发送了 CHANNEL_CLOSE 或者连接已经断开，不能再读写
+    Closed
+}
+
+
+/// 远程进程被信号终止时的信息 (exit-signal)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitSignal {
+    /// 信号名称，不带 SIG 前缀，例如 KILL
+    pub signal: String,
+    pub core_dumped: bool,
+    pub message: String
+}
+
 
+impl ExitSignal {
+    /*
+        string    signal name (without the "SIG" prefix)
+        boolean   core dumped
+        string    error message in ISO-10646 UTF-8 encoding
+        string    language tag [RFC3066]
+    */
+    pub(crate) fn parse(reader: &mut Reader<'_>) -> SshResult<Self> {
+        let signal = util::from_utf8(reader.string()?.to_vec())?;
+        let core_dumped = reader.u8()? != 0;
+        let message = util::from_utf8(reader.string()?.to_vec())?;
+        Ok(ExitSignal {
+            signal,
+            core_dumped,
+            message
+        })
+    }
+}
+
+
+/// 发送给远程进程的信号 (RFC 4254 6.9)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signal {
+    Abrt,
+    Alrm,
+    Fpe,
+    Hup,
+    Ill,
+    Int,
+    Kill,
+    Pipe,
+    Quit,
+    Segv,
+    Term,
+    Usr1,
+    Usr2,
+    /// 其它信号名称，不带 SIG 前缀
+    Custom(String)
+}
+
+impl Signal {
+    /// 不带 SIG 前缀的信号名称，例如 INT
+    pub fn name(&self) -> &str {
+        match self {
+            Signal::Abrt => "ABRT",
+            Signal::Alrm => "ALRM",
+            Signal::Fpe => "FPE",
+            Signal::Hup => "HUP",
+            Signal::Ill => "ILL",
+            Signal::Int => "INT",
+            Signal::Kill => "KILL",
+            Signal::Pipe => "PIPE",
+            Signal::Quit => "QUIT",
+            Signal::Segv => "SEGV",
+            Signal::Term => "TERM",
+            Signal::Usr1 => "USR1",
+            Signal::Usr2 => "USR2",
+            Signal::Custom(name) => name.as_str()
+        }
+    }
+}
+
+
+/// 伪终端参数 (pty-req)，默认值与不传参数时相同:
+/// xterm-256color，80x24 字符，640x480 像素，输入输出波特率 115200
+#[derive(Debug, Clone)]
+pub struct PtyOptions {
+    /// TERM 环境变量，例如 xterm-256color
+    pub term: String,
+    /// 宽度 (字符)
+    pub cols: u32,
+    /// 高度 (行)
+    pub rows: u32,
+    /// 宽度 (像素)，不知道时为 0
+    pub width_px: u32,
+    /// 高度 (像素)，不知道时为 0
+    pub height_px: u32,
+    /// 终端模式，操作码和参数，见 RFC 4254 8
+    /// 操作码必须在 1 - 159 之间，结尾的 TTY_OP_END 会自动加上
+    pub modes: Vec<(u8, u32)>
+}
+
+impl PtyOptions {
+    /// 中断字符，例如 3 (Ctrl-C)
+    pub const VINTR: u8 = 1;
+    /// 为 INTR、QUIT、SUSP 产生信号
+    pub const ISIG: u8 = 50;
+    /// 规范模式，按行处理输入
+    pub const ICANON: u8 = 51;
+    /// 回显输入的字符，为 0 时不回显，例如通过管道输入密码
+    pub const ECHO: u8 = 53;
+    pub const TTY_OP_ISPEED: u8 = 128;
+    pub const TTY_OP_OSPEED: u8 = 129;
+
+    pub fn new() -> Self {
+        PtyOptions {
+            term: ssh_str::XTERM_VAR.to_string(),
+            cols: limits::PTY_COLUMNS,
+            rows: limits::PTY_ROWS,
+            width_px: limits::PTY_WIDTH,
+            height_px: limits::PTY_HEIGHT,
+            modes: vec![
+                (PtyOptions::TTY_OP_ISPEED, limits::PTY_BAUD_RATE),
+                (PtyOptions::TTY_OP_OSPEED, limits::PTY_BAUD_RATE)
+            ]
+        }
+    }
+
+    /// 增加一个终端模式，已有相同操作码时替换它的参数
+    pub fn mode(mut self, opcode: u8, value: u32) -> Self {
+        self.modes.retain(|(op, _)| *op != opcode);
+        self.modes.push((opcode, value));
+        self
+    }
+
+    /// encoded terminal modes: 每个模式为 byte opcode + uint32 argument，以 TTY_OP_END (0) 结束
+    pub(crate) fn encoded_modes(&self) -> SshResult<Vec<u8>> {
+        let mut model = vec![];
+        for (opcode, value) in &self.modes {
+            if !(1..160).contains(opcode) {
+                log::error!("invalid terminal mode opcode: {}", opcode);
+                return Err(SshError::from(format!("invalid terminal mode opcode: {}", opcode)))
+            }
+            model.push(*opcode);
+            model.extend(value.to_be_bytes());
+        }
+        model.push(0_u8);             // TTY_OP_END
+        Ok(model)
+    }
+}
 
-pub fn current_client_channel_no() -> u32 {
-    let client_channel_no = CLIENT_CHANNEL_NO.load(Relaxed);
-    CLIENT_CHANNEL_NO.fetch_add(1, Relaxed);
-    client_channel_no
+impl Default for PtyOptions {
+    fn default() -> Self {
+        PtyOptions::new()
+    }
 }
 
 
+/// 通道关闭后的汇总信息
+#[derive(Debug, Clone)]
+pub struct CloseSummary {
+    /// 是否收到了服务端的 EOF
+    pub eof_received: bool,
+    pub exit_status: Option<u32>,
+    pub exit_signal: Option<ExitSignal>,
+    /// 收到的数据字节数
+    pub bytes_in: u64,
+    /// 发送的数据字节数
+    pub bytes_out: u64,
+    pub initiator: CloseInitiator
+}
+
 
-pub struct Channel {
+/// 通道
+/// 默认的 `Channel` 在运行时检查状态，`Channel<Open>` 等类型见 `channel_state`
+pub struct Channel<S = Dynamic> {
+    pub(crate) client: Arc<Mutex<Client>>,
     pub(crate) remote_close: bool,
     pub(crate) local_close: bool,
-    pub(crate) window_size: WindowSize
+    pub(crate) window_size: WindowSize,
+    pub(crate) stats: Option<ChannelStats>,
+    pub(crate) eof_received: bool,
+    pub(crate) eof_sent: bool,
+    pub(crate) exit_status: Option<u32>,
+    pub(crate) exit_signal: Option<ExitSignal>,
+    pub(crate) close_initiator: Option<CloseInitiator>,
+    /// 服务端已经确认打开通道，释放时需要发送 CHANNEL_CLOSE
+    pub(crate) confirmed: bool,
+    /// 字段已经转移到另一个状态的通道 (`into_state`)，释放时什么都不做
+    pub(crate) detached: bool,
+    /// 关闭通道时收到的输出和 stderr，由包装的通道 (例如 `ChannelExec`) 取走
+    pub(crate) drained: Vec<u8>,
+    pub(crate) drained_stderr: Vec<u8>,
+    pub(crate) state: PhantomData<S>
 }
 
-impl Deref for Channel {
+impl<S> Deref for Channel<S> {
     type Target = WindowSize;
 
     fn deref(&self) -> &Self::Target {
@@ -40,76 +232,148 @@ impl Deref for Channel {
     }
 }
 
-impl DerefMut for Channel {
+impl<S> DerefMut for Channel<S> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.window_size
     }
 }
 
 impl Channel {
-    pub(crate) fn other(&mut self, message_code: u8, mut result: Data) -> SshResult<()> {
-        match message_code {
-            ssh_msg_code::SSH_MSG_GLOBAL_REQUEST => {
-                let mut data = Data::new();
-                data.put_u8(ssh_msg_code::SSH_MSG_REQUEST_FAILURE);
-                let client = client::default()?;
-                client.write(data)?;
+    pub(crate) fn new(client: Arc<Mutex<Client>>, window_size: WindowSize) -> Self {
+        Channel {
+            client,
+            remote_close: false,
+            local_close: false,
+            window_size,
+            stats: None,
+            eof_received: false,
+            eof_sent: false,
+            exit_status: None,
+            exit_signal: None,
+            close_initiator: None,
+            confirmed: false,
+            detached: false,
+            drained: vec![],
+            drained_stderr: vec![],
+            state: PhantomData
+        }
+    }
+}
+
+impl<S> Channel<S> {
+    /// 转换为另一个状态，字段保持不变
+    /// Channel 实现了 Drop，字段不能直接移出，转移之后原来的通道标记为 detached
+    pub(crate) fn into_state<T>(mut self) -> Channel<T> {
+        self.detached = true;
+        Channel {
+            client: self.client.clone(),
+            remote_close: self.remote_close,
+            local_close: self.local_close,
+            window_size: std::mem::replace(&mut self.window_size, WindowSize::with_local_window_size(0)),
+            stats: self.stats.take(),
+            eof_received: self.eof_received,
+            eof_sent: self.eof_sent,
+            exit_status: self.exit_status,
+            exit_signal: self.exit_signal.take(),
+            close_initiator: self.close_initiator,
+            confirmed: self.confirmed,
+            detached: false,
+            drained: std::mem::take(&mut self.drained),
+            drained_stderr: std::mem::take(&mut self.drained_stderr),
+            state: PhantomData
+        }
+    }
+
+    /// 客户端通道编号，与 `Event` 中的 id 对应
+    pub fn id(&self) -> u32 {
+        self.client_channel
+    }
+
+    /// 通道当前的状态
+    /// 除了这个通道已经处理的消息，还会检查连接上已经收到、还没有读取的 CHANNEL_EOF 和 CHANNEL_CLOSE，
+    /// 所以只写不读的通道也能发现服务端已经关闭了它
+    pub fn state(&self) -> ChannelState {
+        if self.is_finished() {
+            return ChannelState::Closed
+        }
+        let mut eof_received = self.eof_received;
+        if let Ok(client) = client::locking(&self.client) {
+            if client.disconnected.is_some() {
+                return ChannelState::Closed
             }
-            ssh_msg_code::SSH_MSG_KEXINIT => {
-                let vec = result.to_vec();
-                let mut data = Data::from(vec![message_code]);
-                data.extend(vec);
-                let h = h::get();
-                h.set_i_s(data.as_slice());
-                kex::processing_server_algorithm(data)?;
-                kex::send_algorithm()?;
-                let config = config::config();
+            if let Some(record) = client.records.get(&self.client_channel) {
+                if record.initiator == Some(CloseInitiator::Remote) {
+                    return ChannelState::Closed
+                }
+                eof_received |= record.eof_received;
+            }
+        }
+        match (eof_received, self.eof_sent) {
+            (true, _) => ChannelState::EofReceived,
+            (false, true) => ChannelState::EofSent,
+            (false, false) => ChannelState::Open
+        }
+    }
 
-                // 缓存密钥交换算法
-                key_exchange::put(config.algorithm.matching_key_exchange_algorithm()?);
-                // 公钥算法
-                public_key::put(config.algorithm.matching_public_key_algorithm()?);
+    /// 通道是否已经关闭，例如用于从连接池中移除失效的通道
+    pub fn is_closed(&self) -> bool {
+        self.state() == ChannelState::Closed
+    }
 
-                h.set_v_c(config.version.client_version.as_str());
-                h.set_v_s(config.version.server_version.as_str());
+    /// 已经处理了任意一方的 CHANNEL_CLOSE，之后不会再收到这个通道的数据，读取直接返回
+    pub(crate) fn is_finished(&self) -> bool {
+        self.remote_close || self.local_close
+    }
 
-                kex::send_qc()?;
+    pub(crate) fn client(&self) -> SshResult<MutexGuard<'_, Client>> {
+        client::locking(&self.client)
+    }
 
-                kex::verify_signature_and_new_keys()?
+    pub(crate) fn other(&mut self, message_code: u8, mut result: Data) -> SshResult<()> {
+        match message_code {
+            ssh_msg_code::SSH_MSG_GLOBAL_REQUEST => {
+                self.client()?.global_request(result)?;
             }
+            ssh_msg_code::SSH_MSG_KEXINIT => kex::rekey(&mut *self.client()?, result)?,
             ssh_msg_code::SSH_MSG_KEXDH_REPLY => {
-                // 生成session_id并且获取signature
-                let sig = kex::generate_signature(result)?;
-                // 验签
-                let session_id = h::get().digest();
-                let flag = public_key::get()
-                    .verify_signature(h::get().k_s.as_ref(),
-                                      &session_id, &sig)?;
-                if !flag {
-                    log::error!("signature verification failure.");
-                    return Err(SshError::from("signature verification failure."))
-                }
-                log::info!("signature verification success.");
+                kex::verify_signature(&mut *self.client()?, result)?;
             }
-            ssh_msg_code::SSH_MSG_NEWKEYS => kex::new_keys()?,
-            // 通道大小 暂不处理
+            ssh_msg_code::SSH_MSG_NEWKEYS => kex::new_keys(&mut *self.client()?)?,
+            // 服务端增加的远程窗口，写数据时按远程窗口拆分和等待，见 `Client::write_data`
             ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST => {
-                // 接收方通道号， 暂时不需要
-                result.get_u32();
-                // 需要调整增加的窗口大小
-                let rws = result.get_u32();
-                self.window_size.add_remote_window_size(rws);
-                self.window_size.add_remote_max_window_size(rws);
+                let cc = result.get_u32();
+                if cc == self.client_channel {
+                    let rws = result.get_u32();
+                    self.window_size.add_remote_window_size(rws);
+                    self.window_size.add_remote_max_window_size(rws);
+                }
             },
-            ssh_msg_code::SSH_MSG_CHANNEL_EOF => {}
-            ssh_msg_code::SSH_MSG_CHANNEL_REQUEST => {}
+            // 服务端不会再发送数据，但通道仍然可以写，直到收到 CHANNEL_CLOSE
+            ssh_msg_code::SSH_MSG_CHANNEL_EOF => {
+                let cc = result.get_u32();
+                if cc == self.client_channel {
+                    log::info!("channel eof received.");
+                    self.eof_received = true;
+                }
+            }
+            ssh_msg_code::SSH_MSG_CHANNEL_REQUEST => {
+                let cc = result.get_u32();
+                if cc == self.client_channel {
+                    self.channel_request(result)?
+                }
+            }
             ssh_msg_code::SSH_MSG_CHANNEL_SUCCESS => {}
-            ssh_msg_code::SSH_MSG_CHANNEL_FAILURE => return Err(SshError::from("channel failure.")),
+            ssh_msg_code::SSH_MSG_CHANNEL_FAILURE => {
+                let cc = result.get_u32();
+                if cc == self.client_channel {
+                    return Err(SshError::from("channel failure."))
+                }
+            }
             ssh_msg_code::SSH_MSG_CHANNEL_CLOSE => {
                 let cc = result.get_u32();
                 if cc == self.client_channel {
-                    self.remote_close = true;
-                    self.close()?;
+                    self.remote_closed();
+                    self.shutdown(limits::CLOSE_TIMEOUT)?;
                 }
             }
             _ => {}
@@ -117,25 +381,335 @@ impl Channel {
         Ok(())
     }
 
-    pub fn open_shell(self) -> SshResult<ChannelShell> {
-        log::info!("shell opened.");
-        return ChannelShell::open(self)
+    /*
+        byte      SSH_MSG_CHANNEL_REQUEST
+        uint32    recipient channel
+        string    request type
+        boolean   want reply
+        ....      type-specific data follows
+    */
+    /// want reply 为 true 时回复，不认识的请求 (例如 ClientAliveInterval 的 keepalive@openssh.com)
+    /// 回复 SSH_MSG_CHANNEL_FAILURE，服务端收到任意回复都会认为客户端还在
+    fn channel_request(&mut self, mut result: Data) -> SshResult<()> {
+        let request_type = util::from_utf8(result.get_u8s())?;
+        let want_reply = result.get_u8() != 0;
+        let known = match request_type.as_str() {
+            ssh_str::EXIT_STATUS => {
+                let status = result.get_u32();
+                log::info!("remote exit status: {}", status);
+                self.exit_status = Some(status);
+                true
+            }
+            ssh_str::EXIT_SIGNAL => {
+                let signal = ExitSignal::parse(&mut Reader::new(result.as_slice(), "invalid exit signal."))?;
+                log::info!("remote exit signal: {}", signal.signal);
+                self.exit_signal = Some(signal);
+                true
+            }
+            _ => {
+                log::info!("server channel request: {}, want reply: {}", request_type, want_reply);
+                false
+            }
+        };
+        if !want_reply || self.local_close {
+            return Ok(())
+        }
+        let message_code = match known {
+            true => ssh_msg_code::SSH_MSG_CHANNEL_SUCCESS,
+            false => ssh_msg_code::SSH_MSG_CHANNEL_FAILURE
+        };
+        let mut data = Data::new();
+        data.put_u8(message_code)
+            .put_u32(self.server_channel);
+        self.client()?.write(data)
     }
 
-    pub fn open_exec(self) -> SshResult<ChannelExec> {
-        log::info!("exec opened.");
-        return Ok(ChannelExec::open(self))
+    pub(crate) fn remote_closed(&mut self) {
+        if self.close_initiator.is_none() {
+            self.close_initiator = Some(CloseInitiator::Remote)
+        }
+        self.remote_close = true;
     }
 
-    pub fn open_scp(self) -> SshResult<ChannelScp> {
-        log::info!("scp opened.");
-        return Ok(ChannelScp::open(self))
+    /*
+        byte      SSH_MSG_CHANNEL_EOF
+        uint32    recipient channel
+    */
+    // 只发送一次，之后不能再写数据，但仍然可以读取服务端的数据
+    pub(crate) fn eof(&mut self) -> SshResult<()> {
+        if self.eof_sent || self.local_close { return Ok(()); }
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_EOF)
+            .put_u32(self.server_channel);
+        self.client()?.write(data)?;
+        log::info!("channel eof sent.");
+        self.eof_sent = true;
+        Ok(())
     }
 
-    pub fn close(&mut self) -> SshResult<()> {
-        log::info!("channel close.");
-        self.send_close()?;
-        self.receive_close()
+    /// 写数据之前检查通道是否还可以写
+    /// 已经关闭时返回 `SshErrorKind::ChannelClosed`，包括连接上还没有读取的 CHANNEL_CLOSE
+    pub(crate) fn check_writable(&self) -> SshResult<()> {
+        if self.is_closed() {
+            log::error!("channel closed.");
+            return Err(SshError::from(SshErrorKind::ChannelClosed))
+        }
+        if self.eof_sent {
+            log::error!("channel eof already sent.");
+            return Err(SshError::from("channel eof already sent."))
+        }
+        Ok(())
+    }
+
+    /*
+        byte      SSH_MSG_CHANNEL_REQUEST
+        uint32    recipient channel
+        string    "pty-req"
+        boolean   want_reply
+        string    TERM environment variable value (e.g., vt100)
+        uint32    terminal width, characters (e.g., 80)
+        uint32    terminal height, rows (e.g., 24)
+        uint32    terminal width, pixels (e.g., 640)
+        uint32    terminal height, pixels (e.g., 480)
+        string    encoded terminal modes
+    */
+    /// 请求一个伪终端，shell 通道总是请求，exec 通道由调用方决定
+    pub(crate) fn send_pty_request(&self, want_reply: bool, options: &PtyOptions) -> SshResult<()> {
+        let model = options.encoded_modes()?;
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
+            .put_u32(self.server_channel)
+            .put_str(ssh_str::PTY_REQ)
+            .put_u8(want_reply as u8)
+            .put_str(options.term.as_str())
+            .put_u32(options.cols)
+            .put_u32(options.rows)
+            .put_u32(options.width_px)
+            .put_u32(options.height_px)
+            .put_u8s(&model);
+        self.client()?.write(data)
+    }
+
+    /*
+        byte      SSH_MSG_CHANNEL_REQUEST
+        uint32    recipient channel
+        string    "break"
+        boolean   want_reply
+        uint32    break-length in milliseconds
+    */
+    /// 服务端不能发送 BREAK 时回应 FAILURE，返回 `SshErrorKind::ChannelRequestFailed`
+    pub(crate) fn send_break(&mut self, length_ms: u32) -> SshResult<()> {
+        if self.remote_close || self.local_close {
+            log::error!("channel already closed.");
+            return Err(SshError::from("channel already closed."))
+        }
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
+            .put_u32(self.server_channel)
+            .put_str(ssh_str::BREAK)
+            .put_u8(true as u8)
+            .put_u32(length_ms);
+        self.client()?.write(data)?;
+        self.wait_reply(ssh_str::BREAK)?;
+        log::info!("break [{} ms] sent.", length_ms);
+        Ok(())
+    }
+
+    /*
+        byte      SSH_MSG_CHANNEL_REQUEST
+        uint32    recipient channel
+        string    "signal"
+        boolean   FALSE
+        string    signal name (without the "SIG" prefix)
+    */
+    /// 服务端不回应信号请求，进程退出后仍然通过 exit-status 或 exit-signal 得知退出原因
+    pub(crate) fn send_signal(&self, signal: &Signal) -> SshResult<()> {
+        if self.remote_close || self.local_close {
+            log::error!("channel already closed.");
+            return Err(SshError::from("channel already closed."))
+        }
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
+            .put_u32(self.server_channel)
+            .put_str(ssh_str::SIGNAL)
+            .put_u8(false as u8)
+            .put_str(signal.name());
+        self.client()?.write(data)?;
+        log::info!("signal [{}] sent.", signal.name());
+        Ok(())
+    }
+
+    /*
+        byte      SSH_MSG_CHANNEL_REQUEST
+        uint32    recipient channel
+        string    "env"
+        boolean   want reply
+        string    variable name
+        string    variable value
+    */
+    /// 需要在 shell 或 exec 请求之前发送
+    /// 服务端只接受 AcceptEnv 中允许的变量，其它的会被忽略，不请求回应所以不会报错
+    pub(crate) fn send_env(&self, name: &str, value: &str) -> SshResult<()> {
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
+            .put_u32(self.server_channel)
+            .put_str(ssh_str::ENV)
+            .put_u8(false as u8)
+            .put_str(name)
+            .put_str(value);
+        self.client()?.write(data)
+    }
+
+    /*
+        byte      SSH_MSG_CHANNEL_REQUEST
+        uint32    recipient channel
+        string    "auth-agent-req@openssh.com"
+        boolean   want reply
+    */
+    pub(crate) fn send_agent_request(&self) -> SshResult<()> {
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
+            .put_u32(self.server_channel)
+            .put_str(ssh_str::AUTH_AGENT_REQ)
+            .put_u8(false as u8);
+        let mut client = self.client()?;
+        client.write(data)?;
+        client.agent_forwarding = true;
+        Ok(())
+    }
+
+    /// 等待 want_reply 为 true 的通道请求的回应，request 为请求类型
+    /// 服务端回应 SSH_MSG_CHANNEL_FAILURE 时返回 `SshErrorKind::ChannelRequestFailed`
+    pub(crate) fn wait_reply(&mut self, request: &str) -> SshResult<()> {
+        // 等待回应时收到的输出，例如 shell 中发送 break 时设备打印的内容
+        let mut pending = vec![];
+        loop {
+            let mut results = self.client()?.read_channel(self.client_channel)?.into_iter();
+            while let Some(mut result) = results.next() {
+                if result.is_empty() { continue }
+                if matches!(result[0], ssh_msg_code::SSH_MSG_CHANNEL_DATA | ssh_msg_code::SSH_MSG_CHANNEL_EXTENDED_DATA) {
+                    pending.push(result);
+                    continue
+                }
+                let message_code = result.get_u8();
+                match message_code {
+                    ssh_msg_code::SSH_MSG_CHANNEL_SUCCESS => {
+                        let cc = result.get_u32();
+                        if cc == self.client_channel {
+                            // 这些输出和同一次读取中回应之后的消息 (例如命令的输出) 留给之后的读取
+                            pending.extend(results);
+                            self.client()?.unread_channel(self.client_channel, pending);
+                            return Ok(())
+                        }
+                    }
+                    ssh_msg_code::SSH_MSG_CHANNEL_FAILURE => {
+                        let cc = result.get_u32();
+                        if cc == self.client_channel {
+                            pending.extend(results);
+                            self.client()?.unread_channel(self.client_channel, pending);
+                            log::error!("channel request [{}] failed.", request);
+                            return Err(SshError::from(SshErrorKind::ChannelRequestFailed {
+                                request: request.to_string()
+                            }))
+                        }
+                    }
+                    _ => self.other(message_code, result)?
+                }
+            }
+        }
+    }
+
+    pub(crate) fn tick_stats(&mut self, queue_depth: usize) {
+        if let Some(stats) = &mut self.stats {
+            stats.tick(&self.window_size, queue_depth)
+        }
+    }
+
+    /// 先读取还在传输中的数据，再发送 CHANNEL_CLOSE 并等待服务端的 CHANNEL_CLOSE，
+    /// 总共最多等待 timeout，期间收到的数据保存在 drained 中
+    /// 出错时仍然发送最后一次统计
+    pub(crate) fn shutdown(&mut self, timeout: Duration) -> SshResult<()> {
+        let deadline = Instant::now() + timeout;
+        let result = self.drain(deadline)
+            .and_then(|_| self.send_close())
+            .and_then(|_| self.receive_close(deadline));
+        if let Err(e) = result {
+            self.report_last_stats();
+            return Err(e)
+        }
+        self.finish()
+    }
+
+    // 读取到服务端的 EOF 为止，命令结束时输出的最后一部分不会因为先发送了 CHANNEL_CLOSE 而丢失。
+    // 服务端一直不发送 EOF 时 (例如还在运行的 shell)，连续 DRAIN_IDLE_TIMEOUT 没有这个通道的数据
+    // 就认为没有传输中的数据了
+    fn drain(&mut self, deadline: Instant) -> SshResult<()> {
+        let mut last_data = Instant::now();
+        while !self.eof_received && !self.remote_close && !self.local_close {
+            let now = Instant::now();
+            if now >= deadline || now - last_data >= limits::DRAIN_IDLE_TIMEOUT {
+                return Ok(())
+            }
+            let read_deadline = deadline.min(last_data + limits::DRAIN_IDLE_TIMEOUT);
+            let results = client::locking(&self.client)?
+                .read_data_before(&mut self.window_size, read_deadline)?;
+            for mut result in results {
+                if result.is_empty() { continue }
+                let message_code = result.get_u8();
+                if self.drain_message(message_code, &mut result) {
+                    last_data = Instant::now();
+                    continue
+                }
+                self.other(message_code, result)?
+            }
+        }
+        Ok(())
+    }
+
+    /// 关闭前后收到的数据保存到 drained，不是数据消息时返回 false
+    fn drain_message(&mut self, message_code: u8, result: &mut Data) -> bool {
+        let is_data = matches!(message_code,
+            ssh_msg_code::SSH_MSG_CHANNEL_DATA | ssh_msg_code::SSH_MSG_CHANNEL_EXTENDED_DATA);
+        if !is_data {
+            return false
+        }
+        if result.get_u32() != self.client_channel {
+            return true
+        }
+        match message_code {
+            ssh_msg_code::SSH_MSG_CHANNEL_DATA => self.drained.append(&mut result.get_u8s()),
+            _ => {
+                if result.get_u32() == ssh_msg_code::SSH_EXTENDED_DATA_STDERR {
+                    self.drained_stderr.append(&mut result.get_u8s())
+                }
+            }
+        }
+        true
+    }
+
+    pub(crate) fn close_summary(&self) -> CloseSummary {
+        CloseSummary {
+            eof_received: self.eof_received,
+            exit_status: self.exit_status,
+            exit_signal: self.exit_signal.clone(),
+            bytes_in: self.window_size.bytes_in,
+            bytes_out: self.window_size.bytes_out,
+            initiator: self.close_initiator.unwrap_or(CloseInitiator::Local)
+        }
+    }
+
+    // 通道关闭后不再接收这个通道的消息
+    fn finish(&mut self) -> SshResult<()> {
+        let result = self.client().map(|mut v| v.remove_channel(self.client_channel));
+        self.report_last_stats();
+        result
+    }
+
+    /// 通道关闭或者被释放时发送最后一次统计 (last 为 true)，之后不再统计
+    fn report_last_stats(&mut self) {
+        if let Some(mut stats) = self.stats.take() {
+            stats.report(&self.window_size, 0, true)
+        }
     }
 
     fn send_close(&mut self) -> SshResult<()> {
@@ -143,25 +717,36 @@ impl Channel {
         let mut data = Data::new();
         data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE)
             .put_u32(self.server_channel);
-        let client = client::default()?;
+        let mut client = client::locking(&self.client)?;
         client.write(data)?;
+        client.local_closed(self.client_channel);
+        if self.close_initiator.is_none() {
+            self.close_initiator = Some(CloseInitiator::Local)
+        }
         self.local_close = true;
         Ok(())
     }
 
-    fn receive_close(&mut self) -> SshResult<()> {
+    // 最多等待到 deadline, 服务端一直不回复 CHANNEL_CLOSE 时放弃等待，
+    // 避免其它通道的数据不断刷新读取超时导致一直阻塞
+    fn receive_close(&mut self, deadline: Instant) -> SshResult<()> {
         if self.remote_close { return Ok(()); }
         loop {
-            let client = client::default()?;
-            let results = client.read()?; // close 时不消耗窗口空间
+            if Instant::now() >= deadline {
+                log::error!("wait channel close time out, give up waiting.");
+                return Ok(())
+            }
+            // close 时不消耗窗口空间，没有数据时读取中已经等待过 poll_interval
+            let results = self.client()?.read_channel(self.client_channel)?;
             for mut result in results {
                 if result.is_empty() { continue }
                 let message_code = result.get_u8();
+                if self.drain_message(message_code, &mut result) { continue }
                 match message_code {
                     ssh_msg_code::SSH_MSG_CHANNEL_CLOSE => {
                         let cc = result.get_u32();
                         if cc == self.client_channel {
-                            self.remote_close = true;
+                            self.remote_closed();
                             return Ok(())
                         }
                     }
@@ -171,3 +756,197 @@ impl Channel {
         }
     }
 }
+
+/// 没有调用 `close` 就释放通道时 (例如 `?` 提前返回)，尽量发送 CHANNEL_CLOSE，
+/// 不等待服务端的回应，出错时只记录日志。
+/// 服务端还没有确认打开的通道没有远程编号，只停止接收这个通道的消息。
+/// 开启了统计时先发送最后一次统计
+impl<S> Drop for Channel<S> {
+    fn drop(&mut self) {
+        if self.detached { return }
+        self.report_last_stats();
+        let mut client = match self.client.lock() {
+            Ok(v) => v,
+            Err(_) => return
+        };
+        client.remove_channel(self.client_channel);
+        if !self.confirmed || self.local_close || client.closed || client.disconnected.is_some() {
+            return
+        }
+        log::info!("channel dropped without close, send channel close.");
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE)
+            .put_u32(self.server_channel);
+        if let Err(e) = client.write(data) {
+            log::error!("channel close error: {}", e);
+        }
+    }
+}
+
+impl Channel {
+    pub fn open_shell(self) -> SshResult<ChannelShell> {
+        self.open_shell_with_pty(&PtyOptions::new())
+    }
+
+    /// 使用指定的伪终端参数打开 shell
+    pub fn open_shell_with_pty(self, options: &PtyOptions) -> SshResult<ChannelShell> {
+        log::info!("shell opened.");
+        ChannelShell::open(self, options)
+    }
+
+    pub fn open_exec(self) -> SshResult<ChannelExec> {
+        log::info!("exec opened.");
+        return Ok(ChannelExec::open(self))
+    }
+
+    /// 打开 exec 通道并请求伪终端，等同于 `open_exec` 之后调用 `ChannelExec::request_pty_with`
+    pub fn open_exec_with_pty(self, options: &PtyOptions) -> SshResult<ChannelExec> {
+        let mut exec = self.open_exec()?;
+        exec.request_pty_with(options)?;
+        Ok(exec)
+    }
+
+    pub fn open_scp(self) -> SshResult<ChannelScp> {
+        log::info!("scp opened.");
+        return Ok(ChannelScp::open(self))
+    }
+
+    pub fn open_subsystem(self, name: &str) -> SshResult<ChannelSubsystem> {
+        log::info!("subsystem [{}] opened.", name);
+        ChannelSubsystem::open(self, name)
+    }
+
+    /// 设置通道统计回调
+    /// 读取数据时按 interval 间隔回调一次，通道关闭时会再回调一次 last 为 true 的快照
+    pub fn set_stats_interval<F>(&mut self, interval: Duration, callback: F)
+    where
+        F: FnMut(ChannelStatsSnapshot) + Send + 'static
+    {
+        self.stats = Some(ChannelStats::new(interval, callback))
+    }
+
+    /// 关闭通道，最多等待 1.5 秒，见 `close_with_timeout`
+    pub fn close(&mut self) -> SshResult<()> {
+        self.close_with_timeout(limits::CLOSE_TIMEOUT)
+    }
+
+    /// 关闭通道，总共最多等待 timeout
+    /// 先读取到服务端的 EOF 为止 (一段时间内没有数据时不再等待)，再发送 CHANNEL_CLOSE 并等待服务端的 CHANNEL_CLOSE。
+    /// 期间收到的数据不会丢弃，由 `ChannelShell::close` 等返回，`ChannelExec` 之后仍然可以通过 `read` 取得
+    /// 已经关闭过时什么都不做
+    pub fn close_with_timeout(&mut self, timeout: Duration) -> SshResult<()> {
+        if self.local_close {
+            return Ok(())
+        }
+        log::info!("channel close.");
+        self.shutdown(timeout)
+    }
+
+    /// 发送 CHANNEL_EOF，关闭写的一端
+    /// 服务端的数据仍然可以继续读取，直到服务端关闭通道
+    pub fn send_eof(&mut self) -> SshResult<()> {
+        self.eof()
+    }
+
+    /// 设置远程进程的环境变量，需要在 `open_exec` 的 `exec` 或者 `open_shell` 之前调用
+    /// sshd 只接受 AcceptEnv 中允许的变量名，其它的会被服务端静默忽略，这里不会返回错误
+    pub fn set_env(&mut self, name: &str, value: &str) -> SshResult<()> {
+        self.send_env(name, value)
+    }
+
+    /// 请求转发本地的 ssh-agent，需要在 `exec` 或者 `open_shell` 之前调用
+    /// 没有设置 SSH_AUTH_SOCK 时不发送请求，只记录错误日志。
+    /// 服务端没有开启 AllowAgentForwarding 时会静默忽略，远程的程序看不到 agent
+    pub fn request_agent_forwarding(&mut self) -> SshResult<()> {
+        if std::env::var_os(agent_forward::SSH_AUTH_SOCK).is_none() {
+            log::error!("SSH_AUTH_SOCK is not set, skip agent forwarding.");
+            return Ok(())
+        }
+        self.send_agent_request()
+    }
+
+    /// 请求 X11 转发，需要在 `exec` 或者 `open_shell` 之前调用
+    /// single_connection 为 true 时只转发一个 X11 连接。
+    /// 没有设置 DISPLAY 时不发送请求，只记录错误日志。
+    /// 服务端没有开启 X11Forwarding 时会静默忽略，远程的程序看不到 DISPLAY
+    pub fn request_x11_forwarding(&mut self, single_connection: bool) -> SshResult<()> {
+        self.client()?.send_x11_request(self.server_channel, single_connection)
+    }
+
+    /// 等待通道完全关闭
+    /// 会一直读取到服务端的 CHANNEL_CLOSE 为止，期间收到的数据保存在 drained 中，
+    /// 然后回复 CHANNEL_CLOSE 并返回退出状态等信息
+    pub fn wait_closed(&mut self, timeout: Duration) -> SshResult<CloseSummary> {
+        let deadline = Instant::now() + timeout;
+        while !self.remote_close {
+            if Instant::now() > deadline {
+                log::error!("wait channel close time out.");
+                return Err(SshError::from(SshErrorKind::Timeout))
+            }
+            let results = client::locking(&self.client)?
+                .read_data(&mut self.window_size)?;
+            for mut result in results {
+                if result.is_empty() { continue }
+                let message_code = result.get_u8();
+                if self.drain_message(message_code, &mut result) { continue }
+                match message_code {
+                    ssh_msg_code::SSH_MSG_CHANNEL_CLOSE => {
+                        let cc = result.get_u32();
+                        if cc == self.client_channel {
+                            self.remote_closed();
+                        }
+                    }
+                    _ => self.other(message_code, result)?
+                }
+            }
+        }
+        self.send_close()?;
+        self.finish()?;
+        Ok(self.close_summary())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::{limits, mock};
+    use crate::channel_stats::ChannelStatsSnapshot;
+    use crate::window_size::WindowSize;
+    use super::Channel;
+
+    fn channel() -> (Channel, Arc<Mutex<Vec<ChannelStatsSnapshot>>>) {
+        let (client, _server) = mock::connection();
+        let mut window_size = WindowSize::with_local_window_size(limits::LOCAL_WINDOW_SIZE);
+        window_size.client_channel = 1;
+        window_size.server_channel = 2;
+        let mut channel = Channel::new(Arc::new(Mutex::new(client)), window_size);
+        channel.confirmed = true;
+        let snapshots = Arc::new(Mutex::new(vec![]));
+        let received = snapshots.clone();
+        channel.set_stats_interval(Duration::from_secs(60), move |v| received.lock().unwrap().push(v));
+        (channel, snapshots)
+    }
+
+    #[test]
+    fn drop_reports_last_stats() {
+        let (channel, snapshots) = channel();
+        drop(channel);
+        let snapshots = snapshots.lock().unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert!(snapshots[0].last);
+    }
+
+    #[test]
+    fn failed_shutdown_reports_last_stats() {
+        let (mut channel, snapshots) = channel();
+        channel.client().unwrap().disconnected = Some((11, "bye".to_string()));
+        assert!(channel.shutdown(Duration::from_millis(100)).is_err());
+        assert_eq!(snapshots.lock().unwrap().len(), 1);
+        assert!(snapshots.lock().unwrap()[0].last);
+        // 已经发送过最后一次统计，释放时不再发送
+        drop(channel);
+        assert_eq!(snapshots.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,126 @@
+use std::borrow::BorrowMut;
+use std::io;
+use std::time::Duration;
+use crate::constant::ssh_msg_code;
+use crate::error::{self, SshResult};
+use crate::data::Data;
+use crate::channel::Channel;
+use crate::{client, limits};
+
+
+/// direct-tcpip 通道，由服务端连接到目标地址后转发数据
+/// 实现了 `Read` 和 `Write`，没有数据可读时返回 `WouldBlock`
+pub struct ChannelDirectTcpip {
+    pub(crate) channel: Channel,
+    /// 上一次读取中没有被取走的数据
+    buf: Vec<u8>
+}
+
+impl ChannelDirectTcpip {
+
+    pub(crate) fn open(channel: Channel) -> Self {
+        ChannelDirectTcpip {
+            channel,
+            buf: vec![]
+        }
+    }
+
+    /// 客户端通道编号，与 `Event` 中的 id 对应
+    pub fn id(&self) -> u32 {
+        self.channel.id()
+    }
+
+    /// 通道是否已经关闭，见 `Channel::is_closed`
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+
+    /// 通道关闭之后不再读取连接，只返回缓存的数据
+    pub fn read(&mut self) -> SshResult<Vec<u8>> {
+        let mut buf = std::mem::take(&mut self.buf);
+        if self.channel.is_finished() {
+            return Ok(buf)
+        }
+        let results = client::locking(&self.channel.client)?
+            .read_data(self.channel.window_size.borrow_mut())?;
+        let queue_depth = results.len();
+        for mut result in results {
+            if result.is_empty() { continue }
+            let message_code = result.get_u8();
+            match message_code {
+                ssh_msg_code::SSH_MSG_CHANNEL_DATA => {
+                    let cc = result.get_u32();
+                    if cc == self.channel.client_channel {
+                        buf.append(&mut result.get_u8s());
+                    }
+                }
+                _ => self.channel.other(message_code, result)?
+            }
+        }
+        self.channel.tick_stats(queue_depth);
+        Ok(buf)
+    }
+
+    /// 按服务端的最大数据包大小拆分为多个 SSH_MSG_CHANNEL_DATA，远程窗口用完时等待服务端调整窗口
+    /// 通道关闭之后返回 `SshErrorKind::ChannelClosed`
+    pub fn write(&mut self, buf: &[u8]) -> SshResult<()> {
+        self.channel.check_writable()?;
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
+            .put_u32(self.channel.server_channel)
+            .put_u8s(buf);
+        client::locking(&self.channel.client)?
+            .write_data(data, Some(self.channel.window_size.borrow_mut()))?;
+        self.channel.tick_stats(0);
+        Ok(())
+    }
+
+    /// 关闭通道，返回还没有读取的输出 (包括关闭时读到的)，见 `Channel::close_with_timeout`
+    pub fn close(self) -> SshResult<Vec<u8>> {
+        self.close_with_timeout(limits::CLOSE_TIMEOUT)
+    }
+
+    /// 与 `close` 相同，总共最多等待 timeout
+    pub fn close_with_timeout(mut self, timeout: Duration) -> SshResult<Vec<u8>> {
+        self.channel.close_with_timeout(timeout)?;
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.append(&mut self.channel.drained);
+        Ok(buf)
+    }
+
+    fn is_eof(&self) -> bool {
+        self.channel.eof_received || self.channel.remote_close
+    }
+}
+
+impl io::Read for ChannelDirectTcpip {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            if self.is_eof() {
+                return Ok(0)
+            }
+            self.buf = ChannelDirectTcpip::read(self).map_err(error::to_io_error)?;
+            if self.buf.is_empty() {
+                if self.is_eof() {
+                    return Ok(0)
+                }
+                return Err(io::Error::from(io::ErrorKind::WouldBlock))
+            }
+        }
+        let len = buf.len().min(self.buf.len());
+        buf[..len].copy_from_slice(&self.buf[..len]);
+        self.buf.drain(..len);
+        Ok(len)
+    }
+}
+
+impl io::Write for ChannelDirectTcpip {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        ChannelDirectTcpip::write(self, buf).map_err(error::to_io_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,69 +1,570 @@
 use std::borrow::BorrowMut;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
 use crate::constant::{ssh_msg_code, ssh_str};
-use crate::error::SshResult;
+use crate::error::{self, SshError, SshErrorKind, SshResult};
 use crate::data::Data;
-use crate::channel::Channel;
-use crate::client;
+use crate::channel::{Channel, CloseSummary, ExitSignal, PtyOptions, Signal};
+use crate::{client, limits};
 
 
+/// 远程命令的退出状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitStatus {
+    code: Option<u32>,
+    signal: Option<ExitSignal>
+}
+
+impl ExitStatus {
+    pub(crate) fn from_summary(summary: &CloseSummary) -> Self {
+        ExitStatus {
+            code: summary.exit_status,
+            signal: summary.exit_signal.clone()
+        }
+    }
+
+    /// 退出码为 0 时为 true
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
 
-pub struct ChannelExec(pub(crate) Channel);
+    /// 服务端没有发送 exit-status 时为 None (例如进程被信号终止)
+    pub fn code(&self) -> Option<u32> {
+        self.code
+    }
+
+    pub fn signal(&self) -> Option<&ExitSignal> {
+        self.signal.as_ref()
+    }
+
+    /// 与 std 的 `ExitStatus::exit_ok` 一致，退出码不为 0 时返回错误
+    pub fn exit_ok(&self) -> Result<(), ExitStatusError> {
+        if self.success() {
+            return Ok(())
+        }
+        Err(ExitStatusError(self.clone()))
+    }
+}
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (&self.code, &self.signal) {
+            (Some(code), _) => write!(f, "exit status: {}", code),
+            (None, Some(signal)) => write!(f, "signal: {}", signal.signal),
+            (None, None) => write!(f, "exit status: unknown")
+        }
+    }
+}
+
+
+/// 退出码不为 0 的退出状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitStatusError(ExitStatus);
+
+impl ExitStatusError {
+    pub fn code(&self) -> Option<u32> {
+        self.0.code()
+    }
+
+    pub fn into_status(self) -> ExitStatus {
+        self.0
+    }
+}
+
+impl Display for ExitStatusError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "process exited unsuccessfully: {}", self.0)
+    }
+}
+
+impl Error for ExitStatusError {
+}
+
+impl From<ExitStatusError> for SshError {
+    fn from(e: ExitStatusError) -> Self {
+        SshError::from(SshErrorKind::CommandFailed {
+            status: e.0,
+            stderr_tail: String::new()
+        })
+    }
+}
+
+
+/// 远程命令的输出
+#[derive(Debug, Clone)]
+pub struct ExecOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub status: ExitStatus
+}
+
+impl ExecOutput {
+    pub fn exit_ok(&self) -> Result<(), ExitStatusError> {
+        self.status.exit_ok()
+    }
+}
+
+
+/// `Session::exec` 的结果，退出码不为 0 时也是 Ok
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecResult {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// 服务端发送的退出码，与 `ChannelExec::exit_status` 相同，进程被信号终止时为 None
+    pub exit_status: Option<u32>,
+    /// 进程被信号终止时的信号信息，与 `ChannelExec::exit_signal` 相同
+    pub exit_signal: Option<ExitSignal>
+}
+
+impl ExecResult {
+    /// 退出码为 0 时为 true
+    pub fn success(&self) -> bool {
+        self.exit_status == Some(0)
+    }
+}
+
+impl From<ExecOutput> for ExecResult {
+    fn from(output: ExecOutput) -> Self {
+        ExecResult {
+            stdout: output.stdout,
+            stderr: output.stderr,
+            exit_status: output.status.code,
+            exit_signal: output.status.signal
+        }
+    }
+}
+
+
+/// 执行命令的选项
+#[derive(Debug, Clone)]
+pub struct ExecOptions {
+    check: bool,
+    stderr_tail: usize
+}
+
+impl ExecOptions {
+    pub fn new() -> Self {
+        ExecOptions {
+            check: false,
+            stderr_tail: limits::STDERR_TAIL_LEN
+        }
+    }
+
+    /// 为 true 时退出码不为 0 返回 `SshErrorKind::CommandFailed`，默认不检查
+    pub fn check(mut self, check: bool) -> Self {
+        self.check = check;
+        self
+    }
+
+    /// CommandFailed 中携带的 stderr 末尾字节数，默认 1024
+    pub fn stderr_tail(mut self, len: usize) -> Self {
+        self.stderr_tail = len;
+        self
+    }
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        ExecOptions::new()
+    }
+}
+
+
+/// 执行命令的通道
+/// stdout 和 stderr 分别缓存，`read` 和 `read_stderr` 各自取出对应的数据
+pub struct ChannelExec {
+    pub(crate) channel: Channel,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>
+}
 
 impl ChannelExec {
 
 
     pub(crate) fn open(channel: Channel) -> Self {
-        ChannelExec(channel)
+        ChannelExec {
+            channel,
+            stdout: vec![],
+            stderr: vec![]
+        }
+    }
+
+    /// 客户端通道编号，与 `Event` 中的 id 对应
+    pub fn id(&self) -> u32 {
+        self.channel.id()
+    }
+
+    /// 通道是否已经关闭，见 `Channel::is_closed`
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+
+    /// 在 `exec` 之前设置远程命令的环境变量，见 `Channel::set_env`
+    pub fn set_env(&mut self, name: &str, value: &str) -> SshResult<()> {
+        self.channel.send_env(name, value)
+    }
+
+    /// 在 `exec` 之前请求一个伪终端 (pty-req)
+    /// 默认不请求，与 OpenSSH 的 `ssh host cmd` 一致。有伪终端时命令会认为自己在终端中运行，
+    /// 输出可能带有颜色和分页，stderr 也会合并到 stdout 中。服务端拒绝时返回错误
+    pub fn request_pty(&mut self) -> SshResult<()> {
+        self.request_pty_with(&PtyOptions::new())
+    }
+
+    /// 使用指定的终端类型、大小和模式请求伪终端，见 `PtyOptions`
+    pub fn request_pty_with(&mut self, options: &PtyOptions) -> SshResult<()> {
+        self.channel.send_pty_request(true, options)?;
+        self.channel.wait_reply(ssh_str::PTY_REQ)
     }
 
-    fn exec_command(&self, command: &str) -> SshResult<()> {
+    /// 发送要执行的命令，之后通过 `read` 和 `read_stderr` 读取输出
+    pub fn exec(&mut self, command: &str) -> SshResult<()> {
         let mut data = Data::new();
         data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
-            .put_u32(self.0.server_channel)
+            .put_u32(self.channel.server_channel)
             .put_str(ssh_str::EXEC)
             .put_u8(true as u8)
             .put_str(command);
-        let client = client::default()?;
-        client.write(data)
+        self.channel.client()?.write(data)
     }
 
-    fn get_data(&mut self, v: &mut Vec<u8>) -> SshResult<()> {
-        let client = client::default()?;
-        let results = client.read_data(Some(self.0.window_size.borrow_mut()))?;
+    /// 向远程进程发送信号，例如用 `Signal::Int` 中断正在运行的命令，连接和其它通道不受影响
+    /// 服务端不回应，进程退出后可以通过 `exit_signal` 或 `exit_status` 得知退出原因。
+    /// 服务端不支持时 (例如 7.9 之前的 OpenSSH) 信号会被忽略
+    pub fn signal(&mut self, signal: Signal) -> SshResult<()> {
+        self.channel.send_signal(&signal)
+    }
+
+    /// 写入远程命令的 stdin，在 `exec` 之后调用，例如 `cat > file`、`sort`
+    /// 按服务端的最大数据包大小拆分为多个 SSH_MSG_CHANNEL_DATA，远程窗口不够时等待服务端调整窗口。
+    /// `send_eof` 之后不能再写，通道关闭之后返回 `SshErrorKind::ChannelClosed`
+    pub fn write(&mut self, buf: &[u8]) -> SshResult<()> {
+        self.channel.check_writable()?;
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
+            .put_u32(self.channel.server_channel)
+            .put_u8s(buf);
+        client::locking(&self.channel.client)?
+            .write_data(data, Some(self.channel.window_size.borrow_mut()))?;
+        self.channel.tick_stats(0);
+        Ok(())
+    }
+
+    /// 发送 CHANNEL_EOF，远程命令的 stdin 结束，例如 `cat`、`wc -c` 等读到 EOF 才输出结果的命令
+    /// 只发送一次，命令的输出仍然可以继续读取，直到服务端关闭通道，见 `Channel::send_eof`
+    pub fn send_eof(&mut self) -> SshResult<()> {
+        self.channel.eof()
+    }
+
+    /// 读取 stdout，没有数据时返回空的 Vec
+    /// 同时收到的 stderr 会被缓存，由 `read_stderr` 返回
+    pub fn read(&mut self) -> SshResult<Vec<u8>> {
+        if self.stdout.is_empty() && !self.is_remote_eof() {
+            self.get_data()?;
+        }
+        Ok(std::mem::take(&mut self.stdout))
+    }
+
+    /// 读取 stderr (SSH_EXTENDED_DATA_STDERR)，没有数据时返回空的 Vec
+    /// 同时收到的 stdout 会被缓存，由 `read` 返回
+    pub fn read_stderr(&mut self) -> SshResult<Vec<u8>> {
+        if self.stderr.is_empty() && !self.is_remote_eof() {
+            self.get_data()?;
+        }
+        Ok(std::mem::take(&mut self.stderr))
+    }
+
+    /// 服务端不会再发送数据，并且缓存的 stdout 和 stderr 都已经读取
+    pub fn is_eof(&self) -> bool {
+        self.is_remote_eof() && self.stdout.is_empty() && self.stderr.is_empty()
+    }
+
+    /// 等待服务端关闭通道，返回命令的退出状态
+    /// 等待期间收到的输出不会丢弃，之后仍然可以通过 `read` 和 `read_stderr` 取得。
+    /// 退出码不为 0 时不会返回错误，关闭之后再调用返回同样的结果
+    pub fn close(&mut self) -> SshResult<ExitStatus> {
+        let timeout = self.channel.client()?.timeout.duration();
+        let summary = self.channel.wait_closed(timeout)?;
+        self.stdout.append(&mut self.channel.drained);
+        self.stderr.append(&mut self.channel.drained_stderr);
+        Ok(ExitStatus::from_summary(&summary))
+    }
+
+    /// 服务端发送的退出码 (exit-status)
+    /// 服务端在关闭通道之前发送，`close` 之后一定已经收到，
+    /// 进程被信号终止或者还没有收到时为 None
+    pub fn exit_status(&self) -> Option<u32> {
+        self.channel.exit_status
+    }
+
+    /// 进程被信号终止时服务端发送的信号信息 (exit-signal)，例如被 kill -9 时为 KILL
+    /// 这时服务端一般不会发送 exit-status，`exit_status` 为 None
+    pub fn exit_signal(&self) -> Option<&ExitSignal> {
+        self.channel.exit_signal.as_ref()
+    }
+
+    fn is_remote_eof(&self) -> bool {
+        self.channel.eof_received || self.channel.remote_close
+    }
+
+    fn is_nonblocking(&self) -> io::Result<bool> {
+        let client = self.channel.client().map_err(error::to_io_error)?;
+        Ok(client.nonblocking)
+    }
+
+    fn get_data(&mut self) -> SshResult<()> {
+        if self.channel.is_finished() {
+            return Ok(())
+        }
+        let results = client::locking(&self.channel.client)?
+            .read_data(self.channel.window_size.borrow_mut())?;
+        let queue_depth = results.len();
         for mut result in results {
             if result.is_empty() { continue }
             let message_code = result.get_u8();
             match message_code {
                 ssh_msg_code::SSH_MSG_CHANNEL_DATA => {
                     let cc = result.get_u32();
-                    if cc == self.0.client_channel {
-                        v.append(&mut result.get_u8s());
+                    if cc == self.channel.client_channel {
+                        self.stdout.append(&mut result.get_u8s());
+                    }
+                }
+                ssh_msg_code::SSH_MSG_CHANNEL_EXTENDED_DATA => {
+                    let cc = result.get_u32();
+                    let data_type = result.get_u32();
+                    if cc == self.channel.client_channel && data_type == ssh_msg_code::SSH_EXTENDED_DATA_STDERR {
+                        self.stderr.append(&mut result.get_u8s());
                     }
                 }
                 ssh_msg_code::SSH_MSG_CHANNEL_CLOSE => {
                     let cc = result.get_u32();
-                    if cc == self.0.client_channel {
-                        self.0.remote_close = true;
-                        self.0.close()?;
+                    if cc == self.channel.client_channel {
+                        self.channel.remote_closed();
                     }
                 }
-                _ => self.0.other(message_code, result)?
+                _ => self.channel.other(message_code, result)?
             }
         }
+        self.channel.tick_stats(queue_depth);
         Ok(())
     }
 
-    pub fn send_command(mut self, command: &str) -> SshResult<Vec<u8>> {
-        self.exec_command(command)?;
-        let mut r = vec![];
+    pub fn send_command(self, command: &str) -> SshResult<Vec<u8>> {
+        Ok(self.output(command, &ExecOptions::new())?.stdout)
+    }
+
+    /// 执行命令并返回 stdout, stderr 和退出状态
+    /// options 开启 check 时，退出码不为 0 返回 `SshErrorKind::CommandFailed`
+    pub fn output(mut self, command: &str, options: &ExecOptions) -> SshResult<ExecOutput> {
+        self.exec(command)?;
+        while !self.is_remote_eof() {
+            self.get_data()?;
+        }
+        let stdout = std::mem::take(&mut self.stdout);
+        let stderr = std::mem::take(&mut self.stderr);
+        let timeout = self.channel.client()?.timeout.duration();
+        let summary = self.channel.wait_closed(timeout)?;
+        let output = ExecOutput {
+            stdout,
+            stderr,
+            status: ExitStatus::from_summary(&summary)
+        };
+        if options.check && !output.status.success() {
+            let start = output.stderr.len().saturating_sub(options.stderr_tail);
+            return Err(SshError::from(SshErrorKind::CommandFailed {
+                status: output.status,
+                stderr_tail: String::from_utf8_lossy(&output.stderr[start..]).to_string()
+            }))
+        }
+        Ok(output)
+    }
+
+    /// 执行命令，通过返回的 `ExecStream` 逐块读取 stdout，不把全部输出保存在内存中
+    /// 适合输出很大的命令，例如 `cat bigfile`、`journalctl`
+    pub fn exec_streaming(mut self, command: &str) -> SshResult<ExecStream> {
+        self.exec(command)?;
+        Ok(ExecStream {
+            exec: self,
+            status: None,
+            done: false
+        })
+    }
+}
+
+
+/// 命令输出的流，见 `ChannelExec::exec_streaming`
+/// 每次迭代返回收到的一块 stdout，只在取走数据后才读取连接，
+/// 所以本地窗口按调用方消耗的速度调整，调用方处理得慢时服务端会等待。
+/// 服务端发送 EOF 后等待通道关闭、记录退出状态，之后迭代结束，出错后迭代也会结束
+pub struct ExecStream {
+    exec: ChannelExec,
+    status: Option<ExitStatus>,
+    done: bool
+}
+
+impl ExecStream {
+    /// 客户端通道编号，与 `Event` 中的 id 对应
+    pub fn id(&self) -> u32 {
+        self.exec.id()
+    }
+
+    /// 目前为止收到的 stderr，取出后清空，不读取连接
+    /// stderr 在读取 stdout 的同时被缓存，输出很多 stderr 的命令需要及时取走
+    pub fn take_stderr(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.exec.stderr)
+    }
+
+    /// 命令的退出状态，迭代结束之前为 None
+    pub fn exit_status(&self) -> Option<&ExitStatus> {
+        self.status.as_ref()
+    }
+
+    /// 向远程进程发送信号，见 `ChannelExec::signal`
+    pub fn signal(&mut self, signal: Signal) -> SshResult<()> {
+        self.exec.signal(signal)
+    }
+
+    fn next_chunk(&mut self) -> SshResult<Option<Vec<u8>>> {
         loop {
-            self.get_data(&mut r)?;
-            if self.0.remote_close
-                && self.0.local_close
-            {
-                break
+            if !self.exec.stdout.is_empty() {
+                return Ok(Some(std::mem::take(&mut self.exec.stdout)))
+            }
+            if self.exec.is_remote_eof() {
+                self.status = Some(self.exec.close()?);
+                return Ok(None)
+            }
+            self.exec.get_data()?;
+        }
+    }
+}
+
+impl Iterator for ExecStream {
+    type Item = SshResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        match self.next_chunk() {
+            Ok(Some(v)) => Some(Ok(v)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// 读取的是 stdout，同时收到的 stderr 仍然缓存，由 `read_stderr` 返回
+/// 与 `ChannelShell` 相同，服务端发送 EOF 后返回 0，非阻塞模式下没有数据可读时返回 `WouldBlock`
+impl io::Read for ChannelExec {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.stdout.is_empty() {
+            if self.is_remote_eof() {
+                return Ok(0)
+            }
+            self.get_data().map_err(error::to_io_error)?;
+            if self.stdout.is_empty() && !self.is_remote_eof() && self.is_nonblocking()? {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock))
             }
         }
-        Ok(r)
+        let len = buf.len().min(self.stdout.len());
+        buf[..len].copy_from_slice(&self.stdout[..len]);
+        self.stdout.drain(..len);
+        Ok(len)
+    }
+}
+
+/// 写入的是远程命令的 stdin，例如用 `io::copy` 把本地文件传给远程命令，写完后调用 `send_eof`
+impl io::Write for ChannelExec {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        ChannelExec::write(self, buf).map_err(error::to_io_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use crate::channel::Channel;
+    use crate::constant::ssh_msg_code;
+    use crate::data::Data;
+    use crate::error::SshErrorKind;
+    use crate::mock::{self, MockServer};
+    use crate::window_size::WindowSize;
+    use crate::limits;
+    use super::ChannelExec;
+
+    /// 远程窗口足够大，最大数据包大小为 1000 字节的数据加上 9 字节的消息头
+    fn exec() -> (ChannelExec, MockServer) {
+        let (mut client, server) = mock::client();
+        client.register_channel(1);
+        let mut window_size = WindowSize::with_local_window_size(limits::LOCAL_WINDOW_SIZE);
+        window_size.client_channel = 1;
+        window_size.server_channel = 7;
+        window_size.add_remote_window_size(1 << 20);
+        window_size.set_remote_max_packet_size(1009);
+        let mut channel = Channel::new(Arc::new(Mutex::new(client)), window_size);
+        channel.confirmed = true;
+        (ChannelExec::open(channel), server)
+    }
+
+    /// client 发送的 CHANNEL_DATA 中的数据，其它消息只保留消息编号
+    fn sent(server: &MockServer) -> Vec<Result<Vec<u8>, u8>> {
+        server.received().into_iter().map(|mut v| {
+            let message_code = v.get_u8();
+            if message_code != ssh_msg_code::SSH_MSG_CHANNEL_DATA {
+                return Err(message_code)
+            }
+            assert_eq!(v.get_u32(), 7);
+            Ok(v.get_u8s())
+        }).collect()
+    }
+
+    #[test]
+    fn stdin_is_split_by_max_packet_size() {
+        let (mut exec, server) = exec();
+        let stdin = (0..2500).map(|v| v as u8).collect::<Vec<u8>>();
+        exec.write_all(&stdin).unwrap();
+        exec.send_eof().unwrap();
+        let sent = sent(&server);
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[0].as_ref().unwrap(), &stdin[..1000]);
+        assert_eq!(sent[1].as_ref().unwrap(), &stdin[1000..2000]);
+        assert_eq!(sent[2].as_ref().unwrap(), &stdin[2000..]);
+        assert_eq!(sent[3], Err(ssh_msg_code::SSH_MSG_CHANNEL_EOF));
+        assert_eq!(exec.channel.window_size.bytes_out, 2500);
+    }
+
+    #[test]
+    fn write_after_eof_is_an_error() {
+        let (mut exec, server) = exec();
+        exec.send_eof().unwrap();
+        assert!(exec.write(b"late").is_err());
+        assert_eq!(sent(&server), [Err(ssh_msg_code::SSH_MSG_CHANNEL_EOF)]);
+    }
+
+    #[test]
+    fn write_after_remote_close_is_channel_closed() {
+        let (mut exec, server) = exec();
+        let mut close = Data::new();
+        close.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE).put_u32(1);
+        server.send(close);
+        // 读取时处理 CHANNEL_CLOSE
+        exec.channel.client().unwrap().read().unwrap();
+        let e = exec.write(b"data").unwrap_err();
+        assert_eq!(*e.kind(), SshErrorKind::ChannelClosed);
+        assert!(sent(&server).is_empty());
     }
 }
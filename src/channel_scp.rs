@@ -2,7 +2,8 @@ use std::borrow::BorrowMut;
 use std::path::{Path, PathBuf};
 use crate::constant::{scp, ssh_msg_code, ssh_str};
 use crate::data::Data;
-use crate::error::{SshResult, SshError};
+use crate::error::{SshResult, SshError, SshErrorKind};
+use crate::transfer::TransferOptions;
 use crate::{Channel, client};
 
 
@@ -23,6 +24,24 @@ impl ChannelScp {
     }
 
 
+    /// 设置传输选项，需要在 upload / download 之前调用
+    pub fn set_transfer_options(&mut self, options: TransferOptions) {
+        self.channel.window_size.watchdog = options.watchdog()
+    }
+
+    /// 传输停滞时关闭通道，其它错误原样返回
+    pub(crate) fn abort_if_stalled<T>(&mut self, result: SshResult<T>) -> SshResult<T> {
+        if let Err(e) = &result {
+            if let SshErrorKind::TransferStalled { .. } = e.kind() {
+                log::error!("transfer stalled, close the channel.");
+                if let Err(e) = self.channel.close() {
+                    log::error!("close channel error, error info: {:?}", e);
+                }
+            }
+        }
+        result
+    }
+
     pub(crate) fn send_str(&mut self, cmd: &str) -> SshResult<()> {
         self.send_bytes(cmd.as_bytes())
     }
@@ -36,16 +55,23 @@ impl ChannelScp {
         data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
             .put_u32(self.channel.server_channel)
             .put_u8s(bytes);
-        let client = client::default()?;
-        client.write_data(data, Some(self.channel.window_size.borrow_mut()))
+        client::locking(&self.channel.client)?
+            .write_data(data, Some(self.channel.window_size.borrow_mut()))?;
+        self.channel.tick_stats(0);
+        Ok(())
     }
 
     pub(crate) fn read_data(&mut self) -> SshResult<Vec<u8>> {
         let mut vec = vec![];
         loop {
             if !vec.is_empty() { break }
-            let client = client::default()?;
-            let results = client.read_data(Some(self.channel.window_size.borrow_mut()))?;
+            let mut client = client::locking(&self.channel.client)?;
+            let results = client.read_data(self.channel.window_size.borrow_mut())?;
+            if results.is_empty() {
+                self.channel.window_size.check_stall(&mut client)?;
+            }
+            drop(client);
+            self.channel.tick_stats(results.len());
             for mut result in results {
                 let message_code = result.get_u8();
                 match message_code {
@@ -58,7 +84,7 @@ impl ChannelScp {
                     ssh_msg_code::SSH_MSG_CHANNEL_CLOSE => {
                         let cc = result.get_u32();
                         if cc == self.channel.client_channel {
-                            self.channel.remote_close = true;
+                            self.channel.remote_closed();
                             self.channel.close()?;
                             return Ok(vec)
                         }
@@ -77,8 +103,7 @@ impl ChannelScp {
             .put_str(ssh_str::EXEC)
             .put_u8(true as u8)
             .put_str(command);
-        let client = client::default()?;
-        client.write(data)
+        self.channel.client()?.write(data)
     }
 
     pub(crate) fn command_init(&self, remote_path: &str, arg: &str) -> String {
@@ -5,7 +5,6 @@ use std::io::Write;
 use std::path::Path;
 use crate::constant::{permission, scp};
 use crate::error::{SshError, SshResult};
-use crate::slog::log;
 use crate::channel_scp::{ChannelScp, ScpFile, check_path};
 use crate::util;
 
@@ -29,7 +28,8 @@ impl ChannelScp {
         self.exec_scp(self.command_init(remote_path_str, scp::SOURCE).as_str())?;
         let mut scp_file = ScpFile::new();
         scp_file.local_path = self.local_path.clone();
-        self.process_d(&mut scp_file)?;
+        let result = self.process_d(&mut scp_file);
+        self.abort_if_stalled(result)?;
 
         log::info!("files download successful.");
 
@@ -99,7 +99,7 @@ impl ChannelScp {
             it is possible that the path does not exist, \
             which does not affect subsequent operations. \
             error info: {:?}, path: {:?}", e, scp_file.local_path.to_str());
-                return Err(SshError::from(format!("file open error: {}", e.to_string())))
+                return Err(SshError::from(e))
             }
         };
         log::debug!("dir: [{}] download completed.", scp_file.name);
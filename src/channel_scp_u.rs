@@ -5,9 +5,8 @@ use std::path::Path;
 use std::time::SystemTime;
 use crate::constant::{permission, scp};
 use crate::error::{SshError, SshResult};
-use crate::slog::log;
 use crate::channel_scp::{ChannelScp, check_path, ScpFile};
-use crate::util;
+use crate::{limits, util};
 
 impl ChannelScp {
     pub fn upload<S: AsRef<OsStr> + ?Sized>(mut self, local_path: &S, remote_path: &S) -> SshResult<()> {
@@ -24,10 +23,12 @@ impl ChannelScp {
         local [{}] files will be synchronized to the remote [{}] folder.", remote_path_str, local_path_str);
 
         self.exec_scp(self.command_init(remote_path_str, scp::SINK).as_str())?;
-        self.get_end()?;
+        let result = self.get_end();
+        self.abort_if_stalled(result)?;
         let mut scp_file = ScpFile::new();
         scp_file.local_path = local_path.to_path_buf();
-        self.file_all(&mut scp_file)?;
+        let result = self.file_all(&mut scp_file);
+        self.abort_if_stalled(result)?;
 
         log::info!("files upload successful.");
 
@@ -98,7 +99,7 @@ impl ChannelScp {
 
         let mut count = 0;
         loop {
-            let mut s = [0u8; 20480];
+            let mut s = [0u8; limits::SCP_CHUNK_SIZE];
             let i = file.read(&mut s)?;
             count = count + i;
             self.send_bytes(&s[..i])?;
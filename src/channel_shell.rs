@@ -1,56 +1,57 @@
 use std::borrow::BorrowMut;
-use crate::error::SshResult;
+use std::io;
+use std::time::{Duration, Instant};
+use crate::error::{self, SshResult};
 use crate::data::Data;
 use crate::constant::{ssh_msg_code, ssh_str};
-use crate::channel::Channel;
-use crate::client;
+use crate::channel::{Channel, PtyOptions, Signal};
+use crate::{client, limits};
 
 
-pub struct ChannelShell(pub(crate) Channel);
+/// 交互式 shell 通道
+/// 实现了 `Read` 和 `Write`
+pub struct ChannelShell {
+    pub(crate) channel: Channel,
+    /// 上一次读取中没有被取走的数据
+    buf: Vec<u8>
+}
 
 impl ChannelShell {
 
-    pub(crate) fn open(mut channel: Channel) -> SshResult<Self> {
+    pub(crate) fn open(mut channel: Channel, options: &PtyOptions) -> SshResult<Self> {
         // shell 形式需要一个伪终端
-        ChannelShell::request_pty(&channel)?;
+        channel.send_pty_request(false, options)?;
         ChannelShell::get_shell(&channel)?;
-        loop {
-            let client = client::default()?;
-            let results = client.read()?;
+        // 同一次读取中 SUCCESS 前后可能已经有 shell 的输出
+        let mut buf = vec![];
+        let mut started = false;
+        while !started {
+            let results = client::locking(&channel.client)?
+                .read_data(channel.window_size.borrow_mut())?;
             for mut result in results {
                 if result.is_empty() { continue }
                 let message_code = result.get_u8();
                 match message_code {
                     ssh_msg_code::SSH_MSG_CHANNEL_SUCCESS => {
-                        return Ok(ChannelShell(channel))
+                        let cc = result.get_u32();
+                        if cc == channel.client_channel {
+                            started = true
+                        }
+                    }
+                    ssh_msg_code::SSH_MSG_CHANNEL_DATA => {
+                        let cc = result.get_u32();
+                        if cc == channel.client_channel {
+                            buf.append(&mut result.get_u8s());
+                        }
                     }
                     _ => channel.other(message_code, result)?
                 }
             }
         }
-    }
-
-    fn request_pty(channel: &Channel) -> SshResult<()> {
-        let mut data = Data::new();
-        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
-            .put_u32(channel.server_channel)
-            .put_str(ssh_str::PTY_REQ)
-            .put_u8(false as u8)
-            .put_str(ssh_str::XTERM_VAR)
-            .put_u32(80)
-            .put_u32(24)
-            .put_u32(640)
-            .put_u32(480);
-        let model = [
-            128,                  // TTY_OP_ISPEED
-            0, 1, 0xc2, 0,        // 115200
-            129,                  // TTY_OP_OSPEED
-            0, 1, 0xc2, 0,        // 115200 again
-            0_u8,                 // TTY_OP_END
-        ];
-        data.put_u8s(&model);
-        let client = client::default()?;
-        client.write(data)
+        Ok(ChannelShell {
+            channel,
+            buf
+        })
     }
 
     fn get_shell(channel: &Channel) -> SshResult<()> {
@@ -59,42 +60,251 @@ impl ChannelShell {
             .put_u32(channel.server_channel)
             .put_str(ssh_str::SHELL)
             .put_u8(true as u8);
-        let client = client::default()?;
-        client.write(data)
+        channel.client()?.write(data)
+    }
+
+    /// 客户端通道编号，与 `Event` 中的 id 对应
+    pub fn id(&self) -> u32 {
+        self.channel.id()
+    }
+
+    /// 通道是否已经关闭，见 `Channel::is_closed`
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+
+    /// 向 shell 进程发送信号，见 `ChannelExec::signal`
+    /// 有伪终端时，中断前台命令一般写入 Ctrl-C (0x03) 即可
+    pub fn signal(&mut self, signal: Signal) -> SshResult<()> {
+        self.channel.send_signal(&signal)
+    }
+
+    /// 发送串口 BREAK (RFC 4335)，用于通过 ssh 连接串口控制台的终端服务器，
+    /// length_ms 为 BREAK 的时长 (毫秒)，服务端可能按设备的能力调整。
+    /// 等待服务端回应，不支持时返回 `SshErrorKind::ChannelRequestFailed`，通道仍然可用
+    pub fn send_break(&mut self, length_ms: u32) -> SshResult<()> {
+        self.channel.send_break(length_ms)
     }
 
     pub fn read(&mut self) -> SshResult<Vec<u8>> {
-        let mut buf = vec![];
-        let client = client::default()?;
-        let results = client.read_data(Some(self.0.window_size.borrow_mut()))?;
+        self.read_before(None)
+    }
+
+    /// 最多等待 timeout 读取 shell 的输出，到时仍然没有数据时返回 Ok(None)，
+    /// 例如等待 2 秒提示符，没有出现就放弃。服务端已经发送 EOF、不会再有输出时返回空的 Vec。
+    /// 到时一个数据包只读到一部分时，剩下的部分在下一次读取时继续，不会错位。
+    /// 等待时不会因为 `Session::set_timeout` 返回超时错误
+    pub fn read_timeout(&mut self, timeout: Duration) -> SshResult<Option<Vec<u8>>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let buf = self.read_before(Some(deadline))?;
+            if !buf.is_empty() || self.is_remote_eof() {
+                return Ok(Some(buf))
+            }
+            if Instant::now() >= deadline {
+                return Ok(None)
+            }
+        }
+    }
+
+    /// 只读取已经到达的输出，没有数据时立即返回 Ok(None)，见 `read_timeout`
+    /// 阻塞模式下 (`Session::set_nonblocking(false)`) 最多等待一次读取的时间 (100 毫秒)
+    pub fn try_read(&mut self) -> SshResult<Option<Vec<u8>>> {
+        self.read_timeout(Duration::ZERO)
+    }
+
+    /// 按行读取 shell 的输出，见 `ShellLines`
+    pub fn lines(&mut self) -> ShellLines<'_> {
+        ShellLines { lines: self.raw_lines() }
+    }
+
+    /// 与 `lines` 相同，但返回每一行的原始字节，不做 UTF-8 解码
+    pub fn raw_lines(&mut self) -> ShellRawLines<'_> {
+        ShellRawLines { shell: self, done: false }
+    }
+
+    /// 取出缓存中的下一行，去掉结尾的 `\n` 或 `\r\n` (有伪终端时换行是 `\r\n`)
+    /// 服务端发送 EOF 或者关闭通道后，没有换行的最后一段也作为一行返回
+    fn next_line(&mut self) -> SshResult<Option<Vec<u8>>> {
+        // 已经查找过换行的部分，新读取的数据只需要从这里开始找
+        let mut scanned = 0;
+        loop {
+            if let Some(pos) = self.buf[scanned..].iter().position(|v| *v == b'\n') {
+                let mut line: Vec<u8> = self.buf.drain(..=(scanned + pos)).collect();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(Some(line))
+            }
+            scanned = self.buf.len();
+            if self.is_remote_eof() {
+                if self.buf.is_empty() {
+                    return Ok(None)
+                }
+                return Ok(Some(std::mem::take(&mut self.buf)))
+            }
+            self.buf = self.read()?;
+        }
+    }
+
+    fn read_before(&mut self, deadline: Option<Instant>) -> SshResult<Vec<u8>> {
+        let mut buf = std::mem::take(&mut self.buf);
+        if self.channel.is_finished() {
+            return Ok(buf)
+        }
+        let results = {
+            let mut client = client::locking(&self.channel.client)?;
+            match deadline {
+                Some(v) => client.read_data_before(self.channel.window_size.borrow_mut(), v)?,
+                None => client.read_data(self.channel.window_size.borrow_mut())?
+            }
+        };
+        let queue_depth = results.len();
         for mut result in results {
             if result.is_empty() { continue }
             let message_code = result.get_u8();
             match message_code {
                 ssh_msg_code::SSH_MSG_CHANNEL_DATA => {
                     let cc = result.get_u32();
-                    if cc == self.0.client_channel {
+                    if cc == self.channel.client_channel {
                         let mut vec = result.get_u8s();
                         buf.append(&mut vec);
                     }
                 }
-                _ => self.0.other(message_code, result)?
+                _ => self.channel.other(message_code, result)?
             }
         }
+        self.channel.tick_stats(queue_depth);
         Ok(buf)
     }
 
+    /// 写入 shell 的标准输入，按服务端的最大数据包大小拆分为多个 SSH_MSG_CHANNEL_DATA，
+    /// 远程窗口用完时阻塞，直到服务端调整窗口，写入 0 字节时什么都不做
     pub fn write(&mut self, buf: &[u8]) -> SshResult<()> {
+        if buf.is_empty() {
+            return Ok(())
+        }
+        self.channel.check_writable()?;
         let mut data = Data::new();
         data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
-            .put_u32(self.0.server_channel)
+            .put_u32(self.channel.server_channel)
             .put_u8s(buf);
-        let client = client::default()?;
-        client.write_data(data, Some(self.0.window_size.borrow_mut()))
+        client::locking(&self.channel.client)?
+            .write_data(data, Some(self.channel.window_size.borrow_mut()))?;
+        self.channel.tick_stats(0);
+        Ok(())
+    }
+
+    /// 发送 CHANNEL_EOF，shell 进程的标准输入结束，见 `Channel::send_eof`
+    /// 之后写入返回错误，输出仍然可以继续读取，直到服务端关闭通道
+    pub fn send_eof(&mut self) -> SshResult<()> {
+        self.channel.eof()
+    }
+
+    /// 服务端已经发送 EOF 或者关闭了通道，并且缓存的输出都已经读取
+    /// 用于区分暂时没有数据和输出已经结束
+    pub fn is_eof(&self) -> bool {
+        self.is_remote_eof() && self.buf.is_empty()
+    }
+
+    /// 关闭通道，返回还没有读取的输出 (包括关闭时读到的)，见 `Channel::close_with_timeout`
+    pub fn close(self) -> SshResult<Vec<u8>> {
+        self.close_with_timeout(limits::CLOSE_TIMEOUT)
+    }
+
+    /// 与 `close` 相同，总共最多等待 timeout
+    pub fn close_with_timeout(mut self, timeout: Duration) -> SshResult<Vec<u8>> {
+        self.channel.close_with_timeout(timeout)?;
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.append(&mut self.channel.drained);
+        Ok(buf)
+    }
+
+    fn is_remote_eof(&self) -> bool {
+        self.channel.eof_received || self.channel.remote_close
+    }
+
+    fn is_nonblocking(&self) -> io::Result<bool> {
+        let client = self.channel.client().map_err(error::to_io_error)?;
+        Ok(client.nonblocking)
+    }
+}
+
+/// 非阻塞模式下没有数据可读时返回 `WouldBlock`，
+/// 阻塞模式下一直等到有数据、服务端发送 EOF 或者超时 (`Session::set_timeout`)，
+/// 所以阻塞模式下可以直接用于 `io::copy`、`BufReader` 等
+impl io::Read for ChannelShell {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buf.is_empty() {
+            if self.is_remote_eof() {
+                return Ok(0)
+            }
+            self.buf = ChannelShell::read(self).map_err(error::to_io_error)?;
+            if self.buf.is_empty() && !self.is_remote_eof() && self.is_nonblocking()? {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock))
+            }
+        }
+        let len = buf.len().min(self.buf.len());
+        buf[..len].copy_from_slice(&self.buf[..len]);
+        self.buf.drain(..len);
+        Ok(len)
+    }
+}
+
+impl io::Write for ChannelShell {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        ChannelShell::write(self, buf).map_err(error::to_io_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
+}
+
+
+/// shell 输出的行，见 `ChannelShell::lines`
+/// 一行可能分在多个数据包中，没有收到换行之前不会返回，
+/// 每一行按 UTF-8 解码，无效的字节替换为 U+FFFD。
+/// 服务端发送 EOF 或者关闭通道后迭代结束，出错后迭代也会结束。
+/// 迭代器只借用通道，没有取走的数据 (包括还没有换行的部分) 留给之后的读取
+pub struct ShellLines<'a> {
+    lines: ShellRawLines<'a>
+}
 
-    pub fn close(mut self) -> SshResult<()> {
-        self.0.close()
+impl Iterator for ShellLines<'_> {
+    type Item = SshResult<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lines.next()
+            .map(|v| v.map(|line| String::from_utf8_lossy(&line).into_owned()))
     }
+}
+
+/// shell 输出的原始字节行，见 `ChannelShell::raw_lines`，除了不解码之外与 `ShellLines` 相同
+pub struct ShellRawLines<'a> {
+    shell: &'a mut ChannelShell,
+    done: bool
+}
+
+impl Iterator for ShellRawLines<'_> {
+    type Item = SshResult<Vec<u8>>;
 
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        match self.shell.next_line() {
+            Ok(Some(v)) => Some(Ok(v)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
 }
@@ -0,0 +1,279 @@
+//! 带类型的通道状态
+//!
+//! 在错误的状态下调用通道的方法 (例如服务端确认之前发送请求、关闭之后写数据) 只能在运行时发现。
+//! `Channel<Opening>`、`Channel<Open>` 和 `Channel<Closed>` 把状态放到类型里:
+//! 只有 `Channel<Open>` 可以读写和发送请求，状态转换会消耗原来的值，误用在编译时就会报错。
+//! 默认的 `Channel` (即 `Channel<Dynamic>`) 保持原来运行时检查状态的行为，
+//! 可以通过 `Channel<Open>::into_dynamic` 转换。
+//!
+//! ```rust,no_run
+//! use ssh_rs::ssh;
+//!
+//! let mut session = ssh::create_session();
+//! session.set_user_and_password("user", "password");
+//! session.connect("127.0.0.1:22").unwrap();
+//! let mut channel = session.begin_channel().unwrap().confirm().unwrap();
+//! channel.exec("ls -all").unwrap();
+//! let mut output = vec![];
+//! while !channel.is_remote_closed() {
+//!     output.extend(channel.read().unwrap());
+//! }
+//! let channel = channel.close().unwrap();
+//! println!("{}, exit status: {:?}", String::from_utf8(output).unwrap(), channel.summary().exit_status);
+//! ```
+//!
+//! 服务端确认之前不能发送请求:
+//! ```compile_fail,E0599
+//! use ssh_rs::Channel;
+//! use ssh_rs::channel_state::Opening;
+//!
+//! fn exec(mut channel: Channel<Opening>) {
+//!     channel.exec("ls -all").unwrap();
+//! }
+//! ```
+//!
+//! 关闭之后不能再使用:
+//! ```compile_fail,E0382
+//! use ssh_rs::Channel;
+//! use ssh_rs::channel_state::Open;
+//!
+//! fn write(mut channel: Channel<Open>) {
+//!     channel.close().unwrap();
+//!     channel.write(b"ls -all").unwrap();
+//! }
+//! ```
+//!
+//! 已关闭的通道没有读写方法:
+//! ```compile_fail,E0599
+//! use ssh_rs::Channel;
+//! use ssh_rs::channel_state::Open;
+//!
+//! fn write(channel: Channel<Open>) {
+//!     let mut channel = channel.close().unwrap();
+//!     channel.write(b"ls -all").unwrap();
+//! }
+//! ```
+
+use std::borrow::BorrowMut;
+use std::time::Duration;
+use crate::channel::{Channel, CloseSummary, PtyOptions};
+use crate::{client, limits};
+use crate::constant::{ssh_msg_code, ssh_str};
+use crate::data::Data;
+use crate::error::{SshError, SshErrorKind, SshResult};
+
+
+/// 运行时检查状态的通道，`Channel` 的默认状态
+pub enum Dynamic {}
+
+/// 已发送打开请求，等待服务端确认
+pub enum Opening {}
+
+/// 服务端已确认，可以读写和发送请求
+pub enum Open {}
+
+/// 已经关闭，只能查看关闭的汇总信息
+pub enum Closed {}
+
+
+impl Channel<Opening> {
+    /// 等待服务端确认打开通道
+    /// 服务端拒绝时返回错误，通道不再可用
+    pub fn confirm(mut self) -> SshResult<Channel<Open>> {
+        let (server_channel, rws, max_packet_size) = match self.receive_open_channel() {
+            Ok(v) => v,
+            Err(e) => {
+                self.client()?.remove_channel(self.client_channel);
+                return Err(e)
+            }
+        };
+        self.window_size.server_channel = server_channel;
+        self.window_size.add_remote_window_size(rws);
+        self.window_size.add_remote_max_window_size(rws);
+        self.window_size.set_remote_max_packet_size(max_packet_size);
+        self.confirmed = true;
+        Ok(self.into_state())
+    }
+
+    // 远程回应是否可以打开通道
+    fn receive_open_channel(&mut self) -> SshResult<(u32, u32, u32)> {
+        loop {
+            let mut results = self.client()?.read_channel(self.client_channel)?.into_iter();
+            while let Some(mut result) = results.next() {
+                if result.is_empty() { continue }
+                let message_code = result.get_u8();
+                match message_code {
+                    // 打开请求通过
+                    ssh_msg_code::SSH_MSG_CHANNEL_OPEN_CONFIRMATION => {
+                        // 接收方通道号
+                        let cc = result.get_u32();
+                        if cc != self.client_channel { continue }
+                        // 发送方通道号
+                        let server_channel = result.get_u32();
+                        // 远程初始窗口大小
+                        let rws = result.get_u32();
+                        // 远程的最大数据包大小
+                        let max_packet_size = result.get_u32();
+                        // 同一次读到的后续消息留给通道
+                        let mut client = self.client()?;
+                        for data in results {
+                            client.push_channel_data(self.client_channel, data)
+                        }
+                        return Ok((server_channel, rws, max_packet_size));
+                    },
+                    /*
+                        byte SSH_MSG_CHANNEL_OPEN_FAILURE
+                        uint32 recipient channel
+                        uint32 reason code
+                        string description，ISO-10646 UTF-8 编码[RFC3629]
+                        string language tag，[RFC3066]
+                    */
+                    // 打开请求拒绝
+                    ssh_msg_code::SSH_MSG_CHANNEL_OPEN_FAILURE => {
+                        let cc = result.get_u32();
+                        if cc != self.client_channel { continue }
+                        // 失败原因码
+                        let code = result.get_u32();
+                        // 消息详情 默认utf-8编码，无效的字节替换后保留其余内容，管理员看的就是这段说明
+                        let description = String::from_utf8_lossy(&result.get_u8s()).to_string();
+                        // language tag 暂不处理， 应该是 en-US
+                        result.get_u8s();
+
+                        log::error!("channel open failed, reason code: {}, description: {}", code, description);
+                        return Err(SshError::from(SshErrorKind::ChannelOpenFailed {
+                            reason_code: code,
+                            description
+                        }))
+                    },
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+
+impl Channel<Open> {
+    /// 执行命令，等待服务端回应请求
+    pub fn exec(&mut self, command: &str) -> SshResult<()> {
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
+            .put_u32(self.server_channel)
+            .put_str(ssh_str::EXEC)
+            .put_u8(true as u8)
+            .put_str(command);
+        self.client()?.write(data)?;
+        self.wait_reply(ssh_str::EXEC)
+    }
+
+    /// 在 `exec` 之前设置环境变量，见 `Channel::set_env`
+    pub fn set_env(&mut self, name: &str, value: &str) -> SshResult<()> {
+        self.send_env(name, value)
+    }
+
+    /// 在 `exec` 之前请求一个伪终端，见 `ChannelExec::request_pty`
+    pub fn request_pty(&mut self) -> SshResult<()> {
+        self.request_pty_with(&PtyOptions::new())
+    }
+
+    /// 使用指定的参数请求伪终端，见 `PtyOptions`
+    pub fn request_pty_with(&mut self, options: &PtyOptions) -> SshResult<()> {
+        self.send_pty_request(true, options)?;
+        self.wait_reply(ssh_str::PTY_REQ)
+    }
+
+    /// 读取一次连接，返回收到的数据 (不包括 stderr)
+    /// 没有数据时返回空的 Vec
+    /// 服务端关闭通道后 `is_remote_closed` 为 true，之后的读取直接返回空的 Vec，需要调用 `close` 完成关闭
+    pub fn read(&mut self) -> SshResult<Vec<u8>> {
+        let mut buf = vec![];
+        if self.is_finished() {
+            return Ok(buf)
+        }
+        let results = client::locking(&self.client)?
+            .read_data(self.window_size.borrow_mut())?;
+        let queue_depth = results.len();
+        for mut result in results {
+            if result.is_empty() { continue }
+            let message_code = result.get_u8();
+            match message_code {
+                ssh_msg_code::SSH_MSG_CHANNEL_DATA => {
+                    let cc = result.get_u32();
+                    if cc == self.client_channel {
+                        buf.append(&mut result.get_u8s());
+                    }
+                }
+                ssh_msg_code::SSH_MSG_CHANNEL_EXTENDED_DATA => {}
+                ssh_msg_code::SSH_MSG_CHANNEL_CLOSE => {
+                    let cc = result.get_u32();
+                    if cc == self.client_channel {
+                        self.remote_closed();
+                    }
+                }
+                _ => self.other(message_code, result)?
+            }
+        }
+        self.tick_stats(queue_depth);
+        Ok(buf)
+    }
+
+    /// 按服务端的最大数据包大小拆分为多个 SSH_MSG_CHANNEL_DATA，远程窗口用完时等待服务端调整窗口
+    /// `send_eof` 之后返回错误
+    pub fn write(&mut self, buf: &[u8]) -> SshResult<()> {
+        self.check_writable()?;
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
+            .put_u32(self.server_channel)
+            .put_u8s(buf);
+        client::locking(&self.client)?
+            .write_data(data, Some(self.window_size.borrow_mut()))?;
+        self.tick_stats(0);
+        Ok(())
+    }
+
+    /// 服务端是否已经发送了 CHANNEL_CLOSE
+    pub fn is_remote_closed(&self) -> bool {
+        self.remote_close
+    }
+
+    /// 服务端是否已经发送了 CHANNEL_EOF，之后不会再收到数据
+    pub fn is_eof(&self) -> bool {
+        self.eof_received
+    }
+
+    /// 发送 CHANNEL_EOF，关闭写的一端，之后仍然可以读取，直到服务端关闭通道
+    pub fn send_eof(&mut self) -> SshResult<()> {
+        self.eof()
+    }
+
+    /// 关闭通道，最多等待 1.5 秒，见 `close_with_timeout`
+    pub fn close(self) -> SshResult<Channel<Closed>> {
+        self.close_with_timeout(limits::CLOSE_TIMEOUT)
+    }
+
+    /// 先读取到服务端的 EOF 为止，再发送 CHANNEL_CLOSE 并等待服务端的 CHANNEL_CLOSE，总共最多等待 timeout，
+    /// 期间收到的数据由 `Channel<Closed>::take_output` 取得
+    pub fn close_with_timeout(mut self, timeout: Duration) -> SshResult<Channel<Closed>> {
+        log::info!("channel close.");
+        self.shutdown(timeout)?;
+        Ok(self.into_state())
+    }
+
+    /// 转换为运行时检查状态的 `Channel`，例如用于 `open_exec`
+    pub fn into_dynamic(self) -> Channel {
+        self.into_state()
+    }
+}
+
+
+impl Channel<Closed> {
+    /// 退出状态、收发的字节数等信息
+    pub fn summary(&self) -> CloseSummary {
+        self.close_summary()
+    }
+
+    /// 关闭时收到的 stdout 和 stderr，取走后为空
+    pub fn take_output(&mut self) -> (Vec<u8>, Vec<u8>) {
+        (std::mem::take(&mut self.drained), std::mem::take(&mut self.drained_stderr))
+    }
+}
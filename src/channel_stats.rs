@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+use crate::window_size::WindowSize;
+
+
+/// 通道统计快照
+/// 每次回调时传入，字节数为距离上一次回调之间的增量
+#[derive(Debug, Clone)]
+pub struct ChannelStatsSnapshot {
+    /// 上次回调以来收到的字节数
+    pub bytes_in: u64,
+    /// 上次回调以来发送的字节数
+    pub bytes_out: u64,
+    /// 当前本地窗口大小
+    pub local_window_size: u32,
+    /// 当前远程窗口大小
+    pub remote_window_size: u32,
+    /// 最近一次读取中缓存待处理的数据包数量
+    pub queue_depth: usize,
+    /// 通道关闭后的最后一次回调为 true
+    pub last: bool,
+}
+
+
+pub(crate) struct ChannelStats {
+    interval: Duration,
+    callback: Box<dyn FnMut(ChannelStatsSnapshot) + Send>,
+    last_time: Instant,
+    last_bytes_in: u64,
+    last_bytes_out: u64,
+}
+
+impl ChannelStats {
+    pub(crate) fn new<F>(interval: Duration, callback: F) -> Self
+    where
+        F: FnMut(ChannelStatsSnapshot) + Send + 'static
+    {
+        ChannelStats {
+            interval,
+            callback: Box::new(callback),
+            last_time: Instant::now(),
+            last_bytes_in: 0,
+            last_bytes_out: 0,
+        }
+    }
+
+    /// 到达回调间隔时才触发回调
+    /// 读取繁忙时多次调用会合并到同一次回调里
+    pub(crate) fn tick(&mut self, ws: &WindowSize, queue_depth: usize) {
+        if self.last_time.elapsed() < self.interval {
+            return;
+        }
+        self.report(ws, queue_depth, false)
+    }
+
+    pub(crate) fn report(&mut self, ws: &WindowSize, queue_depth: usize, last: bool) {
+        let snapshot = ChannelStatsSnapshot {
+            bytes_in: ws.bytes_in - self.last_bytes_in,
+            bytes_out: ws.bytes_out - self.last_bytes_out,
+            local_window_size: ws.local_window_size(),
+            remote_window_size: ws.remote_window_size(),
+            queue_depth,
+            last,
+        };
+        self.last_time = Instant::now();
+        self.last_bytes_in = ws.bytes_in;
+        self.last_bytes_out = ws.bytes_out;
+        (self.callback)(snapshot)
+    }
+}
@@ -0,0 +1,193 @@
+use std::borrow::BorrowMut;
+use std::io;
+use std::time::Duration;
+use crate::error::{self, SshError, SshErrorKind, SshResult};
+use crate::data::Data;
+use crate::constant::{ssh_msg_code, ssh_str};
+use crate::channel::Channel;
+use crate::{client, limits};
+
+
+/// 子系统通道，例如 sftp、netconf
+/// 不请求伪终端，读写的是子系统协议的原始数据，实现了 `Read` 和 `Write`
+pub struct ChannelSubsystem {
+    pub(crate) channel: Channel,
+    /// 上一次读取中没有被取走的数据
+    buf: Vec<u8>
+}
+
+impl ChannelSubsystem {
+
+    pub(crate) fn open(mut channel: Channel, name: &str) -> SshResult<Self> {
+        ChannelSubsystem::get_subsystem(&channel, name)?;
+        // 同一次读取中 SUCCESS 之后可能已经有子系统的数据
+        let mut buf = vec![];
+        let mut started = false;
+        while !started {
+            let results = client::locking(&channel.client)?
+                .read_data(channel.window_size.borrow_mut())?;
+            for mut result in results {
+                if result.is_empty() { continue }
+                let message_code = result.get_u8();
+                match message_code {
+                    ssh_msg_code::SSH_MSG_CHANNEL_SUCCESS => {
+                        let cc = result.get_u32();
+                        if cc == channel.client_channel {
+                            log::info!("subsystem [{}] started.", name);
+                            started = true
+                        }
+                    }
+                    ssh_msg_code::SSH_MSG_CHANNEL_FAILURE => {
+                        let cc = result.get_u32();
+                        if cc == channel.client_channel {
+                            log::error!("subsystem [{}] is not available.", name);
+                            // 通道已经打开，关闭失败时仍然返回子系统不可用的错误
+                            if let Err(e) = channel.close() {
+                                log::error!("channel close error: {}", e);
+                            }
+                            return Err(SshError::from(SshErrorKind::SubsystemUnavailable {
+                                name: name.to_string()
+                            }))
+                        }
+                    }
+                    ssh_msg_code::SSH_MSG_CHANNEL_DATA => {
+                        let cc = result.get_u32();
+                        if cc == channel.client_channel {
+                            buf.append(&mut result.get_u8s());
+                        }
+                    }
+                    _ => channel.other(message_code, result)?
+                }
+            }
+        }
+        Ok(ChannelSubsystem {
+            channel,
+            buf
+        })
+    }
+
+    /*
+        byte      SSH_MSG_CHANNEL_REQUEST
+        uint32    recipient channel
+        string    "subsystem"
+        boolean   want reply
+        string    subsystem name
+    */
+    fn get_subsystem(channel: &Channel, name: &str) -> SshResult<()> {
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
+            .put_u32(channel.server_channel)
+            .put_str(ssh_str::SUBSYSTEM)
+            .put_u8(true as u8)
+            .put_str(name);
+        channel.client()?.write(data)
+    }
+
+    /// 客户端通道编号，与 `Event` 中的 id 对应
+    pub fn id(&self) -> u32 {
+        self.channel.id()
+    }
+
+    /// 通道是否已经关闭，见 `Channel::is_closed`
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+
+    /// 读取子系统的输出，没有数据时返回空的 Vec
+    /// 读取后按消耗的数据调整本地窗口
+    pub fn read(&mut self) -> SshResult<Vec<u8>> {
+        let mut buf = std::mem::take(&mut self.buf);
+        if self.channel.is_finished() {
+            return Ok(buf)
+        }
+        let results = client::locking(&self.channel.client)?
+            .read_data(self.channel.window_size.borrow_mut())?;
+        let queue_depth = results.len();
+        for mut result in results {
+            if result.is_empty() { continue }
+            let message_code = result.get_u8();
+            match message_code {
+                ssh_msg_code::SSH_MSG_CHANNEL_DATA => {
+                    let cc = result.get_u32();
+                    if cc == self.channel.client_channel {
+                        let mut vec = result.get_u8s();
+                        buf.append(&mut vec);
+                    }
+                }
+                _ => self.channel.other(message_code, result)?
+            }
+        }
+        self.channel.tick_stats(queue_depth);
+        Ok(buf)
+    }
+
+    /// 写入子系统，按服务端的最大数据包大小拆分为多个 SSH_MSG_CHANNEL_DATA，远程窗口不够时等待服务端调整窗口
+    pub fn write(&mut self, buf: &[u8]) -> SshResult<()> {
+        self.channel.check_writable()?;
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
+            .put_u32(self.channel.server_channel)
+            .put_u8s(buf);
+        client::locking(&self.channel.client)?
+            .write_data(data, Some(self.channel.window_size.borrow_mut()))?;
+        self.channel.tick_stats(0);
+        Ok(())
+    }
+
+    /// 发送 CHANNEL_EOF，见 `Channel::send_eof`
+    pub fn send_eof(&mut self) -> SshResult<()> {
+        self.channel.eof()
+    }
+
+    /// 关闭通道，返回还没有读取的输出 (包括关闭时读到的)，见 `Channel::close_with_timeout`
+    pub fn close(self) -> SshResult<Vec<u8>> {
+        self.close_with_timeout(limits::CLOSE_TIMEOUT)
+    }
+
+    /// 与 `close` 相同，总共最多等待 timeout
+    pub fn close_with_timeout(mut self, timeout: Duration) -> SshResult<Vec<u8>> {
+        self.channel.close_with_timeout(timeout)?;
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.append(&mut self.channel.drained);
+        Ok(buf)
+    }
+
+    fn is_eof(&self) -> bool {
+        self.channel.eof_received || self.channel.remote_close
+    }
+
+    fn is_nonblocking(&self) -> io::Result<bool> {
+        let client = self.channel.client().map_err(error::to_io_error)?;
+        Ok(client.nonblocking)
+    }
+}
+
+/// 与 `ChannelShell` 相同，非阻塞模式下没有数据可读时返回 `WouldBlock`
+impl io::Read for ChannelSubsystem {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buf.is_empty() {
+            if self.is_eof() {
+                return Ok(0)
+            }
+            self.buf = ChannelSubsystem::read(self).map_err(error::to_io_error)?;
+            if self.buf.is_empty() && !self.is_eof() && self.is_nonblocking()? {
+                return Err(io::Error::from(io::ErrorKind::WouldBlock))
+            }
+        }
+        let len = buf.len().min(self.buf.len());
+        buf[..len].copy_from_slice(&self.buf[..len]);
+        self.buf.drain(..len);
+        Ok(len)
+    }
+}
+
+impl io::Write for ChannelSubsystem {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        ChannelSubsystem::write(self, buf).map_err(error::to_io_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
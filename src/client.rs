@@ -1,111 +1,534 @@
+use std::collections::{HashMap, VecDeque};
 use std::io;
-use std::net::{Shutdown, TcpStream, ToSocketAddrs};
-use std::ops::{Deref, DerefMut};
-use crate::error::{SshError, SshResult};
-use crate::slog::log;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::algorithm::compression::Compression;
+use crate::algorithm::encryption::Ciphers;
+use crate::algorithm::hash::h::H;
+use crate::algorithm::key_exchange::KeyExchange;
+use crate::algorithm::public_key::PublicKey;
+use crate::algorithm::negotiated::{Negotiated, NegotiatedAlgorithms};
+use crate::config::Config;
+use crate::constant::ssh_msg_code;
+use crate::data::Data;
+use crate::channel::CloseInitiator;
+use crate::error::{SshError, SshErrorKind, SshResult};
+use crate::event::{ChannelRecord, Event};
+use crate::forwarded_channel::ForwardedChannel;
+use crate::host_keys::{HostKeysProve, HostKeysUpdate};
+use crate::keepalive::Keepalive;
+use crate::limits;
 use crate::timeout::Timeout;
+use crate::transport::{IoTransport, Transport};
+use crate::user_info::UserInfo;
+use crate::x11_forward::X11Forwarding;
 
 
 pub struct Client {
-    pub(crate) stream: TcpStream,
+    pub(crate) stream: Box<dyn Transport>,
     pub(crate) sequence: Sequence,
-    pub(crate) timeout: Timeout
+    /// 当前两个方向上使用的加密算法，密钥交换完成之前为 None
+    pub(crate) encryption: Option<Ciphers>,
+    /// 每个方向上的 mac/tag 长度
+    pub(crate) integrity: Negotiated,
+    /// 两个方向上的压缩状态
+    pub(crate) compression: Compression,
+    pub(crate) timeout: Timeout,
+    /// 认证信息、双方的版本和算法列表
+    pub(crate) config: Config,
+    /// 当前这次密钥交换中计算 exchange hash 的数据
+    pub(crate) h: H,
+    /// 当前这次密钥交换协商出的密钥交换算法和主机密钥算法
+    pub(crate) key_exchange: Option<Box<dyn KeyExchange>>,
+    pub(crate) public_key: Option<Box<dyn PublicKey>>,
+    /// 下一个客户端通道编号，每个连接从 0 开始
+    pub(crate) next_channel: u32,
+    /// 已打开的通道, 按客户端通道编号缓存还没有被读取的消息
+    pub(crate) channels: HashMap<u32, VecDeque<Data>>,
+    /// 已打开通道的状态记录
+    pub(crate) records: HashMap<u32, ChannelRecord>,
+    /// poll_event 中还没有返回的事件
+    pub(crate) events: VecDeque<Event>,
+    /// 最近收到的消息编号，传输停滞时用于诊断
+    pub(crate) recent_messages: VecDeque<u8>,
+    /// 最近一次密钥交换中服务端的主机密钥 (key blob)
+    pub(crate) host_key: Option<Vec<u8>>,
+    /// 最近一次密钥交换协商出的算法
+    pub(crate) negotiated: Option<NegotiatedAlgorithms>,
+    /// 第一次密钥交换的 exchange hash
+    pub(crate) session_id: Option<Vec<u8>>,
+    /// 双方都支持严格的密钥交换，见 kex::check_initial_kex
+    pub(crate) kex_strict: bool,
+    /// 服务端在 SSH_MSG_EXT_INFO 中通告的 server-sig-algs，服务端没有发送时为 None
+    pub(crate) server_sig_algs: Option<Vec<String>>,
+    /// 服务端通过 hostkeys-00@openssh.com 通告的主机密钥
+    pub(crate) announced_host_keys: Vec<Vec<u8>>,
+    /// 开启 UpdateHostKeys 时更新 known_hosts 需要的信息
+    pub(crate) host_keys_update: Option<HostKeysUpdate>,
+    /// 已发送还没有收到回应的全局请求，服务端按发送的顺序回应
+    pub(crate) global_requests: VecDeque<PendingRequest>,
+    pub(crate) keepalive: Option<Keepalive>,
+    /// 已经在某个通道上请求了 agent 转发，之后接受服务端打开的 agent 通道
+    pub(crate) agent_forwarding: bool,
+    /// 已经在某个通道上请求了 X11 转发，之后接受服务端打开的 x11 通道
+    pub(crate) x11_forwarding: Option<X11Forwarding>,
+    /// 转发中的 agent、x11 通道，按客户端通道编号
+    pub(crate) forwarded: HashMap<u32, ForwardedChannel>,
+    /// 重新协商密钥中，发送 SSH_MSG_KEXINIT 之后到 SSH_MSG_NEWKEYS 之前
+    /// 只允许发送传输层的消息
+    pub(crate) rekeying: bool,
+    /// 重新协商密钥期间要发送的其它消息，新的密钥生效后按顺序发送
+    pub(crate) deferred: VecDeque<Data>,
+    /// 用户设置的重新协商密钥的限制
+    pub(crate) rekey_limit: RekeyLimit,
+    /// 最近一次新的密钥生效的时间
+    pub(crate) last_kex: Instant,
+    /// SSH_MSG_NEWKEYS 之后同一次读取到的数据，
+    /// 已经使用新的密钥加密，新的密钥生效之后再处理；
+    /// 以及还没有读完的数据包，下一次读取时继续
+    pub(crate) pending: Vec<u8>,
+    /// 服务端发送的 SSH_MSG_DISCONNECT 中的 reason code 和 description，
+    /// 之后的读取都返回 `SshErrorKind::Disconnected`
+    pub(crate) disconnected: Option<(u32, String)>,
+    /// 连接已经由 `Session::close` 关闭，之后释放的通道不再发送 CHANNEL_CLOSE
+    pub(crate) closed: bool,
+    /// TcpStream 是否为非阻塞模式，见 `Session::set_nonblocking`
+    pub(crate) nonblocking: bool,
+    /// 连接上没有数据时，两次读取之间等待的时间，见 `Session::set_poll_interval`
+    pub(crate) poll_interval: Duration,
+    /// 读取的截止时间，见 `read_data_before`
+    pub(crate) read_deadline: Option<Instant>
 }
 
+/// 需要回应的全局请求
+pub(crate) enum PendingRequest {
+    Keepalive,
+    HostKeysProve(HostKeysProve),
+}
+
+/// 达到任意一个限制时重新协商密钥，见 `Session::set_rekey_limit`
+#[derive(Clone, Copy)]
+pub(crate) struct RekeyLimit {
+    /// 每个方向上使用同一个密钥加密或解密的最大字节数
+    pub(crate) bytes: u64,
+    /// 同一个密钥最长使用的时间，None 为不限制
+    pub(crate) interval: Option<Duration>
+}
+
+impl Default for RekeyLimit {
+    fn default() -> Self {
+        RekeyLimit {
+            bytes: limits::REKEY_BYTES,
+            interval: Some(limits::REKEY_INTERVAL)
+        }
+    }
+}
+
+/// 数据包序号 (RFC 4253 6.4)
+/// 每个数据包加一，达到 2^32 后回到 0
+///
+/// 同时记录上次密钥交换之后每个方向上的数据包数量和字节数，
+/// 用于在达到加密算法的限制之前重新协商密钥
 #[derive(Clone)]
 pub(crate) struct Sequence {
     pub(crate) client_sequence_num: u32,
-    pub(crate) server_sequence_num: u32
+    pub(crate) server_sequence_num: u32,
+    pub(crate) client_traffic: Traffic,
+    pub(crate) server_traffic: Traffic
+}
+
+/// 使用同一个密钥的数据包数量和加密的字节数
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Traffic {
+    pub(crate) packets: u64,
+    pub(crate) bytes: u64
+}
+
+impl Traffic {
+    fn add(&mut self, len: usize) {
+        self.packets = self.packets.saturating_add(1);
+        self.bytes = self.bytes.saturating_add(len as u64);
+    }
+
+    fn exceeds(&self, max_bytes: u64) -> bool {
+        self.packets >= limits::MAX_PACKETS_BEFORE_REKEY || self.bytes >= max_bytes
+    }
 }
 
 impl Sequence {
+    pub(crate) fn new() -> Self {
+        Sequence {
+            client_sequence_num: 0,
+            server_sequence_num: 0,
+            client_traffic: Traffic::default(),
+            server_traffic: Traffic::default()
+        }
+    }
 
     pub(crate) fn client_auto_increment(&mut self) {
-        if self.client_sequence_num == u32::MAX {
-            self.client_sequence_num = 0;
-        }
-        self.client_sequence_num += 1;
+        self.client_sequence_num = self.client_sequence_num.wrapping_add(1);
     }
 
     pub(crate) fn server_auto_increment(&mut self) {
-        if self.server_sequence_num == u32::MAX {
-            self.server_sequence_num = 0;
-        }
-        self.server_sequence_num += 1;
+        self.server_sequence_num = self.server_sequence_num.wrapping_add(1);
+    }
+
+    /// 发送了一个加密的数据包，len 为加密的长度
+    pub(crate) fn client_encrypted(&mut self, len: usize) {
+        self.client_traffic.add(len)
+    }
+
+    /// 收到了一个加密的数据包，len 为解密的长度
+    pub(crate) fn server_decrypted(&mut self, len: usize) {
+        self.server_traffic.add(len)
+    }
+
+    /// 新的密钥生效，重新开始计数
+    pub(crate) fn reset_traffic(&mut self) {
+        self.client_traffic = Traffic::default();
+        self.server_traffic = Traffic::default();
     }
 }
 
 impl Client {
-    pub(crate) fn connect<A: ToSocketAddrs>(addr: A) -> SshResult<Client> {
-        match TcpStream::connect(addr) {
-            Ok(stream) => {
-                // default nonblocking
-                stream.set_nonblocking(true).unwrap();
-                Ok(
-                    Client{
-                        stream,
-                        sequence: Sequence {
-                            client_sequence_num: 0,
-                            server_sequence_num: 0
-                        },
-                        timeout: Timeout::new()
-                    }
-                )
+    pub(crate) fn connect<A: ToSocketAddrs>(addr: A, nonblocking: bool) -> SshResult<Client> {
+        Client::connect_addrs(addr, None, nonblocking)
+    }
+
+    /// 每个地址单独等待 timeout
+    pub(crate) fn connect_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration, nonblocking: bool) -> SshResult<Client> {
+        Client::connect_addrs(addr, Some(timeout), nonblocking)
+    }
+
+    /// 按解析的顺序依次尝试每一个地址，使用第一个连接成功的，
+    /// 例如同时有 AAAA 和 A 记录、网络上的 IPv6 不通时仍然可以通过 IPv4 连接
+    fn connect_addrs<A: ToSocketAddrs>(addr: A, timeout: Option<Duration>, nonblocking: bool) -> SshResult<Client> {
+        let mut errors = vec![];
+        for addr in addr.to_socket_addrs()? {
+            let result = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr)
+            };
+            match result {
+                Ok(stream) => {
+                    log::info!("tcp connected to [{}].", addr);
+                    return Client::from_stream(stream, nonblocking)
+                }
+                Err(e) => {
+                    log::error!("connect to [{}] error, error info: {:?}", addr, e);
+                    errors.push((addr, e))
+                }
             }
-            Err(e) => Err(SshError::from(e))
+        }
+        Err(connect_error(errors))
+    }
+
+    /// 阻塞模式下读取最多等待 BLOCKING_READ_TIMEOUT，
+    /// 超时后和非阻塞模式一样按没有数据处理，保证读取超时、保活等检查可以执行
+    pub(crate) fn from_stream(stream: TcpStream, nonblocking: bool) -> SshResult<Client> {
+        stream.set_nonblocking(nonblocking)?;
+        if !nonblocking {
+            stream.set_read_timeout(Some(limits::BLOCKING_READ_TIMEOUT))?;
+        }
+        let mut client = Client::from_transport(Box::new(stream));
+        client.nonblocking = nonblocking;
+        Ok(client)
+    }
+
+    /// 任意 Read + Write 的流
+    /// 流是阻塞模式时，读取会一直等到有数据为止
+    pub(crate) fn from_io<T>(io: T) -> Client
+    where
+        T: Read + Write + Send + 'static
+    {
+        Client::from_transport(Box::new(IoTransport(io)))
+    }
+
+    fn from_transport(stream: Box<dyn Transport>) -> Client {
+        Client {
+            stream,
+            sequence: Sequence::new(),
+            encryption: None,
+            integrity: Negotiated::default(),
+            compression: Compression::new(),
+            timeout: Timeout::new(Duration::from_secs(limits::DEFAULT_TIMEOUT_SECS)),
+            config: Config::new(UserInfo::from_password("", "")),
+            h: H::new(),
+            key_exchange: None,
+            public_key: None,
+            next_channel: 0,
+            channels: HashMap::new(),
+            records: HashMap::new(),
+            events: VecDeque::new(),
+            recent_messages: VecDeque::new(),
+            host_key: None,
+            negotiated: None,
+            session_id: None,
+            kex_strict: false,
+            server_sig_algs: None,
+            announced_host_keys: vec![],
+            host_keys_update: None,
+            global_requests: VecDeque::new(),
+            keepalive: None,
+            agent_forwarding: false,
+            x11_forwarding: None,
+            forwarded: HashMap::new(),
+            rekeying: false,
+            deferred: VecDeque::new(),
+            rekey_limit: RekeyLimit::default(),
+            last_kex: Instant::now(),
+            pending: vec![],
+            disconnected: None,
+            closed: false,
+            nonblocking: false,
+            poll_interval: limits::POLL_INTERVAL,
+            read_deadline: None
+        }
+    }
+
+
+    pub(crate) fn encryption(&mut self) -> SshResult<&mut Ciphers> {
+        match self.encryption.as_mut() {
+            Some(v) => Ok(v),
+            None => Err(SshError::from("key exchange not completed."))
         }
     }
 
+    pub(crate) fn key_exchange(&self) -> SshResult<&dyn KeyExchange> {
+        match &self.key_exchange {
+            Some(v) => Ok(v.as_ref()),
+            None => Err(SshError::from("key exchange algorithm not negotiated."))
+        }
+    }
+
+    pub(crate) fn public_key(&self) -> SshResult<&dyn PublicKey> {
+        match &self.public_key {
+            Some(v) => Ok(v.as_ref()),
+            None => Err(SshError::from("public key algorithm not negotiated."))
+        }
+    }
+
+    /// 当前这次密钥交换的 exchange hash
+    pub(crate) fn exchange_hash(&self) -> SshResult<Vec<u8>> {
+        Ok(self.h.digest(self.key_exchange()?.get_hash_type()))
+    }
+
+    /// 任意一个方向达到当前加密算法或者用户设置的字节数限制，
+    /// 或者密钥使用的时间超过了用户设置的时间时需要重新协商密钥
+    /// 正在重新协商或者还没有完成第一次密钥交换时为 false
+    pub(crate) fn rekey_needed(&self) -> bool {
+        let ciphers = match &self.encryption {
+            Some(v) if !self.rekeying => v,
+            _ => return false
+        };
+        let limit = self.rekey_limit;
+        let client_max = ciphers.client_to_server.max_bytes_before_rekey().min(limit.bytes);
+        let server_max = ciphers.server_to_client.max_bytes_before_rekey().min(limit.bytes);
+        self.sequence.client_traffic.exceeds(client_max)
+            || self.sequence.server_traffic.exceeds(server_max)
+            || limit.interval.is_some_and(|v| self.last_kex.elapsed() >= v)
+    }
+
+    /// 服务端断开连接后，读写和关闭都返回 `SshErrorKind::Disconnected`
+    pub(crate) fn disconnect_error(&self) -> Option<SshError> {
+        self.disconnected.as_ref().map(|(code, description)| SshError::from(SshErrorKind::Disconnected {
+            code: *code,
+            description: description.clone()
+        }))
+    }
 
+    /// 服务端断开连接时连接已经关闭
     pub(crate) fn close(&mut self) -> Result<(), SshError> {
-        match self.stream.shutdown(Shutdown::Both) {
+        if let Some(e) = self.disconnect_error() {
+            return Err(e)
+        }
+        self.closed = true;
+        match self.stream.shutdown() {
             Ok(o) => Ok(o),
             Err(e) => Err(SshError::from(e))
         }
     }
 
+    /*
+        byte      SSH_MSG_DISCONNECT
+        uint32    reason code
+        string    description in ISO-10646 UTF-8 encoding [RFC3629]
+        string    language tag [RFC3066]
+    */
+    /// 发送 SSH_MSG_DISCONNECT 并关闭连接
+    pub(crate) fn disconnect(&mut self, reason: u8, description: &str) -> SshResult<()> {
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_DISCONNECT)
+            .put_u32(reason as u32)
+            .put_str(description)
+            .put_str("");
+        self.write(data)?;
+        self.close()
+    }
+
+    /// 服务端违反了协议，发送 SSH_DISCONNECT_PROTOCOL_ERROR 并断开连接
+    pub(crate) fn protocol_violation(&mut self, message: &str) -> SshError {
+        log::error!("protocol violation: {}", message);
+        if let Err(e) = self.disconnect(ssh_msg_code::SSH_DISCONNECT_PROTOCOL_ERROR, message) {
+            log::error!("disconnect error: {:?}", e);
+        }
+        SshError::from(SshErrorKind::ProtocolViolation {
+            message: message.to_string()
+        })
+    }
+
+    /// 分配一个客户端通道编号
+    pub(crate) fn next_client_channel(&mut self) -> u32 {
+        let client_channel = self.next_channel;
+        self.next_channel = self.next_channel.wrapping_add(1);
+        client_channel
+    }
+
+    pub(crate) fn register_channel(&mut self, client_channel: u32) {
+        self.channels.insert(client_channel, VecDeque::new());
+        self.records.insert(client_channel, ChannelRecord::default());
+    }
+
+    pub(crate) fn remove_channel(&mut self, client_channel: u32) {
+        self.channels.remove(&client_channel);
+        self.records.remove(&client_channel);
+    }
+
+    /// 本地发送了 SSH_MSG_CHANNEL_CLOSE
+    pub(crate) fn local_closed(&mut self, client_channel: u32) {
+        if let Some(record) = self.records.get_mut(&client_channel) {
+            record.initiator.get_or_insert(CloseInitiator::Local);
+        }
+    }
+
+    /// 连接上暂时没有数据 (WouldBlock) 时等待 poll_interval 再读取，避免一直占用 cpu
+    /// 阻塞模式下读取已经在内核中等待过 BLOCKING_READ_TIMEOUT
+    pub(crate) fn idle(&self) {
+        if !self.poll_interval.is_zero() && !self.deadline_passed() {
+            thread::sleep(self.poll_interval)
+        }
+    }
+
+    pub(crate) fn deadline_passed(&self) -> bool {
+        self.read_deadline.is_some_and(|v| Instant::now() >= v)
+    }
+
+    /// 阻塞模式下读取超时时，unix 返回 WouldBlock，windows 返回 TimedOut
     pub(crate) fn is_would_block(e: &io::Error) -> bool {
-        e.kind() == io::ErrorKind::WouldBlock
+        matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
     }
 
 }
 
-impl Deref for Client {
-    type Target = TcpStream;
 
-    fn deref(&self) -> &Self::Target {
-        &self.stream
+
+
+// 同一个 session 下的所有通道共享一个 client
+pub(crate) fn locking(client: &Arc<Mutex<Client>>) -> SshResult<MutexGuard<'_, Client>> {
+    match client.lock() {
+        Ok(v) => Ok(v),
+        Err(e) => {
+            log::error!("get client mutex error, error info: {:?}", e);
+            Err(SshError::from(format!("get client mutex error, error info: {:?}", e)))
+        }
     }
 }
 
-impl DerefMut for Client {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.stream
+/// 只有一个地址时返回原来的 io 错误，
+/// 多个地址都失败时返回一个包含每个地址错误的 io 错误，ErrorKind 是最后一个地址的
+fn connect_error(mut errors: Vec<(SocketAddr, io::Error)>) -> SshError {
+    if errors.len() <= 1 {
+        return match errors.pop() {
+            Some((_, e)) => SshError::from(e),
+            None => SshError::from("could not resolve to any address")
+        }
     }
+    let kind = errors.last().map(|(_, e)| e.kind()).unwrap_or(io::ErrorKind::Other);
+    let message = errors.iter()
+        .map(|(addr, e)| format!("{}: {}", addr, e))
+        .collect::<Vec<String>>()
+        .join("; ");
+    SshError::from(io::Error::new(kind, format!("could not connect to any address: {}", message)))
 }
 
 
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use crate::config::AlgorithmList;
+    use crate::constant::{algorithms, ssh_msg_code};
+    use crate::data::Data;
+    use crate::mock::{self, MockServer};
+    use crate::packet::Packet;
+    use super::Client;
 
-static mut CLIENT: Option<Client> = None;
+    fn connect(cipher: &str) -> (Client, MockServer) {
+        let (mut client, server) = mock::connection();
+        let algorithm = &mut client.config.algorithm;
+        algorithm.client_algorithm.c_encryption_algorithm.0 = vec![cipher.to_string()];
+        algorithm.client_algorithm.s_encryption_algorithm.0 = vec![cipher.to_string()];
+        algorithm.server_algorithm = AlgorithmList::client_algorithm();
+        client.encryption = Some(algorithm.matching_encryption_algorithm(&mock::keys()).unwrap());
+        (client, server)
+    }
 
-pub(crate) fn connect<A: ToSocketAddrs>(addr: A) -> Result<(), SshError> {
-    unsafe {
-        let client = Client::connect(addr)?;
-        CLIENT = Some(client);
-        Ok(())
+    // 服务端用同样的算法和密钥解密 client 发送的数据包
+    fn decrypt(client: &Client, buf: &[u8]) -> Vec<Vec<u8>> {
+        let mut decryption = client.config.algorithm
+            .matching_encryption_algorithm(&mock::keys()).unwrap()
+            .client_to_server;
+        let mac_len = decryption.integrity().len();
+        let mut payloads = vec![];
+        let mut pos = 0;
+        let mut sequence_num = 0;
+        while pos < buf.len() {
+            let len = decryption.packet_len(sequence_num, &buf[pos..]) + mac_len;
+            let mut packet = buf[pos..pos + len].to_vec();
+            let plain = decryption.decrypt(sequence_num, &mut packet).unwrap();
+            payloads.push(Packet::from(plain).unpacking().unwrap().to_vec());
+            pos += len;
+            sequence_num += 1;
+        }
+        payloads
     }
-}
 
-pub(crate) fn default() -> SshResult<&'static mut Client> {
-    unsafe {
-        match &mut CLIENT {
-            None => {
-                log::error!("Client null pointer");
-                Err(SshError::from("Client null pointer"))
-            }
-            Some(v) => {
-                Ok(v)
+    #[test]
+    fn clients_hold_separate_cipher_state() {
+        let ciphers = [algorithms::ENCRYPTION_AES128_CTR, algorithms::ENCRYPTION_CHACHA20_POLY1305_OPENSSH];
+        let handles = ciphers.map(|cipher| thread::spawn(move || {
+            let (mut client, server) = connect(cipher);
+            let mut expected = vec![];
+            for i in 0..20_u8 {
+                let mut data = Data::new();
+                data.put_u8(ssh_msg_code::SSH_MSG_IGNORE).put_u8s(&[i; 100]);
+                expected.push(data.to_vec());
+                client.write(data).unwrap();
+                thread::yield_now();
             }
-        }
+            assert_eq!(decrypt(&client, &server.received_raw()), expected);
+            client.config.algorithm.negotiated().cipher_client_to_server
+        }));
+        let negotiated = handles.map(|v| v.join().unwrap());
+        assert_eq!(negotiated, ciphers);
+    }
+
+    #[test]
+    fn channel_numbers_are_per_client_and_unique() {
+        let (client, _server) = mock::connection();
+        let client = Arc::new(Mutex::new(client));
+        let handles = (0..4).map(|_| {
+            let client = client.clone();
+            thread::spawn(move || (0..100).map(|_| client.lock().unwrap().next_client_channel()).collect::<Vec<u32>>())
+        }).collect::<Vec<_>>();
+        let numbers = handles.into_iter().flat_map(|v| v.join().unwrap()).collect::<HashSet<u32>>();
+        assert_eq!(numbers, (0..400).collect::<HashSet<u32>>());
+        // 另一个连接的通道编号重新从 0 开始
+        let (mut other, _server) = mock::connection();
+        assert_eq!(other.next_client_channel(), 0);
     }
 }
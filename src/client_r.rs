@@ -1,119 +1,673 @@
-use std::io;
 use std::io::Read;
-use std::sync::atomic::Ordering::Relaxed;
-use crate::client::Client;
-use crate::constant::size;
-use crate::data::Data;
-use crate::algorithm::encryption::IS_ENCRYPT;
-use crate::{SshError, SshResult};
-use crate::algorithm::encryption;
+use std::time::Instant;
+use crate::client::{Client, PendingRequest};
+use crate::constant::{ssh_msg_code, ssh_str};
+use crate::limits;
+use crate::data::{Data, Reader};
+use crate::{kex, util, SshError, SshResult};
+use crate::error::SshErrorKind;
+use crate::channel::{CloseInitiator, ExitSignal};
+use crate::event::{ChannelRecord, Event};
 use crate::packet::Packet;
 use crate::window_size::WindowSize;
 
 impl Client {
 
-    pub(crate) fn read_version(&mut self) -> Vec<u8> {
-        let mut v = [0_u8; 128];
+    /// 读取服务端的版本行，版本行之前的其它文本行会被忽略 (RFC 4253 4.2)
+    /// 行以 CR LF 结尾，也接受只有 LF 的行，返回的版本不包括行尾，
+    /// 同一次读取中版本行之后的数据 (例如 SSH_MSG_KEXINIT) 留给之后的读取
+    pub(crate) fn read_version(&mut self) -> SshResult<String> {
+        let mut buf = vec![];
+        let mut banner_len = 0;
         loop {
+            while let Some(pos) = buf.iter().position(|v| *v == b'\n') {
+                let remaining = buf.split_off(pos + 1);
+                let mut line = std::mem::replace(&mut buf, remaining);
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                if line.starts_with(b"SSH-") {
+                    if line.len() + 2 > limits::MAX_VERSION_LEN {
+                        log::error!("server version is too long.");
+                        return Err(SshError::from("error in version negotiation, server version is too long."))
+                    }
+                    self.pending = buf;
+                    return util::from_utf8(line)
+                }
+                banner_len += line.len();
+                log::info!("server banner: [{}]", String::from_utf8_lossy(&line));
+            }
+            if banner_len + buf.len() > limits::MAX_PRE_BANNER_LEN {
+                log::error!("too much text before the server version.");
+                return Err(SshError::from("error in version negotiation, server version not found."))
+            }
+            let mut v = [0_u8; 128];
             match self.stream.read(&mut v) {
-                Ok(i) => { return (&v[..i]).to_vec() }
-                Err(_) => continue
+                Ok(0) => {
+                    log::error!("connection closed before the server version.");
+                    return Err(SshError::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)))
+                }
+                Ok(i) => buf.extend_from_slice(&v[..i]),
+                Err(e) => self.would_block(e)?
             };
         }
     }
 
+    /// 读取非通道的消息
+    /// 读到的通道消息会放到对应通道的队列中
     pub(crate) fn read(&mut self) -> Result<Vec<Data>, SshError> {
-        self.read_data(None)
+        let mut results = vec![];
+        for data in self.read_packets()? {
+            match recipient_channel(data.as_slice()) {
+                Some(cc) => self.push_channel_data(cc, data),
+                None => results.push(data)
+            }
+        }
+        Ok(results)
+    }
+
+    /// 读取某个通道的消息，同时处理本地窗口大小
+    pub(crate) fn read_data(&mut self, lws: &mut WindowSize) -> SshResult<Vec<Data>> {
+        let results = self.read_channel(lws.client_channel)?;
+        for data in &results {
+            lws.process_local_window_size(data.as_slice(), self)?
+        }
+        Ok(results)
+    }
+
+    /// 与 `read_data` 相同，但是到了 deadline 就不再等待：
+    /// 一个数据包只读到一部分时保存起来，下一次读取时继续，
+    /// 没有数据时也不会因为 `Session::set_timeout` 返回超时错误
+    pub(crate) fn read_data_before(&mut self, lws: &mut WindowSize, deadline: Instant) -> SshResult<Vec<Data>> {
+        self.read_deadline = Some(deadline);
+        let results = self.read_data(lws);
+        self.read_deadline = None;
+        results
+    }
+
+    /// 读取某个通道的消息，不处理窗口大小
+    /// 先返回队列中缓存的消息，队列为空时才从连接读取，
+    /// 返回值中还会包含非通道的消息 (例如 SSH_MSG_KEXINIT)
+    pub(crate) fn read_channel(&mut self, client_channel: u32) -> SshResult<Vec<Data>> {
+        if let Some(queue) = self.channels.get_mut(&client_channel) {
+            if !queue.is_empty() {
+                return Ok(queue.drain(..).collect())
+            }
+        }
+        let mut results = vec![];
+        for data in self.read_packets()? {
+            match recipient_channel(data.as_slice()) {
+                Some(cc) if cc != client_channel => self.push_channel_data(cc, data),
+                _ => results.push(data)
+            }
+        }
+        Ok(results)
+    }
+
+    /// 等待远程窗口时读取连接，返回这次收到的这个通道的 WINDOW_ADJUST 增加的窗口大小
+    /// 通道队列中只取出 WINDOW_ADJUST，其它消息留在队列中，读到的其它通道消息放到对应通道的队列中，
+    /// 重新协商密钥在这里完成，全局请求见 `receive_other`
+    pub(crate) fn read_window_adjust(&mut self, client_channel: u32) -> SshResult<u32> {
+        let mut size: u32 = 0;
+        if let Some(queue) = self.channels.get_mut(&client_channel) {
+            queue.retain(|data| {
+                if data.first() != Some(&ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST) {
+                    return true
+                }
+                size = size.saturating_add(window_adjust_size(data.as_slice()));
+                false
+            });
+        }
+        if size > 0 {
+            return Ok(size)
+        }
+        for mut data in self.read_packets()? {
+            match recipient_channel(data.as_slice()) {
+                Some(cc) if cc == client_channel
+                    && data[0] == ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST => {
+                    size = size.saturating_add(window_adjust_size(data.as_slice()));
+                }
+                Some(cc) => self.push_channel_data(cc, data),
+                None if data.first() == Some(&ssh_msg_code::SSH_MSG_KEXINIT) => {
+                    data.get_u8();
+                    kex::rekey(self, data)?
+                }
+                None => self.receive_other(data, "window adjust")?
+            }
+        }
+        Ok(size)
+    }
+
+    /// 等待某个消息时收到的其它非通道消息，waiting 为等待的消息，只用于日志
+    /// 全局请求和 `next_event` 中一样回应，其它消息不处理。
+    /// 全局请求的回应 (REQUEST_SUCCESS / FAILURE) 已经在 `intercept` 中处理
+    pub(crate) fn receive_other(&mut self, mut data: Data, waiting: &str) -> SshResult<()> {
+        if data.first() == Some(&ssh_msg_code::SSH_MSG_GLOBAL_REQUEST) {
+            data.get_u8();
+            self.global_request(data)?;
+            return Ok(())
+        }
+        log::debug!("ignore message while waiting for {}: [{:?}]", waiting, data.first());
+        Ok(())
     }
 
-    pub(crate) fn read_data(&mut self, lws: Option<&mut WindowSize>) -> SshResult<Vec<Data>> {
+    /// 放回通道队列的最前面，下一次 read_channel 先返回这些消息
+    pub(crate) fn unread_channel(&mut self, client_channel: u32, data: Vec<Data>) {
+        if let Some(queue) = self.channels.get_mut(&client_channel) {
+            for v in data.into_iter().rev() {
+                queue.push_front(v)
+            }
+        }
+    }
+
+    pub(crate) fn push_channel_data(&mut self, client_channel: u32, data: Data) {
+        match self.channels.get_mut(&client_channel) {
+            Some(queue) => queue.push_back(data),
+            None => log::debug!("drop message of unknown channel: [{}]", client_channel)
+        }
+    }
+
+    fn read_packets(&mut self) -> SshResult<Vec<Data>> {
+        // 服务端已经断开了连接
+        if let Some(e) = self.disconnect_error() {
+            return Err(e)
+        }
+
         // 判断超时时间
-        // 如果超时,即抛出异常
-        self.timeout.is_timeout()?;
+        // 如果超时,即抛出异常，设置了截止时间时由调用方决定等待多久
+        if self.read_deadline.is_none() {
+            self.timeout.is_timeout()?;
+        }
+
+        // 收到的数据达到加密算法的限制之前重新协商密钥
+        if self.rekey_needed() {
+            kex::initiate_rekey(self)?
+        }
+
+        // 上一次读取之后 agent、X server 的回应
+        self.pump_forwarded()?;
 
+        // 每个数据包和它的序列号
         let mut results = vec![];
-        let mut result = vec![0; size::BUF_SIZE as usize];
-        let len = match self.stream.read(&mut result) {
+        // 上一次读取中 SSH_MSG_NEWKEYS 之后的数据，新的密钥已经生效
+        let mut result = std::mem::take(&mut self.pending);
+        if result.is_empty() {
+            result = vec![0; limits::MAX_PACKET_SIZE];
+            let len = self.read_stream(&mut result)?;
+            if len == 0 {
+                return Ok(vec![])
+            }
+            result.truncate(len);
+        }
+        // 处理未加密数据
+        if self.encryption.is_none() {
+            self.process_data(result, &mut results)?
+        }
+        // 处理加密数据
+        else {
+            self.process_data_encrypt(result, &mut results)?
+        }
+
+        for (_, data) in &results {
+            self.record_message(data.as_slice());
+            if let Some(cc) = recipient_channel(data.as_slice()) {
+                self.track_channel(cc, data.as_slice())
+            }
+        }
+
+        // 保活、主机密钥通告和证明的回应、IGNORE、DEBUG 不需要交给调用方
+        let mut unhandled = vec![];
+        for (sequence_num, data) in results {
+            // 第一次密钥交换完成之前还没有加密
+            if self.encryption.is_none() {
+                kex::check_initial_kex(self, sequence_num, data.as_slice())?
+            }
+            // 严格的密钥交换中每次 SSH_MSG_NEWKEYS 之后序列号从 0 开始，之后的数据包还没有处理
+            if self.kex_strict && data.first() == Some(&ssh_msg_code::SSH_MSG_NEWKEYS) {
+                self.sequence.server_sequence_num = 0
+            }
+            if data.first() == Some(&ssh_msg_code::SSH_MSG_DISCONNECT) {
+                self.receive_disconnect(data)?;
+                break
+            }
+            if data.first().is_some_and(|v| !is_known_message(*v)) {
+                self.send_unimplemented(sequence_num, data[0])?;
+                continue
+            }
+            if !self.intercept(data.as_slice())? {
+                unhandled.push(data)
+            }
+        }
+        let results = unhandled;
+        // 刚收到的 agent、x11 数据
+        self.pump_forwarded()?;
+
+        // 断开之前收到的消息先返回，下一次读取再返回错误
+        Ok(results)
+    }
+
+    /// 从连接读取数据，没有数据时返回 0
+    fn read_stream(&mut self, buf: &mut [u8]) -> SshResult<usize> {
+        match self.stream.read(buf) {
             Ok(len) => {
-                if len <= 0 {
-                    return Ok(results)
+                if len == 0 {
+                    return Ok(0)
                 }
 
                 // 从服务段正常读取到数据的话
                 // 就刷新超时时间
                 self.timeout.renew();
+                if let Some(keepalive) = &mut self.keepalive {
+                    keepalive.renew()
+                }
 
-                len
+                Ok(len)
             },
             Err(e) => {
                 if Client::is_would_block(&e) {
-                    return Ok(results)
+                    // 空闲时发送保活请求
+                    self.send_keepalive()?;
+                    self.idle();
+                    return Ok(0)
                 }
-                return Err(SshError::from(e))
+                Err(SshError::from(e))
+            }
+        }
+    }
+
+    /// 读取一次连接，返回下一个事件
+    /// 通道消息会先放到对应通道的队列中
+    pub(crate) fn next_event(&mut self) -> SshResult<Option<Event>> {
+        if self.events.is_empty() {
+            for data in self.read_packets()? {
+                self.dispatch_event(data)?
+            }
+        }
+        Ok(self.events.pop_front())
+    }
+
+    fn dispatch_event(&mut self, mut data: Data) -> SshResult<()> {
+        if let Some(cc) = recipient_channel(data.as_slice()) {
+            // 已经关闭或者没有打开过的通道
+            if !self.channels.contains_key(&cc) {
+                log::debug!("drop message of unknown channel: [{}]", cc);
+                return Ok(())
             }
+            let event = match data[0] {
+                ssh_msg_code::SSH_MSG_CHANNEL_DATA
+                | ssh_msg_code::SSH_MSG_CHANNEL_EXTENDED_DATA => Some(Event::ChannelData { id: cc }),
+                ssh_msg_code::SSH_MSG_CHANNEL_CLOSE => self.records.get(&cc)
+                    .map(|v| Event::ChannelClosed { id: cc, summary: v.summary() }),
+                _ => None
+            };
+            self.push_channel_data(cc, data);
+            self.events.extend(event);
+            return Ok(())
+        }
+        if data.is_empty() {
+            return Ok(())
+        }
+        let message_code = data.get_u8();
+        match message_code {
+            ssh_msg_code::SSH_MSG_GLOBAL_REQUEST => {
+                let name = self.global_request(data)?;
+                self.events.push_back(Event::GlobalRequest { name })
+            }
+            ssh_msg_code::SSH_MSG_KEXINIT => {
+                kex::rekey(self, data)?;
+                self.events.push_back(Event::RekeyCompleted)
+            }
+            _ => log::debug!("ignore message: [{}]", message_code)
+        }
+        Ok(())
+    }
+
+    /*
+        byte      SSH_MSG_GLOBAL_REQUEST
+        string    request name in US-ASCII only
+        boolean   want reply
+        ....      request-specific data follows
+    */
+    /// 不支持任何服务端的全局请求 (例如 ClientAliveInterval 的 keepalive@openssh.com)，
+    /// 只在 want reply 为 true 时回复 SSH_MSG_REQUEST_FAILURE，返回请求的名称。
+    /// data 中已经取出了消息代码
+    pub(crate) fn global_request(&mut self, data: Data) -> SshResult<String> {
+        let mut reader = Reader::new(data.as_slice(), "invalid global request.");
+        let name = util::from_utf8(reader.string()?.to_vec())?;
+        let want_reply = reader.u8()? != 0;
+        log::info!("server global request: {}, want reply: {}", name, want_reply);
+        if want_reply {
+            let mut data = Data::new();
+            data.put_u8(ssh_msg_code::SSH_MSG_REQUEST_FAILURE);
+            self.write(data)?;
+        }
+        Ok(name)
+    }
+
+    /*
+        byte      SSH_MSG_DISCONNECT
+        uint32    reason code
+        string    description in ISO-10646 UTF-8 encoding [RFC3629]
+        string    language tag [RFC3066]
+    */
+    /// 服务端断开连接，关闭连接并记录原因
+    /// poll_event 先返回 Disconnected 事件，其它读取返回 `SshErrorKind::Disconnected`
+    fn receive_disconnect(&mut self, mut data: Data) -> SshResult<()> {
+        data.get_u8();
+        let code = data.get_u32();
+        let description = util::from_utf8(data.get_u8s())?;
+        log::info!("server disconnected, reason code: {}, description: {}", code, description);
+        if let Err(e) = self.close() {
+            log::debug!("close after server disconnect: {}", e)
+        }
+        self.events.push_back(Event::Disconnected { reason: description.clone() });
+        self.disconnected = Some((code, description));
+        Ok(())
+    }
+
+    /*
+        byte      SSH_MSG_DEBUG
+        boolean   always_display
+        string    message in ISO-10646 UTF-8 encoding [RFC3629]
+        string    language tag [RFC3066]
+    */
+    /// always_display 为 true 的消息应该展示给用户，其它的只用于调试
+    fn receive_debug(&mut self, data: &[u8]) {
+        let mut data = Data::from(data);
+        data.get_u8();
+        let always_display = data.get_u8() != 0;
+        let message = String::from_utf8_lossy(&data.get_u8s()).to_string();
+        match always_display {
+            true => log::info!("server debug message: {}", message),
+            false => log::debug!("server debug message: {}", message)
+        }
+    }
+
+    /*
+        byte       SSH_MSG_EXT_INFO
+        uint32     nr-extensions
+        repeat the following 2 fields "nr-extensions" times:
+          string   extension-name
+          string   extension-value (binary)
+    */
+    /// 只使用 server-sig-algs，其它扩展忽略。格式错误的消息只记录日志，之后按服务端没有发送处理
+    fn receive_ext_info(&mut self, data: &[u8]) {
+        let mut reader = Reader::new(&data[1..], "invalid ext info.");
+        let count = match reader.u32() {
+            Ok(v) => v,
+            Err(e) => return log::error!("ignore ext info message, error info: {:?}", e)
         };
+        for _ in 0..count {
+            let extension = reader.string().and_then(|name| Ok((name, reader.string()?)));
+            let (name, value) = match extension {
+                Ok(v) => v,
+                Err(e) => return log::error!("ignore ext info message, error info: {:?}", e)
+            };
+            if name != ssh_str::SERVER_SIG_ALGS.as_bytes() {
+                log::debug!("ignore extension: {}", String::from_utf8_lossy(name));
+                continue
+            }
+            let algorithms = String::from_utf8_lossy(value).to_string();
+            log::info!("server signature algorithms: [{}]", algorithms);
+            self.server_sig_algs = Some(algorithms.split(',').map(|v| v.to_string()).collect())
+        }
+    }
 
-        result.truncate(len);
-        // 处理未加密数据
-        if !IS_ENCRYPT.load(Relaxed) {
-            self.process_data(result, &mut results);
+    /*
+        byte      SSH_MSG_UNIMPLEMENTED
+        uint32    packet sequence number of rejected message
+    */
+    /// 不认识的消息按收到的顺序回应 SSH_MSG_UNIMPLEMENTED (RFC 4253 11.4)
+    fn send_unimplemented(&mut self, sequence_num: u32, message_code: u8) -> SshResult<()> {
+        log::info!("unimplemented message: [{}], sequence number: {}", message_code, sequence_num);
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_UNIMPLEMENTED)
+            .put_u32(sequence_num);
+        self.write(data)
+    }
+
+    /*
+        byte      SSH_MSG_CHANNEL_OPEN
+        string    channel type in US-ASCII only
+        uint32    sender channel
+        uint32    initial window size
+        uint32    maximum packet size
+        ....      channel type specific data follows
+    */
+    /// 只接受 agent 和 X11 转发的通道，其它服务端发起的通道 (例如 forwarded-tcpip)
+    /// 回应 SSH_MSG_CHANNEL_OPEN_FAILURE
+    fn receive_channel_open(&mut self, data: &[u8]) -> SshResult<()> {
+        let mut reader = Reader::new(data.get(1..).unwrap_or_default(), "invalid channel open.");
+        let channel_type = String::from_utf8_lossy(reader.string()?).to_string();
+        let sender_channel = reader.u32()?;
+        if channel_type == ssh_str::AUTH_AGENT || channel_type == ssh_str::X11 {
+            let window = reader.u32()?;
+            let max_packet_size = reader.u32()?;
+            return match channel_type.as_str() {
+                ssh_str::AUTH_AGENT => self.accept_agent_channel(sender_channel, window, max_packet_size),
+                _ => self.accept_x11_channel(sender_channel, window, max_packet_size)
+            }
         }
-        // 处理加密数据
-        else {
-            self.process_data_encrypt(result, &mut results, lws)?
+        log::info!("reject channel open request from server: [{}]", channel_type);
+        self.send_open_failure(sender_channel,
+                               ssh_msg_code::SSH_OPEN_UNKNOWN_CHANNEL_TYPE,
+                               format!("unsupported channel type: {}", channel_type).as_str())
+    }
+
+    /*
+        byte      SSH_MSG_CHANNEL_OPEN_FAILURE
+        uint32    recipient channel
+        uint32    reason code
+        string    description in ISO-10646 UTF-8 encoding
+        string    language tag
+    */
+    pub(crate) fn send_open_failure(&mut self, sender_channel: u32, reason_code: u32, description: &str) -> SshResult<()> {
+        let mut reply = Data::new();
+        reply.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_OPEN_FAILURE)
+            .put_u32(sender_channel)
+            .put_u32(reason_code)
+            .put_str(description)
+            .put_str("");
+        self.write(reply)
+    }
+
+    /// 处理 client 自己发起或负责的消息，处理过的消息返回 true
+    fn intercept(&mut self, data: &[u8]) -> SshResult<bool> {
+        match data.first() {
+            Some(&ssh_msg_code::SSH_MSG_CHANNEL_OPEN) => {
+                self.receive_channel_open(data)?;
+                Ok(true)
+            }
+            Some(&ssh_msg_code::SSH_MSG_IGNORE) => Ok(true),
+            Some(&ssh_msg_code::SSH_MSG_EXT_INFO) => {
+                self.receive_ext_info(data);
+                Ok(true)
+            }
+            Some(&ssh_msg_code::SSH_MSG_DEBUG) => {
+                self.receive_debug(data);
+                Ok(true)
+            }
+            Some(&ssh_msg_code::SSH_MSG_UNIMPLEMENTED) => {
+                let sequence_num = Data::from(&data[1..]).get_u32();
+                log::error!("server does not implement the message with sequence number {}.", sequence_num);
+                Ok(true)
+            }
+            Some(&ssh_msg_code::SSH_MSG_GLOBAL_REQUEST) => {
+                let name = Reader::new(&data[1..], "invalid global request.").string().ok();
+                if name != Some(ssh_str::HOSTKEYS.as_bytes()) {
+                    return Ok(false)
+                }
+                self.receive_host_keys(data)?;
+                Ok(true)
+            }
+            Some(&ssh_msg_code::SSH_MSG_REQUEST_SUCCESS)
+            | Some(&ssh_msg_code::SSH_MSG_REQUEST_FAILURE) => {
+                match self.global_requests.pop_front() {
+                    None => Ok(false),
+                    Some(PendingRequest::Keepalive) => {
+                        if let Some(keepalive) = &mut self.keepalive {
+                            keepalive.reply()
+                        }
+                        Ok(true)
+                    }
+                    Some(PendingRequest::HostKeysProve(request)) => {
+                        self.receive_host_keys_proof(request, data);
+                        Ok(true)
+                    }
+                }
+            }
+            // agent、x11 通道的消息, 见 forwarded_channel
+            _ => match recipient_channel(data) {
+                Some(cc) if self.forwarded.contains_key(&cc) => {
+                    self.receive_forwarded_message(cc, data)?;
+                    Ok(true)
+                }
+                _ => Ok(false)
+            }
         }
+    }
 
-        Ok(results)
+    fn record_message(&mut self, data: &[u8]) {
+        let message_code = match data.first() {
+            Some(v) => *v,
+            None => return
+        };
+        if self.recent_messages.len() >= limits::RECENT_MESSAGES_LEN {
+            self.recent_messages.pop_front();
+        }
+        self.recent_messages.push_back(message_code)
+    }
+
+    /// 记录通道的状态，不影响消息本身的处理
+    fn track_channel(&mut self, client_channel: u32, data: &[u8]) {
+        let record = match self.records.get_mut(&client_channel) {
+            Some(v) => v,
+            None => return
+        };
+        // 格式错误的消息由通道自己处理时报错，这里只是不记录
+        if let Err(e) = track_message(record, data) {
+            log::debug!("channel [{}] message not tracked: {:?}", client_channel, e)
+        }
+    }
+
+    /*
+        byte      SSH_MSG_GLOBAL_REQUEST
+        string    "keepalive@openssh.com"
+        boolean   want reply
+    */
+    fn send_keepalive(&mut self) -> SshResult<()> {
+        let keepalive = match &mut self.keepalive {
+            Some(v) if v.is_due() => v,
+            _ => return Ok(())
+        };
+        // 密钥交换过程中不发送，重新协商密钥时也一样，否则请求会排队到新的密钥生效之后
+        if self.encryption.is_none() || self.rekeying {
+            return Ok(())
+        }
+        if let Err(e) = keepalive.sent() {
+            self.close()?;
+            return Err(e)
+        }
+        log::debug!("send keepalive.");
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_GLOBAL_REQUEST)
+            .put_str(ssh_str::KEEPALIVE)
+            .put_u8(true as u8);
+        self.write(data)?;
+        self.global_requests.push_back(PendingRequest::Keepalive);
+        Ok(())
     }
 
-    fn process_data(&mut self, mut result: Vec<u8>, results: &mut Vec<Data>) {
+    fn process_data(&mut self, mut result: Vec<u8>, results: &mut Vec<(u32, Data)>) -> SshResult<()> {
         // 未加密
-        self.sequence.server_auto_increment();
-        let packet_len = &result[..4];
-        let mut packet_len_slice = [0_u8; 4];
-        packet_len_slice.copy_from_slice(packet_len);
-        let packet_len = (u32::from_be_bytes(packet_len_slice) as usize) + 4;
-        // 唯一处理 server Key Exchange Reply 和 New Keys 会一块发
-        if result.len() > packet_len {
-            let (v1, v2) = result.split_at_mut(packet_len);
-            let data = Packet::from(v1.to_vec()).unpacking();
-            results.push(data);
-            result = v2.to_vec();
+        // 唯一处理 server Key Exchange Reply 和 New Keys 会一块发，
+        // New Keys 之后的数据包已经使用新的密钥加密
+        loop {
+            let sequence_num = self.sequence.server_sequence_num;
+            if !self.fill_packet(&mut result, limits::PACKET_LENGTH_LEN)? {
+                break;
+            }
+            let packet_len = Reader::new(&result, "invalid packet length.").u32()? as usize;
+            let packet_len = match packet_len.checked_add(limits::PACKET_LENGTH_LEN) {
+                Some(v) if v <= limits::MAX_PACKET_LEN => v,
+                _ => return Err(self.protocol_violation("invalid packet length"))
+            };
+            if !self.fill_packet(&mut result, packet_len)? {
+                break;
+            }
+            self.sequence.server_auto_increment();
+            let remaining = result.split_off(packet_len);
+            let data = self.unpack(result)?;
+            let new_keys = data.first() == Some(&ssh_msg_code::SSH_MSG_NEWKEYS);
+            results.push((sequence_num, data));
+            if new_keys && !remaining.is_empty() {
+                self.pending = remaining;
+                break;
+            }
+            if remaining.is_empty() {
+                break;
+            }
+            result = remaining;
         }
-        let data = Packet::from(result).unpacking();
-        results.push(data);
+        Ok(())
+    }
 
+    /// 去掉 packet_length、padding_length 和 padding，padding_length 不合法时断开连接
+    fn unpack(&mut self, packet: Vec<u8>) -> SshResult<Data> {
+        match Packet::from(packet).unpacking() {
+            Ok(v) => Ok(v),
+            Err(_) => Err(self.protocol_violation("invalid packet padding length"))
+        }
     }
 
     fn process_data_encrypt(&mut self,
                             mut result: Vec<u8>,
-                            results: &mut Vec<Data>,
-                            mut lws: Option<&mut WindowSize>)
+                            results: &mut Vec<(u32, Data)>)
         -> SshResult<()>
     {
         loop {
-            self.sequence.server_auto_increment();
-            if result.len() < 4 {
-                self.check_result_len(&mut result)?;
+            // 第一个数据包的序列号为 0，数据包读完之后才增加
+            let sequence_num = self.sequence.server_sequence_num;
+            // 解密 packet_length 需要第一个完整的分组，数据包不会比一个分组短
+            let min_len = self.encryption()?.server_to_client.bsize().max(limits::PACKET_LENGTH_LEN);
+            if !self.fill_packet(&mut result, min_len)? {
+                break;
             }
-            let key = encryption::get();
-            let data_len = key.data_len(self.sequence.server_sequence_num, result.as_slice());
-            // let packet_len = self.get_encrypt_packet_length(&result[..4], key);
-            // let data_len = (packet_len + 4 + 16) as usize;
-
-            if result.len() < data_len {
-                self.get_encrypt_data(&mut result, data_len)?;
+            // 各个算法的 packet_len 都不改变解密的状态，下一次读取时可以重新计算
+            let data_len = self.encryption()?.server_to_client.packet_len(sequence_num, result.as_slice())
+                + self.integrity.server_to_client.len();
+            if !self.fill_packet(&mut result, data_len)? {
+                break;
             }
+            self.sequence.server_auto_increment();
             let (this, remaining) = result.split_at_mut(data_len);
+            self.sequence.server_decrypted(data_len);
             let decryption_result =
-                key.decrypt(self.sequence.server_sequence_num, &mut this.to_vec())?;
-            let data = Packet::from(decryption_result).unpacking();
-            // 判断是否需要修改窗口大小
-            if let Some(v) = &mut lws {
-                v.process_local_window_size(data.as_slice(), self)?
-            }
-            results.push(data);
-            if remaining.len() <= 0 {
+                match self.encryption()?.server_to_client.decrypt(sequence_num, &mut this.to_vec()) {
+                    Ok(v) => v,
+                    Err(e) => return Err(self.integrity_failure(e))
+                };
+            let data = self.unpack(decryption_result)?;
+            let data = match self.compression.decompress(data.as_slice()) {
+                None => data,
+                Some(Ok(v)) => Data::from(v),
+                Some(Err(e)) => return Err(self.compression_failure(e))
+            };
+            // zlib@openssh.com 从 SSH_MSG_USERAUTH_SUCCESS 之后的数据包开始压缩，
+            // 同一次读取中后面的数据包已经是压缩的
+            if data.first() == Some(&ssh_msg_code::SSH_MSG_USERAUTH_SUCCESS) {
+                self.compression.authenticated()
+            }
+            // 重新协商密钥时，SSH_MSG_NEWKEYS 之后的数据包使用新的密钥，
+            // 留到调用方处理完 SSH_MSG_NEWKEYS 之后再解密
+            let new_keys = data.first() == Some(&ssh_msg_code::SSH_MSG_NEWKEYS);
+            results.push((sequence_num, data));
+            if new_keys && !remaining.is_empty() {
+                self.pending = remaining.to_vec();
+                break;
+            }
+            if remaining.is_empty() {
                 break;
             }
             result = remaining.to_vec();
@@ -122,68 +676,317 @@ impl Client {
     }
 
 
-    fn get_encrypt_data(&mut self, result: &mut Vec<u8>, data_len: usize) -> SshResult<()> {
-        loop {
-            let mut buf = vec![0; size::BUF_SIZE as usize];
-            match self.stream.read(&mut buf) {
-                Ok(len) => {
-                    if len > 0 {
-                        buf.truncate(len);
-                        result.extend(buf);
-                    }
-                    if result.len() >= data_len {
-                        return Ok(())
-                    }
-                },
-                Err(e) => {
-                    if e.kind() == io::ErrorKind::WouldBlock {
-                        continue;
-                    }
-                    return Err(SshError::from(e))
-                }
-            };
+    /// 校验失败后连接上的数据已经不可信，发送 SSH_MSG_DISCONNECT 并断开连接
+    fn integrity_failure(&mut self, e: SshError) -> SshError {
+        if *e.kind() != SshErrorKind::MacVerificationFailed {
+            return e
+        }
+        if let Err(e) = self.disconnect(ssh_msg_code::SSH_DISCONNECT_MAC_ERROR, "mac verification failure.") {
+            log::error!("disconnect error: {:?}", e);
         }
+        e
     }
 
-    // fn get_encrypt_packet_length(&self, len: &[u8], key: &mut ChaCha20Poly1305) -> u32 {
-    //     let mut packet_len_slice = [0_u8; 4];
-    //     packet_len_slice.copy_from_slice(len);
-    //     let packet_len_slice = key.server_key
-    //         .decrypt_packet_length(
-    //             self.sequence.server_sequence_num,
-    //             packet_len_slice);
-    //     u32::from_be_bytes(packet_len_slice)
-    // }
-
-    // fn get_encrypt_packet_length(&self, len: &[u8], key: &mut AesCtr) -> u32 {
-    //     // let mut packet_len_slice = [0_u8; 4];
-    //     // key.decryption()
-    //     // packet_len_slice.copy_from_slice(len);
-    //     // let packet_len_slice = key.server_key
-    //     //     .decrypt_packet_length(
-    //     //         self.sequence.server_sequence_num,
-    //     //         packet_len_slice);
-    //     // u32::from_be_bytes(packet_len_slice)
-    // }
-
-    fn check_result_len(&mut self, result: &mut Vec<u8>) -> SshResult<usize> {
-        loop {
-            let mut buf = vec![0; size::BUF_SIZE as usize];
+    /// 解压失败后后面的数据包都无法解压，发送 SSH_MSG_DISCONNECT 并断开连接
+    fn compression_failure(&mut self, e: SshError) -> SshError {
+        log::error!("{}", e);
+        if let Err(e) = self.disconnect(ssh_msg_code::SSH_DISCONNECT_COMPRESSION_ERROR, "decompression error.") {
+            log::error!("disconnect error: {:?}", e);
+        }
+        e
+    }
+
+    /// 读取到 result 至少有 len 字节，读完时返回 true
+    /// 到了 `read_deadline` 或者超时时，已经读到的部分放回 pending 留到下一次读取，
+    /// 数据包的序列号还没有增加，同一次读取中已经读完的数据包照常返回，不会错位。
+    /// 超时的错误在下一次读取开始时返回
+    fn fill_packet(&mut self, result: &mut Vec<u8>, len: usize) -> SshResult<bool> {
+        while result.len() < len {
+            let mut buf = vec![0; limits::MAX_PACKET_SIZE];
             match self.stream.read(&mut buf) {
-                Ok(len) => {
-                    buf.truncate(len);
-                    result.extend(buf);
-                    if result.len() >= 4 {
-                        return Ok(len)
-                    }
-                },
-                Err(e) => {
-                    if e.kind() == io::ErrorKind::WouldBlock {
-                        continue;
+                Ok(0) => return Err(connection_closed()),
+                Ok(n) => {
+                    self.timeout.renew();
+                    result.extend_from_slice(&buf[..n]);
+                }
+                Err(e) if Client::is_would_block(&e) => {
+                    if self.deadline_passed() || self.timeout.is_timeout().is_err() {
+                        log::debug!("packet incomplete, {} of {} bytes read.", result.len(), len);
+                        self.pending = std::mem::take(result);
+                        return Ok(false)
                     }
-                    return Err(SshError::from(e))
+                    self.idle()
                 }
+                Err(e) => return Err(SshError::from(e))
             };
         }
+        Ok(true)
+    }
+
+    /// 版本行还没有读完时连接上暂时没有数据，等待之后继续读取
+    /// 超过读取超时时间 (`Session::set_timeout`) 仍然没有数据时返回超时错误，不会一直等待
+    fn would_block(&mut self, e: std::io::Error) -> SshResult<()> {
+        if !Client::is_would_block(&e) {
+            return Err(SshError::from(e))
+        }
+        self.timeout.is_timeout()?;
+        self.idle();
+        Ok(())
+    }
+}
+
+
+/// 一个数据包读到一半时服务端关闭了连接
+fn connection_closed() -> SshError {
+    log::error!("connection closed in the middle of a packet.");
+    SshError::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+}
+
+
+/*
+    byte      SSH_MSG_CHANNEL_WINDOW_ADJUST
+    uint32    recipient channel
+    uint32    bytes to add
+*/
+/// WINDOW_ADJUST 增加的窗口大小，格式错误时不增加
+fn window_adjust_size(data: &[u8]) -> u32 {
+    let mut reader = Reader::new(data, "invalid window adjust.");
+    match reader.u8().and_then(|_| reader.u32()).and_then(|_| reader.u32()) {
+        Ok(v) => v,
+        Err(e) => {
+            log::debug!("{}", e);
+            0
+        }
+    }
+}
+
+
+/// 通道消息的第一个字段是接收方通道编号
+/// 协议中定义的、client 可能收到的消息
+/// 30 - 49 由密钥交换方法定义，60 - 79 由认证方法定义
+fn is_known_message(message_code: u8) -> bool {
+    matches!(message_code, 1..=7 | 20 | 21 | 30..=49 | 50..=53 | 60..=79 | 80..=82 | 90..=100)
+}
+
+fn track_message(record: &mut ChannelRecord, data: &[u8]) -> SshResult<()> {
+    let mut reader = Reader::new(data, "invalid channel message.");
+    let message_code = reader.u8()?;
+    reader.u32()?;
+    match message_code {
+        ssh_msg_code::SSH_MSG_CHANNEL_DATA => {
+            record.bytes_in += reader.string()?.len() as u64
+        }
+        ssh_msg_code::SSH_MSG_CHANNEL_EXTENDED_DATA => {
+            reader.u32()?;
+            record.bytes_in += reader.string()?.len() as u64
+        }
+        ssh_msg_code::SSH_MSG_CHANNEL_EOF => record.eof_received = true,
+        ssh_msg_code::SSH_MSG_CHANNEL_REQUEST => {
+            let request_type = reader.string()?;
+            reader.u8()?;
+            if request_type == ssh_str::EXIT_STATUS.as_bytes() {
+                record.exit_status = Some(reader.u32()?)
+            } else if request_type == ssh_str::EXIT_SIGNAL.as_bytes() {
+                record.exit_signal = Some(ExitSignal::parse(&mut reader)?)
+            }
+        }
+        ssh_msg_code::SSH_MSG_CHANNEL_CLOSE => {
+            record.initiator.get_or_insert(CloseInitiator::Remote);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn recipient_channel(data: &[u8]) -> Option<u32> {
+    match data.first() {
+        Some(&(ssh_msg_code::SSH_MSG_CHANNEL_OPEN_CONFIRMATION..=ssh_msg_code::SSH_MSG_CHANNEL_FAILURE))
+            if data.len() >= 5 => {
+            let mut cc = [0_u8; 4];
+            cc.copy_from_slice(&data[1..5]);
+            Some(u32::from_be_bytes(cc))
+        }
+        _ => None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use crate::constant::{ssh_msg_code, ssh_str};
+    use crate::data::Data;
+    use crate::error::SshErrorKind;
+    use crate::mock;
+
+    /// 未加密的数据包，padding 固定为 4 字节
+    fn packet(payload: &[u8]) -> Vec<u8> {
+        let mut packet = Data::new();
+        packet.put_u32(payload.len() as u32 + 5)
+            .put_u8(4)
+            .extend(payload);
+        packet.extend(&[0; 4]);
+        packet.to_vec()
+    }
+
+    fn is_protocol_violation(e: &crate::SshError) -> bool {
+        matches!(e.kind(), SshErrorKind::ProtocolViolation { .. })
+    }
+
+    #[test]
+    fn short_channel_open_is_an_error() {
+        let (mut client, server) = mock::connection();
+        let mut payload = Data::new();
+        payload.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_OPEN)
+            .put_str(ssh_str::AUTH_AGENT)
+            .put_u32(1);
+        server.send_raw(&packet(payload.as_slice()));
+        assert!(client.read().is_err());
+    }
+
+    #[test]
+    fn truncated_exit_signal_is_not_tracked() {
+        let (mut client, server) = mock::connection();
+        client.register_channel(1);
+        let mut payload = Data::new();
+        payload.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
+            .put_u32(1)
+            .put_str(ssh_str::EXIT_SIGNAL)
+            .put_u8(false as u8)
+            .put_str("KILL");
+        server.send_raw(&packet(payload.as_slice()));
+        assert!(client.read().unwrap().is_empty());
+        assert_eq!(client.records[&1].exit_signal, None);
+        assert_eq!(client.channels[&1].len(), 1);
+    }
+
+    #[test]
+    fn bad_padding_length_is_a_protocol_violation() {
+        let (mut client, server) = mock::connection();
+        server.send_raw(&[0, 0, 0, 5, 200, ssh_msg_code::SSH_MSG_IGNORE, 0, 0, 0]);
+        assert!(is_protocol_violation(&client.read().unwrap_err()));
+    }
+
+    #[test]
+    fn oversized_packet_length_is_a_protocol_violation() {
+        let (mut client, server) = mock::connection();
+        server.send_raw(&[0xff, 0xff, 0xff, 0xff, 4]);
+        assert!(is_protocol_violation(&client.read().unwrap_err()));
+    }
+
+    #[test]
+    fn partial_length_prefix_is_kept_for_the_next_read() {
+        let (mut client, server) = mock::connection();
+        let mut payload = Data::new();
+        payload.put_u8(ssh_msg_code::SSH_MSG_DEBUG)
+            .put_u8(false as u8)
+            .put_str("hello")
+            .put_str("");
+        let packet = packet(payload.as_slice());
+        server.send_raw(&packet[..2]);
+        client.read_deadline = Some(Instant::now());
+        assert!(client.read().unwrap().is_empty());
+        client.read_deadline = None;
+        server.send_raw(&packet[2..]);
+        let results = client.read().unwrap();
+        assert!(results.is_empty());
+        assert!(client.pending.is_empty());
+        assert_eq!(client.sequence.server_sequence_num, 1);
+    }
+
+    fn channel_data(client_channel: u32, v: &[u8]) -> Data {
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
+            .put_u32(client_channel)
+            .put_u8s(v);
+        data
+    }
+
+    fn payloads(v: Vec<Data>) -> Vec<Vec<u8>> {
+        v.into_iter().map(|v| v.to_vec()).collect()
+    }
+
+    #[test]
+    fn interleaved_channel_data_is_queued_per_channel() {
+        let (mut client, server) = mock::client();
+        client.register_channel(1);
+        client.register_channel(2);
+        for i in 0..3_u8 {
+            server.send(channel_data(1, &[1, i]));
+            server.send(channel_data(2, &[2, i]));
+            // 没有注册的通道
+            server.send(channel_data(3, &[3, i]));
+        }
+        let one = payloads(client.read_channel(1).unwrap());
+        assert_eq!(one, (0..3).map(|i| channel_data(1, &[1, i]).to_vec()).collect::<Vec<Vec<u8>>>());
+        assert!(client.channels[&1].is_empty());
+        assert!(!client.channels.contains_key(&3));
+        // 通道 2 的消息按收到的顺序放在队列中，读取时不再从连接读取
+        let two = payloads(client.read_channel(2).unwrap());
+        assert_eq!(two, (0..3).map(|i| channel_data(2, &[2, i]).to_vec()).collect::<Vec<Vec<u8>>>());
+        assert!(client.read_channel(2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn window_adjust_leaves_other_messages_queued() {
+        let (mut client, server) = mock::client();
+        client.register_channel(1);
+        client.register_channel(2);
+        server.send(channel_data(1, b"a"));
+        server.send(channel_data(2, b"b"));
+        let mut adjust = Data::new();
+        adjust.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST)
+            .put_u32(1)
+            .put_u32(100);
+        server.send(adjust);
+        assert_eq!(client.read_window_adjust(1).unwrap(), 100);
+        assert_eq!(payloads(client.read_channel(1).unwrap()), [channel_data(1, b"a").to_vec()]);
+        assert_eq!(payloads(client.read_channel(2).unwrap()), [channel_data(2, b"b").to_vec()]);
+    }
+
+    fn global_request(want_reply: bool) -> Data {
+        let mut request = Data::new();
+        request.put_u8(ssh_msg_code::SSH_MSG_GLOBAL_REQUEST)
+            .put_str("keepalive@openssh.com")
+            .put_u8(want_reply as u8);
+        request
+    }
+
+    #[test]
+    fn global_request_is_answered_while_waiting_for_window() {
+        let (mut client, server) = mock::client();
+        client.register_channel(1);
+        server.send(global_request(true));
+        let mut adjust = Data::new();
+        adjust.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST)
+            .put_u32(1)
+            .put_u32(100);
+        server.send(adjust);
+        assert_eq!(client.read_window_adjust(1).unwrap(), 100);
+        assert_eq!(payloads(server.received()), [vec![ssh_msg_code::SSH_MSG_REQUEST_FAILURE]]);
+    }
+
+    #[test]
+    fn truncated_window_adjust_is_ignored() {
+        let (mut client, server) = mock::client();
+        client.register_channel(1);
+        let truncated = vec![ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST, 0, 0, 0, 1, 0, 0];
+        client.push_channel_data(1, Data::from(truncated.clone()));
+        assert_eq!(client.read_window_adjust(1).unwrap(), 0);
+        server.send(Data::from(truncated));
+        assert_eq!(client.read_window_adjust(1).unwrap(), 0);
+        assert!(client.read_channel(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn truncated_global_request_is_an_error() {
+        let (mut client, server) = mock::client();
+        client.register_channel(1);
+        let mut request = Data::new();
+        request.put_u8(ssh_msg_code::SSH_MSG_GLOBAL_REQUEST)
+            .put_str("keepalive@openssh.com");
+        server.send(request);
+        assert!(client.read_window_adjust(1).is_err());
+        assert!(server.received().is_empty());
     }
 }
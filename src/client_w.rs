@@ -1,11 +1,10 @@
+use std::io;
 use std::io::Write;
-use std::sync::atomic::Ordering::Relaxed;
 use crate::client::Client;
+use crate::constant::ssh_msg_code;
 use crate::data::Data;
-use crate::algorithm::encryption::IS_ENCRYPT;
 use crate::packet::Packet;
-use crate::{SshError, SshResult};
-use crate::algorithm::encryption;
+use crate::{kex, SshError, SshResult};
 use crate::window_size::WindowSize;
 
 impl Client {
@@ -23,39 +22,139 @@ impl Client {
     }
 
     pub fn write_data(&mut self, data: Data, rws: Option<&mut WindowSize>) -> Result<(), SshError> {
-        let buf = if IS_ENCRYPT.load(Relaxed) {
-            if let Some(rws) = rws {
-                rws.process_remote_window_size(data.as_slice(), self)?;
+        if let Some(e) = self.disconnect_error() {
+            return Err(e)
+        }
+        match rws {
+            Some(rws) if self.encryption.is_some() && is_channel_data(&data) => {
+                self.write_channel_data(data, rws)
+            }
+            _ => self.write_packet(data)
+        }
+    }
+
+    /*
+        byte      SSH_MSG_CHANNEL_DATA / SSH_MSG_CHANNEL_EXTENDED_DATA
+        uint32    recipient channel
+        [uint32   data_type_code]
+        string    data
+    */
+    /// 按远程的最大数据包大小和窗口拆分为多个数据包发送，窗口用完时等待 WINDOW_ADJUST
+    fn write_channel_data(&mut self, mut data: Data, rws: &mut WindowSize) -> SshResult<()> {
+        let message_code = data.get_u8();
+        let server_channel = data.get_u32();
+        let data_type = match message_code {
+            ssh_msg_code::SSH_MSG_CHANNEL_EXTENDED_DATA => Some(data.get_u32()),
+            _ => None
+        };
+        let payload = data.get_u8s();
+        let header_len = match data_type {
+            Some(_) => 13,
+            None => 9
+        };
+        let mut offset = 0;
+        loop {
+            if !payload.is_empty() {
+                rws.wait_remote_window(self)?;
+            }
+            let len = rws.sendable(header_len).min(payload.len() - offset);
+            let mut chunk = Data::new();
+            chunk.put_u8(message_code).put_u32(server_channel);
+            if let Some(data_type) = data_type {
+                chunk.put_u32(data_type);
+            }
+            chunk.put_u8s(&payload[offset..(offset + len)]);
+            rws.consume_remote_window(len as u32, self);
+            self.write_packet(chunk)?;
+            offset += len;
+            if offset >= payload.len() {
+                return Ok(())
+            }
+        }
+    }
+
+    fn write_packet(&mut self, data: Data) -> SshResult<()> {
+        if !is_transport_message(&data) {
+            // 重新协商密钥期间只能发送传输层的消息 (RFC 4253 7.1)，
+            // 其它消息 (例如密钥交换中回应服务端的请求) 等新的密钥生效后发送，见 `flush_deferred`
+            // 同一个 client 的读写都在锁内，其它线程的写入会等待锁，直到密钥交换完成
+            if self.rekeying {
+                log::debug!("rekey in progress, defer message [{:?}].", data.first());
+                self.deferred.push_back(data);
+                return Ok(())
+            }
+            // 达到加密算法的限制之前重新协商密钥，密钥交换的消息本身不触发
+            if self.rekey_needed() {
+                kex::initiate_rekey(self)?
             }
-            self.get_encryption_data(data)?
+        }
+        let buf = if self.encryption.is_some() {
+            let buf = self.get_encryption_data(data)?;
+            self.sequence.client_encrypted(buf.len());
+            buf
         } else {
             let mut packet = Packet::from(data);
-            packet.build(false);
+            packet.build(None);
             packet.to_vec()
         };
         self.sequence.client_auto_increment();
-        loop {
-            if let Err(e) = self.stream.write(&buf) {
-                if Client::is_would_block(&e) {
-                    continue
-                }
-                return Err(SshError::from(e))
-            } else {
-                break
+        // 非阻塞模式下发送缓冲区满时可能只写入一部分
+        let mut offset = 0;
+        while offset < buf.len() {
+            match self.stream.write(&buf[offset..]) {
+                Ok(0) => return Err(SshError::from(io::Error::from(io::ErrorKind::WriteZero))),
+                Ok(len) => offset += len,
+                Err(e) if Client::is_would_block(&e) => self.idle(),
+                Err(e) => return Err(SshError::from(e))
             }
         }
         if let Err(e) = self.stream.flush() {
             return Err(SshError::from(e))
         }
+        if let Some(keepalive) = &mut self.keepalive {
+            keepalive.renew()
+        }
         Ok(())
     }
 
 
-    pub(crate) fn get_encryption_data(&self, data: Data) -> SshResult<Vec<u8>> {
+    /// 按顺序发送重新协商密钥期间暂存的消息，在新的密钥生效之后调用
+    pub(crate) fn flush_deferred(&mut self) -> SshResult<()> {
+        while let Some(data) = self.deferred.pop_front() {
+            self.write_packet(data)?
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_encryption_data(&mut self, data: Data) -> SshResult<Vec<u8>> {
+        let data = match self.compression.compress(data.as_slice()) {
+            Some(v) => Data::from(v),
+            None => data
+        };
+        let sequence_num = self.sequence.client_sequence_num;
+        let encryption = &mut self.encryption()?.client_to_server;
         let mut packet = Packet::from(data);
-        packet.build(true);
+        packet.build(Some(encryption.as_ref()));
         let mut buf = packet.to_vec();
-        encryption::get().encrypt(self.sequence.client_sequence_num, &mut buf);
+        encryption.encrypt(sequence_num, &mut buf);
         Ok(buf)
     }
 }
+
+
+fn is_channel_data(data: &[u8]) -> bool {
+    matches!(data.first(),
+        Some(&ssh_msg_code::SSH_MSG_CHANNEL_DATA) | Some(&ssh_msg_code::SSH_MSG_CHANNEL_EXTENDED_DATA))
+}
+
+
+/// 密钥交换期间允许发送的消息 (RFC 4253 7.1)
+/// 1 - 49 中除了 SSH_MSG_SERVICE_REQUEST 和 SSH_MSG_SERVICE_ACCEPT
+fn is_transport_message(data: &[u8]) -> bool {
+    match data.first() {
+        Some(&ssh_msg_code::SSH_MSG_SERVICE_REQUEST)
+        | Some(&ssh_msg_code::SSH_MSG_SERVICE_ACCEPT) => false,
+        Some(&v) => (ssh_msg_code::SSH_MSG_DISCONNECT..ssh_msg_code::SSH_MSG_USERAUTH_REQUEST).contains(&v),
+        None => false
+    }
+}
@@ -1,33 +1,25 @@
-use crate::constant::{algorithms, CLIENT_VERSION};
+use crate::constant::{algorithms, ssh_str, CLIENT_VERSION};
 use crate::data::Data;
-use crate::slog::log;
 use crate::{SshError, SshResult};
-use crate::algorithm::encryption::{AesCtr128, ChaCha20Poly1305, Encryption};
+use crate::error::SshErrorKind;
+use crate::algorithm::encryption::{AesCtr128, AesGcm, ChaCha20Poly1305, Ciphers, Encryption};
+#[cfg(feature = "legacy-algorithms")]
+use crate::algorithm::encryption::TripleDesCbc;
+use crate::algorithm::hash::{Direction, HASH};
 use crate::algorithm::key_exchange::curve25519::CURVE25519;
 use crate::algorithm::key_exchange::ecdh_sha2_nistp256::EcdhP256;
 use crate::algorithm::key_exchange::KeyExchange;
+use crate::algorithm::mac::etm::Etm;
 use crate::algorithm::mac::hmac_sha1::HMacSha1;
+use crate::algorithm::mac::hmac_sha2_256::HMacSha2256;
+use crate::algorithm::mac::hmac_sha2_512::HMacSha2512;
 use crate::algorithm::mac::Mac;
-use crate::algorithm::public_key::{Ed25519, PublicKey, RSA};
+use crate::algorithm::negotiated::NegotiatedAlgorithms;
+use crate::algorithm::public_key::{self, Certified, Ed25519, PublicKey};
 use crate::user_info::UserInfo;
 
 
-pub(crate) static mut CONFIG: Option<Config> = None;
-
-
-pub(crate) fn init(user_info: UserInfo) {
-    unsafe {
-        CONFIG = Some(Config::new(user_info));
-    }
-}
-
-pub(crate) fn config() -> &'static mut Config {
-    unsafe {
-        CONFIG.as_mut().unwrap()
-    }
-}
-
-
+/// 每个连接各自的配置和协商状态，见 `Client::config`
 pub(crate) struct Config {
     pub(crate) auth: UserInfo,
     pub(crate) version: VersionConfig,
@@ -57,12 +49,25 @@ impl VersionConfig {
             server_version: String::new()
         }
     }
+    /// 只支持 SSH-2.0，SSH-1.99 表示服务端同时支持 1 和 2 (RFC 4253 5.1)
     pub(crate) fn validation(&self) -> SshResult<()> {
-        if !self.server_version.contains("SSH-2.0") {
-            log::error!("error in version negotiation, version mismatch.");
-            return Err(SshError::from("error in version negotiation, version mismatch."))
+        // SSH-protoversion-softwareversion SP comments
+        let proto_version = self.server_version.strip_prefix("SSH-")
+            .and_then(|v| v.split('-').next())
+            .unwrap_or_default();
+        match proto_version {
+            "2.0" | "1.99" => Ok(()),
+            v if v.starts_with("1.") => {
+                log::error!("error in version negotiation, server only supports SSH protocol 1.");
+                Err(SshError::from(format!("error in version negotiation, server only supports SSH protocol 1: [{}]",
+                                           self.server_version)))
+            }
+            _ => {
+                log::error!("error in version negotiation, version mismatch.");
+                Err(SshError::from(format!("error in version negotiation, unsupported protocol version: [{}]",
+                                           self.server_version)))
+            }
         }
-       Ok(())
     }
 }
 
@@ -81,27 +86,59 @@ impl AlgorithmConfig {
     }
 
 
-    /// 匹配合适的mac算法
+    /// 协商出的全部算法，没有共同算法的一项为空字符串
+    /// AEAD 加密算法不协商 mac, 和 ssh -v 一样显示为 <implicit>
+    pub(crate) fn negotiated(&self) -> NegotiatedAlgorithms {
+        let c = &self.client_algorithm;
+        let s = &self.server_algorithm;
+        let cipher_client_to_server = get_algorithm(&c.c_encryption_algorithm.0, &s.c_encryption_algorithm.0);
+        let cipher_server_to_client = get_algorithm(&c.s_encryption_algorithm.0, &s.s_encryption_algorithm.0);
+        let mac = |cipher: &str, client: &Vec<String>, server: &Vec<String>| match is_aead(cipher) {
+            true => IMPLICIT_MAC.to_string(),
+            false => get_algorithm(client, server)
+        };
+        NegotiatedAlgorithms {
+            kex: get_algorithm(&c.key_exchange_algorithm.0, &s.key_exchange_algorithm.0),
+            host_key: get_algorithm(&c.public_key_algorithm.0, &s.public_key_algorithm.0),
+            mac_client_to_server: mac(&cipher_client_to_server, &c.c_mac_algorithm.0, &s.c_mac_algorithm.0),
+            mac_server_to_client: mac(&cipher_server_to_client, &c.s_mac_algorithm.0, &s.s_mac_algorithm.0),
+            cipher_client_to_server,
+            cipher_server_to_client,
+            compression_client_to_server: get_algorithm(&c.c_compression_algorithm.0, &s.c_compression_algorithm.0),
+            compression_server_to_client: get_algorithm(&c.s_compression_algorithm.0, &s.s_compression_algorithm.0),
+        }
+    }
+
+    /// 匹配某个方向上合适的mac算法
     /// 目前支持：
-    ///     1. hmac-sha1
-    pub(crate) fn matching_mac_algorithm(&self) -> SshResult<Box<dyn Mac>> {
-        // 目前是加密和解密使用一个算法
-        // 所以直接取一个算法为准
-        let mac_algorithm: String = get_algorithm(
-            &self.client_algorithm.c_mac_algorithm.0,
-            &self.server_algorithm.c_mac_algorithm.0
-        );
+    ///     1. hmac-sha2-256-etm@openssh.com
+    ///     2. hmac-sha2-512-etm@openssh.com
+    ///     3. hmac-sha2-256
+    ///     4. hmac-sha2-512
+    ///     5. hmac-sha1
+    pub(crate) fn matching_mac_algorithm(&self, direction: Direction) -> SshResult<Box<dyn Mac>> {
+        let client = self.client_algorithm.mac_algorithm(direction);
+        let server = self.server_algorithm.mac_algorithm(direction);
+        let mac_algorithm: String = get_algorithm(&client.0, &server.0);
 
         match mac_algorithm.as_str() {
+            algorithms::MAC_HMAC_SHA2_256_ETM => Ok(Box::new(Etm::<HMacSha2256>::new())),
+            algorithms::MAC_HMAC_SHA2_512_ETM => Ok(Box::new(Etm::<HMacSha2512>::new())),
+            algorithms::MAC_HMAC_SHA2_256 => Ok(Box::new(HMacSha2256::new())),
+            algorithms::MAC_HMAC_SHA2_512 => Ok(Box::new(HMacSha2512::new())),
             algorithms::MAC_HMAC_SHA1 => Ok(Box::new(HMacSha1::new())),
             _ => {
                 log::error!("description the mac algorithm fails to match, \
                 algorithms supported by the server: {},\
                 algorithms supported by the client: {}",
-                    self.server_algorithm.c_mac_algorithm.to_string(),
-                    self.client_algorithm.c_mac_algorithm.to_string()
+                    server.to_string(),
+                    client.to_string()
                 );
-                Err(SshError::from("key exchange error."))
+                Err(SshError::from(SshErrorKind::NegotiationFailed {
+                    category: "mac".to_string(),
+                    client: client.to_string(),
+                    server: server.to_string()
+                }))
             }
         }
     }
@@ -110,50 +147,81 @@ impl AlgorithmConfig {
     /// 匹配合适的加密算法
     /// 目前支持:
     ///     1. chacha20-poly1305@openssh.com
-    ///     2. aes128-ctr
-    pub(crate) fn matching_encryption_algorithm(&self) -> SshResult<Box<dyn Encryption>> {
-        // 目前是加密和解密使用一个算法
-        // 所以直接取一个算法为准
-        let encryption_algorithm: String = get_algorithm(
-            &self.client_algorithm.c_encryption_algorithm.0,
-            &self.server_algorithm.c_encryption_algorithm.0
-        );
+    ///     2. aes128-gcm@openssh.com
+    ///     3. aes256-gcm@openssh.com
+    ///     4. aes128-ctr
+    ///     5. 3des-cbc (feature `legacy-algorithms`)
+    /// 两个方向分别协商，可以是不同的算法
+    pub(crate) fn matching_encryption_algorithm(&self, hash: &HASH) -> SshResult<Ciphers> {
+        Ok(Ciphers {
+            client_to_server: self.matching_cipher(hash, Direction::ClientToServer)?,
+            server_to_client: self.matching_cipher(hash, Direction::ServerToClient)?
+        })
+    }
+
+    /// 不是 AEAD 的加密算法同时协商这个方向上的 mac 算法
+    fn matching_cipher(&self, hash: &HASH, direction: Direction) -> SshResult<Box<dyn Encryption>> {
+        let client = self.client_algorithm.encryption_algorithm(direction);
+        let server = self.server_algorithm.encryption_algorithm(direction);
+        let encryption_algorithm: String = get_algorithm(&client.0, &server.0);
         match encryption_algorithm.as_str() {
-            algorithms::ENCRYPTION_CHACHA20_POLY1305_OPENSSH => Ok(Box::new(ChaCha20Poly1305::new())),
-            algorithms::ENCRYPTION_AES128_CTR => Ok(Box::new(AesCtr128::new())),
+            algorithms::ENCRYPTION_CHACHA20_POLY1305_OPENSSH => Ok(Box::new(ChaCha20Poly1305::new(hash, direction))),
+            algorithms::ENCRYPTION_AES128_GCM_OPENSSH
+            | algorithms::ENCRYPTION_AES256_GCM_OPENSSH => Ok(Box::new(AesGcm::new(&encryption_algorithm, hash, direction))),
+            algorithms::ENCRYPTION_AES128_CTR => {
+                Ok(Box::new(AesCtr128::new(hash, direction, self.matching_mac_algorithm(direction)?)))
+            }
+            #[cfg(feature = "legacy-algorithms")]
+            algorithms::ENCRYPTION_3DES_CBC => {
+                Ok(Box::new(TripleDesCbc::new(hash, direction, self.matching_mac_algorithm(direction)?)))
+            }
             _ => {
                 log::error!("description the encryption algorithm fails to match, \
                 algorithms supported by the server: {},\
                 algorithms supported by the client: {}",
-                    self.server_algorithm.c_encryption_algorithm.to_string(),
-                    self.client_algorithm.c_encryption_algorithm.to_string()
+                    server.to_string(),
+                    client.to_string()
                 );
-                Err(SshError::from("key exchange error."))
+                Err(SshError::from(SshErrorKind::NegotiationFailed {
+                    category: "encryption".to_string(),
+                    client: client.to_string(),
+                    server: server.to_string()
+                }))
             }
         }
-
     }
 
     /// 匹配合适的公钥签名算法
     /// 目前支持:
-    ///     1. ed25519.rs
-    ///     2. ssh-rsa
+    ///     1. ssh-ed25519-cert-v01@openssh.com
+    ///     2. ed25519.rs
+    ///     3. ecdsa-sha2-nistp256
+    ///     4. ecdsa-sha2-nistp384
+    ///     5. rsa-sha2-512
+    ///     6. rsa-sha2-256
+    ///     7. ssh-rsa
     pub(crate) fn matching_public_key_algorithm(&self) -> SshResult<Box<dyn PublicKey>> {
         let public_key_algorithm: String = get_algorithm(
             &self.client_algorithm.public_key_algorithm.0,
             &self.server_algorithm.public_key_algorithm.0
         );
-        match public_key_algorithm.as_str() {
-            algorithms::PUBLIC_KEY_ED25519 => Ok(Box::new(Ed25519::new())),
-            algorithms::PUBLIC_KEY_RSA => Ok(Box::new(RSA::new())),
-            _ => {
+        if public_key_algorithm == algorithms::PUBLIC_KEY_ED25519_CERT {
+            return Ok(Box::new(Certified(Box::new(Ed25519::new()))))
+        }
+        match public_key::from_algorithm(&public_key_algorithm) {
+            Some(v) => Ok(v),
+            None => {
                 log::error!("description the signature algorithm fails to match, \
                 algorithms supported by the server: {},\
                 algorithms supported by the client: {}",
                     self.server_algorithm.public_key_algorithm.to_string(),
                     self.client_algorithm.public_key_algorithm.to_string()
                 );
-                Err(SshError::from("key exchange error."))
+                Err(SshError::from(SshErrorKind::NegotiationFailed {
+                    category: "host key".to_string(),
+                    client: self.client_algorithm.public_key_algorithm.to_string(),
+                    server: self.server_algorithm.public_key_algorithm.to_string()
+                }))
             }
         }
     }
@@ -177,13 +245,46 @@ impl AlgorithmConfig {
                     self.server_algorithm.key_exchange_algorithm.to_string(),
                     self.client_algorithm.key_exchange_algorithm.to_string()
                 );
-                Err(SshError::from("key exchange error."))
+                Err(SshError::from(SshErrorKind::NegotiationFailed {
+                    category: "key exchange".to_string(),
+                    client: self.client_algorithm.key_exchange_algorithm.to_string(),
+                    server: self.server_algorithm.key_exchange_algorithm.to_string()
+                }))
             }
         }
     }
 
 }
 
+/// 按给定的顺序保留客户端支持的算法
+/// 结果为空时无法协商，返回 NegotiationFailed
+pub(crate) fn preferred_algorithm(category: &str, supported: &[String], preferred: &[String]) -> SshResult<Vec<String>> {
+    let list = preferred.iter()
+        .filter(|v| supported.contains(v))
+        .cloned()
+        .collect::<Vec<String>>();
+    if list.is_empty() {
+        log::error!("none of the preferred {} algorithms is supported: {}", category, to_string(preferred));
+        return Err(SshError::from(SshErrorKind::NegotiationFailed {
+            category: category.to_string(),
+            client: to_string(preferred),
+            server: String::new()
+        }))
+    }
+    Ok(list)
+}
+
+/// AEAD 加密算法协商出的 mac 名称
+const IMPLICIT_MAC: &str = "<implicit>";
+
+/// AEAD 加密算法自带认证标签，不使用协商的 mac
+fn is_aead(encryption_algorithm: &str) -> bool {
+    matches!(encryption_algorithm,
+        algorithms::ENCRYPTION_CHACHA20_POLY1305_OPENSSH
+        | algorithms::ENCRYPTION_AES128_GCM_OPENSSH
+        | algorithms::ENCRYPTION_AES256_GCM_OPENSSH)
+}
+
 fn get_algorithm(c_algorithm: &Vec<String>, s_algorithm: &Vec<String>) -> String {
     for x in c_algorithm {
         if s_algorithm.contains(x) {
@@ -231,9 +332,31 @@ impl AlgorithmList {
         }
     }
 
-    pub(crate) fn as_i(&self) -> Vec<u8> {
+    pub(crate) fn encryption_algorithm(&self, direction: Direction) -> &EncryptionAlgorithm {
+        match direction {
+            Direction::ClientToServer => &self.c_encryption_algorithm,
+            Direction::ServerToClient => &self.s_encryption_algorithm,
+        }
+    }
+
+    pub(crate) fn mac_algorithm(&self, direction: Direction) -> &MacAlgorithm {
+        match direction {
+            Direction::ClientToServer => &self.c_mac_algorithm,
+            Direction::ServerToClient => &self.s_mac_algorithm,
+        }
+    }
+
+    /// initial 为 true (第一次密钥交换) 时在密钥交换算法的最后加上 ext-info-c 和 kex-strict-c，它们不参与协商
+    pub(crate) fn as_i(&self, initial: bool) -> Vec<u8> {
+        let mut key_exchange_algorithm = self.key_exchange_algorithm.to_string();
+        if initial {
+            for v in [ssh_str::EXT_INFO_C, ssh_str::KEX_STRICT_C] {
+                key_exchange_algorithm.push(',');
+                key_exchange_algorithm.push_str(v);
+            }
+        }
         let mut data = Data::new();
-        data.put_str(self.key_exchange_algorithm.to_string().as_str());
+        data.put_str(key_exchange_algorithm.as_str());
         data.put_str(self.public_key_algorithm.to_string().as_str());
         data.put_str(self.c_encryption_algorithm.to_string().as_str());
         data.put_str(self.s_encryption_algorithm.to_string().as_str());
@@ -287,7 +410,12 @@ impl PublicKeyAlgorithm {
     pub(crate) fn get_client() -> Self {
         PublicKeyAlgorithm(
             vec![
+                algorithms::PUBLIC_KEY_ED25519_CERT.to_string(),
                 algorithms::PUBLIC_KEY_ED25519.to_string(),
+                algorithms::PUBLIC_KEY_ECDSA_SHA2_NISTP256.to_string(),
+                algorithms::PUBLIC_KEY_ECDSA_SHA2_NISTP384.to_string(),
+                algorithms::PUBLIC_KEY_RSA_SHA2_512.to_string(),
+                algorithms::PUBLIC_KEY_RSA_SHA2_256.to_string(),
                 algorithms::PUBLIC_KEY_RSA.to_string()
             ]
         )
@@ -307,7 +435,12 @@ impl EncryptionAlgorithm {
         EncryptionAlgorithm(
             vec![
                 algorithms::ENCRYPTION_CHACHA20_POLY1305_OPENSSH.to_string(),
+                algorithms::ENCRYPTION_AES128_GCM_OPENSSH.to_string(),
+                algorithms::ENCRYPTION_AES256_GCM_OPENSSH.to_string(),
                 algorithms::ENCRYPTION_AES128_CTR.to_string(),
+                // 只在其它算法都不支持时使用
+                #[cfg(feature = "legacy-algorithms")]
+                algorithms::ENCRYPTION_3DES_CBC.to_string(),
             ]
         )
     }
@@ -324,6 +457,10 @@ impl MacAlgorithm {
     pub(crate) fn get_client() -> Self {
         MacAlgorithm(
             vec![
+                algorithms::MAC_HMAC_SHA2_256_ETM.to_string(),
+                algorithms::MAC_HMAC_SHA2_512_ETM.to_string(),
+                algorithms::MAC_HMAC_SHA2_256.to_string(),
+                algorithms::MAC_HMAC_SHA2_512.to_string(),
                 algorithms::MAC_HMAC_SHA1.to_string(),
             ]
         )
@@ -342,7 +479,18 @@ impl CompressionAlgorithm {
     pub(crate) fn get_client() -> Self {
         CompressionAlgorithm(
             vec![
-                algorithms::COMPRESSION_ALGORITHMS.to_string(),
+                algorithms::COMPRESSION_NONE.to_string(),
+            ]
+        )
+    }
+
+    /// 开启压缩时的算法列表，优先使用认证之后才开始的 zlib@openssh.com
+    pub(crate) fn get_compressed() -> Self {
+        CompressionAlgorithm(
+            vec![
+                algorithms::COMPRESSION_ZLIB_OPENSSH.to_string(),
+                algorithms::COMPRESSION_ZLIB.to_string(),
+                algorithms::COMPRESSION_NONE.to_string(),
             ]
         )
     }
@@ -16,6 +16,8 @@ pub mod ssh_str {
     pub const PASSWORD                  :&'static str = "password";
     /// 打开一个会话
     pub const SESSION                   :&'static str = "session";
+    /// 转发到服务端可以访问的地址
+    pub const DIRECT_TCPIP              :&str = "direct-tcpip";
     /// 启动一个命令解释程序
     pub const SHELL                     :&'static str = "shell";
     /// 执行一个命令
@@ -26,6 +28,41 @@ pub mod ssh_str {
     pub const PTY_REQ                   :&'static str = "pty-req";
     /// 伪终端的样式
     pub const XTERM_VAR                 :&'static str = "xterm-256color";
+    /// 远程命令的退出状态
+    pub const EXIT_STATUS               :&str = "exit-status";
+    /// 远程命令被信号终止
+    pub const EXIT_SIGNAL               :&str = "exit-signal";
+    /// 向远程进程发送信号
+    pub const SIGNAL                    :&str = "signal";
+    /// 设置远程进程的环境变量
+    pub const ENV                       :&str = "env";
+    /// 启动一个子系统，例如 sftp
+    pub const SUBSYSTEM                 :&str = "subsystem";
+    /// 发送串口 BREAK (RFC 4335)
+    pub const BREAK                     :&str = "break";
+    /// 保活的全局请求
+    pub const KEEPALIVE                 :&str = "keepalive@openssh.com";
+    /// 服务端通告当前的主机密钥
+    pub const HOSTKEYS                  :&str = "hostkeys-00@openssh.com";
+    /// 要求服务端证明持有主机密钥的私钥
+    pub const HOSTKEYS_PROVE            :&str = "hostkeys-prove-00@openssh.com";
+    /// 请求转发本地的 ssh-agent
+    pub const AUTH_AGENT_REQ            :&str = "auth-agent-req@openssh.com";
+    /// 服务端打开的 agent 通道
+    pub const AUTH_AGENT                :&str = "auth-agent@openssh.com";
+    /// 请求 X11 转发
+    pub const X11_REQ                   :&str = "x11-req";
+    /// 服务端打开的 X11 通道
+    pub const X11                       :&str = "x11";
+    /// 放在密钥交换算法列表中，表示客户端接受 SSH_MSG_EXT_INFO (RFC 8308)
+    pub const EXT_INFO_C                :&str = "ext-info-c";
+    /// SSH_MSG_EXT_INFO 中服务端接受的用户认证签名算法
+    pub const SERVER_SIG_ALGS           :&str = "server-sig-algs";
+    /// 客户端在 SSH_MSG_KEXINIT 中表示支持严格的密钥交换 (OpenSSH 的 PROTOCOL 1.9)，
+    /// 防御 Terrapin 攻击 (CVE-2023-48795)
+    pub const KEX_STRICT_C              :&str = "kex-strict-c-v00@openssh.com";
+    /// 服务端在 SSH_MSG_KEXINIT 中表示支持严格的密钥交换
+    pub const KEX_STRICT_S              :&str = "kex-strict-s-v00@openssh.com";
 }
 
 #[allow(dead_code)]
@@ -80,17 +117,6 @@ pub mod scp {
 }
 
 
-/// 一些默认大小
-#[allow(dead_code)]
-pub mod size {
-    pub const ONE_GB                    :u32    = 1073741824;
-    /// 最大数据包大小
-    pub const BUF_SIZE                  :usize  = 32768;
-    /// 默认客户端的窗口大小
-    pub const LOCAL_WINDOW_SIZE         :u32    = 2097152;
-}
-
-
 /// ssh 消息码
 #[allow(dead_code)]
 pub mod ssh_msg_code {
@@ -100,6 +126,7 @@ pub mod ssh_msg_code {
     pub const SSH_MSG_DEBUG                                     :u8 = 4;
     pub const SSH_MSG_SERVICE_REQUEST                           :u8 = 5;
     pub const SSH_MSG_SERVICE_ACCEPT                            :u8 = 6;
+    pub const SSH_MSG_EXT_INFO                                  :u8 = 7;
     pub const SSH_MSG_KEXINIT                                   :u8 = 20;
     pub const SSH_MSG_NEWKEYS                                   :u8 = 21;
     pub const SSH_MSG_KEXDH_INIT                                :u8 = 30;
@@ -107,7 +134,10 @@ pub mod ssh_msg_code {
     pub const SSH_MSG_USERAUTH_REQUEST                          :u8 = 50;
     pub const SSH_MSG_USERAUTH_FAILURE                          :u8 = 51;
     pub const SSH_MSG_USERAUTH_SUCCESS                          :u8 = 52;
+    pub const SSH_MSG_USERAUTH_BANNER                           :u8 = 53;
     pub const SSH_MSG_USERAUTH_PK_OK                            :u8 = 60;
+    /// 与 SSH_MSG_USERAUTH_PK_OK 的编号相同，只在密码认证中出现
+    pub const SSH_MSG_USERAUTH_PASSWD_CHANGEREQ                 :u8 = 60;
     pub const SSH_MSG_GLOBAL_REQUEST                            :u8 = 80;
     pub const SSH_MSG_REQUEST_SUCCESS                           :u8 = 81;
     pub const SSH_MSG_REQUEST_FAILURE                           :u8 = 82;
@@ -147,6 +177,10 @@ pub mod ssh_msg_code {
     pub const SSH_OPEN_CONNECT_FAILED                           :u32 = 2;
     pub const SSH_OPEN_UNKNOWN_CHANNEL_TYPE                     :u32 = 3;
     pub const SSH_OPEN_RESOURCE_SHORTAGE                        :u32 = 4;
+
+
+    // SSH_MSG_CHANNEL_EXTENDED_DATA 数据类型
+    pub const SSH_EXTENDED_DATA_STDERR                          :u32 = 1;
 }
 
 
@@ -159,17 +193,32 @@ pub mod algorithms {
 
     /// 非对称签名算法
     pub const PUBLIC_KEY_ED25519                                :&'static str = "ssh-ed25519";
+    pub const PUBLIC_KEY_ED25519_CERT                           :&str = "ssh-ed25519-cert-v01@openssh.com";
     pub const PUBLIC_KEY_RSA                                    :&'static str = "ssh-rsa";
+    pub const PUBLIC_KEY_RSA_SHA2_256                           :&str = "rsa-sha2-256";
+    pub const PUBLIC_KEY_RSA_SHA2_512                           :&str = "rsa-sha2-512";
+    pub const PUBLIC_KEY_ECDSA_SHA2_NISTP256                    :&str = "ecdsa-sha2-nistp256";
+    pub const PUBLIC_KEY_ECDSA_SHA2_NISTP384                    :&str = "ecdsa-sha2-nistp384";
 
     /// 对称加密算法
     pub const ENCRYPTION_CHACHA20_POLY1305_OPENSSH              :&'static str = "chacha20-poly1305@openssh.com";
     pub const ENCRYPTION_AES128_CTR                             :&'static str = "aes128-ctr";
+    pub const ENCRYPTION_AES128_GCM_OPENSSH                     :&str = "aes128-gcm@openssh.com";
+    pub const ENCRYPTION_AES256_GCM_OPENSSH                     :&str = "aes256-gcm@openssh.com";
+    #[cfg(feature = "legacy-algorithms")]
+    pub const ENCRYPTION_3DES_CBC                               :&str = "3des-cbc";
 
     /// MAC（消息验证码）算法
+    pub const MAC_HMAC_SHA2_256_ETM                             :&str = "hmac-sha2-256-etm@openssh.com";
+    pub const MAC_HMAC_SHA2_512_ETM                             :&str = "hmac-sha2-512-etm@openssh.com";
+    pub const MAC_HMAC_SHA2_256                                 :&str = "hmac-sha2-256";
+    pub const MAC_HMAC_SHA2_512                                 :&str = "hmac-sha2-512";
     pub const MAC_HMAC_SHA1                                     :&'static str = "hmac-sha1";
 
     /// 压缩算法
-    pub const COMPRESSION_ALGORITHMS                            :&'static str = "none";
+    pub const COMPRESSION_NONE                                  :&str = "none";
+    pub const COMPRESSION_ZLIB                                  :&str = "zlib";
+    pub const COMPRESSION_ZLIB_OPENSSH                          :&str = "zlib@openssh.com";
 }
 
 /// 密钥交换后进行HASH时候需要的常量值
@@ -1,4 +1,5 @@
 use std::ops::{Deref, DerefMut};
+use crate::error::{SshError, SshResult};
 
 
 /// **byte**
@@ -91,14 +92,17 @@ impl Data {
     // 对于正数，如果最高位将被置为 1，则必须在前面加一个值为 0 的字节。
     // 禁止包含值为 0 或 255 的非必要的前导字节（leading bytes）。
     // 零必须被存储为具有 0 个字节的数据的字符串。
-    pub fn put_mpint(&mut self, v: &[u8]) -> Vec<u8> {
+    // v 为大端的无符号整数，ssh 中用到的 mpint (共享密钥 K、rsa 的 e 和 n 等) 都不是负数
+    pub fn put_mpint(&mut self, v: &[u8]) -> &mut Self {
+        let start = v.iter().position(|b| *b != 0).unwrap_or(v.len());
+        let v = &v[start..];
         let mut result: Vec<u8> = Vec::new();
         // 0x80 = 128
-        if v[0] & 0x80 != 0 {
+        if v.first().is_some_and(|b| b & 0x80 != 0) {
             result.push(0);
         }
         result.extend(v);
-        self.put_u8s(&result).to_vec()
+        self.put_u8s(&result)
     }
 
     // 跳过多少位数据
@@ -129,6 +133,19 @@ impl Data {
         self.0 = (&self.0[len..]).to_vec();
         bytes
     }
+
+    // string 类型，uint32 长度加上数据，与 get_u8s 相同
+    pub fn get_string(&mut self) -> Vec<u8> {
+        self.get_u8s()
+    }
+
+    // 获取 mpint，返回去掉前导 0 的大端无符号整数，零返回空数组
+    // 与 put_mpint 相同，只用于非负数
+    pub fn get_mpint(&mut self) -> Vec<u8> {
+        let v = self.get_u8s();
+        let start = v.iter().position(|b| *b != 0).unwrap_or(v.len());
+        v[start..].to_vec()
+    }
 }
 
 
@@ -163,3 +180,114 @@ impl DerefMut for Data {
         &mut self.0
     }
 }
+
+
+/// 数据来自服务端，读取时需要检查长度
+/// 长度不够时返回 error 作为错误信息
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    error: &'static str
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buf: &'a [u8], error: &'static str) -> Self {
+        Reader {
+            buf,
+            pos: 0,
+            error
+        }
+    }
+
+    /// 已经读取的字节数
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> SshResult<&'a [u8]> {
+        let end = self.pos.checked_add(len);
+        match end.and_then(|end| self.buf.get(self.pos..end)) {
+            Some(v) => {
+                self.pos += len;
+                Ok(v)
+            }
+            None => Err(SshError::from(self.error))
+        }
+    }
+
+    pub(crate) fn u8(&mut self) -> SshResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> SshResult<u32> {
+        let mut v = [0_u8; 4];
+        v.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_be_bytes(v))
+    }
+
+    pub(crate) fn u64(&mut self) -> SshResult<u64> {
+        let mut v = [0_u8; 8];
+        v.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(v))
+    }
+
+    pub(crate) fn string(&mut self) -> SshResult<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Data, Reader};
+
+    fn mpint(v: &[u8]) -> Vec<u8> {
+        Data::new().put_mpint(v).to_vec()
+    }
+
+    #[test]
+    fn put_mpint_encoding() {
+        // RFC 4251 5 中的例子
+        assert_eq!(mpint(&[]), [0, 0, 0, 0]);
+        assert_eq!(mpint(&[0]), [0, 0, 0, 0]);
+        assert_eq!(mpint(&[0x09, 0xa3, 0x78, 0xf9, 0xb2, 0xe3, 0x32, 0xa7]),
+                   [0, 0, 0, 8, 0x09, 0xa3, 0x78, 0xf9, 0xb2, 0xe3, 0x32, 0xa7]);
+        assert_eq!(mpint(&[0x80]), [0, 0, 0, 2, 0, 0x80]);
+        // 前导 0 去掉后最高位为 1，需要重新补一个 0
+        assert_eq!(mpint(&[0x00, 0xff]), [0, 0, 0, 2, 0, 0xff]);
+        assert_eq!(mpint(&[0x00, 0x00, 0x7f]), [0, 0, 0, 1, 0x7f]);
+        assert_eq!(mpint(&[0xde, 0xad, 0xbe, 0xef]), [0, 0, 0, 5, 0, 0xde, 0xad, 0xbe, 0xef]);
+    }
+
+    #[test]
+    fn get_mpint_round_trip() {
+        let values: [&[u8]; 5] = [&[], &[0x80], &[0xff], &[0x7f, 0x00], &[0xde, 0xad, 0xbe, 0xef]];
+        for v in values {
+            let mut data = Data::from(mpint(v));
+            assert_eq!(data.get_mpint(), v);
+            assert!(data.is_empty());
+        }
+        let mut data = Data::from(mpint(&[0x00, 0xff]));
+        assert_eq!(data.get_mpint(), [0xff]);
+    }
+
+    #[test]
+    fn reader_checks_the_length() {
+        let mut data = Data::new();
+        data.put_u32(7).put_str("abc").put_u8(1);
+        let mut reader = Reader::new(data.as_slice(), "short.");
+        assert_eq!(reader.u32().unwrap(), 7);
+        assert_eq!(reader.string().unwrap(), b"abc");
+        assert_eq!(reader.position(), 11);
+        assert!(reader.u32().is_err());
+        // 读取失败时不移动位置
+        assert_eq!(reader.u8().unwrap(), 1);
+        assert!(reader.is_empty());
+        assert!(reader.u8().is_err());
+    }
+}
@@ -1,6 +1,9 @@
 use std::fmt::{Debug, Display, Formatter};
 use std::{fmt, io};
 use std::error::Error;
+use crate::channel_exec::ExitStatus;
+use crate::constant::ssh_msg_code;
+use crate::transfer::TransferSnapshot;
 
 
 pub type SshResult<I> = Result<I, SshError>;
@@ -13,6 +16,20 @@ impl SshError {
     pub fn kind(&self) -> &SshErrorKind {
         &self.inner
     }
+
+    /// `IoError` 中原来的 `io::ErrorKind`，例如 `ConnectionReset`、`WouldBlock`，其它错误为 None
+    /// 读取超过 `Session::set_timeout` 的时限是 `SshErrorKind::Timeout`，不是 io 错误
+    pub fn io_kind(&self) -> Option<io::ErrorKind> {
+        match &self.inner {
+            SshErrorKind::IoError(v) => Some(v.kind()),
+            _ => None
+        }
+    }
+
+    /// 取出错误类型，例如取出 `IoError` 中原来的 `io::Error`
+    pub fn into_kind(self) -> SshErrorKind {
+        self.inner
+    }
 }
 
 
@@ -20,22 +37,17 @@ impl Debug for SshError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match &self.inner {
             SshErrorKind::IoError(ie) => {
-                write!(f, r"IoError: {{ Kind({:?}), Message({}) }}", ie.kind(), ie.to_string())
+                write!(f, r"IoError: {{ Kind({:?}), Message({}) }}", ie.kind(), ie)
             }
-            _ => { write!(f, r"Error: {{ Kind({:?}), Message({}) }}", self.inner, self.inner.to_string()) }
+            _ => { write!(f, r"Error: {{ Kind({:?}), Message({}) }}", self.inner, self.inner) }
         }
     }
 }
 
+/// 只输出错误信息，`IoError` 中原来的 `io::Error` 通过 `source` 取得
 impl Display for SshError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match &self.inner {
-            SshErrorKind::IoError(ie) => {
-                write!(f, r"IoError: {{ Kind({:?}) }}", ie.kind())
-            }
-            _ => { write!(f, r"Error: {{ Kind({:?}) }}", self.inner) }
-        }
-
+        Display::fmt(&self.inner, f)
     }
 }
 
@@ -44,7 +56,82 @@ impl Display for SshError {
 pub enum SshErrorKind {
     IoError(io::Error),
     SshError(String),
-    Timeout
+    Timeout,
+    /// 主机密钥与 known_hosts 记录不一致
+    HostKeyMismatch {
+        expected: String,
+        actual: String
+    },
+    /// 严格模式下 known_hosts 中没有该主机的记录
+    HostKeyUnknown {
+        fingerprint: String
+    },
+    /// 主机密钥已被吊销 (@revoked 或 Session::revoke_host_key)
+    HostKeyRevoked {
+        fingerprint: String
+    },
+    /// 客户端和服务端没有共同支持的算法
+    NegotiationFailed {
+        /// 算法类别: key exchange, host key, encryption, mac
+        category: String,
+        client: String,
+        server: String
+    },
+    /// 主机证书不在有效期内
+    HostCertificateExpired {
+        key_id: String,
+        valid_after: u64,
+        valid_before: u64
+    },
+    /// 主机证书的 principals 中没有该主机名
+    HostCertificatePrincipal {
+        host: String,
+        principals: Vec<String>
+    },
+    /// 服务端发送了当前状态下不应该出现的消息，连接已断开
+    ProtocolViolation {
+        message: String
+    },
+    /// 开启 check 时远程命令的退出码不为 0
+    CommandFailed {
+        status: ExitStatus,
+        /// stderr 末尾的部分内容
+        stderr_tail: String
+    },
+    /// 数据包的 mac 或 AEAD 认证标签校验失败，连接已断开
+    MacVerificationFailed,
+    /// 服务端拒绝打开通道 (SSH_MSG_CHANNEL_OPEN_FAILURE)
+    ChannelOpenFailed {
+        /// SSH_OPEN_ADMINISTRATIVELY_PROHIBITED 等，见 RFC 4254 5.1
+        reason_code: u32,
+        description: String
+    },
+    /// 开启 stall_watchdog 时传输超过时限没有收发数据，通道已关闭
+    TransferStalled {
+        snapshot: Box<TransferSnapshot>
+    },
+    /// 服务端发送了 SSH_MSG_DISCONNECT，连接已关闭
+    Disconnected {
+        /// SSH_DISCONNECT_NO_MORE_AUTH_METHODS_AVAILABLE 等，见 RFC 4253 11.1
+        code: u32,
+        description: String
+    },
+    /// 服务端拒绝了子系统请求 (SSH_MSG_CHANNEL_FAILURE)，例如没有配置该子系统
+    SubsystemUnavailable {
+        name: String
+    },
+    /// 服务端拒绝了 want_reply 为 true 的通道请求 (SSH_MSG_CHANNEL_FAILURE)，通道仍然可用
+    ChannelRequestFailed {
+        /// 请求类型，例如 exec、pty-req、break
+        request: String
+    },
+    /// 通道已经关闭 (任意一方发送了 CHANNEL_CLOSE)，不能再写
+    ChannelClosed,
+    /// 连续多次保活请求没有回应，连接已断开，见 `Session::set_keepalive_interval`
+    KeepaliveTimeout {
+        /// 没有回应的请求次数
+        missed: u32
+    }
 }
 
 
@@ -55,6 +142,40 @@ impl PartialEq<Self> for SshErrorKind {
             (&SshErrorKind::SshError(v1), &SshErrorKind::SshError(v2)) => v1.eq(v2),
             (&SshErrorKind::IoError(io1), &SshErrorKind::IoError(io2)) => io1.kind() == io2.kind(),
             (&SshErrorKind::Timeout, &SshErrorKind::Timeout) => true,
+            (&SshErrorKind::MacVerificationFailed, &SshErrorKind::MacVerificationFailed) => true,
+            (&SshErrorKind::ChannelClosed, &SshErrorKind::ChannelClosed) => true,
+            (&SshErrorKind::HostKeyMismatch { expected: e1, actual: a1 },
+                &SshErrorKind::HostKeyMismatch { expected: e2, actual: a2 }) => e1 == e2 && a1 == a2,
+            (&SshErrorKind::HostKeyUnknown { fingerprint: f1 },
+                &SshErrorKind::HostKeyUnknown { fingerprint: f2 }) => f1 == f2,
+            (&SshErrorKind::HostKeyRevoked { fingerprint: f1 },
+                &SshErrorKind::HostKeyRevoked { fingerprint: f2 }) => f1 == f2,
+            (&SshErrorKind::NegotiationFailed { category: c1, client: l1, server: r1 },
+                &SshErrorKind::NegotiationFailed { category: c2, client: l2, server: r2 }) => {
+                c1 == c2 && l1 == l2 && r1 == r2
+            }
+            (&SshErrorKind::HostCertificateExpired { key_id: k1, valid_after: a1, valid_before: b1 },
+                &SshErrorKind::HostCertificateExpired { key_id: k2, valid_after: a2, valid_before: b2 }) => {
+                k1 == k2 && a1 == a2 && b1 == b2
+            }
+            (&SshErrorKind::HostCertificatePrincipal { host: h1, principals: p1 },
+                &SshErrorKind::HostCertificatePrincipal { host: h2, principals: p2 }) => h1 == h2 && p1 == p2,
+            (&SshErrorKind::ProtocolViolation { message: m1 },
+                &SshErrorKind::ProtocolViolation { message: m2 }) => m1 == m2,
+            (&SshErrorKind::CommandFailed { status: s1, stderr_tail: t1 },
+                &SshErrorKind::CommandFailed { status: s2, stderr_tail: t2 }) => s1 == s2 && t1 == t2,
+            (&SshErrorKind::ChannelOpenFailed { reason_code: c1, description: d1 },
+                &SshErrorKind::ChannelOpenFailed { reason_code: c2, description: d2 }) => c1 == c2 && d1 == d2,
+            (&SshErrorKind::TransferStalled { snapshot: s1 },
+                &SshErrorKind::TransferStalled { snapshot: s2 }) => s1 == s2,
+            (&SshErrorKind::Disconnected { code: c1, description: d1 },
+                &SshErrorKind::Disconnected { code: c2, description: d2 }) => c1 == c2 && d1 == d2,
+            (&SshErrorKind::SubsystemUnavailable { name: n1 },
+                &SshErrorKind::SubsystemUnavailable { name: n2 }) => n1 == n2,
+            (&SshErrorKind::ChannelRequestFailed { request: r1 },
+                &SshErrorKind::ChannelRequestFailed { request: r2 }) => r1 == r2,
+            (&SshErrorKind::KeepaliveTimeout { missed: m1 },
+                &SshErrorKind::KeepaliveTimeout { missed: m2 }) => m1 == m2,
             _ => false
         }
     }
@@ -63,18 +184,109 @@ impl PartialEq<Self> for SshErrorKind {
 
 
 
-impl SshErrorKind {
-    fn to_string(&self) -> String {
-        match &self {
+impl Display for SshErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let message = match &self {
             SshErrorKind::SshError(e) => e.to_string(),
-            SshErrorKind::IoError(v) => v.to_string(),
-            SshErrorKind::Timeout => "time out.".to_string()
+            SshErrorKind::IoError(v) => format!("io error: {:?}", v.kind()),
+            SshErrorKind::Timeout => "time out.".to_string(),
+            SshErrorKind::MacVerificationFailed => "mac verification failure.".to_string(),
+            SshErrorKind::HostKeyMismatch { expected, actual } => {
+                format!("host key mismatch, expected fingerprint: {}, actual fingerprint: {}", expected, actual)
+            }
+            SshErrorKind::HostKeyUnknown { fingerprint } => {
+                format!("host key is not found in known_hosts, fingerprint: {}", fingerprint)
+            }
+            SshErrorKind::HostKeyRevoked { fingerprint } => {
+                format!("host key is revoked, fingerprint: {}", fingerprint)
+            }
+            SshErrorKind::NegotiationFailed { category, client, server } => {
+                format!("no matching {} algorithm, client: [{}], server: [{}]", category, client, server)
+            }
+            SshErrorKind::HostCertificateExpired { key_id, valid_after, valid_before } => {
+                format!("host certificate [{}] is only valid from {} to {}", key_id, valid_after, valid_before)
+            }
+            SshErrorKind::HostCertificatePrincipal { host, principals } => {
+                format!("host certificate is not valid for [{}], principals: {}", host, principals.join(","))
+            }
+            SshErrorKind::ProtocolViolation { message } => {
+                format!("protocol violation: {}", message)
+            }
+            SshErrorKind::CommandFailed { status, stderr_tail } => {
+                format!("command failed, {}, stderr: {}", status, stderr_tail)
+            }
+            SshErrorKind::ChannelOpenFailed { reason_code, description } => {
+                format!("channel open failed, {}: {}", open_failure_reason(*reason_code), description)
+            }
+            SshErrorKind::TransferStalled { snapshot } => {
+                format!("transfer stalled, {}", snapshot.to_json())
+            }
+            SshErrorKind::Disconnected { code, description } => {
+                format!("disconnected by server, {}: {}", disconnect_reason(*code), description)
+            }
+            SshErrorKind::SubsystemUnavailable { name } => {
+                format!("subsystem not available: {}", name)
+            }
+            SshErrorKind::ChannelRequestFailed { request } => {
+                format!("channel request failed: {}", request)
+            }
+            SshErrorKind::ChannelClosed => "channel closed.".to_string(),
+            SshErrorKind::KeepaliveTimeout { missed } => {
+                format!("keepalive time out, {} requests unanswered.", missed)
+            }
+        };
+        f.write_str(&message)
+    }
+}
+
+impl Error for SshErrorKind {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SshErrorKind::IoError(v) => Some(v),
+            _ => None
         }
     }
 }
 
 
+fn open_failure_reason(reason_code: u32) -> String {
+    match reason_code {
+        ssh_msg_code::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED => "SSH_OPEN_ADMINISTRATIVELY_PROHIBITED".to_string(),
+        ssh_msg_code::SSH_OPEN_CONNECT_FAILED => "SSH_OPEN_CONNECT_FAILED".to_string(),
+        ssh_msg_code::SSH_OPEN_UNKNOWN_CHANNEL_TYPE => "SSH_OPEN_UNKNOWN_CHANNEL_TYPE".to_string(),
+        ssh_msg_code::SSH_OPEN_RESOURCE_SHORTAGE => "SSH_OPEN_RESOURCE_SHORTAGE".to_string(),
+        _ => format!("reason code {}", reason_code)
+    }
+}
+
+
+fn disconnect_reason(code: u32) -> String {
+    let name = match u8::try_from(code) {
+        Ok(ssh_msg_code::SSH_DISCONNECT_HOST_NOT_ALLOWED_TO_CONNECT) => "SSH_DISCONNECT_HOST_NOT_ALLOWED_TO_CONNECT",
+        Ok(ssh_msg_code::SSH_DISCONNECT_PROTOCOL_ERROR) => "SSH_DISCONNECT_PROTOCOL_ERROR",
+        Ok(ssh_msg_code::SSH_DISCONNECT_KEY_EXCHANGE_FAILED) => "SSH_DISCONNECT_KEY_EXCHANGE_FAILED",
+        Ok(ssh_msg_code::SSH_DISCONNECT_RESERVED) => "SSH_DISCONNECT_RESERVED",
+        Ok(ssh_msg_code::SSH_DISCONNECT_MAC_ERROR) => "SSH_DISCONNECT_MAC_ERROR",
+        Ok(ssh_msg_code::SSH_DISCONNECT_COMPRESSION_ERROR) => "SSH_DISCONNECT_COMPRESSION_ERROR",
+        Ok(ssh_msg_code::SSH_DISCONNECT_SERVICE_NOT_AVAILABLE) => "SSH_DISCONNECT_SERVICE_NOT_AVAILABLE",
+        Ok(ssh_msg_code::SSH_DISCONNECT_PROTOCOL_VERSION_NOT_SUPPORTED) => "SSH_DISCONNECT_PROTOCOL_VERSION_NOT_SUPPORTED",
+        Ok(ssh_msg_code::SSH_DISCONNECT_HOST_KEY_NOT_VERIFIABLE) => "SSH_DISCONNECT_HOST_KEY_NOT_VERIFIABLE",
+        Ok(ssh_msg_code::SSH_DISCONNECT_CONNECTION_LOST) => "SSH_DISCONNECT_CONNECTION_LOST",
+        Ok(ssh_msg_code::SSH_DISCONNECT_BY_APPLICATION) => "SSH_DISCONNECT_BY_APPLICATION",
+        Ok(ssh_msg_code::SSH_DISCONNECT_TOO_MANY_CONNECTIONS) => "SSH_DISCONNECT_TOO_MANY_CONNECTIONS",
+        Ok(ssh_msg_code::SSH_DISCONNECT_AUTH_CANCELLED_BY_USER) => "SSH_DISCONNECT_AUTH_CANCELLED_BY_USER",
+        Ok(ssh_msg_code::SSH_DISCONNECT_NO_MORE_AUTH_METHODS_AVAILABLE) => "SSH_DISCONNECT_NO_MORE_AUTH_METHODS_AVAILABLE",
+        Ok(ssh_msg_code::SSH_DISCONNECT_ILLEGAL_USER_NAME) => "SSH_DISCONNECT_ILLEGAL_USER_NAME",
+        _ => return format!("reason code {}", code)
+    };
+    name.to_string()
+}
+
+
 impl Error for SshError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.inner.source()
+    }
 }
 
 impl From<SshErrorKind> for SshError {
@@ -104,7 +316,18 @@ impl From<String> for SshError {
 impl From<io::Error> for SshError {
     fn from(kind: io::Error) -> Self {
         SshError {
-            inner: SshErrorKind::IoError(io::Error::from(kind.kind()))
+            inner: SshErrorKind::IoError(kind)
         }
     }
-}
\ No newline at end of file
+}
+
+
+/// 用于实现 `io::Read` 和 `io::Write` 的通道，io 错误原样返回，
+/// 其它错误包装为 `ErrorKind::Other`，可以通过 `get_ref` 取得 `SshError`
+pub(crate) fn to_io_error(e: SshError) -> io::Error {
+    match e.inner {
+        SshErrorKind::IoError(v) => v,
+        SshErrorKind::ChannelClosed => io::Error::new(io::ErrorKind::BrokenPipe, SshError::from(SshErrorKind::ChannelClosed)),
+        inner => io::Error::other(SshError::from(inner))
+    }
+}
@@ -0,0 +1,143 @@
+use crate::channel::{CloseInitiator, CloseSummary, ExitSignal};
+
+
+/// session 级别的事件, 由 `Session::poll_event` 返回
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// 通道收到了数据，可以调用该通道的 read 读取
+    ChannelData {
+        id: u32
+    },
+    /// 服务端关闭了通道，之后仍需要调用该通道的 close 回复关闭
+    ChannelClosed {
+        id: u32,
+        summary: CloseSummary
+    },
+    /// 服务端发送的全局请求，需要回复时已经回复了 SSH_MSG_REQUEST_FAILURE
+    GlobalRequest {
+        name: String
+    },
+    /// 服务端发起的密钥重新交换已完成
+    RekeyCompleted,
+    /// 服务端断开了连接
+    Disconnected {
+        reason: String
+    },
+}
+
+
+/// 从连接上经过的消息中记录的通道状态
+/// 用于生成 ChannelClosed 事件的汇总信息
+#[derive(Default)]
+pub(crate) struct ChannelRecord {
+    pub(crate) eof_received: bool,
+    pub(crate) exit_status: Option<u32>,
+    pub(crate) exit_signal: Option<ExitSignal>,
+    pub(crate) bytes_in: u64,
+    pub(crate) bytes_out: u64,
+    pub(crate) initiator: Option<CloseInitiator>
+}
+
+impl ChannelRecord {
+    pub(crate) fn summary(&self) -> CloseSummary {
+        CloseSummary {
+            eof_received: self.eof_received,
+            exit_status: self.exit_status,
+            exit_signal: self.exit_signal.clone(),
+            bytes_in: self.bytes_in,
+            bytes_out: self.bytes_out,
+            initiator: self.initiator.unwrap_or(CloseInitiator::Remote)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::channel::{CloseInitiator, CloseSummary};
+    use crate::client::Client;
+    use crate::constant::{ssh_msg_code, ssh_str};
+    use crate::data::Data;
+    use crate::mock::{self, MockServer};
+    use super::Event;
+
+    fn message(message_code: u8, client_channel: u32) -> Data {
+        let mut data = Data::new();
+        data.put_u8(message_code).put_u32(client_channel);
+        data
+    }
+
+    /// 未加密时每次读取只处理一个数据包，逐个发送
+    fn events(client: &mut Client, server: &MockServer, messages: Vec<Data>) -> Vec<Event> {
+        let mut events = vec![];
+        for data in messages {
+            server.send(data);
+            while let Some(event) = client.next_event().unwrap() {
+                events.push(event)
+            }
+        }
+        events
+    }
+
+    fn closed(events: &[Event]) -> Option<&CloseSummary> {
+        events.iter().find_map(|v| match v {
+            Event::ChannelClosed { summary, .. } => Some(summary),
+            _ => None
+        })
+    }
+
+    #[test]
+    fn record_tracks_remote_close() {
+        let (mut client, server) = mock::connection();
+        client.register_channel(1);
+        let mut data = message(ssh_msg_code::SSH_MSG_CHANNEL_DATA, 1);
+        data.put_u8s(b"hello");
+        let mut extended = message(ssh_msg_code::SSH_MSG_CHANNEL_EXTENDED_DATA, 1);
+        // SSH_EXTENDED_DATA_STDERR
+        extended.put_u32(1).put_u8s(b"err");
+        let mut exit_status = message(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST, 1);
+        exit_status.put_str(ssh_str::EXIT_STATUS).put_u8(false as u8).put_u32(3);
+        let events = events(&mut client, &server, vec![
+            data,
+            extended,
+            message(ssh_msg_code::SSH_MSG_CHANNEL_EOF, 1),
+            exit_status,
+            message(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE, 1)
+        ]);
+        assert_eq!(events.iter().filter(|v| matches!(v, Event::ChannelData { id: 1 })).count(), 2);
+        let summary = closed(&events).unwrap();
+        assert!(summary.eof_received);
+        assert_eq!(summary.exit_status, Some(3));
+        assert_eq!(summary.exit_signal, None);
+        assert_eq!(summary.bytes_in, 8);
+        assert_eq!(summary.initiator, CloseInitiator::Remote);
+    }
+
+    #[test]
+    fn local_close_is_kept_as_initiator() {
+        let (mut client, server) = mock::connection();
+        client.register_channel(1);
+        client.local_closed(1);
+        let events = events(&mut client, &server, vec![message(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE, 1)]);
+        let summary = closed(&events).unwrap();
+        assert!(!summary.eof_received);
+        assert_eq!(summary.initiator, CloseInitiator::Local);
+    }
+
+    #[test]
+    fn removed_channel_is_not_tracked() {
+        let (mut client, server) = mock::connection();
+        client.register_channel(1);
+        client.register_channel(2);
+        client.remove_channel(1);
+        assert!(!client.records.contains_key(&1));
+        let events = events(&mut client, &server, vec![
+            message(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE, 1),
+            message(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE, 2)
+        ]);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], Event::ChannelClosed { id: 2, .. }));
+        assert!(!client.channels.contains_key(&1));
+        assert_eq!(client.channels[&2].len(), 1);
+    }
+}
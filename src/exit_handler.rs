@@ -0,0 +1,181 @@
+//! 进程退出时关闭所有存活的 session
+//!
+//! 只有开启 `ctrlc` feature 时才会编译这个模块。
+//! 每个 session 连接后都会把 client 的弱引用登记到一个全局列表里，
+//! `session.close()` 时移除。
+//!
+//! 线程相关的注意事项:
+//! - 信号处理函数只会往管道里写一个字节，真正的关闭在一个后台线程里完成，
+//!   然后恢复默认的信号处理并重新发送该信号，进程按原来的方式退出。
+//! - 如果某个 client 正被其它线程持有 (例如正在读取数据)，只会在总时限内尝试获取锁，
+//!   获取不到就跳过，不会阻塞退出。
+//! - 所有 session 的关闭总共最多用时 500 毫秒。
+//! - 正常退出时 (main 返回或者调用 `std::process::exit`) 通过 atexit 执行同样的关闭流程。
+
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::client::Client;
+use crate::constant::ssh_msg_code;
+use crate::error::{SshError, SshResult};
+use crate::limits::{EXIT_TIMEOUT, POLL_INTERVAL};
+
+
+static REGISTRY: Mutex<Vec<Weak<Mutex<Client>>>> = Mutex::new(Vec::new());
+
+static INSTALL: Once = Once::new();
+
+/// 信号处理函数写入的管道
+static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+
+/// 登记一个存活的 client
+pub(crate) fn register(client: &Arc<Mutex<Client>>) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.retain(|v| v.strong_count() > 0);
+        registry.push(Arc::downgrade(client));
+    }
+}
+
+/// 移除 client, 同时清理已经释放的弱引用
+pub(crate) fn deregister(client: &Arc<Mutex<Client>>) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.retain(|v| v.strong_count() > 0 && v.as_ptr() != Arc::as_ptr(client));
+    }
+}
+
+
+/// 安装退出处理
+/// 收到 SIGINT/SIGTERM/SIGHUP 或正常退出时，
+/// 向所有存活的 session 发送 SSH_MSG_DISCONNECT 并关闭连接
+/// 多次调用只会安装一次
+pub fn install_exit_handler() -> SshResult<()> {
+    let mut result = Ok(());
+    INSTALL.call_once(|| result = install());
+    result
+}
+
+fn install() -> SshResult<()> {
+    let mut fds = [0 as libc::c_int; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(SshError::from(std::io::Error::last_os_error()))
+    }
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+    PIPE_WRITE.store(write_fd, Relaxed);
+    thread::Builder::new()
+        .name("ssh-exit-handler".to_string())
+        .spawn(move || wait_signal(read_fd))?;
+    unsafe {
+        for sig in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            libc::signal(sig, on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        }
+        libc::atexit(on_exit);
+    }
+    log::info!("exit handler installed.");
+    Ok(())
+}
+
+// 信号处理函数里只能调用异步信号安全的函数
+extern "C" fn on_signal(sig: libc::c_int) {
+    let b = sig as u8;
+    unsafe {
+        libc::write(PIPE_WRITE.load(Relaxed), &b as *const u8 as *const libc::c_void, 1);
+    }
+}
+
+extern "C" fn on_exit() {
+    shutdown_all(EXIT_TIMEOUT)
+}
+
+fn wait_signal(read_fd: libc::c_int) {
+    let mut b = 0_u8;
+    loop {
+        let n = unsafe { libc::read(read_fd, &mut b as *mut u8 as *mut libc::c_void, 1) };
+        if n == 1 { break }
+        if n < 0 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+            continue
+        }
+        return
+    }
+    let sig = b as libc::c_int;
+    log::info!("received signal [{}], closing sessions.", sig);
+    shutdown_all(EXIT_TIMEOUT);
+    unsafe {
+        libc::signal(sig, libc::SIG_DFL);
+        libc::raise(sig);
+    }
+}
+
+
+/// 在 timeout 内关闭所有登记的 client
+pub(crate) fn shutdown_all(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let clients = match REGISTRY.lock() {
+        Ok(mut registry) => registry.drain(..).collect::<Vec<_>>(),
+        Err(_) => return
+    };
+    for client in clients.iter().filter_map(|v| v.upgrade()) {
+        loop {
+            if let Ok(mut client) = client.try_lock() {
+                if let Err(e) = disconnect(&mut client) {
+                    log::error!("disconnect error: {:?}", e);
+                }
+                break
+            }
+            if Instant::now() >= deadline {
+                log::error!("close session time out, skipped.");
+                break
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+fn disconnect(client: &mut Client) -> SshResult<()> {
+    client.disconnect(ssh_msg_code::SSH_DISCONNECT_BY_APPLICATION, "process exit")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex, Weak};
+    use crate::client::Client;
+    use super::{deregister, register, REGISTRY};
+
+    fn client() -> Arc<Mutex<Client>> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = Client::connect(listener.local_addr().unwrap(), true).unwrap();
+        Arc::new(Mutex::new(client))
+    }
+
+    /// 其它测试的 session 也会登记，只检查指定的 client
+    fn registered(client: &Weak<Mutex<Client>>) -> bool {
+        REGISTRY.lock().unwrap().iter().any(|v| v.ptr_eq(client))
+    }
+
+    #[test]
+    fn register_and_deregister() {
+        let client = client();
+        let weak = Arc::downgrade(&client);
+        register(&client);
+        assert!(registered(&weak));
+        deregister(&client);
+        assert!(!registered(&weak));
+    }
+
+    #[test]
+    fn dropped_clients_are_removed() {
+        let (dropped, alive) = (client(), client());
+        let weak = Arc::downgrade(&dropped);
+        register(&dropped);
+        drop(dropped);
+        // 已经释放的弱引用在下一次登记时清理
+        register(&alive);
+        assert!(!registered(&weak));
+        assert!(registered(&Arc::downgrade(&alive)));
+        deregister(&alive);
+    }
+}
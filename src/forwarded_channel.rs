@@ -0,0 +1,250 @@
+//! 服务端打开、转发到本地的通道 (agent 转发和 X11 转发)
+//!
+//! 库中没有后台线程，这些通道由 client 在读取连接时处理，不会出现在 `poll_event` 中:
+//! 服务端发来的数据写入本地的连接，本地的回应在每次读取连接时取出并发送给服务端，
+//! 所以等待命令输出 (例如 `ChannelExec::output`) 的同时转发也在进行。
+//! 任意一方关闭后通道随之关闭
+
+use std::io::{self, Read, Write};
+use crate::client::Client;
+use crate::constant::ssh_msg_code;
+use crate::data::Data;
+use crate::error::SshResult;
+use crate::limits;
+use crate::x11_forward::X11Setup;
+
+
+pub(crate) trait LocalStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> LocalStream for T {}
+
+
+/// 一个转发中的通道
+pub(crate) struct ForwardedChannel {
+    /// 通道类型，只用于日志
+    kind: &'static str,
+    server_channel: u32,
+    /// 非阻塞的本地连接
+    stream: Box<dyn LocalStream>,
+    /// X11 的连接建立请求，替换其中的 cookie 之后才写入本地
+    x11_setup: Option<X11Setup>,
+    /// 服务端发来、还没有写入本地的数据
+    to_local: Vec<u8>,
+    /// 本地的回应中还没有发送给服务端的部分 (远程窗口不够)
+    to_server: Vec<u8>,
+    /// 已经收到、还没有通过 WINDOW_ADJUST 归还的本地窗口
+    consumed: u32,
+    remote_window: u32,
+    remote_max_packet_size: u32,
+    /// 本地关闭了连接或者读写出错
+    local_closed: bool,
+    /// 已经发送 CHANNEL_CLOSE
+    close_sent: bool,
+    /// 收到了服务端的 CHANNEL_CLOSE
+    remote_closed: bool,
+}
+
+impl ForwardedChannel {
+    /// window 和 max_packet_size 来自服务端的 SSH_MSG_CHANNEL_OPEN
+    pub(crate) fn new(kind: &'static str,
+                      server_channel: u32,
+                      window: u32,
+                      max_packet_size: u32,
+                      stream: Box<dyn LocalStream>) -> Self {
+        ForwardedChannel {
+            kind,
+            server_channel,
+            stream,
+            x11_setup: None,
+            to_local: vec![],
+            to_server: vec![],
+            consumed: 0,
+            remote_window: window,
+            remote_max_packet_size: max_packet_size,
+            local_closed: false,
+            close_sent: false,
+            remote_closed: false,
+        }
+    }
+
+    pub(crate) fn with_x11_setup(mut self, setup: X11Setup) -> Self {
+        self.x11_setup = Some(setup);
+        self
+    }
+
+    fn receive_data(&mut self, mut data: Vec<u8>) {
+        self.consumed += data.len() as u32;
+        let setup = match &mut self.x11_setup {
+            Some(v) => v,
+            None => return self.to_local.append(&mut data)
+        };
+        match setup.receive(&data) {
+            Ok(Some(mut v)) => {
+                self.to_local.append(&mut v);
+                self.x11_setup = None
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::error!("x11 connection setup error: {}", e);
+                self.x11_setup = None;
+                self.local_closed = true
+            }
+        }
+    }
+
+    /// 与本地交换数据，本地关闭或出错时只关闭这个通道
+    fn transfer(&mut self) {
+        while !self.to_local.is_empty() {
+            match self.stream.write(&self.to_local) {
+                Ok(0) => {
+                    self.local_closed = true;
+                    break
+                }
+                Ok(len) => {
+                    self.to_local.drain(..len);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::error!("write {} connection error: {}", self.kind, e);
+                    self.local_closed = true;
+                    break
+                }
+            }
+        }
+        let mut buf = vec![0; limits::MAX_PACKET_SIZE];
+        while !self.local_closed && self.to_server.len() < limits::FORWARD_BUFFER_LEN {
+            match self.stream.read(&mut buf) {
+                Ok(0) => self.local_closed = true,
+                Ok(len) => self.to_server.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::error!("read {} connection error: {}", self.kind, e);
+                    self.local_closed = true
+                }
+            }
+        }
+    }
+
+    /// 一个数据包最多可以携带的数据，与 `WindowSize::sendable` 相同
+    fn sendable(&self) -> usize {
+        let max_packet_size = match self.remote_max_packet_size {
+            0 => limits::MAX_PACKET_SIZE,
+            v => v as usize
+        };
+        max_packet_size.saturating_sub(9).max(1).min(self.remote_window as usize)
+    }
+
+    /// 转发数据并发送窗口调整、回应和关闭，通道已经关闭、可以移除时返回 false
+    fn pump(&mut self, client: &mut Client) -> SshResult<bool> {
+        if self.remote_closed {
+            if !self.close_sent {
+                client.write(self.message(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE))?;
+            }
+            log::info!("{} channel closed.", self.kind);
+            return Ok(false)
+        }
+        if self.close_sent {
+            return Ok(true)
+        }
+        if !self.local_closed {
+            self.transfer();
+        }
+        // 本地还没有写完的数据较多时先不归还窗口，服务端等待
+        if self.consumed >= limits::LOCAL_WINDOW_SIZE / 2
+            && self.to_local.len() < limits::LOCAL_WINDOW_SIZE as usize / 2 {
+            let mut data = self.message(ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST);
+            data.put_u32(self.consumed);
+            client.write(data)?;
+            self.consumed = 0;
+        }
+        while !self.to_server.is_empty() && self.remote_window > 0 {
+            let len = self.sendable().min(self.to_server.len());
+            let mut data = self.message(ssh_msg_code::SSH_MSG_CHANNEL_DATA);
+            data.put_u8s(&self.to_server[..len]);
+            client.write(data)?;
+            self.to_server.drain(..len);
+            self.remote_window -= len as u32;
+        }
+        if self.local_closed && self.to_server.is_empty() {
+            client.write(self.message(ssh_msg_code::SSH_MSG_CHANNEL_EOF))?;
+            client.write(self.message(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE))?;
+            self.close_sent = true;
+        }
+        Ok(true)
+    }
+
+    fn message(&self, message_code: u8) -> Data {
+        let mut data = Data::new();
+        data.put_u8(message_code)
+            .put_u32(self.server_channel);
+        data
+    }
+}
+
+
+impl Client {
+    /// 本地连接已经建立，回应 SSH_MSG_CHANNEL_OPEN_CONFIRMATION 并开始转发
+    pub(crate) fn open_forwarded(&mut self, channel: ForwardedChannel) -> SshResult<()> {
+        let client_channel = self.next_client_channel();
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_OPEN_CONFIRMATION)
+            .put_u32(channel.server_channel)
+            .put_u32(client_channel)
+            .put_u32(limits::LOCAL_WINDOW_SIZE)
+            .put_u32(limits::MAX_PACKET_SIZE as u32);
+        log::info!("{} channel opened.", channel.kind);
+        self.forwarded.insert(client_channel, channel);
+        self.write(data)
+    }
+
+    /// 转发中的通道的消息，只记录下来，数据在 `pump_forwarded` 中转发
+    pub(crate) fn receive_forwarded_message(&mut self, client_channel: u32, data: &[u8]) -> SshResult<()> {
+        let channel = match self.forwarded.get_mut(&client_channel) {
+            Some(v) => v,
+            None => return Ok(())
+        };
+        let mut data = Data::from(data);
+        let message_code = data.get_u8();
+        data.get_u32();
+        match message_code {
+            ssh_msg_code::SSH_MSG_CHANNEL_DATA if !channel.close_sent => {
+                channel.receive_data(data.get_u8s())
+            }
+            ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST => {
+                channel.remote_window = channel.remote_window.saturating_add(data.get_u32())
+            }
+            ssh_msg_code::SSH_MSG_CHANNEL_CLOSE => channel.remote_closed = true,
+            // 转发的通道上没有可以接受的请求
+            ssh_msg_code::SSH_MSG_CHANNEL_REQUEST => {
+                data.get_u8s();
+                if data.get_u8() != 0 {
+                    let reply = channel.message(ssh_msg_code::SSH_MSG_CHANNEL_FAILURE);
+                    self.write(reply)?;
+                }
+            }
+            // agent 和 X11 的连接都不需要半关闭，服务端的 EOF 之后等待 CHANNEL_CLOSE 即可
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 每次读取连接时转发所有通道的数据，重新协商密钥期间不发送
+    pub(crate) fn pump_forwarded(&mut self) -> SshResult<()> {
+        if self.forwarded.is_empty() || self.rekeying {
+            return Ok(())
+        }
+        let ids: Vec<u32> = self.forwarded.keys().copied().collect();
+        for client_channel in ids {
+            let mut channel = match self.forwarded.remove(&client_channel) {
+                Some(v) => v,
+                None => continue
+            };
+            if channel.pump(self)? {
+                self.forwarded.insert(client_channel, channel);
+            }
+        }
+        Ok(())
+    }
+}
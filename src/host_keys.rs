@@ -0,0 +1,187 @@
+//! 主机密钥轮换 (OpenSSH 的 UpdateHostKeys)
+//!
+//! 认证完成后 OpenSSH 服务端会发送 hostkeys-00@openssh.com 全局请求，通告当前所有的主机密钥。
+//! 开启更新后，known_hosts 中没有的密钥要先通过 hostkeys-prove-00@openssh.com
+//! 让服务端用对应的私钥签名，校验通过后才追加到 known_hosts，同时删除该主机不再使用的密钥。
+//!
+//! 只有本次连接的主机密钥是通过 known_hosts 中的记录校验 (或 AcceptNew 刚刚记录) 的才会更新，
+//! 证书或者 AcceptAll 放行的连接只记录通告的密钥，不修改文件。
+
+use std::path::PathBuf;
+use crate::algorithm::public_key;
+use crate::client::{Client, PendingRequest};
+use crate::constant::{ssh_msg_code, ssh_str};
+use crate::data::{Data, Reader};
+use crate::error::{SshError, SshResult};
+use crate::known_hosts::{self, CheckResult, KnownHosts};
+
+
+const INVALID_MESSAGE: &str = "invalid hostkeys message.";
+
+
+/// 更新 known_hosts 需要的信息
+pub(crate) struct HostKeysUpdate {
+    pub(crate) path: PathBuf,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// 追加的记录是否哈希主机名
+    pub(crate) hashed: bool,
+    /// Session 中设置的已吊销主机密钥
+    pub(crate) revoked: Vec<Vec<u8>>,
+}
+
+/// 等待服务端证明的主机密钥
+pub(crate) struct HostKeysProve {
+    /// 服务端通告的全部主机密钥
+    announced: Vec<Vec<u8>>,
+    /// known_hosts 中还没有、需要证明的主机密钥
+    keys: Vec<Vec<u8>>,
+}
+
+
+impl Client {
+    /*
+        byte      SSH_MSG_GLOBAL_REQUEST
+        string    "hostkeys-00@openssh.com"
+        boolean   want reply (false)
+        string[]  host key blobs
+    */
+    /// 通告的内容有问题时只记录日志，不影响连接
+    pub(crate) fn receive_host_keys(&mut self, data: &[u8]) -> SshResult<()> {
+        let keys = match parse_host_keys(data) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("ignore hostkeys message, error info: {:?}", e);
+                return Ok(())
+            }
+        };
+        log::info!("server announced {} host keys.", keys.len());
+        self.announced_host_keys = keys.clone();
+        let update = match &self.host_keys_update {
+            None => return Ok(()),
+            Some(v) => v
+        };
+        match self.host_key.as_ref() {
+            Some(v) if keys.contains(v) => {}
+            // 通告中没有本次连接使用的主机密钥时不做任何修改
+            _ => {
+                log::error!("server did not announce the host key in use, skip updating known_hosts.");
+                return Ok(())
+            }
+        }
+        let known_hosts = match KnownHosts::from_path(&update.path) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("read known_hosts error, error info: {:?}", e);
+                return Ok(())
+            }
+        };
+        let mut new = vec![];
+        for key in &keys {
+            let key_type = known_hosts::key_type(key).unwrap_or_default();
+            if public_key::from_algorithm(&key_type).is_none() {
+                log::debug!("skip unsupported host key type: {}", key_type);
+                continue
+            }
+            match known_hosts.check(&update.host, update.port, key) {
+                CheckResult::Ok => {}
+                CheckResult::Revoked => log::info!("skip revoked host key: {}", known_hosts::fingerprint(key)),
+                _ if update.revoked.contains(key) => log::info!("skip revoked host key: {}", known_hosts::fingerprint(key)),
+                _ => new.push(key.clone())
+            }
+        }
+        if new.is_empty() {
+            update_known_hosts(update, &keys, &[]);
+            return Ok(())
+        }
+        // 新的密钥需要服务端证明持有私钥
+        log::info!("request the server to prove {} new host keys.", new.len());
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_GLOBAL_REQUEST)
+            .put_str(ssh_str::HOSTKEYS_PROVE)
+            .put_u8(true as u8);
+        for key in &new {
+            data.put_u8s(key);
+        }
+        self.write(data)?;
+        self.global_requests.push_back(PendingRequest::HostKeysProve(HostKeysProve {
+            announced: keys,
+            keys: new
+        }));
+        Ok(())
+    }
+
+    /*
+        byte      SSH_MSG_REQUEST_SUCCESS
+        string[]  signatures, 与请求中的主机密钥一一对应
+    */
+    /// 任意一个签名校验失败时都不修改 known_hosts
+    pub(crate) fn receive_host_keys_proof(&mut self, request: HostKeysProve, data: &[u8]) {
+        if data.first() != Some(&ssh_msg_code::SSH_MSG_REQUEST_SUCCESS) {
+            log::error!("server refused to prove the host keys.");
+            return
+        }
+        match self.verify_host_keys_proof(&request.keys, &data[1..]) {
+            Ok(true) => {
+                log::info!("host keys proof verification success.");
+                if let Some(update) = &self.host_keys_update {
+                    update_known_hosts(update, &request.announced, &request.keys)
+                }
+            }
+            Ok(false) => log::error!("host keys proof verification failure."),
+            Err(e) => log::error!("host keys proof verification error, error info: {:?}", e)
+        }
+    }
+
+    /*
+        签名的数据:
+        string    "hostkeys-prove-00@openssh.com"
+        string    session identifier
+        string    host key blob
+    */
+    fn verify_host_keys_proof(&self, keys: &[Vec<u8>], signatures: &[u8]) -> SshResult<bool> {
+        let session_id = match &self.session_id {
+            Some(v) => v,
+            None => return Err(SshError::from("session id not generated."))
+        };
+        let mut reader = Reader::new(signatures, INVALID_MESSAGE);
+        for key in keys {
+            let sig = reader.string()?;
+            let mut signed = Data::new();
+            signed.put_str(ssh_str::HOSTKEYS_PROVE)
+                .put_u8s(session_id)
+                .put_u8s(key);
+            if !public_key::verify(key, signed.as_slice(), sig)? {
+                log::error!("bad signature for host key: {}", known_hosts::fingerprint(key));
+                return Ok(false)
+            }
+        }
+        Ok(reader.is_empty())
+    }
+}
+
+
+fn parse_host_keys(data: &[u8]) -> SshResult<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(data.get(1..).unwrap_or_default(), INVALID_MESSAGE);
+    // 请求名称
+    reader.string()?;
+    // want reply
+    reader.u8()?;
+    let mut keys = vec![];
+    while !reader.is_empty() {
+        keys.push(reader.string()?.to_vec())
+    }
+    Ok(keys)
+}
+
+/// 更新失败只记录日志，不影响连接
+fn update_known_hosts(update: &HostKeysUpdate, announced: &[Vec<u8>], added: &[Vec<u8>]) {
+    if let Err(e) = known_hosts::update_host_keys(&update.path,
+                                                  &update.host,
+                                                  update.port,
+                                                  announced,
+                                                  added,
+                                                  update.hashed) {
+        log::error!("update known_hosts error, error info: {:?}", e);
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::{Duration, Instant};
+use crate::error::{SshError, SshErrorKind, SshResult};
+
+
+/// 保活
+/// 一段时间没有收发数据包时发送 keepalive@openssh.com 全局请求，
+/// 连续 max_missed 次没有回应时断开连接
+pub(crate) struct Keepalive {
+    interval: Duration,
+    max_missed: u32,
+    /// 已发送但没有收到回应的次数
+    missed: u32,
+    last_time: Instant,
+}
+
+impl Keepalive {
+    pub(crate) fn new(interval: Duration, max_missed: u32) -> Self {
+        Keepalive {
+            interval,
+            max_missed,
+            missed: 0,
+            last_time: Instant::now(),
+        }
+    }
+
+    /// 收到服务端的数据或者发送了数据包
+    pub(crate) fn renew(&mut self) {
+        self.last_time = Instant::now()
+    }
+
+    /// 收到 keepalive 的回应
+    pub(crate) fn reply(&mut self) {
+        self.missed = 0
+    }
+
+    pub(crate) fn is_due(&self) -> bool {
+        self.last_time.elapsed() >= self.interval
+    }
+
+    /// 记录发送了一次请求
+    /// 超过 max_missed 次没有回应时返回超时错误
+    pub(crate) fn sent(&mut self) -> SshResult<()> {
+        if self.missed >= self.max_missed {
+            log::error!("keepalive time out, {} requests unanswered.", self.missed);
+            return Err(SshError::from(SshErrorKind::KeepaliveTimeout {
+                missed: self.missed
+            }))
+        }
+        self.missed += 1;
+        self.last_time = Instant::now();
+        Ok(())
+    }
+}
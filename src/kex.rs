@@ -1,9 +1,7 @@
-use std::sync::atomic::Ordering;
-use crate::constant::ssh_msg_code;
-use crate::algorithm::encryption::IS_ENCRYPT;
-use crate::error::{SshError, SshResult};
-use crate::data::Data;
-use crate::slog::log;
+use std::time::Instant;
+use crate::constant::{ssh_msg_code, ssh_str};
+use crate::error::{SshError, SshErrorKind, SshResult};
+use crate::data::{Data, Reader};
 use crate::config::{
     CompressionAlgorithm,
     EncryptionAlgorithm,
@@ -11,38 +9,35 @@ use crate::config::{
     MacAlgorithm,
     PublicKeyAlgorithm
 };
-use crate::{client, config, util};
-use crate::algorithm::{key_exchange, public_key};
-use crate::algorithm::hash::h;
+use crate::{known_hosts, limits, util};
+use crate::client::Client;
+use crate::algorithm::hash::HASH;
+use crate::algorithm::negotiated::Negotiated;
 
 
 /// 发送客户端的算法列表
-pub(crate) fn send_algorithm() -> SshResult<()> {
-    let config = config::config();
-    log::info!("client algorithms: [{}]", config.algorithm.client_algorithm.to_string());
-    if IS_ENCRYPT.load(Ordering::Relaxed) {
-        IS_ENCRYPT.store(false, Ordering::Relaxed);
-    }
+pub(crate) fn send_algorithm(client: &mut Client) -> SshResult<()> {
+    let client_algorithm = &client.config.algorithm.client_algorithm;
+    log::info!("client algorithms: [{}]", client_algorithm.to_string());
     let mut data = Data::new();
     data.put_u8(ssh_msg_code::SSH_MSG_KEXINIT);
     data.extend(util::cookie());
-    data.extend(config.algorithm.client_algorithm.as_i());
+    // 只在第一次密钥交换中通告 ext-info-c (RFC 8308 2.1) 和 kex-strict-c
+    data.extend(client_algorithm.as_i(client.session_id.is_none()));
     data.put_str("")
         .put_str("")
         .put_u8(false as u8)
         .put_u32(0_u32);
 
     // h 加入客户端算法信息
-    h::get().set_i_c(data.as_slice());
+    client.h.set_i_c(data.as_slice());
 
-    let client = client::default()?;
     client.write(data)
 }
 
 
 /// 获取服务端的算法列表
-pub(crate) fn receive_algorithm() -> SshResult<()> {
-    let client = client::default()?;
+pub(crate) fn receive_algorithm(client: &mut Client) -> SshResult<()> {
     loop {
         let results = client.read()?;
         for result in results {
@@ -51,8 +46,8 @@ pub(crate) fn receive_algorithm() -> SshResult<()> {
             match message_code {
                 ssh_msg_code::SSH_MSG_KEXINIT => {
                     // h 加入服务端算法信息
-                    h::get().set_i_s(result.as_slice());
-                    return processing_server_algorithm(result)
+                    client.h.set_i_s(result.as_slice());
+                    return processing_server_algorithm(client, result)
                 }
                 _ => {}
             }
@@ -61,41 +56,73 @@ pub(crate) fn receive_algorithm() -> SshResult<()> {
 }
 
 /// 发送客户端公钥
-pub(crate) fn send_qc() -> SshResult<()> {
+pub(crate) fn send_qc(client: &mut Client) -> SshResult<()> {
     let mut data = Data::new();
     data.put_u8(ssh_msg_code::SSH_MSG_KEXDH_INIT);
-    data.put_u8s(key_exchange::get().get_public_key());
-    let client = client::default()?;
+    data.put_u8s(client.key_exchange()?.get_public_key());
     client.write(data)
 }
 
+/// 按这次协商出的算法创建密钥交换算法和主机密钥算法，h 加入双方的版本
+pub(crate) fn matching_key_exchange(client: &mut Client) -> SshResult<()> {
+    let config = &client.config;
+    // 缓存密钥交换算法
+    client.key_exchange = Some(config.algorithm.matching_key_exchange_algorithm()?);
+    // 公钥算法
+    client.public_key = Some(config.algorithm.matching_public_key_algorithm()?);
+
+    client.h.set_v_c(config.version.client_version.as_str());
+    client.h.set_v_s(config.version.server_version.as_str());
+    Ok(())
+}
+
+/// 验证 SSH_MSG_KEXDH_REPLY 中服务端的签名，返回这次密钥交换的 exchange hash
+/// data 为去掉消息码之后的数据
+pub(crate) fn verify_signature(client: &mut Client, data: Data) -> SshResult<Vec<u8>> {
+    // 生成session_id并且获取signature
+    let sig = generate_signature(client, data)?;
+    // 验签
+    let exchange_hash = client.exchange_hash()?;
+    let flag = client.public_key()?
+        .verify_signature(client.h.k_s.as_ref(), &exchange_hash, &sig)?;
+    if !flag {
+        log::error!("signature verification failure.");
+        return Err(SshError::from("signature verification failure."))
+    }
+    log::info!("signature verification success.");
+    Ok(exchange_hash)
+}
+
 
 /// 接收服务端公钥和签名，并验证签名的正确性
-pub(crate) fn verify_signature_and_new_keys() -> SshResult<()> {
+pub(crate) fn verify_signature_and_new_keys(client: &mut Client) -> SshResult<()> {
     loop {
-        let client = client::default()?;
         let results = client.read()?;
         for mut result in results {
             if result.is_empty() { continue }
             let message_code = result.get_u8();
             match message_code {
                 ssh_msg_code::SSH_MSG_KEXDH_REPLY => {
-                    // 生成session_id并且获取signature
-                    let sig = generate_signature(result)?;
-                    // 验签
-                    let session_id = h::get().digest();
-                    let flag = public_key::get()
-                        .verify_signature(h::get().k_s.as_ref(),
-                                          &session_id, &sig)?;
-                    if !flag {
-                        log::error!("signature verification failure.");
-                        return Err(SshError::from("signature verification failure."))
+                    let session_id = verify_signature(client, result)?;
+                    // h 中保存的 k_s 带有4位长度
+                    let host_key = client.h.k_s[4..].to_vec();
+                    // 主机密钥只在第一次密钥交换之后校验 (`Session::verify_host_key`)，
+                    // 和 OpenSSH 一样，重新协商密钥时服务端必须使用同一个主机密钥
+                    if let Some(expected) = client.host_key.clone() {
+                        if expected != host_key {
+                            return Err(host_key_changed(client, &expected, &host_key))
+                        }
                     }
-                    log::info!("signature verification success.");
+                    client.host_key = Some(host_key);
+                    // 第一次密钥交换的 exchange hash 作为整个连接的 session id
+                    client.session_id.get_or_insert(session_id);
                 }
                 ssh_msg_code::SSH_MSG_NEWKEYS => {
-                    new_keys()?;
+                    new_keys(client)?;
                     log::info!("send new keys");
+                    let negotiated = client.config.algorithm.negotiated();
+                    log::info!("negotiated algorithms:\n{}", negotiated);
+                    client.negotiated = Some(negotiated);
                     return Ok(())
                 }
                 _ => {}
@@ -104,41 +131,167 @@ pub(crate) fn verify_signature_and_new_keys() -> SshResult<()> {
     }
 }
 
+/// 重新协商密钥时服务端换了主机密钥，断开连接
+fn host_key_changed(client: &mut Client, expected: &[u8], actual: &[u8]) -> SshError {
+    let expected = known_hosts::fingerprint(expected);
+    let actual = known_hosts::fingerprint(actual);
+    log::error!("host key changed during rekey, expected: {}, actual: {}", expected, actual);
+    if let Err(e) = client.disconnect(ssh_msg_code::SSH_DISCONNECT_HOST_KEY_NOT_VERIFIABLE, "host key changed during rekey.") {
+        log::error!("disconnect error: {:?}", e);
+    }
+    SshError::from(SshErrorKind::HostKeyMismatch { expected, actual })
+}
+
+/// 处理服务端发起的密钥重新交换
+/// data 为去掉消息码之后的 SSH_MSG_KEXINIT
+pub(crate) fn rekey(client: &mut Client, data: Data) -> SshResult<()> {
+    client.rekeying = true;
+    send_algorithm(client)?;
+    exchange(client, data)
+}
+
+/// 客户端发起的密钥重新交换
+/// 发送 SSH_MSG_KEXINIT 之后等待服务端的 SSH_MSG_KEXINIT，
+/// 期间收到的通道消息放到对应通道的队列中，密钥交换完成后再处理，
+/// 服务端的全局请求的回应在新的密钥生效后发送
+pub(crate) fn initiate_rekey(client: &mut Client) -> SshResult<()> {
+    log::info!("initiate rekey.");
+    client.rekeying = true;
+    send_algorithm(client)?;
+    loop {
+        for mut data in client.read()? {
+            if data.first() == Some(&ssh_msg_code::SSH_MSG_KEXINIT) {
+                data.get_u8();
+                return exchange(client, data)
+            }
+            client.receive_other(data, "server kex init")?
+        }
+    }
+}
+
+/// 双方都已经发送了 SSH_MSG_KEXINIT，完成剩下的密钥交换
+fn exchange(client: &mut Client, data: Data) -> SshResult<()> {
+    let vec = data.to_vec();
+    let mut data = Data::from(vec![ssh_msg_code::SSH_MSG_KEXINIT]);
+    data.extend(vec);
+    client.h.set_i_s(data.as_slice());
+    processing_server_algorithm(client, data)?;
+    matching_key_exchange(client)?;
+
+    send_qc(client)?;
+
+    verify_signature_and_new_keys(client)?;
+    client.rekeying = false;
+    client.flush_deferred()
+}
+
 /// SSH_MSG_NEWKEYS 代表密钥交换完成
-pub(crate) fn new_keys() -> Result<(), SshError> {
+/// 之后的数据包使用这次协商出的算法和密钥，重新协商密钥时替换掉原来的
+pub(crate) fn new_keys(client: &mut Client) -> Result<(), SshError> {
     let mut data = Data::new();
     data.put_u8(ssh_msg_code::SSH_MSG_NEWKEYS);
-    let client = client::default()?;
     client.write(data)?;
-    IS_ENCRYPT.store(true, Ordering::Relaxed);
+    if client.kex_strict {
+        client.sequence.client_sequence_num = 0
+    }
+    let hash_type = client.key_exchange()?.get_hash_type();
+    let hash = match &client.session_id {
+        Some(session_id) => HASH::new(&client.h, hash_type, session_id),
+        None => return Err(SshError::from("session id not generated."))
+    };
+    let ciphers = client.config.algorithm.matching_encryption_algorithm(&hash)?;
+    client.integrity = Negotiated::new(
+        ciphers.client_to_server.integrity(),
+        ciphers.server_to_client.integrity()
+    );
+    client.encryption = Some(ciphers);
+    client.sequence.reset_traffic();
+    client.last_kex = Instant::now();
+    if let Some(negotiated) = &client.negotiated {
+        client.compression.negotiated(
+            &negotiated.compression_client_to_server,
+            &negotiated.compression_server_to_client
+        );
+    }
     Ok(())
 }
 
 /// 生成签名
-pub(crate) fn generate_signature(mut data: Data) -> Result<Vec<u8>, SshError> {
+fn generate_signature(client: &mut Client, mut data: Data) -> Result<Vec<u8>, SshError> {
     let ks = data.get_u8s();
-    let h_val = h::get();
-    h_val.set_k_s(&ks);
-    // TODO 未进行密钥指纹验证！！
+    client.h.set_k_s(&ks);
+    // 主机密钥由 Session::verify_host_key 校验，重新协商密钥时见 verify_signature_and_new_keys
     let qs = data.get_u8s();
-    h_val.set_q_c(key_exchange::get().get_public_key());
-    h_val.set_q_s(&qs);
-    let vec = key_exchange::get().get_shared_secret(qs)?;
-    h_val.set_k(&vec);
+    let key_exchange = match &client.key_exchange {
+        Some(v) => v,
+        None => return Err(SshError::from("key exchange algorithm not negotiated."))
+    };
+    client.h.set_q_c(key_exchange.get_public_key());
+    client.h.set_q_s(&qs);
+    let vec = key_exchange.get_shared_secret(qs)?;
+    client.h.set_k(&vec);
     let h = data.get_u8s();
     let mut hd = Data::from(h);
-    hd.get_u8s();
+    // 签名算法需要和协商的主机密钥算法一致
+    let algorithm = util::from_utf8(hd.get_u8s())?;
+    let expected = client.public_key()?.signature_algorithm();
+    if algorithm != expected {
+        log::error!("signature algorithm mismatch, expected: {}, actual: {}", expected, algorithm);
+        return Err(SshError::from(format!("signature algorithm mismatch, expected: {}, actual: {}", expected, algorithm)))
+    }
     let signature = hd.get_u8s();
     Ok(signature)
 }
 
+/// 第一次密钥交换中检查服务端的每个消息 (严格的密钥交换，OpenSSH 的 PROTOCOL 1.9)
+/// 服务端的 SSH_MSG_KEXINIT 中有 kex-strict-s 时，它必须是服务端的第一个数据包，
+/// 之后直到 SSH_MSG_NEWKEYS 只能出现密钥交换的消息 (包括 IGNORE、DEBUG)，否则断开连接。
+/// Terrapin 攻击 (CVE-2023-48795) 在密钥交换中插入或删除消息来篡改序列号，
+/// 配合每次 SSH_MSG_NEWKEYS 之后序列号从 0 开始，这样的篡改都会被发现
+pub(crate) fn check_initial_kex(client: &mut Client, sequence_num: u32, data: &[u8]) -> SshResult<()> {
+    let message_code = match data.first() {
+        Some(v) => *v,
+        None => return Ok(())
+    };
+    if message_code == ssh_msg_code::SSH_MSG_KEXINIT && !client.kex_strict && is_kex_strict(data) {
+        if sequence_num != 0 {
+            return Err(client.protocol_violation("strict kex: SSH_MSG_KEXINIT is not the first packet"))
+        }
+        log::info!("strict kex enabled.");
+        client.kex_strict = true;
+        return Ok(())
+    }
+    if !client.kex_strict {
+        return Ok(())
+    }
+    match message_code {
+        // 断开连接的原因仍然交给调用方
+        ssh_msg_code::SSH_MSG_DISCONNECT
+        | ssh_msg_code::SSH_MSG_NEWKEYS
+        | 30..=49 => Ok(()),
+        _ => Err(client.protocol_violation(
+            &format!("strict kex: unexpected message [{}] during key exchange", message_code)))
+    }
+}
+
+/// 服务端的 SSH_MSG_KEXINIT 的密钥交换算法中是否有 kex-strict-s
+fn is_kex_strict(data: &[u8]) -> bool {
+    let mut reader = Reader::new(data, "invalid kex init.");
+    let algorithms = reader.u8()
+        .and_then(|_| reader.bytes(limits::KEX_COOKIE_LEN))
+        .and_then(|_| reader.string());
+    match algorithms {
+        Ok(v) => v.split(|b| *b == b',').any(|v| v == ssh_str::KEX_STRICT_S.as_bytes()),
+        Err(_) => false
+    }
+}
+
 /// 处理服务端的算法列表
-pub(crate) fn processing_server_algorithm(mut data: Data) -> SshResult<()> {
+pub(crate) fn processing_server_algorithm(client: &mut Client, mut data: Data) -> SshResult<()> {
     data.get_u8();
     // 跳过16位cookie
-    data.skip(16);
-    let config = config::config();
-    let server_algorithm = &mut config.algorithm.server_algorithm;
+    data.skip(limits::KEX_COOKIE_LEN);
+    let server_algorithm = &mut client.config.algorithm.server_algorithm;
     server_algorithm.key_exchange_algorithm     =   KeyExchangeAlgorithm(util::vec_u8_to_string(data.get_u8s(), ",")?);
     server_algorithm.public_key_algorithm       =   PublicKeyAlgorithm(util::vec_u8_to_string(data.get_u8s(), ",")?);
     server_algorithm.c_encryption_algorithm     =   EncryptionAlgorithm(util::vec_u8_to_string(data.get_u8s(), ",")?);
@@ -150,3 +303,166 @@ pub(crate) fn processing_server_algorithm(mut data: Data) -> SshResult<()> {
     log::info!("server algorithms: [{}]", server_algorithm.to_string());
     return Ok(())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use crate::constant::ssh_msg_code;
+    use crate::data::Data;
+    use crate::error::SshErrorKind;
+    use crate::keepalive::Keepalive;
+    use crate::known_hosts;
+    use crate::mock::{self, Peer};
+    use crate::window_size::WindowSize;
+    use crate::limits;
+
+    /// 服务端每次调整的远程窗口
+    const WINDOW: usize = 1 << 16;
+
+    fn channel_message(message_code: u8, server_channel: u32) -> Data {
+        let mut data = Data::new();
+        data.put_u8(message_code).put_u32(server_channel);
+        data
+    }
+
+    #[test]
+    fn messages_during_rekey_are_deferred() {
+        let (mut client, server) = mock::client();
+        client.rekeying = true;
+        client.write(channel_message(ssh_msg_code::SSH_MSG_CHANNEL_EOF, 7)).unwrap();
+        client.write(channel_message(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE, 7)).unwrap();
+        let mut ignore = Data::new();
+        ignore.put_u8(ssh_msg_code::SSH_MSG_IGNORE).put_str("");
+        client.write(ignore).unwrap();
+        // 传输层的消息照常发送
+        let sent = server.received().iter().map(|v| v[0]).collect::<Vec<u8>>();
+        assert_eq!(sent, [ssh_msg_code::SSH_MSG_IGNORE]);
+        client.rekeying = false;
+        client.flush_deferred().unwrap();
+        let sent = server.received().iter().map(|v| v[0]).collect::<Vec<u8>>();
+        assert_eq!(sent, [ssh_msg_code::SSH_MSG_CHANNEL_EOF, ssh_msg_code::SSH_MSG_CHANNEL_CLOSE]);
+        assert!(client.deferred.is_empty());
+    }
+
+    #[test]
+    fn no_keepalive_during_rekey() {
+        let (mut client, server) = mock::client();
+        client.keepalive = Some(Keepalive::new(Duration::ZERO, 3));
+        let handle = thread::spawn(move || {
+            let mut peer = Peer::new(server, 1);
+            peer.kex();
+            thread::sleep(Duration::from_millis(50));
+            (peer.try_recv(), peer.skipped)
+        });
+        super::initiate_rekey(&mut client).unwrap();
+        let (after, skipped) = handle.join().unwrap();
+        assert!(skipped.is_empty());
+        assert!(after.is_none());
+        assert!(client.global_requests.is_empty());
+        assert!(client.deferred.is_empty());
+    }
+
+    #[test]
+    fn rekey_keeps_the_same_host_key() {
+        let (mut client, server) = mock::client();
+        client.host_key = Some(Peer::new(server.clone(), 1).host_key());
+        let handle = thread::spawn(move || {
+            let mut peer = Peer::new(server, 1);
+            peer.kex() && peer.kex()
+        });
+        super::initiate_rekey(&mut client).unwrap();
+        super::initiate_rekey(&mut client).unwrap();
+        assert!(handle.join().unwrap());
+    }
+
+    #[test]
+    fn host_key_change_on_rekey_is_rejected() {
+        let (mut client, server) = mock::client();
+        let verified = Peer::new(server.clone(), 2).host_key();
+        client.host_key = Some(verified.clone());
+        let handle = thread::spawn(move || {
+            let mut peer = Peer::new(server, 1);
+            let done = peer.kex();
+            (done, peer.host_key())
+        });
+        let e = super::initiate_rekey(&mut client).unwrap_err();
+        let (done, host_key) = handle.join().unwrap();
+        assert!(!done);
+        match e.kind() {
+            SshErrorKind::HostKeyMismatch { expected, actual } => {
+                assert_eq!(*expected, known_hosts::fingerprint(&verified));
+                assert_eq!(*actual, known_hosts::fingerprint(&host_key));
+            }
+            _ => panic!("unexpected error: {:?}", e)
+        }
+        assert_eq!(client.host_key, Some(verified));
+    }
+
+    #[test]
+    fn global_request_during_rekey_is_answered_with_new_keys() {
+        let (mut client, server) = mock::client();
+        let handle = thread::spawn(move || {
+            let mut peer = Peer::new(server, 1);
+            // 服务端发送请求时还没有收到 client 的 SSH_MSG_KEXINIT
+            let mut request = Data::new();
+            request.put_u8(ssh_msg_code::SSH_MSG_GLOBAL_REQUEST)
+                .put_str("keepalive@openssh.com")
+                .put_u8(true as u8);
+            peer.send(request);
+            assert!(peer.kex());
+            (peer.recv(), peer.skipped)
+        });
+        super::initiate_rekey(&mut client).unwrap();
+        let (reply, skipped) = handle.join().unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(reply.to_vec(), [ssh_msg_code::SSH_MSG_REQUEST_FAILURE]);
+    }
+
+    #[test]
+    fn server_rekey_during_chunked_write() {
+        let (mut client, server) = mock::client();
+        client.register_channel(1);
+        let mut window = WindowSize::with_local_window_size(limits::LOCAL_WINDOW_SIZE);
+        window.client_channel = 1;
+        window.server_channel = 7;
+        window.set_remote_max_packet_size(32768);
+        window.add_remote_window_size(WINDOW as u32);
+        let payload = (0..1 << 20).map(|v| (v % 251) as u8).collect::<Vec<u8>>();
+        let total = payload.len();
+        let handle = thread::spawn(move || {
+            let mut peer = Peer::new(server, 1);
+            let mut received = vec![];
+            let mut rekeyed = false;
+            while received.len() < total {
+                let mut data = peer.recv();
+                assert_eq!(data.get_u8(), ssh_msg_code::SSH_MSG_CHANNEL_DATA);
+                assert_eq!(data.get_u32(), 7);
+                received.extend(data.get_u8s());
+                if received.len() % WINDOW != 0 {
+                    continue
+                }
+                // 收完一个窗口的数据再调整窗口，写到一半时 client 在等待窗口时收到 SSH_MSG_KEXINIT
+                if !rekeyed && received.len() >= total / 2 {
+                    peer.kex();
+                    rekeyed = true;
+                }
+                let mut adjust = channel_message(ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST, 1);
+                adjust.put_u32(WINDOW as u32);
+                peer.send(adjust);
+            }
+            (received, peer.skipped)
+        });
+        let mut data = channel_message(ssh_msg_code::SSH_MSG_CHANNEL_DATA, 7);
+        data.put_u8s(&payload);
+        client.write_data(data, Some(&mut window)).unwrap();
+        let (received, skipped) = handle.join().unwrap();
+        assert!(received == payload);
+        // 密钥交换期间 client 没有发送其它消息
+        assert!(skipped.is_empty());
+        assert!(!client.rekeying);
+        assert!(client.negotiated.is_some());
+        assert_eq!(window.bytes_out, total as u64);
+    }
+}
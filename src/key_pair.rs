@@ -3,10 +3,12 @@ use std::io::Read;
 use std::path::Path;
 use rsa::pkcs1::FromRsaPrivateKey;
 use rsa::PublicKeyParts;
-use crate::algorithm::hash::h;
+use crate::algorithm::backend::{self, Digest};
+use crate::constant::algorithms;
 use crate::data::Data;
 use crate::{SshError, SshResult};
 
+#[derive(Clone)]
 pub struct KeyPair {
     pub(crate) private_key: String,
     pub(crate) key_type: String,
@@ -62,23 +64,29 @@ impl KeyPair {
         self.blob.to_vec()
     }
 
-    pub(crate) fn signature(&self, buf: &[u8]) -> Vec<u8> {
-        let session_id = h::get().digest();
+    /// algorithm 为 ssh-rsa、rsa-sha2-256 或 rsa-sha2-512 (RFC 8332)，密钥 blob 中的类型都是 ssh-rsa
+    /// session_id 为第一次密钥交换的 exchange hash
+    pub(crate) fn signature(&self, session_id: &[u8], buf: &[u8], algorithm: &str) -> Vec<u8> {
         let mut sd = Data::new();
-        sd.put_u8s(session_id.as_slice());
+        sd.put_u8s(session_id);
         sd.extend_from_slice(buf);
+        let (hash, digest) = match algorithm {
+            algorithms::PUBLIC_KEY_RSA_SHA2_256 => (rsa::Hash::SHA2_256, Digest::Sha256),
+            algorithms::PUBLIC_KEY_RSA_SHA2_512 => (rsa::Hash::SHA2_512, Digest::Sha512),
+            _ => (rsa::Hash::SHA1, Digest::Sha1)
+        };
         let scheme = rsa::PaddingScheme::PKCS1v15Sign {
-            hash: Some(rsa::Hash::SHA1)
+            hash: Some(hash)
         };
-        let digest = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, sd.as_slice());
-        let msg = digest.as_ref();
+        let digest = backend::digest(digest, sd.as_slice());
+        let msg = digest.as_slice();
 
 
         let rprk = rsa::RsaPrivateKey::from_pkcs1_pem(self.private_key.as_str()).unwrap();
 
         let sign = rprk.sign(scheme, msg).unwrap();
         let mut ss = Data::new();
-        ss.put_str(self.key_type.as_str());
+        ss.put_str(algorithm);
         ss.put_u8s(sign.as_slice());
         ss.to_vec()
     }
@@ -0,0 +1,633 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use base64ct::{Base64, Base64Unpadded, Encoding};
+use rand::Rng;
+use rand::rngs::OsRng;
+use crate::algorithm::backend::{self, Digest};
+use crate::algorithm::hash::md5;
+use crate::{SshError, SshResult};
+
+
+/// known_hosts 文件的默认端口
+const DEFAULT_PORT: u16 = 22;
+
+/// 哈希主机名的前缀 |1|salt|hash
+const HASH_MAGIC: &str = "|1|";
+
+/// HMAC-SHA1 的盐长度
+const HASH_SALT_LEN: usize = 20;
+
+
+/// 主机密钥校验结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckResult {
+    /// 主机密钥与记录一致
+    Ok,
+    /// 主机存在同类型的记录，但密钥不一致
+    Mismatch,
+    /// 没有该主机的记录
+    Unknown,
+    /// 主机密钥已被 @revoked 标记吊销
+    Revoked,
+}
+
+
+/// 主机密钥不在 known_hosts 中时的处理方式
+/// 无论哪种方式，密钥不一致时都会连接失败
+/// 只在设置了 known_hosts 时使用，默认和 StrictHostKeyChecking yes 一样拒绝连接
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HostKeyPolicy {
+    /// 拒绝连接
+    #[default]
+    Strict,
+    /// 把主机密钥追加到 known_hosts 后继续连接 (StrictHostKeyChecking accept-new)
+    AcceptNew,
+    /// 不记录主机密钥，直接继续连接
+    AcceptAll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Marker {
+    CertAuthority,
+    Revoked,
+}
+
+
+/// 主机名的匹配方式
+#[derive(Debug, Clone)]
+pub(crate) enum Hosts {
+    /// 逗号分隔的主机名模式
+    Patterns(Vec<String>),
+    /// HashKnownHosts 生成的 |1|salt|hash
+    Hashed {
+        salt: Vec<u8>,
+        hash: Vec<u8>
+    },
+}
+
+
+/// known_hosts 中的一行记录
+/// [marker] hostnames keytype base64-key [comment]
+#[derive(Debug, Clone)]
+pub(crate) struct Entry {
+    pub(crate) marker: Option<Marker>,
+    pub(crate) hosts: Hosts,
+    pub(crate) key_type: String,
+    pub(crate) key: Vec<u8>,
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let mut field = fields.next()?;
+        let marker = match field {
+            "@cert-authority" => Some(Marker::CertAuthority),
+            "@revoked" => Some(Marker::Revoked),
+            _ => None
+        };
+        if marker.is_some() {
+            field = fields.next()?;
+        } else if field.starts_with('@') {
+            // 未知的标记，跳过该行
+            return None
+        }
+        let hosts = match field.strip_prefix(HASH_MAGIC) {
+            Some(v) => {
+                let (salt, hash) = v.split_once('|')?;
+                Hosts::Hashed {
+                    salt: Base64::decode_vec(salt).ok()?,
+                    hash: Base64::decode_vec(hash).ok()?
+                }
+            }
+            None => Hosts::Patterns(
+                field.split(',')
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string())
+                    .collect::<Vec<String>>()
+            )
+        };
+        let key_type = fields.next()?.to_string();
+        let key = Base64::decode_vec(fields.next()?).ok()?;
+        // 剩余的部分是注释，不需要处理
+        Some(Entry {
+            marker,
+            hosts,
+            key_type,
+            key
+        })
+    }
+
+    /// 匹配主机名
+    /// 和没有哈希的记录一样，非默认端口的哈希记录是 [host]:port 的哈希
+    pub(crate) fn matches(&self, host: &str, port: u16) -> bool {
+        match &self.hosts {
+            Hosts::Patterns(patterns) => match_patterns(patterns, &host_port(host, port)),
+            Hosts::Hashed { salt, hash } => {
+                hash_host(salt, &host_port(&host.to_lowercase(), port)) == *hash
+            }
+        }
+    }
+
+    /// 是否为只属于该主机的记录 (没有通配符，也不和其它主机共用一行)
+    /// 更新主机密钥时只会删除这样的记录
+    pub(crate) fn exclusive(&self, host: &str, port: u16) -> bool {
+        match &self.hosts {
+            Hosts::Patterns(patterns) => {
+                let host = host_port(&host.to_lowercase(), port);
+                !patterns.is_empty() && patterns.iter().all(|v| v.to_lowercase() == host)
+            }
+            Hosts::Hashed { .. } => self.matches(host, port)
+        }
+    }
+}
+
+
+/// 任意一个否定模式匹配上时，整条记录都不匹配
+fn match_patterns(patterns: &[String], host: &str) -> bool {
+    let host = host.to_lowercase();
+    let mut matched = false;
+    for pattern in patterns {
+        let pattern = pattern.to_lowercase();
+        match pattern.strip_prefix('!') {
+            Some(p) => {
+                if wildcard_match(&host, p) {
+                    return false
+                }
+            }
+            None => {
+                if wildcard_match(&host, &pattern) {
+                    matched = true
+                }
+            }
+        }
+    }
+    matched
+}
+
+
+pub struct KnownHosts {
+    pub(crate) entries: Vec<Entry>,
+}
+
+impl KnownHosts {
+
+    pub fn new() -> Self {
+        KnownHosts {
+            entries: vec![]
+        }
+    }
+
+    /// 读取 known_hosts 文件
+    /// 文件不存在时返回一个空的记录
+    pub fn from_path<P: AsRef<Path>>(path: P) -> SshResult<Self> {
+        let path = expand_home(path.as_ref());
+        match fs::read_to_string(path.as_path()) {
+            Ok(s) => Ok(KnownHosts::parse(&s)),
+            Err(e) => {
+                if e.kind() == io::ErrorKind::NotFound {
+                    log::info!("known_hosts file [{:?}] does not exist.", path);
+                    return Ok(KnownHosts::new())
+                }
+                Err(SshError::from(e))
+            }
+        }
+    }
+
+    /// 解析 known_hosts 格式的字符串
+    /// 无法解析的行会被忽略
+    pub fn parse(s: &str) -> Self {
+        let mut entries = vec![];
+        for line in s.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            match Entry::parse(line) {
+                None => log::debug!("skip invalid known_hosts line: [{}]", line),
+                Some(entry) => entries.push(entry)
+            }
+        }
+        KnownHosts {
+            entries
+        }
+    }
+
+    /// 校验主机密钥
+    /// key 为服务端发送的主机密钥数据 (key blob)
+    pub fn check(&self, host: &str, port: u16, key: &[u8]) -> CheckResult {
+        let key_type = key_type(key);
+        let mut result = CheckResult::Unknown;
+        for entry in self.entries.iter().filter(|e| e.matches(host, port)) {
+            match entry.marker {
+                Some(Marker::Revoked) => {
+                    if entry.key == key {
+                        return CheckResult::Revoked
+                    }
+                }
+                Some(Marker::CertAuthority) => {}
+                None => {
+                    if entry.key == key {
+                        result = CheckResult::Ok
+                    } else if result != CheckResult::Ok
+                        && key_type.as_deref() == Some(entry.key_type.as_str()) {
+                        result = CheckResult::Mismatch
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// 校验签发主机证书的 CA 公钥
+    /// 主机匹配的 @cert-authority 记录中有该公钥时返回 Ok
+    pub(crate) fn check_ca(&self, host: &str, port: u16, ca_key: &[u8]) -> CheckResult {
+        let mut result = CheckResult::Unknown;
+        for entry in self.entries.iter().filter(|e| e.key == ca_key && e.matches(host, port)) {
+            match entry.marker {
+                Some(Marker::Revoked) => return CheckResult::Revoked,
+                Some(Marker::CertAuthority) => result = CheckResult::Ok,
+                None => {}
+            }
+        }
+        result
+    }
+
+    /// 获取主机已记录的同类型密钥
+    pub(crate) fn known_key(&self, host: &str, port: u16, key_type: &str) -> Option<&[u8]> {
+        self.entries.iter()
+            .filter(|e| e.marker.is_none() && e.key_type == key_type && e.matches(host, port))
+            .map(|e| e.key.as_slice())
+            .next()
+    }
+}
+
+impl Default for KnownHosts {
+    fn default() -> Self {
+        KnownHosts::new()
+    }
+}
+
+
+/// 非默认端口的主机在 known_hosts 中写作 [host]:port
+pub(crate) fn host_port(host: &str, port: u16) -> String {
+    if port == DEFAULT_PORT {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+/// 生成一行 known_hosts 记录
+/// hashed 为 true 时主机名按 HashKnownHosts 的格式写成 |1|salt|hash
+pub fn format_entry(host: &str, port: u16, key: &[u8], hashed: bool) -> String {
+    let host = host_port(&host.to_lowercase(), port);
+    let host = if hashed {
+        let salt: [u8; HASH_SALT_LEN] = OsRng.gen();
+        format!("{}{}|{}",
+                HASH_MAGIC,
+                Base64::encode_string(&salt),
+                Base64::encode_string(&hash_host(&salt, &host)))
+    } else {
+        host
+    };
+    format!("{} {} {}",
+            host,
+            key_type(key).unwrap_or_default(),
+            Base64::encode_string(key))
+}
+
+/// 把一行记录追加到 known_hosts 文件
+/// 文件不存在时以 0600 权限创建，整行通过一次 O_APPEND 写入
+pub fn append_entry<P: AsRef<Path>>(path: P, line: &str) -> SshResult<()> {
+    let path = expand_home(path.as_ref());
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    // 原文件最后一行没有换行时先补上
+    let mut buf = match fs::read(path.as_path()) {
+        Ok(v) if !v.is_empty() && !v.ends_with(b"\n") => String::from("\n"),
+        _ => String::new()
+    };
+    buf.push_str(line);
+    buf.push('\n');
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path.as_path())?;
+    file.write_all(buf.as_bytes())?;
+    file.flush()?;
+    log::info!("add host key to known_hosts [{:?}].", path);
+    Ok(())
+}
+
+/// 按服务端通告的主机密钥更新 known_hosts (UpdateHostKeys)
+/// 删除只属于该主机、但不在 announced 中的密钥，再追加 added 中的密钥
+/// 其它行 (注释、带标记的记录、和其它主机共用的记录) 保持不变
+/// 先写入临时文件再重命名，不会留下写了一半的文件
+pub(crate) fn update_host_keys<P: AsRef<Path>>(path: P,
+                                               host: &str,
+                                               port: u16,
+                                               announced: &[Vec<u8>],
+                                               added: &[Vec<u8>],
+                                               hashed: bool)
+    -> SshResult<()>
+{
+    let path = expand_home(path.as_ref());
+    let s = fs::read_to_string(path.as_path())?;
+    let mut buf = String::new();
+    let mut changed = !added.is_empty();
+    for line in s.lines() {
+        let retired = Entry::parse(line.trim())
+            .filter(|e| e.marker.is_none() && e.exclusive(host, port))
+            .map(|e| !announced.contains(&e.key))
+            .unwrap_or(false);
+        if retired {
+            log::info!("remove retired host key from known_hosts: [{}]", line);
+            changed = true;
+            continue
+        }
+        buf.push_str(line);
+        buf.push('\n');
+    }
+    for key in added {
+        log::info!("add host key to known_hosts, fingerprint: {}", fingerprint(key));
+        buf.push_str(&format_entry(host, port, key, hashed));
+        buf.push('\n');
+    }
+    if !changed {
+        return Ok(())
+    }
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(".tmp");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    file.write_all(buf.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path.as_path())?;
+    Ok(())
+}
+
+/// HMAC-SHA1(salt, host)
+fn hash_host(salt: &[u8], host: &str) -> Vec<u8> {
+    backend::hmac(Digest::Sha1, salt, &[host.as_bytes()])
+}
+
+/// 从 key blob 中取出密钥类型
+pub(crate) fn key_type(key: &[u8]) -> Option<String> {
+    if key.len() < 4 {
+        return None
+    }
+    let mut len = [0_u8; 4];
+    len.copy_from_slice(&key[..4]);
+    let len = u32::from_be_bytes(len) as usize;
+    let v = key.get(4..(4 + len))?;
+    String::from_utf8(v.to_vec()).ok()
+}
+
+/// 和 OpenSSH 一致的 SHA256 指纹: SHA256:无填充的base64
+pub(crate) fn fingerprint(key: &[u8]) -> String {
+    let digest = backend::digest(Digest::Sha256, key);
+    format!("SHA256:{}", Base64Unpadded::encode_string(&digest))
+}
+
+/// 旧格式的 MD5 指纹: MD5:冒号分隔的十六进制
+pub(crate) fn fingerprint_md5(key: &[u8]) -> String {
+    let digest = md5::digest(key);
+    let hex = digest.iter()
+        .map(|v| format!("{:02x}", v))
+        .collect::<Vec<String>>();
+    format!("MD5:{}", hex.join(":"))
+}
+
+pub(crate) fn expand_home(path: &Path) -> PathBuf {
+    if let Ok(p) = path.strip_prefix("~") {
+        if let Some(home) = std::env::var_os("HOME") {
+            return PathBuf::from(home).join(p)
+        }
+    }
+    path.to_path_buf()
+}
+
+/// 通配符匹配, 支持 `*` 和 `?`
+fn wildcard_match(s: &str, pattern: &str) -> bool {
+    let s = s.as_bytes();
+    let p = pattern.as_bytes();
+    let (mut si, mut pi) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == b'?' || p[pi] == s[si]) {
+            si += 1;
+            pi += 1;
+        } else if pi < p.len() && p[pi] == b'*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if let Some((sp, ss)) = star {
+            pi = sp + 1;
+            si = ss + 1;
+            star = Some((sp, ss + 1));
+        } else {
+            return false
+        }
+    }
+    while pi < p.len() && p[pi] == b'*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use base64ct::{Base64, Encoding};
+    use super::{append_entry, fingerprint, fingerprint_md5, format_entry, host_port, match_patterns, wildcard_match,
+                CheckResult, KnownHosts};
+    use crate::data::Data;
+    use crate::mock;
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIAkk1Yze6DZQ72F8gZyt4GUSiN1AghltsjqzyZpISHIc";
+
+    fn patterns(v: &[&str]) -> Vec<String> {
+        v.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn wildcard() {
+        assert!(wildcard_match("example.com", "example.com"));
+        assert!(wildcard_match("example.com", "*"));
+        assert!(wildcard_match("example.com", "*.com"));
+        assert!(wildcard_match("a.example.com", "*.example.*"));
+        assert!(wildcard_match("host1", "host?"));
+        assert!(wildcard_match("", "*"));
+        assert!(wildcard_match("abcabd", "*abd"));
+        assert!(!wildcard_match("example.org", "*.com"));
+        assert!(!wildcard_match("host12", "host?"));
+        assert!(!wildcard_match("host", "host?"));
+        assert!(!wildcard_match("example.com", "example"));
+        assert!(!wildcard_match("", "?"));
+    }
+
+    #[test]
+    fn patterns_with_negation() {
+        let list = patterns(&["*.example.com", "!bad.example.com", "10.0.0.?"]);
+        assert!(match_patterns(&list, "good.example.com"));
+        assert!(match_patterns(&list, "GOOD.Example.COM"));
+        assert!(match_patterns(&list, "10.0.0.1"));
+        assert!(!match_patterns(&list, "bad.example.com"));
+        assert!(!match_patterns(&list, "BAD.example.com"));
+        assert!(!match_patterns(&list, "example.org"));
+        // 只有否定模式时不匹配任何主机
+        assert!(!match_patterns(&patterns(&["!bad.example.com"]), "good.example.com"));
+        // 否定模式优先，与顺序无关
+        assert!(!match_patterns(&patterns(&["!*.internal", "*"]), "db.internal"));
+        assert!(match_patterns(&patterns(&["!*.internal", "*"]), "example.com"));
+        assert!(!match_patterns(&[], "example.com"));
+    }
+
+    #[test]
+    fn non_default_port() {
+        assert_eq!(host_port("example.com", 22), "example.com");
+        assert_eq!(host_port("example.com", 2222), "[example.com]:2222");
+        assert_eq!(host_port("::1", 2222), "[::1]:2222");
+        assert!(match_patterns(&patterns(&["[example.com]:2222"]), &host_port("example.com", 2222)));
+        assert!(!match_patterns(&patterns(&["example.com"]), &host_port("example.com", 2222)));
+    }
+
+    #[test]
+    fn fingerprints_match_ssh_keygen() {
+        // ssh-keygen -l -E md5 / -E sha256 的输出
+        let key = Base64::decode_vec(KEY).unwrap();
+        assert_eq!(fingerprint_md5(&key), "MD5:27:86:ca:4a:67:c4:93:ad:bb:26:ff:17:e1:d6:22:26");
+        assert_eq!(fingerprint(&key), "SHA256:tW3efRLVXMdVUKXBI4kofh/IOEPIWBnLuwplbRB9Gz0");
+    }
+
+    #[test]
+    fn revoked_beats_a_matching_plain_line() {
+        let key = mock::blob(mock::HOST_KEY);
+        for s in [
+            format!("example.com ssh-ed25519 {}\n@revoked *.com ssh-ed25519 {}", mock::HOST_KEY, mock::HOST_KEY),
+            format!("@revoked *.com ssh-ed25519 {}\nexample.com ssh-ed25519 {}", mock::HOST_KEY, mock::HOST_KEY),
+        ] {
+            let known_hosts = KnownHosts::parse(&s);
+            assert_eq!(known_hosts.check("example.com", 22, &key), CheckResult::Revoked);
+            // 吊销记录只对匹配的主机生效
+            assert_eq!(known_hosts.check("example.org", 22, &key), CheckResult::Unknown);
+        }
+    }
+
+    #[test]
+    fn different_key_of_the_same_type_is_a_mismatch() {
+        let key = mock::blob(mock::HOST_KEY);
+        let other = format!("example.com ssh-ed25519 {}", mock::CA_KEY);
+        let known_hosts = KnownHosts::parse(&other);
+        assert_eq!(known_hosts.check("example.com", 22, &key), CheckResult::Mismatch);
+        assert_eq!(known_hosts.check("example.com", 2222, &key), CheckResult::Unknown);
+        // 任意一条记录一致时通过，与顺序无关
+        for s in [
+            format!("{}\nexample.com ssh-ed25519 {}", other, mock::HOST_KEY),
+            format!("example.com ssh-ed25519 {}\n{}", mock::HOST_KEY, other),
+        ] {
+            assert_eq!(KnownHosts::parse(&s).check("example.com", 22, &key), CheckResult::Ok);
+        }
+        // 只有其它类型的密钥时是未知的主机密钥
+        let mut rsa = Data::new();
+        rsa.put_str("ssh-rsa").put_u8s(&[1, 0, 1]).put_u8s(&[7; 64]);
+        let s = format!("example.com ssh-rsa {}", Base64::encode_string(&rsa));
+        assert_eq!(KnownHosts::parse(&s).check("example.com", 22, &key), CheckResult::Unknown);
+    }
+
+    #[test]
+    fn check_ca() {
+        let ca = mock::blob(mock::CA_KEY);
+        let known_hosts = KnownHosts::parse(&format!(
+            "@cert-authority *.example.com,!bad.example.com ssh-ed25519 {}\n\
+             example.org ssh-ed25519 {}\n", mock::CA_KEY, mock::CA_KEY));
+        assert_eq!(known_hosts.check_ca("a.example.com", 22, &ca), CheckResult::Ok);
+        assert_eq!(known_hosts.check_ca("bad.example.com", 22, &ca), CheckResult::Unknown);
+        // 没有 @cert-authority 标记的记录不能签发证书
+        assert_eq!(known_hosts.check_ca("example.org", 22, &ca), CheckResult::Unknown);
+        assert_eq!(known_hosts.check_ca("a.example.com", 22, &mock::blob(mock::HOST_KEY)), CheckResult::Unknown);
+        // @revoked 优先，与顺序无关
+        let revoked = KnownHosts::parse(&format!(
+            "@cert-authority * ssh-ed25519 {}\n@revoked * ssh-ed25519 {}\n", mock::CA_KEY, mock::CA_KEY));
+        assert_eq!(revoked.check_ca("a.example.com", 22, &ca), CheckResult::Revoked);
+    }
+
+    #[test]
+    fn hashed_entries_from_ssh_keygen() {
+        // ssh-keygen -H 哈希 example.com 和 [example.com]:2222 两行得到的记录
+        let known_hosts = KnownHosts::parse(&format!(
+            "|1|cdlDJADQIeBQQgiB2jWl9QfZXYc=|9uOY2nZLjjcu77W85WodRJe6nHM= ssh-ed25519 {}\n\
+             |1|06G9hDiCAVNv4eFARRhAi2NQR3U=|9FmWeXsfUhdbX6RV3rYPZlSfxs8= ssh-ed25519 {}\n", KEY, KEY));
+        let key = Base64::decode_vec(KEY).unwrap();
+        assert_eq!(known_hosts.entries.len(), 2);
+        assert!(known_hosts.entries[0].matches("example.com", 22));
+        assert!(known_hosts.entries[0].matches("EXAMPLE.com", 22));
+        assert!(!known_hosts.entries[0].matches("example.com", 2222));
+        assert!(known_hosts.entries[1].matches("example.com", 2222));
+        assert!(!known_hosts.entries[1].matches("example.com", 22));
+        assert_eq!(known_hosts.check("example.com", 22, &key), CheckResult::Ok);
+        assert_eq!(known_hosts.check("example.com", 2222, &key), CheckResult::Ok);
+        assert_eq!(known_hosts.check("example.com", 2200, &key), CheckResult::Unknown);
+        assert_eq!(known_hosts.check("example.org", 22, &key), CheckResult::Unknown);
+    }
+
+    #[test]
+    fn hashed_format_entry_round_trip() {
+        let key = Base64::decode_vec(KEY).unwrap();
+        assert_eq!(format_entry("Example.COM", 2222, &key, false), format!("[example.com]:2222 ssh-ed25519 {}", KEY));
+        for port in [22, 2222] {
+            let line = format_entry("Example.COM", port, &key, true);
+            let fields = line.split(' ').collect::<Vec<&str>>();
+            assert_eq!(fields.len(), 3);
+            assert!(fields[0].starts_with("|1|"));
+            assert!(!fields[0].contains("example"));
+            assert_eq!(&fields[1..], &["ssh-ed25519", KEY]);
+            // 每次使用新的盐
+            assert_ne!(line, format_entry("Example.COM", port, &key, true));
+            let known_hosts = KnownHosts::parse(&line);
+            assert_eq!(known_hosts.check("example.com", port, &key), CheckResult::Ok);
+            assert_eq!(known_hosts.check("example.com", port + 1, &key), CheckResult::Unknown);
+        }
+    }
+
+    #[test]
+    fn append_entry_is_read_back() {
+        let dir = std::env::temp_dir().join(format!("ssh-rs-known-hosts-{}", std::process::id()));
+        let path: PathBuf = dir.join("ssh").join("known_hosts");
+        let key = Base64::decode_vec(KEY).unwrap();
+        append_entry(&path, &format_entry("example.com", 2222, &key, true)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        // 最后一行没有换行时先补上
+        let mut s = fs::read_to_string(&path).unwrap();
+        s.push_str("# no newline");
+        fs::write(&path, s).unwrap();
+        append_entry(&path, &format_entry("example.com", 22, &key, false)).unwrap();
+        let s = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(s.lines().count(), 3);
+        assert!(s.ends_with(&format!("# no newline\nexample.com ssh-ed25519 {}\n", KEY)));
+        let known_hosts = KnownHosts::parse(&s);
+        assert_eq!(known_hosts.entries.len(), 2);
+        assert_eq!(known_hosts.check("example.com", 2222, &key), CheckResult::Ok);
+        assert_eq!(known_hosts.check("example.com", 22, &key), CheckResult::Ok);
+    }
+}
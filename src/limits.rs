@@ -0,0 +1,114 @@
+//! 各种大小、超时等限制
+//!
+//! 分为两类:
+//! - 协议规定的值 (RFC 4253 / RFC 4254)，不能修改
+//! - 实现上的默认值，可以按需要调整，不影响协议的正确性
+
+use std::time::Duration;
+
+
+// ---------------- 协议规定 ----------------
+
+/// packet_length 字段的长度 (uint32)
+pub(crate) const PACKET_LENGTH_LEN: usize = 4;
+
+/// 数据包头部长度: packet_length (4) + padding_length (1)
+/// payload 从这个偏移开始
+pub(crate) const PACKET_HEADER_LEN: usize = PACKET_LENGTH_LEN + 1;
+
+/// 未加密时的分组长度，整个包的长度必须是它的倍数
+pub(crate) const PLAIN_BLOCK_SIZE: u32 = 8;
+
+/// SSH_MSG_KEXINIT 中 cookie 的长度
+pub(crate) const KEX_COOKIE_LEN: usize = 16;
+
+/// 版本行的最大长度，包括结尾的 CR LF (RFC 4253 4.2)
+pub(crate) const MAX_VERSION_LEN: usize = 255;
+
+
+// ---------------- 默认值 ----------------
+
+/// 打开通道时默认告诉服务端的最大数据包大小，可以通过 `Session::set_max_packet_size` 修改，
+/// 同时也是每次从连接读取的缓冲区大小
+pub(crate) const MAX_PACKET_SIZE: usize = 32768;
+
+/// 收到的数据包 packet_length 的最大值，与 OpenSSH 相同，超过时认为数据包格式错误
+pub(crate) const MAX_PACKET_LEN: usize = 262144;
+
+/// 默认客户端的窗口大小，可以通过 `Session::set_window_size` 修改
+pub(crate) const LOCAL_WINDOW_SIZE: u32 = 2097152;
+
+/// `Session::set_max_packet_size` 允许的最大值，留出消息头的长度，
+/// 压缩时解压后的 payload 不会超过 MAX_DECOMPRESSED_LEN
+pub(crate) const MAX_CHANNEL_PACKET_SIZE: u32 = MAX_DECOMPRESSED_LEN as u32 - 1024;
+
+/// 默认的读写超时时间 (秒)，可以通过 `Session::set_timeout` 修改
+pub(crate) const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// 轮询连接时两次读取之间的默认间隔，可以通过 `Session::set_poll_interval` 修改
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// 阻塞模式下每次读取连接最多等待的时间
+pub(crate) const BLOCKING_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// 默认允许的最大未回应保活请求次数，与 OpenSSH 的 ServerAliveCountMax 相同，
+/// 可以通过 `Session::set_keepalive_count_max` 修改
+pub(crate) const KEEPALIVE_COUNT_MAX: u32 = 3;
+
+/// 关闭通道时等待服务端 CHANNEL_CLOSE 的时限，超时后不再等待
+pub(crate) const CLOSE_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// 关闭通道前读取剩余数据时，连续这么长时间没有数据就不再等待服务端的 EOF
+pub(crate) const DRAIN_IDLE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// scp 上传时每次读取文件的大小
+pub(crate) const SCP_CHUNK_SIZE: usize = 20480;
+
+/// 版本行之前服务端发送的其它文本 (例如设备的登录提示) 的最大长度
+pub(crate) const MAX_PRE_BANNER_LEN: usize = 65536;
+
+/// 传输停滞时诊断快照中保留的最近收到的消息编号数量
+pub(crate) const RECENT_MESSAGES_LEN: usize = 20;
+
+/// 解压后的 payload 最大长度，防止很小的数据包解压出大量数据
+pub(crate) const MAX_DECOMPRESSED_LEN: usize = 262144;
+
+/// 同一个密钥最多发送或接收的数据包数量，达到之后重新协商密钥
+/// 数据包序号是 32 位的，必须在回绕之前重新协商 (RFC 4344 3.1)，这里留出一半的余量
+pub(crate) const MAX_PACKETS_BEFORE_REKEY: u64 = 1 << 31;
+
+/// 默认每个方向上加密或解密 1GB 数据后重新协商密钥 (RFC 4253 9)，
+/// 可以通过 `Session::set_rekey_limit` 修改
+pub(crate) const REKEY_BYTES: u64 = 1 << 30;
+
+/// 默认同一个密钥最多使用 1 小时 (RFC 4253 9)
+pub(crate) const REKEY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// 每个 agent、X11 通道最多缓存的本地数据，远程窗口不够时不再从本地读取
+/// agent 的消息最大为 256KB
+pub(crate) const FORWARD_BUFFER_LEN: usize = 262144;
+
+/// CommandFailed 中默认保留的 stderr 字节数
+pub(crate) const STDERR_TAIL_LEN: usize = 1024;
+
+/// 进程退出时关闭所有 session 的总时限
+#[cfg(feature = "ctrlc")]
+pub(crate) const EXIT_TIMEOUT: Duration = Duration::from_millis(500);
+
+
+// ---------------- 伪终端默认值 ----------------
+
+/// 终端宽度 (字符)
+pub(crate) const PTY_COLUMNS: u32 = 80;
+
+/// 终端高度 (行)
+pub(crate) const PTY_ROWS: u32 = 24;
+
+/// 终端宽度 (像素)
+pub(crate) const PTY_WIDTH: u32 = 640;
+
+/// 终端高度 (像素)
+pub(crate) const PTY_HEIGHT: u32 = 480;
+
+/// 终端的输入输出波特率
+pub(crate) const PTY_BAUD_RATE: u32 = 115200;
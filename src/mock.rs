@@ -0,0 +1,418 @@
+//! # 单元测试使用的内存中的连接
+//! 测试通过 `MockServer` 发送服务端的数据，检查 client 写入的数据
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use base64ct::{Base64, Encoding};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use crate::algorithm::encryption::{ChaCha20Poly1305, Ciphers, Encryption, PacketLength};
+use crate::algorithm::hash::h::H;
+use crate::algorithm::hash::{Direction, HashType, HASH};
+use crate::algorithm::key_exchange::curve25519::CURVE25519;
+use crate::algorithm::key_exchange::KeyExchange;
+use crate::algorithm::negotiated::Integrity;
+use crate::client::Client;
+use crate::constant::{algorithms, ssh_msg_code, CLIENT_VERSION};
+use crate::data::Data;
+use crate::packet::Packet;
+use crate::SshResult;
+
+
+/// `Peer` 发送的版本
+const SERVER_VERSION: &str = "SSH-2.0-mock";
+
+/// 不加密也没有 mac，数据包的格式和密钥交换之前相同
+pub(crate) struct Plain;
+
+impl Encryption for Plain {
+    fn bsize(&self) -> usize {
+        8
+    }
+
+    fn encrypt(&mut self, _: u32, _: &mut Vec<u8>) {}
+
+    fn decrypt(&mut self, sequence_number: u32, buf: &mut [u8]) -> SshResult<Vec<u8>> {
+        let len = self.packet_len(sequence_number, buf);
+        Ok(buf[..len].to_vec())
+    }
+
+    fn packet_len(&mut self, _: u32, buf: &[u8]) -> usize {
+        u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize + 4
+    }
+
+    fn integrity(&self) -> Integrity {
+        Integrity::None
+    }
+
+    fn packet_length(&self) -> PacketLength {
+        PacketLength::Encrypted
+    }
+}
+
+
+/// input 为服务端发送给 client 的数据，没有数据时读取返回 WouldBlock
+/// output 为 client 写入的数据
+pub(crate) struct Mock {
+    input: Arc<Mutex<Vec<u8>>>,
+    output: Arc<Mutex<Vec<u8>>>
+}
+
+impl Read for Mock {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = self.input.lock().unwrap();
+        if input.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::WouldBlock))
+        }
+        let len = input.len().min(buf.len());
+        buf[..len].copy_from_slice(&input[..len]);
+        input.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for Mock {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+/// 连接的另一端，可以在其它线程中发送数据
+#[derive(Clone)]
+pub(crate) struct MockServer {
+    input: Arc<Mutex<Vec<u8>>>,
+    output: Arc<Mutex<Vec<u8>>>
+}
+
+impl MockServer {
+    /// 发送一个数据包，data 为 payload
+    pub(crate) fn send(&self, data: Data) {
+        let mut packet = Packet::from(data);
+        packet.build(Some(&Plain));
+        self.send_raw(&packet.to_vec())
+    }
+
+    /// client 写入的全部数据包的 payload，取出之后清空
+    pub(crate) fn received(&self) -> Vec<Data> {
+        let output = self.received_raw();
+        let mut payloads = vec![];
+        let mut pos = 0;
+        while pos < output.len() {
+            let len = u32::from_be_bytes([output[pos], output[pos + 1], output[pos + 2], output[pos + 3]]) as usize;
+            let padding_len = output[pos + 4] as usize;
+            payloads.push(Data::from(output[pos + 5..pos + 4 + len - padding_len].to_vec()));
+            pos += 4 + len;
+        }
+        payloads
+    }
+
+    /// client 写入的原始数据，取出之后清空
+    pub(crate) fn received_raw(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.output.lock().unwrap())
+    }
+
+    /// 等待 client 写入下一个数据包，返回 payload，5 秒内没有收到时 panic
+    pub(crate) fn recv(&self) -> Data {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            {
+                let mut output = self.output.lock().unwrap();
+                if output.len() >= 4 {
+                    let len = u32::from_be_bytes([output[0], output[1], output[2], output[3]]) as usize + 4;
+                    if output.len() >= len {
+                        let packet = output.drain(..len).collect::<Vec<u8>>();
+                        return Packet::from(packet).unpacking().unwrap()
+                    }
+                }
+            }
+            assert!(Instant::now() < deadline, "no packet from client");
+            thread::sleep(Duration::from_millis(1))
+        }
+    }
+
+    /// 原样发给 client，用来构造格式错误的数据包
+    pub(crate) fn send_raw(&self, data: &[u8]) {
+        self.input.lock().unwrap().extend_from_slice(data)
+    }
+}
+
+
+/// 连接的两端，`Mock` 交给 client，例如 `Session::connect_io`
+pub(crate) fn pipe() -> (Mock, MockServer) {
+    let server = MockServer {
+        input: Arc::new(Mutex::new(vec![])),
+        output: Arc::new(Mutex::new(vec![]))
+    };
+    let mock = Mock {
+        input: server.input.clone(),
+        output: server.output.clone()
+    };
+    (mock, server)
+}
+
+/// 还没有协商加密算法的连接
+pub(crate) fn connection() -> (Client, MockServer) {
+    let (mock, server) = pipe();
+    (Client::from_io(mock), server)
+}
+
+/// 已经完成密钥交换的连接，两个方向都使用 `Plain`
+pub(crate) fn client() -> (Client, MockServer) {
+    let (mut client, server) = connection();
+    client.encryption = Some(Ciphers {
+        client_to_server: Box::new(Plain),
+        server_to_client: Box::new(Plain)
+    });
+    (client, server)
+}
+
+/// 固定的 K 和 H 生成的密钥，同样的算法每次得到同样的密钥
+pub(crate) fn keys() -> HASH {
+    let mut h = H::new();
+    h.set_v_c("SSH-2.0-client");
+    h.set_v_s("SSH-2.0-server");
+    h.set_k(&[0x42; 32]);
+    HASH::new(&h, HashType::SHA256, b"session id")
+}
+
+
+/// ssh-keygen 生成的 ed25519 CA 公钥 (key blob 的 base64)
+pub(crate) const CA_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIObpORejux5SxGfK9Cf7hnTegMj0U/frSmqivX4cvv4Y";
+
+/// ssh-keygen 生成的 ed25519 主机公钥
+pub(crate) const HOST_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIIhC/8JW+0XPTkGi+3V/yUoW/PkDikZDq2TSd6ro4sIV";
+
+/// ssh-keygen -s ca -h -I good -n example.com -V always:forever
+pub(crate) const HOST_CERT: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIBh55FkVRbT9DIbEiGTt/KqO8l/izCsqS8g5fZ5JpXopAAAAIIhC/8JW+0XPTkGi+3V/yUoW/PkDikZDq2TSd6ro4sIVAAAAAAAAAAAAAAACAAAABGdvb2QAAAAPAAAAC2V4YW1wbGUuY29tAAAAAAAAAAD//////////wAAAAAAAAAAAAAAAAAAADMAAAALc3NoLWVkMjU1MTkAAAAg5uk5F6O7HlLEZ8r0J/uGdN6AyPRT9+tKaqK9fhy+/hgAAABTAAAAC3NzaC1lZDI1NTE5AAAAQDhnQMd9OxcqtpgutyO0CD+64shHfyf1im50JQMrzHMUcwJ/9GD+eTYwdGhM7f8MR/6diKVVGsvG0Cm4y52pHA4=";
+
+/// ssh-keygen -s ca -h -I expired -n example.com -V 20000101:20010101
+pub(crate) const EXPIRED_HOST_CERT: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAICG/fpgY4Kebm5W8XZG2opkznVRe4EIjINIcr5xF92w9AAAAIIhC/8JW+0XPTkGi+3V/yUoW/PkDikZDq2TSd6ro4sIVAAAAAAAAAAAAAAACAAAAB2V4cGlyZWQAAAAPAAAAC2V4YW1wbGUuY29tAAAAADhtQ4AAAAAAOk/IgAAAAAAAAAAAAAAAAAAAADMAAAALc3NoLWVkMjU1MTkAAAAg5uk5F6O7HlLEZ8r0J/uGdN6AyPRT9+tKaqK9fhy+/hgAAABTAAAAC3NzaC1lZDI1NTE5AAAAQITz/CO2Qxw00sSbMiDxizsplD+S8paqmDqrpdFWEe7sZJeqqq5r27ymtQE9qcDF4riraB9COALhlEASNO3C7gc=";
+
+/// ssh-keygen -s ca -h -I other -n other.example.com -V always:forever
+pub(crate) const OTHER_HOST_CERT: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAICiqm8avVpC37SebimuJMuzD5FrhZaOKeyOjikHrUdXiAAAAIIhC/8JW+0XPTkGi+3V/yUoW/PkDikZDq2TSd6ro4sIVAAAAAAAAAAAAAAACAAAABW90aGVyAAAAFQAAABFvdGhlci5leGFtcGxlLmNvbQAAAAAAAAAA//////////8AAAAAAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIObpORejux5SxGfK9Cf7hnTegMj0U/frSmqivX4cvv4YAAAAUwAAAAtzc2gtZWQyNTUxOQAAAEDXhXliQ5v1O3AD8CJ9MdZ6AdF23oOAaMPydWg8/n6w+JPsxdwbU87P7o+xo2LvDSMNPpcdxeeVJ7kCWcqjDVwI";
+
+/// ssh-keygen -s ca -I user -n example.com -V always:forever，用户证书
+pub(crate) const USER_CERT: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIPKUtSMo1XvdfejvzYcgkVjX157satfHtg+Cih7eKcJCAAAAIIhC/8JW+0XPTkGi+3V/yUoW/PkDikZDq2TSd6ro4sIVAAAAAAAAAAAAAAABAAAABHVzZXIAAAAPAAAAC2V4YW1wbGUuY29tAAAAAAAAAAD//////////wAAAAAAAACCAAAAFXBlcm1pdC1YMTEtZm9yd2FyZGluZwAAAAAAAAAXcGVybWl0LWFnZW50LWZvcndhcmRpbmcAAAAAAAAAFnBlcm1pdC1wb3J0LWZvcndhcmRpbmcAAAAAAAAACnBlcm1pdC1wdHkAAAAAAAAADnBlcm1pdC11c2VyLXJjAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIObpORejux5SxGfK9Cf7hnTegMj0U/frSmqivX4cvv4YAAAAUwAAAAtzc2gtZWQyNTUxOQAAAEBHxDIQ/kIh7XtadDJtHqP8LrxROu5sqV+CyJRr/mBmgDzn3CvjDFniYX5n/D/tRJ0mt2nnT17KAhXcSFreWy0E";
+
+/// 解码上面的 base64
+pub(crate) fn blob(s: &str) -> Vec<u8> {
+    Base64::decode_vec(s).unwrap()
+}
+
+
+/// 服务端一侧的传输层：版本协商、密钥交换和数据包的加密解密
+/// 只支持 curve25519-sha256、ssh-ed25519 和 chacha20-poly1305@openssh.com，不支持严格的密钥交换。
+/// 第一次密钥交换之前的数据包和 `Plain` 的格式相同，所以也可以和 `client()` 的连接通信
+pub(crate) struct Peer {
+    server: MockServer,
+    /// client 写入的还没有解出的数据
+    buf: Vec<u8>,
+    /// 发给 client 的数据包，为 None 时不加密
+    encryption: Option<Box<dyn Encryption>>,
+    /// client 发送的数据包
+    decryption: Option<Box<dyn Encryption>>,
+    server_sequence: u32,
+    client_sequence: u32,
+    host_key: Ed25519KeyPair,
+    h: H,
+    session_id: Option<Vec<u8>>,
+    /// 等待密钥交换的消息时收到的其它消息
+    pub(crate) skipped: Vec<Data>
+}
+
+impl Peer {
+    /// 不同的 seed 得到不同的主机密钥
+    pub(crate) fn new(server: MockServer, seed: u8) -> Self {
+        let mut h = H::new();
+        h.set_v_c(CLIENT_VERSION);
+        h.set_v_s("");
+        Peer {
+            server,
+            buf: vec![],
+            encryption: None,
+            decryption: None,
+            server_sequence: 0,
+            client_sequence: 0,
+            host_key: Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap(),
+            h,
+            session_id: None,
+            skipped: vec![]
+        }
+    }
+
+    /// 主机密钥的 key blob
+    pub(crate) fn host_key(&self) -> Vec<u8> {
+        let mut blob = Data::new();
+        blob.put_str(algorithms::PUBLIC_KEY_ED25519)
+            .put_u8s(self.host_key.public_key().as_ref());
+        blob.to_vec()
+    }
+
+    pub(crate) fn send(&mut self, data: Data) {
+        let mut packet = Packet::from(data);
+        let buf = match &mut self.encryption {
+            Some(encryption) => {
+                packet.build(Some(encryption.as_ref()));
+                let mut buf = packet.to_vec();
+                encryption.encrypt(self.server_sequence, &mut buf);
+                buf
+            }
+            None => {
+                packet.build(Some(&Plain));
+                packet.to_vec()
+            }
+        };
+        self.server_sequence = self.server_sequence.wrapping_add(1);
+        self.server.send_raw(&buf)
+    }
+
+    /// client 写入的下一个数据包，还没有完整的数据包时返回 None
+    pub(crate) fn try_recv(&mut self) -> Option<Data> {
+        self.buf.extend(self.server.received_raw());
+        let mut plain = Plain;
+        let decryption: &mut dyn Encryption = match &mut self.decryption {
+            Some(v) => v.as_mut(),
+            None => &mut plain
+        };
+        if self.buf.len() < decryption.bsize().max(4) {
+            return None
+        }
+        let len = decryption.packet_len(self.client_sequence, &self.buf) + decryption.integrity().len();
+        if self.buf.len() < len {
+            return None
+        }
+        let mut packet = self.buf.drain(..len).collect::<Vec<u8>>();
+        let packet = decryption.decrypt(self.client_sequence, &mut packet).unwrap();
+        self.client_sequence = self.client_sequence.wrapping_add(1);
+        Some(Packet::from(packet).unpacking().unwrap())
+    }
+
+    /// 等待 client 写入下一个数据包，5 秒内没有收到时 panic
+    pub(crate) fn recv(&mut self) -> Data {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(data) = self.try_recv() {
+                return data
+            }
+            assert!(Instant::now() < deadline, "no packet from client");
+            thread::sleep(Duration::from_millis(1))
+        }
+    }
+
+    /// 等待 client 的某一种消息，之前收到的其它消息放到 skipped 中
+    /// client 断开连接时返回 SSH_MSG_DISCONNECT
+    pub(crate) fn recv_message(&mut self, message_code: u8) -> Data {
+        loop {
+            let data = self.recv();
+            if data.first() == Some(&message_code) || data.first() == Some(&ssh_msg_code::SSH_MSG_DISCONNECT) {
+                return data
+            }
+            self.skipped.push(data)
+        }
+    }
+
+    /// 版本协商和第一次密钥交换，client 断开连接时返回 false
+    pub(crate) fn handshake(&mut self) -> bool {
+        self.server.send_raw(format!("{}\r\n", SERVER_VERSION).as_bytes());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let version = loop {
+            self.buf.extend(self.server.received_raw());
+            if let Some(pos) = self.buf.windows(2).position(|v| v == b"\r\n") {
+                let line = self.buf.drain(..pos + 2).collect::<Vec<u8>>();
+                break String::from_utf8(line[..pos].to_vec()).unwrap()
+            }
+            assert!(Instant::now() < deadline, "no version from client");
+            thread::sleep(Duration::from_millis(1))
+        };
+        self.h.set_v_c(&version);
+        self.h.set_v_s(SERVER_VERSION);
+        self.kex()
+    }
+
+    /// 接受 ssh-userauth 服务和第一个认证请求
+    pub(crate) fn accept(&mut self) {
+        let mut request = self.recv_message(ssh_msg_code::SSH_MSG_SERVICE_REQUEST);
+        request.get_u8();
+        let mut accept = Data::new();
+        accept.put_u8(ssh_msg_code::SSH_MSG_SERVICE_ACCEPT)
+            .put_u8s(&request.get_u8s());
+        self.send(accept);
+        self.recv_message(ssh_msg_code::SSH_MSG_USERAUTH_REQUEST);
+        let mut success = Data::new();
+        success.put_u8(ssh_msg_code::SSH_MSG_USERAUTH_SUCCESS);
+        self.send(success)
+    }
+
+    /// 发送 SSH_MSG_KEXINIT 并完成一次密钥交换，client 已经发送了 SSH_MSG_KEXINIT 时也可以使用
+    /// client 断开连接时返回 false
+    pub(crate) fn kex(&mut self) -> bool {
+        let mut kex_init = Data::new();
+        kex_init.put_u8(ssh_msg_code::SSH_MSG_KEXINIT);
+        kex_init.extend(vec![0; 16]);
+        for name_list in [
+            algorithms::DH_CURVE25519_SHA256,
+            algorithms::PUBLIC_KEY_ED25519,
+            algorithms::ENCRYPTION_CHACHA20_POLY1305_OPENSSH,
+            algorithms::ENCRYPTION_CHACHA20_POLY1305_OPENSSH,
+            algorithms::MAC_HMAC_SHA2_256,
+            algorithms::MAC_HMAC_SHA2_256,
+            algorithms::COMPRESSION_NONE,
+            algorithms::COMPRESSION_NONE,
+            "",
+            ""
+        ] {
+            kex_init.put_str(name_list);
+        }
+        kex_init.put_u8(false as u8).put_u32(0);
+        self.h.set_i_s(kex_init.as_slice());
+        self.send(kex_init);
+
+        let kex_init = self.recv_message(ssh_msg_code::SSH_MSG_KEXINIT);
+        if kex_init.first() != Some(&ssh_msg_code::SSH_MSG_KEXINIT) {
+            return false
+        }
+        self.h.set_i_c(kex_init.as_slice());
+        let mut kex_dh_init = self.recv_message(ssh_msg_code::SSH_MSG_KEXDH_INIT);
+        if kex_dh_init.get_u8() != ssh_msg_code::SSH_MSG_KEXDH_INIT {
+            return false
+        }
+        let q_c = kex_dh_init.get_u8s();
+        let key_exchange = CURVE25519::new().unwrap();
+        let k = key_exchange.get_shared_secret(q_c.clone()).unwrap();
+        let k_s = self.host_key();
+        self.h.set_k_s(&k_s);
+        self.h.set_q_c(&q_c);
+        self.h.set_q_s(key_exchange.get_public_key());
+        self.h.set_k(&k);
+        let exchange_hash = self.h.digest(HashType::SHA256);
+        let session_id = self.session_id.get_or_insert(exchange_hash.clone()).clone();
+
+        let mut signature = Data::new();
+        signature.put_str(algorithms::PUBLIC_KEY_ED25519)
+            .put_u8s(self.host_key.sign(&exchange_hash).as_ref());
+        let mut reply = Data::new();
+        reply.put_u8(ssh_msg_code::SSH_MSG_KEXDH_REPLY)
+            .put_u8s(&k_s)
+            .put_u8s(key_exchange.get_public_key())
+            .put_u8s(signature.as_slice());
+        self.send(reply);
+        let mut new_keys = Data::new();
+        new_keys.put_u8(ssh_msg_code::SSH_MSG_NEWKEYS);
+        self.send(new_keys);
+
+        let hash = HASH::new(&self.h, HashType::SHA256, &session_id);
+        self.encryption = Some(Box::new(ChaCha20Poly1305::new(&hash, Direction::ServerToClient)));
+        if self.recv_message(ssh_msg_code::SSH_MSG_NEWKEYS).first() != Some(&ssh_msg_code::SSH_MSG_NEWKEYS) {
+            return false
+        }
+        self.decryption = Some(Box::new(ChaCha20Poly1305::new(&hash, Direction::ClientToServer)));
+        true
+    }
+}
@@ -1,5 +1,7 @@
-use crate::algorithm::encryption;
+use crate::algorithm::encryption::{Encryption, PacketLength};
 use crate::data::Data;
+use crate::error::{SshError, SshResult};
+use crate::{limits, util};
 
 
 /// ## 数据包整体结构
@@ -46,15 +48,22 @@ pub struct Packet {
 
 impl Packet {
 
-    pub fn unpacking(&mut self) -> Data {
+    /// padding length 超出数据包长度时返回错误
+    pub fn unpacking(&mut self) -> SshResult<Data> {
         if self.value.is_empty() {
-            return Data::new()
+            return Ok(Data::new())
         }
-        let padding_length = *(&self.value[4]);
-        let vec = (&self.value[5..(self.value.len() - padding_length as usize)]).to_vec();
-        let data = Data::from(vec);
+        let padding_length = match self.value.get(limits::PACKET_LENGTH_LEN) {
+            Some(v) => *v as usize,
+            None => return Err(SshError::from("invalid packet padding length."))
+        };
+        let end = match self.value.len().checked_sub(padding_length) {
+            Some(v) if v >= limits::PACKET_HEADER_LEN => v,
+            _ => return Err(SshError::from("invalid packet padding length."))
+        };
+        let data = Data::from(self.value[limits::PACKET_HEADER_LEN..end].to_vec());
         self.data = data.clone();
-        data
+        Ok(data)
     }
 
     #[allow(dead_code)]
@@ -64,16 +73,21 @@ impl Packet {
     }
 
     // 封包
-    pub fn build(&mut self, is_encrypt: bool) {
+    // 密钥交换完成之前 encryption 为 None
+    pub(crate) fn build(&mut self, encryption: Option<&dyn Encryption>) {
         let data_len =  self.data.len() as u32;
-        let bsize = match is_encrypt {
-                true => encryption::get().bsize() as i32,
+        let bsize = match encryption {
+                Some(v) => v.bsize() as i32,
                 // 未加密的填充: 整个包的总长度是8的倍数，并且填充长度不能小于4
-                false => 8,
+                None => limits::PLAIN_BLOCK_SIZE as i32,
+        };
+        let length_excluded = match encryption {
+            Some(v) => v.packet_length() != PacketLength::Encrypted,
+            None => false
         };
         let padding_len = {
             let mut pad = (-((data_len +
-                if is_encrypt && encryption::get().is_cp() { 1 }
+                if length_excluded { 1 }
                 else { 5 }) as i32))
                 & (bsize - 1) as i32;
             if pad < bsize {
@@ -88,8 +102,8 @@ impl Packet {
         // [padding_length, payload]
         buf.extend(self.data.as_slice());
         // [padding_length, payload, randomPadding]
-        // 默认 0
-        buf.extend(vec![0; padding_len as usize]);
+        // 随机填充 (RFC 4253 6)，CBC 模式下填充不能是可预测的内容
+        buf.extend(util::random_padding(padding_len as usize));
         // 获取总长度
         let packet_len = buf.len() as u32;
         let mut packet_len_u8s= packet_len.to_be_bytes().to_vec();
@@ -1,44 +1,584 @@
-use std::net::ToSocketAddrs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use crate::data::Data;
-use crate::constant::{ssh_msg_code, size, ssh_str};
-use crate::error::{SshError, SshResult};
-use crate::slog::{log, Slog};
-use crate::channel::Channel;
+use crate::constant::{ssh_msg_code, ssh_str};
+use crate::error::{SshError, SshErrorKind, SshResult};
+use crate::slog::Slog;
+use crate::channel::{Channel, PtyOptions};
+use crate::channel_state::Opening;
 use crate::channel_scp::ChannelScp;
-use crate::{channel, ChannelExec, ChannelShell, client, config, kex, timeout, util};
-use crate::algorithm::hash::h;
-use crate::algorithm::{encryption, key_exchange, mac, public_key};
-use crate::user_info::AuthType;
+use crate::channel_direct_tcpip::ChannelDirectTcpip;
+use crate::channel_subsystem::ChannelSubsystem;
+use crate::event::Event;
+use crate::{ChannelExec, ChannelShell, client, config, ExecOptions, ExecResult, kex, limits};
+use crate::config::{AlgorithmList, CompressionAlgorithm, Config};
+use crate::client::{Client, RekeyLimit};
+use crate::certificate::{self, Certificate};
+use crate::keepalive::Keepalive;
+use crate::timeout::Timeout;
+use crate::host_keys::HostKeysUpdate;
+use crate::known_hosts::{self, CheckResult, HostKeyPolicy, KnownHosts};
+use crate::algorithm::negotiated::NegotiatedAlgorithms;
+use crate::user_info::{AuthType, UserInfo};
 use crate::window_size::WindowSize;
 
 
-pub struct Session;
+/// 认证横幅回调，见 `Session::set_banner_callback`
+pub(crate) type BannerCallback = Box<dyn FnMut(&str) + Send>;
+
+/// 修改过期密码的回调，见 `Session::set_password_change_callback`
+pub(crate) type PasswordChangeCallback = Box<dyn FnMut(&str) -> Option<String> + Send>;
+
+
+pub struct Session {
+    pub(crate) client: Option<Arc<Mutex<Client>>>,
+    /// 认证使用的用户名和密码或者密钥
+    pub(crate) user_info: Option<UserInfo>,
+    /// 读取等待服务端回应的超时时间
+    pub(crate) timeout: Duration,
+    /// known_hosts 文件路径
+    pub(crate) known_hosts: Option<PathBuf>,
+    /// 查找 known_hosts 时使用的主机名
+    pub(crate) host_name: Option<String>,
+    /// `connect` 传入的主机名和端口
+    pub(crate) connect_host: Option<(String, u16)>,
+    pub(crate) host_key_policy: HostKeyPolicy,
+    /// 写入 known_hosts 时是否哈希主机名
+    pub(crate) hash_known_hosts: bool,
+    /// 按服务端通告的主机密钥更新 known_hosts
+    pub(crate) update_host_keys: bool,
+    /// 保活间隔，None 为不发送
+    pub(crate) keepalive_interval: Option<Duration>,
+    /// 允许的最大未回应保活请求次数
+    pub(crate) keepalive_count_max: u32,
+    /// 应用程序自行管理的已吊销主机密钥 (key blob)
+    pub(crate) revoked_keys: Vec<Vec<u8>>,
+    /// 只协商这些算法，按给定的顺序
+    pub(crate) preferred_ciphers: Option<Vec<String>>,
+    pub(crate) preferred_kex: Option<Vec<String>>,
+    pub(crate) preferred_macs: Option<Vec<String>>,
+    /// 连接时 TcpStream 是否使用非阻塞模式
+    pub(crate) nonblocking: bool,
+    /// TcpStream 的 TCP_NODELAY、SO_RCVBUF、SO_SNDBUF，None 为系统默认值
+    pub(crate) tcp_nodelay: bool,
+    pub(crate) recv_buffer_size: Option<usize>,
+    pub(crate) send_buffer_size: Option<usize>,
+    /// 没有数据时两次读取之间等待的时间
+    pub(crate) poll_interval: Duration,
+    /// 重新协商密钥的字节数和时间限制
+    pub(crate) rekey_limit: RekeyLimit,
+    /// 是否协商 zlib 压缩
+    pub(crate) compression: bool,
+    /// 认证过程中收到 SSH_MSG_USERAUTH_BANNER 时回调
+    pub(crate) banner_callback: Option<BannerCallback>,
+    /// 密码认证中收到 SSH_MSG_USERAUTH_PASSWD_CHANGEREQ 时获取新密码
+    pub(crate) password_change_callback: Option<PasswordChangeCallback>,
+    /// 打开 exec 和 shell 通道时设置的环境变量
+    pub(crate) env: Vec<(String, String)>,
+    /// 打开 exec 和 shell 通道时请求 agent 转发
+    pub(crate) agent_forwarding: bool,
+    /// 打开 exec 和 shell 通道时请求 X11 转发，值为 single connection
+    pub(crate) x11_forwarding: Option<bool>,
+    /// 打开通道时告诉服务端的窗口大小和最大数据包大小
+    pub(crate) window_size: u32,
+    pub(crate) max_packet_size: u32
+}
 
 
 impl Session {
+    pub(crate) fn new() -> Self {
+        Session {
+            client: None,
+            user_info: None,
+            timeout: Duration::from_secs(limits::DEFAULT_TIMEOUT_SECS),
+            known_hosts: None,
+            host_name: None,
+            connect_host: None,
+            host_key_policy: HostKeyPolicy::default(),
+            hash_known_hosts: false,
+            update_host_keys: false,
+            keepalive_interval: None,
+            keepalive_count_max: limits::KEEPALIVE_COUNT_MAX,
+            revoked_keys: vec![],
+            preferred_ciphers: None,
+            preferred_kex: None,
+            preferred_macs: None,
+            nonblocking: true,
+            tcp_nodelay: true,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            poll_interval: limits::POLL_INTERVAL,
+            rekey_limit: RekeyLimit::default(),
+            compression: false,
+            banner_callback: None,
+            password_change_callback: None,
+            env: vec![],
+            agent_forwarding: false,
+            x11_forwarding: None,
+            window_size: limits::LOCAL_WINDOW_SIZE,
+            max_packet_size: limits::MAX_PACKET_SIZE as u32
+        }
+    }
+
+    pub(crate) fn client(&self) -> SshResult<MutexGuard<'_, Client>> {
+        match &self.client {
+            None => {
+                log::error!("Client null pointer");
+                Err(SshError::from("Client null pointer"))
+            }
+            Some(client) => client::locking(client)
+        }
+    }
+
+    /// 为 true 时设置一个输出到标准错误的简单日志记录器
+    /// 日志都通过 `log` 记录，应用程序使用 env_logger 等其它实现时不需要调用
     pub fn is_enable_log(&self, b: bool) {
         if b {
             Slog::default()
         }
     }
 
-    pub fn set_timeout(&self, secs: u64) {
-        unsafe {
-            timeout::TIMEOUT = secs
+    /// 只对这个会话有效，已经连接时立即生效
+    pub fn set_timeout(&mut self, secs: u64) {
+        self.timeout = Duration::from_secs(secs);
+        if let Ok(mut client) = self.client() {
+            client.timeout.set_duration(self.timeout)
+        }
+    }
+
+    /// 连接时 TcpStream 是否使用非阻塞模式，默认 true
+    /// 阻塞模式下没有数据时读取会在内核中等待，不会一直占用 cpu 轮询，
+    /// 对 connect_io 传入的流无效
+    pub fn set_nonblocking(&mut self, b: bool) {
+        self.nonblocking = b
+    }
+
+    /// TcpStream 是否设置 TCP_NODELAY，默认 true
+    /// 关闭 Nagle 算法，shell 中每次按键的小数据包立即发送，不等待合并。
+    /// 大量传输数据、更在意数据包数量时可以设置为 false。
+    /// 连接之前设置时在连接时生效，已经连接时立即生效，对 connect_io 传入的流无效
+    pub fn set_tcp_nodelay(&mut self, b: bool) -> SshResult<()> {
+        self.tcp_nodelay = b;
+        self.apply_connected(|client| client.stream.set_nodelay(b))
+    }
+
+    /// TcpStream 的接收缓冲区大小 (SO_RCVBUF)，默认使用系统的值
+    /// 高延迟、高带宽的连接上可以和 `set_window_size` 一起增大。
+    /// 内核可能调整实际的大小，例如 Linux 会设置为两倍。只支持 unix，
+    /// 生效的时机与 `set_tcp_nodelay` 相同
+    pub fn set_recv_buffer_size(&mut self, size: usize) -> SshResult<()> {
+        self.recv_buffer_size = Some(size);
+        self.apply_connected(|client| client.stream.set_recv_buffer_size(size))
+    }
+
+    /// TcpStream 的发送缓冲区大小 (SO_SNDBUF)，默认使用系统的值，见 `set_recv_buffer_size`
+    pub fn set_send_buffer_size(&mut self, size: usize) -> SshResult<()> {
+        self.send_buffer_size = Some(size);
+        self.apply_connected(|client| client.stream.set_send_buffer_size(size))
+    }
+
+    /// 已经连接时设置 socket 选项
+    fn apply_connected<F>(&self, f: F) -> SshResult<()>
+    where
+        F: FnOnce(&Client) -> io::Result<()>
+    {
+        if self.client.is_none() {
+            return Ok(())
+        }
+        let client = self.client()?;
+        if let Err(e) = f(&client) {
+            log::error!("set socket option error: {}", e);
+            return Err(SshError::from(e))
+        }
+        Ok(())
+    }
+
+    /// 连接时设置的 socket 选项
+    fn apply_socket_options(&self, client: &Client) -> SshResult<()> {
+        let stream = &client.stream;
+        let result = stream.set_nodelay(self.tcp_nodelay)
+            .and_then(|_| match self.recv_buffer_size {
+                Some(size) => stream.set_recv_buffer_size(size),
+                None => Ok(())
+            })
+            .and_then(|_| match self.send_buffer_size {
+                Some(size) => stream.set_send_buffer_size(size),
+                None => Ok(())
+            });
+        if let Err(e) = result {
+            log::error!("set socket option error: {}", e);
+            return Err(SshError::from(e))
+        }
+        Ok(())
+    }
+
+    /// 连接上没有数据时，两次读取之间等待的时间，默认 1 毫秒
+    /// 非阻塞模式下内部等待服务端回应时按这个间隔轮询，间隔越长占用的 cpu 越少、延迟越高，
+    /// 为 0 时不等待。非阻塞模式下 `read` 等方法没有数据时也会等待这个时间再返回
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+        if self.client.is_some() {
+            if let Ok(mut client) = self.client() {
+                client.poll_interval = interval
+            }
+        }
+    }
+
+    /// 重新协商密钥的限制，默认每个方向上 1GB、每 1 小时 (RFC 4253 9)
+    /// 任意一个方向上加密或解密的数据达到 bytes，或者距离上次密钥交换超过 interval 时，
+    /// 客户端发起 SSH_MSG_KEXINIT 重新协商密钥，已经打开的通道不受影响。
+    /// 密钥交换完成之前发送的通道数据会等待新的密钥生效后再发送。
+    /// 加密算法本身的限制 (例如 3des-cbc 的 64 位分组) 更小时使用算法的限制，
+    /// interval 为 None 时不按时间重新协商。只在读写数据时检查，连接空闲时不会发起
+    pub fn set_rekey_limit(&mut self, bytes: u64, interval: Option<Duration>) {
+        self.rekey_limit = RekeyLimit { bytes, interval };
+        if self.client.is_some() {
+            if let Ok(mut client) = self.client() {
+                client.rekey_limit = self.rekey_limit
+            }
+        }
+    }
+
+    /// 通道的本地窗口大小，默认 2MB
+    /// 服务端最多发送窗口大小的数据后等待客户端的 WINDOW_ADJUST，
+    /// 高延迟、高带宽的连接 (例如卫星链路) 上增大窗口可以提高大量数据传输的速度。
+    /// 对之后打开的通道生效，不能小于 `set_max_packet_size` 设置的最大数据包大小
+    pub fn set_window_size(&mut self, size: u32) -> SshResult<()> {
+        self.set_channel_sizes(size, self.max_packet_size)
+    }
+
+    /// 通道的最大数据包大小，默认 32KB，服务端发送的每个 CHANNEL_DATA 最多携带这么多数据
+    /// 对之后打开的通道生效，不能超过 `set_window_size` 设置的窗口大小，也不能超过 255KB
+    pub fn set_max_packet_size(&mut self, size: u32) -> SshResult<()> {
+        self.set_channel_sizes(self.window_size, size)
+    }
+
+    pub(crate) fn set_channel_sizes(&mut self, window_size: u32, max_packet_size: u32) -> SshResult<()> {
+        if max_packet_size == 0 || max_packet_size > limits::MAX_CHANNEL_PACKET_SIZE {
+            log::error!("invalid max packet size {}.", max_packet_size);
+            return Err(SshError::from(format!(
+                "max packet size must be between 1 and {}.", limits::MAX_CHANNEL_PACKET_SIZE)))
+        }
+        if max_packet_size > window_size {
+            log::error!("max packet size {} exceeds the window size {}.", max_packet_size, window_size);
+            return Err(SshError::from(format!(
+                "max packet size ({}) must not exceed the window size ({}).", max_packet_size, window_size)))
+        }
+        self.window_size = window_size;
+        self.max_packet_size = max_packet_size;
+        Ok(())
+    }
+
+    /// 是否协商 zlib 压缩，默认 false
+    /// 开启后优先使用用户认证之后才开始压缩的 zlib@openssh.com，其次是 zlib，
+    /// 服务端不支持压缩时不压缩。需要在 connect 之前设置
+    pub fn set_compression(&mut self, b: bool) {
+        self.compression = b
+    }
+
+    /// 设置认证横幅回调
+    /// 服务端在认证过程中发送的 SSH_MSG_USERAUTH_BANNER (例如法律声明) 会按 UTF-8 解码后传给回调，
+    /// 需要在 connect 之前设置。横幅内容来自服务端，显示到终端之前应该过滤其中的控制字符 (RFC 4252 5.4)
+    pub fn set_banner_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&str) + Send + 'static
+    {
+        self.banner_callback = Some(Box::new(callback))
+    }
+
+    /// 设置修改过期密码的回调
+    /// 密码已过期时服务端回应 SSH_MSG_USERAUTH_PASSWD_CHANGEREQ，回调的参数是服务端的提示信息，
+    /// 返回新密码后使用旧密码和新密码重新认证，返回 None 或者没有设置回调时认证失败。
+    /// 新密码不符合要求时服务端会再次要求修改，回调会被再次调用
+    pub fn set_password_change_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&str) -> Option<String> + Send + 'static
+    {
+        self.password_change_callback = Some(Box::new(callback))
+    }
+
+    /// 之后通过 `open_exec` 和 `open_shell` 打开的通道都设置这个环境变量，同名的变量会被替换
+    /// sshd 只接受 AcceptEnv 中允许的变量名，其它的会被服务端静默忽略，见 `Channel::set_env`
+    pub fn set_env<N: ToString, V: ToString>(&mut self, name: N, value: V) {
+        let name = name.to_string();
+        self.env.retain(|(n, _)| *n != name);
+        self.env.push((name, value.to_string()))
+    }
+
+    /// 之后通过 `open_exec` 和 `open_shell` 打开的通道都请求转发本地的 ssh-agent (SSH_AUTH_SOCK)，
+    /// 远程的程序 (例如 git、ssh) 可以使用本地 agent 中的密钥，私钥不会离开本机。
+    /// 只应该在信任的服务端上开启，服务端的 root 可以在连接期间使用 agent 中的密钥，
+    /// 见 `Channel::request_agent_forwarding`
+    pub fn enable_agent_forwarding(&mut self, b: bool) {
+        self.agent_forwarding = b
+    }
+
+    /// 之后通过 `open_exec` 和 `open_shell` 打开的通道都请求 X11 转发到本地的 DISPLAY，
+    /// 远程的图形程序显示在本地。服务端只拿到随机生成的假 cookie，
+    /// 连接时替换为本地 xauth 中的 cookie。single_connection 为 true 时只转发一个 X11 连接，
+    /// 见 `Channel::request_x11_forwarding`
+    pub fn enable_x11_forwarding(&mut self, single_connection: bool) {
+        self.x11_forwarding = Some(single_connection)
+    }
+
+    /// 设置 known_hosts 文件，连接时校验服务端主机密钥
+    /// 例如 ~/.ssh/known_hosts
+    pub fn set_known_hosts<P: AsRef<Path>>(&mut self, path: P) {
+        self.known_hosts = Some(path.as_ref().to_path_buf())
+    }
+
+    /// 设置查找 known_hosts 时使用的主机名
+    /// 未设置时使用 `connect` 传入的主机名，connect_stream 和 connect_io 使用连接的 ip 地址
+    pub fn set_host_name<S: ToString>(&mut self, host_name: S) {
+        self.host_name = Some(host_name.to_string())
+    }
+
+    /// 服务端主机密钥的 SHA256 指纹，和 OpenSSH 显示的格式一致
+    /// 例如 SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s
+    pub fn host_key_fingerprint(&self) -> Option<String> {
+        self.host_key_raw().map(|v| known_hosts::fingerprint(&v))
+    }
+
+    /// 服务端主机密钥的 MD5 指纹，用于显示旧格式 (ssh -o FingerprintHash=md5)
+    /// 例如 MD5:2d:3c:5a:1f:...
+    pub fn host_key_fingerprint_md5(&self) -> Option<String> {
+        self.host_key_raw().map(|v| known_hosts::fingerprint_md5(&v))
+    }
+
+    /// 服务端主机密钥 (key blob)
+    /// 密钥交换完成后可用，重新协商密钥后会更新
+    pub fn host_key_raw(&self) -> Option<Vec<u8>> {
+        self.client.as_ref()
+            .and_then(|v| client::locking(v).ok())
+            .and_then(|v| v.host_key.clone())
+    }
+
+    /// 把服务端的主机密钥追加到 known_hosts (trust on first use)，例如首次连接后由用户确认指纹再记录
+    /// 格式为 `hostname keytype base64key`，主机名和 set_hash_known_hosts、set_host_name 的设置一致。
+    /// 主机证书记录的是被签名的密钥。已经记录过时不重复追加并返回 false，
+    /// 该主机已经记录了同类型的另一个密钥时返回 HostKeyMismatch，密钥被吊销时返回 HostKeyRevoked
+    pub fn add_to_known_hosts<P: AsRef<Path>>(&self, path: P) -> SshResult<bool> {
+        let client = self.client()?;
+        let mut key = match &client.host_key {
+            None => return Err(SshError::from("host key not received.")),
+            Some(v) => v.clone()
+        };
+        if certificate::is_certificate(&key) {
+            key = Certificate::parse(&key)?.key;
+        }
+        if self.revoked_keys.contains(&key) {
+            return Err(revoked(&key))
+        }
+        let (host, port) = self.known_hosts_host(&client)?;
+        let known_hosts = KnownHosts::from_path(path.as_ref())?;
+        match known_hosts.check(&host, port, &key) {
+            CheckResult::Ok => {
+                log::info!("host key is already in known_hosts.");
+                Ok(false)
+            }
+            CheckResult::Revoked => Err(revoked(&key)),
+            CheckResult::Mismatch => Err(mismatch(&known_hosts, &host, port, &key)),
+            CheckResult::Unknown => {
+                let line = known_hosts::format_entry(&host, port, &key, self.hash_known_hosts);
+                known_hosts::append_entry(path, &line)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// 服务端主机密钥类型，例如 ssh-ed25519
+    pub fn host_key_type(&self) -> Option<String> {
+        self.host_key_raw().and_then(|v| known_hosts::key_type(&v))
+    }
+
+    /// 协商出的密钥交换、主机密钥、加密、mac 和压缩算法
+    /// 密钥交换完成后可用，重新协商密钥后会更新
+    pub fn negotiated(&self) -> Option<NegotiatedAlgorithms> {
+        self.client.as_ref()
+            .and_then(|v| client::locking(v).ok())
+            .and_then(|v| v.negotiated.clone())
+    }
+
+    /// 第一次密钥交换的 exchange hash (RFC 4253 7.2)，整个连接期间不变，重新协商密钥后也不会改变
+    /// 可以用于通道绑定、自己实现的认证扩展和审计日志，密钥交换完成之前为 None
+    pub fn session_id(&self) -> Option<Vec<u8>> {
+        self.client.as_ref()
+            .and_then(|v| client::locking(v).ok())
+            .and_then(|v| v.session_id.clone())
+    }
+
+    /// 服务端通过 ext-info 通告的用户认证签名算法 (server-sig-algs，RFC 8308)
+    /// 服务端没有发送时为 None，这时 RSA 密钥使用 ssh-rsa 签名
+    pub fn server_sig_algs(&self) -> Option<Vec<String>> {
+        self.client.as_ref()
+            .and_then(|v| client::locking(v).ok())
+            .and_then(|v| v.server_sig_algs.clone())
+    }
+
+    /// 设置保活，同时设置 `set_keepalive_interval` 和 `set_keepalive_count_max`
+    pub fn set_keepalive(&mut self, interval: Duration, max_missed: u32) {
+        self.keepalive_interval = Some(interval);
+        self.keepalive_count_max = max_missed;
+        self.apply_keepalive()
+    }
+
+    /// 设置保活间隔 (ServerAliveInterval)，None 为关闭，默认关闭
+    /// 连接上超过 interval 没有收发任何数据包时发送一次 want reply 的 keepalive@openssh.com 请求，
+    /// 用于防止 NAT 和防火墙断开空闲的连接，也可以发现已经失效的连接。
+    /// 库中没有后台线程，保活请求在读取连接时发送，需要持续读取通道或者 poll_event
+    pub fn set_keepalive_interval(&mut self, interval: Option<Duration>) {
+        self.keepalive_interval = interval;
+        self.apply_keepalive()
+    }
+
+    /// 设置连续多少次保活请求没有回应时断开连接 (ServerAliveCountMax)，默认 3
+    /// 超过后读取返回 SshErrorKind::KeepaliveTimeout
+    pub fn set_keepalive_count_max(&mut self, max_missed: u32) {
+        self.keepalive_count_max = max_missed;
+        self.apply_keepalive()
+    }
+
+    /// 已经连接时立即生效，未连接时在认证完成后生效
+    fn apply_keepalive(&mut self) {
+        if self.client.is_none() {
+            return
+        }
+        if let Ok(mut client) = self.client() {
+            client.keepalive = self.keepalive_interval
+                .map(|v| Keepalive::new(v, self.keepalive_count_max))
         }
     }
 
+    /// 设置主机密钥不在 known_hosts 中时的处理方式
+    /// 默认为 HostKeyPolicy::Strict，设置了 known_hosts 时不会在没有提示的情况下接受未知的主机
+    pub fn set_host_key_policy(&mut self, policy: HostKeyPolicy) {
+        self.host_key_policy = policy
+    }
+
+    /// 设置追加到 known_hosts 的记录是否哈希主机名 (HashKnownHosts)
+    pub fn set_hash_known_hosts(&mut self, b: bool) {
+        self.hash_known_hosts = b
+    }
+
+    /// 按服务端通告的主机密钥更新 known_hosts (UpdateHostKeys)
+    /// 新的密钥需要服务端证明持有私钥后才会追加，该主机不再使用的密钥会被删除
+    /// 只有通过 known_hosts 校验的连接才会更新，默认关闭
+    pub fn set_update_host_keys(&mut self, b: bool) {
+        self.update_host_keys = b
+    }
+
+    /// 服务端通过 hostkeys-00@openssh.com 通告的主机密钥 (key blob)
+    /// 通告在认证完成后发送，读取到之后才可用 (例如打开通道或者 poll_event 之后)
+    pub fn announced_host_keys(&self) -> Vec<Vec<u8>> {
+        self.client.as_ref()
+            .and_then(|v| client::locking(v).ok())
+            .map(|v| v.announced_host_keys.clone())
+            .unwrap_or_default()
+    }
+
+    /// 吊销一个主机密钥 (key blob)，与 known_hosts 中的 @revoked 效果相同
+    /// 没有设置 known_hosts 时也会生效
+    pub fn revoke_host_key(&mut self, key: Vec<u8>) {
+        self.revoked_keys.push(key)
+    }
+
+    /// 替换全部已吊销的主机密钥
+    pub fn set_revoked_keys(&mut self, keys: Vec<Vec<u8>>) {
+        self.revoked_keys = keys
+    }
+
+    /// 只协商给定的加密算法，按给定的顺序优先
+    /// 不支持的名称会被忽略
+    pub fn set_preferred_ciphers<S: AsRef<str>>(&mut self, ciphers: &[S]) {
+        self.preferred_ciphers = Some(to_names(ciphers))
+    }
+
+    /// 只协商给定的密钥交换算法，按给定的顺序优先
+    pub fn set_preferred_kex<S: AsRef<str>>(&mut self, kex: &[S]) {
+        self.preferred_kex = Some(to_names(kex))
+    }
+
+    /// 只协商给定的 mac 算法，按给定的顺序优先
+    pub fn set_preferred_macs<S: AsRef<str>>(&mut self, macs: &[S]) {
+        self.preferred_macs = Some(to_names(macs))
+    }
+
 }
 
 impl Session {
 
+    /// 按解析的顺序依次尝试解析出的每一个地址 (IPv6 和 IPv4)，使用第一个连接成功的
+    /// 全部失败时返回的 io 错误中包含每个地址的错误，`io_kind` 是最后一个地址的
+    /// 校验 known_hosts 时使用 addr 中的主机名和端口，见 `ConnectAddr`
     pub fn connect<A>(&mut self, addr: A) -> Result<(), SshError>
     where
-        A: ToSocketAddrs
+        A: ConnectAddr
     {
-
+        self.connect_host = addr.host();
         // tcp 发起连接
-        client::connect(addr)?;
+        let client = Client::connect(addr, self.nonblocking)?;
+        self.handshake(client)
+    }
+
+    /// 带超时时间的连接，每个地址单独等待 timeout
+    /// 尝试地址的顺序和返回的错误与 `connect` 相同
+    pub fn connect_with_timeout<A>(&mut self, addr: A, timeout: Duration) -> SshResult<()>
+    where
+        A: ConnectAddr
+    {
+        self.connect_host = addr.host();
+        let client = Client::connect_timeout(addr, timeout, self.nonblocking)?;
+        self.handshake(client)
+    }
+
+    /// 在已经连接的 TcpStream 上建立 ssh 会话
+    /// 例如通过代理库建立的连接
+    pub fn connect_stream(&mut self, stream: TcpStream) -> SshResult<()> {
+        self.connect_host = None;
+        let client = Client::from_stream(stream, self.nonblocking)?;
+        self.handshake(client)
+    }
+
+    /// 在任意 Read + Write 的流上建立 ssh 会话
+    /// 流没有对端地址，校验 known_hosts 时需要先调用 set_host_name
+    pub fn connect_io<T>(&mut self, io: T) -> SshResult<()>
+    where
+        T: Read + Write + Send + 'static
+    {
+        self.connect_host = None;
+        let client = Client::from_io(io);
+        self.handshake(client)
+    }
+
+    /// 通过已经连接的跳板机连接 host:port，与 `ssh -J` 相同
+    /// 跳板机打开 direct-tcpip 通道，在通道上建立 ssh 会话，known_hosts 按 host:port 校验
+    pub fn via(&mut self, jump: &mut Session, host: &str, port: u16) -> SshResult<()> {
+        let channel = jump.open_direct_tcpip(host, port as u32)?;
+        self.connect_host = Some((host.to_string(), port));
+        self.handshake(Client::from_io(channel))
+    }
+
+    fn handshake(&mut self, mut client: Client) -> SshResult<()> {
+        let user_info = match &self.user_info {
+            Some(v) => v.clone(),
+            None => {
+                log::error!("user info not set.");
+                return Err(SshError::from("user info not set."))
+            }
+        };
+        self.apply_socket_options(&client)?;
+        client.config = Config::new(user_info);
+        client.timeout = Timeout::new(self.timeout);
+        client.poll_interval = self.poll_interval;
+        client.rekey_limit = self.rekey_limit;
+        let client = Arc::new(Mutex::new(client));
+        self.client = Some(client.clone());
+        #[cfg(feature = "ctrlc")]
+        crate::exit_handler::register(&client);
+        let mut client = client::locking(&client)?;
 
         log::info!("session opened.");
 
@@ -46,69 +586,139 @@ impl Session {
 
         // 版本协商
         // 获取服务端版本
-        self.receive_version()?;
+        self.receive_version(&mut client)?;
 
         // 版本验证
-        let config = config::config();
-        config.version.validation()?;
+        client.config.version.validation()?;
         // 发送客户端版本
-        self.send_version()?;
+        self.send_version(&mut client)?;
 
         log::info!("version negotiation was successful.");
 
         log::info!("prepare for key negotiation.");
 
         // 密钥协商
-        kex::send_algorithm()?;
-        kex::receive_algorithm()?;
+        client.config.algorithm.client_algorithm = self.client_algorithm()?;
+        kex::send_algorithm(&mut client)?;
+        kex::receive_algorithm(&mut client)?;
+        kex::matching_key_exchange(&mut client)?;
 
-        // 缓存密钥交换算法
-        key_exchange::put(config.algorithm.matching_key_exchange_algorithm()?);
-        // 公钥算法
-        public_key::put(config.algorithm.matching_public_key_algorithm()?);
+        kex::send_qc(&mut client)?;
+        kex::verify_signature_and_new_keys(&mut client)?;
 
-        h::get().set_v_c(config.version.client_version.as_str());
-        h::get().set_v_s(config.version.server_version.as_str());
+        log::info!("key negotiation successful.");
 
-        kex::send_qc()?;
-        kex::verify_signature_and_new_keys()?;
+        // 校验主机密钥
+        self.verify_host_key(&mut client)?;
 
-        // 加密算法
-        encryption::put(config.algorithm.matching_encryption_algorithm()?);
-        // mac 算法
-        mac::put(config.algorithm.matching_mac_algorithm()?);
+        self.initiate_authentication(&mut client)?;
+        self.authentication(&mut client)?;
 
-        log::info!("key negotiation successful.");
-
-        self.initiate_authentication()?;
-        self.authentication()
+        if let Some(interval) = self.keepalive_interval {
+            client.keepalive = Some(Keepalive::new(interval, self.keepalive_count_max))
+        }
+        Ok(())
     }
 
     pub fn open_channel(&mut self) -> SshResult<Channel> {
+        self.open_channel_type(ssh_str::SESSION, &[])
+    }
+
+    /// 打开 direct-tcpip 通道，由服务端连接 host:port 并转发数据
+    pub fn open_direct_tcpip(&mut self, host: &str, port: u32) -> SshResult<ChannelDirectTcpip> {
+        /*
+            string    host to connect
+            uint32    port to connect
+            string    originator IP address
+            uint32    originator port
+        */
+        let mut data = Data::new();
+        data.put_str(host)
+            .put_u32(port)
+            .put_str("127.0.0.1")
+            .put_u32(0);
+        let channel = self.open_channel_type(ssh_str::DIRECT_TCPIP, &data)?;
+        log::info!("direct-tcpip opened.");
+        Ok(ChannelDirectTcpip::open(channel))
+    }
+
+    fn open_channel_type(&mut self, channel_type: &str, extra: &[u8]) -> SshResult<Channel> {
+        let channel = self.begin_channel_type(channel_type, extra)?.confirm()?;
         log::info!("channel opened.");
-        let client_channel = channel::current_client_channel_no();
-        self.send_open_channel(client_channel)?;
-        let (server_channel, rws) = self.receive_open_channel()?;
-        let mut win_size = WindowSize::new();
-        win_size.server_channel = server_channel;
+        Ok(channel.into_dynamic())
+    }
+
+    /// 发送打开 session 通道的请求，返回还在等待服务端确认的通道
+    /// 需要调用 `confirm` 得到 `Channel<Open>`，见 `channel_state`
+    pub fn begin_channel(&mut self) -> SshResult<Channel<Opening>> {
+        self.begin_channel_type(ssh_str::SESSION, &[])
+    }
+
+    fn begin_channel_type(&mut self, channel_type: &str, extra: &[u8]) -> SshResult<Channel<Opening>> {
+        let client = match &self.client {
+            None => return Err(SshError::from("Client null pointer")),
+            Some(client) => client.clone()
+        };
+        let client_channel = {
+            let mut client = self.client()?;
+            let client_channel = client.next_client_channel();
+            client.register_channel(client_channel);
+            client_channel
+        };
+        if let Err(e) = self.send_open_channel(client_channel, channel_type, extra) {
+            self.client()?.remove_channel(client_channel);
+            return Err(e)
+        }
+        let mut win_size = WindowSize::with_local_window_size(self.window_size);
         win_size.client_channel = client_channel;
-        win_size.add_remote_window_size(rws);
-        win_size.add_remote_max_window_size(rws);
-        Ok(Channel {
-            remote_close: false,
-            local_close: false,
-            window_size: win_size
-        })
+        Ok(Channel::new(client, win_size).into_state())
     }
 
+    /// 打开 exec 通道，`set_env` 设置的环境变量会在通道打开后发送
     pub fn open_exec(&mut self) -> SshResult<ChannelExec> {
-        let channel = self.open_channel()?;
+        let channel = self.open_env_channel()?;
         channel.open_exec()
     }
 
+    /// 打开 exec 通道并请求伪终端，用于确实需要终端的命令
+    /// 默认的 `open_exec` 不请求伪终端，stdout 和 stderr 分开，二进制输出也不会被终端改写
+    pub fn open_exec_with_pty(&mut self, options: &PtyOptions) -> SshResult<ChannelExec> {
+        let channel = self.open_env_channel()?;
+        channel.open_exec_with_pty(options)
+    }
+
+    /// 执行一条命令并等待结束，返回 stdout、stderr 和退出状态，与 `std::process::Command::output` 类似
+    /// 打开一个不带伪终端的 exec 通道，读取全部输出直到服务端关闭通道，每次调用使用新的通道，可以重复调用。
+    /// 返回的错误只来自打开通道和连接，退出码不为 0 时不返回错误，
+    /// 需要检查时使用 `ExecResult::success` 或 `ChannelExec::output`
+    pub fn exec(&mut self, command: &str) -> SshResult<ExecResult> {
+        let output = self.open_exec()?.output(command, &ExecOptions::new())?;
+        Ok(ExecResult::from(output))
+    }
+
+    /// 打开 shell 通道，`set_env` 设置的环境变量会在请求 shell 之前发送
     pub fn open_shell(&mut self) -> SshResult<ChannelShell> {
-        let channel = self.open_channel()?;
-        channel.open_shell()
+        self.open_shell_with_pty(&PtyOptions::new())
+    }
+
+    /// 使用指定的伪终端参数打开 shell 通道，例如本地终端的 TERM 和大小
+    pub fn open_shell_with_pty(&mut self, options: &PtyOptions) -> SshResult<ChannelShell> {
+        let channel = self.open_env_channel()?;
+        channel.open_shell_with_pty(options)
+    }
+
+    fn open_env_channel(&mut self) -> SshResult<Channel> {
+        let mut channel = self.open_channel()?;
+        for (name, value) in &self.env {
+            channel.set_env(name, value)?
+        }
+        if self.agent_forwarding {
+            channel.request_agent_forwarding()?
+        }
+        if let Some(single_connection) = self.x11_forwarding {
+            channel.request_x11_forwarding(single_connection)?
+        }
+        Ok(channel)
     }
 
     pub fn open_scp(&mut self) -> SshResult<ChannelScp> {
@@ -116,99 +726,237 @@ impl Session {
         channel.open_scp()
     }
 
+    /// 打开子系统通道，例如 "sftp"，不请求伪终端
+    /// 服务端拒绝时返回 `SshErrorKind::SubsystemUnavailable`
+    pub fn open_subsystem(&mut self, name: &str) -> SshResult<ChannelSubsystem> {
+        let channel = self.open_channel()?;
+        channel.open_subsystem(name)
+    }
+
+    /// 在 timeout 内等待下一个 session 事件，超时返回 None
+    /// 每次从连接读取到的消息会先分发到各个通道的队列中，
+    /// 适合在单线程的事件循环中同时处理多个通道
+    pub fn poll_event(&mut self, timeout: Duration) -> SshResult<Option<Event>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.client()?.next_event()? {
+                return Ok(Some(event))
+            }
+            // 没有数据时读取中已经等待过 poll_interval
+            if Instant::now() >= deadline {
+                return Ok(None)
+            }
+        }
+    }
+
     pub fn close(self) -> SshResult<()> {
         log::info!("session close.");
-        client::default()?.close()
+        #[cfg(feature = "ctrlc")]
+        if let Some(client) = &self.client {
+            crate::exit_handler::deregister(client)
+        }
+        self.client()?.close()
     }
 
 }
 
+/// 没有调用 `close` 就释放 session 时，尽量发送 SSH_MSG_DISCONNECT 并关闭连接，出错时只记录日志。
+/// 还有通道持有连接时不断开，这些通道仍然可以使用
+impl Drop for Session {
+    fn drop(&mut self) {
+        let client = match self.client.take() {
+            Some(v) => v,
+            None => return
+        };
+        if Arc::strong_count(&client) > 1 { return }
+        #[cfg(feature = "ctrlc")]
+        crate::exit_handler::deregister(&client);
+        let mut client = match client.lock() {
+            Ok(v) => v,
+            Err(_) => return
+        };
+        if client.closed || client.disconnected.is_some() { return }
+        log::info!("session dropped without close, disconnect.");
+        if let Err(e) = client.disconnect(ssh_msg_code::SSH_DISCONNECT_BY_APPLICATION, "session dropped") {
+            log::error!("disconnect error: {}", e);
+        }
+    }
+}
+
 impl Session {
 
     // 本地请求远程打开通道
-    fn send_open_channel(&mut self, client_channel: u32) -> SshResult<()> {
+    // extra 为通道类型相关的数据
+    fn send_open_channel(&mut self, client_channel: u32, channel_type: &str, extra: &[u8]) -> SshResult<()> {
         let mut data = Data::new();
         data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_OPEN)
-            .put_str(ssh_str::SESSION)
+            .put_str(channel_type)
             .put_u32(client_channel)
-            .put_u32(size::LOCAL_WINDOW_SIZE)
-            .put_u32(size::BUF_SIZE as u32);
-        let client = client::default()?;
-        client.write(data)
+            .put_u32(self.window_size)
+            .put_u32(self.max_packet_size);
+        data.extend_from_slice(extra);
+        self.client()?.write(data)
     }
 
-    // 远程回应是否可以打开通道
-    fn receive_open_channel(&mut self) -> SshResult<(u32, u32)> {
-        loop {
-            let client = client::default()?;
-            let results = client.read()?;
-            for mut result in results {
-                if result.is_empty() { continue }
-                let message_code = result.get_u8();
-                match message_code {
-                    // 打开请求通过
-                    ssh_msg_code::SSH_MSG_CHANNEL_OPEN_CONFIRMATION => {
-                        // 接收方通道号
-                        result.get_u32();
-                        // 发送方通道号
-                        let server_channel = result.get_u32();
-                        // 远程初始窗口大小
-                        let rws = result.get_u32();
-                        // 远程的最大数据包大小， 暂时不需要
-                        result.get_u32();
-                        return Ok((server_channel, rws));
-                    },
-                    /*
-                        byte SSH_MSG_CHANNEL_OPEN_FAILURE
-                        uint32 recipient channel
-                        uint32 reason code
-                        string description，ISO-10646 UTF-8 编码[RFC3629]
-                        string language tag，[RFC3066]
-                    */
-                    // 打开请求拒绝
-                    ssh_msg_code::SSH_MSG_CHANNEL_OPEN_FAILURE => {
-                        result.get_u32();
-                        // 失败原因码
-                        let code = result.get_u32();
-                        // 消息详情 默认utf-8编码
-                        let description = String::from_utf8(result.get_u8s())
-                            .unwrap_or(String::from("error"));
-                        // language tag 暂不处理， 应该是 en-US
-                        result.get_u8s();
-
-                        let err_msg = match code {
-                            ssh_msg_code::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED => {
-                                format!("SSH_OPEN_ADMINISTRATIVELY_PROHIBITED: {}", description)
-                            },
-                            ssh_msg_code::SSH_OPEN_CONNECT_FAILED => {
-                                format!("SSH_OPEN_CONNECT_FAILED: {}", description)
-                            },
-                            ssh_msg_code::SSH_OPEN_UNKNOWN_CHANNEL_TYPE => {
-                                format!("SSH_OPEN_UNKNOWN_CHANNEL_TYPE: {}", description)
-                            },
-                            ssh_msg_code::SSH_OPEN_RESOURCE_SHORTAGE => {
-                                format!("SSH_OPEN_RESOURCE_SHORTAGE: {}", description)
-                            },
-                            _ => description
-                        };
-                        return Err(SshError::from(err_msg))
-                    },
-                    _ => {}
+    // 这个会话的算法列表，用户设置的算法列表替换客户端默认的算法列表
+    fn client_algorithm(&self) -> SshResult<AlgorithmList> {
+        let mut list = AlgorithmList::client_algorithm();
+        if let Some(v) = &self.preferred_kex {
+            list.key_exchange_algorithm.0 =
+                config::preferred_algorithm("key exchange", &list.key_exchange_algorithm.0, v)?;
+        }
+        if let Some(v) = &self.preferred_ciphers {
+            let ciphers = config::preferred_algorithm("encryption", &list.c_encryption_algorithm.0, v)?;
+            list.c_encryption_algorithm.0 = ciphers.clone();
+            list.s_encryption_algorithm.0 = ciphers;
+        }
+        if let Some(v) = &self.preferred_macs {
+            let macs = config::preferred_algorithm("mac", &list.c_mac_algorithm.0, v)?;
+            list.c_mac_algorithm.0 = macs.clone();
+            list.s_mac_algorithm.0 = macs;
+        }
+        if self.compression {
+            list.c_compression_algorithm = CompressionAlgorithm::get_compressed();
+            list.s_compression_algorithm = CompressionAlgorithm::get_compressed();
+        }
+        Ok(list)
+    }
+
+    fn verify_host_key(&mut self, client: &mut Client) -> SshResult<()> {
+        let key = match &client.host_key {
+            None => return Err(SshError::from("host key not received.")),
+            Some(v) => v.as_slice()
+        };
+        let cert = if certificate::is_certificate(key) {
+            Some(Certificate::parse(key)?)
+        } else {
+            None
+        };
+        // 证书本身、被签名的密钥和 CA 公钥任意一个被吊销都拒绝连接
+        let mut keys = vec![key];
+        if let Some(cert) = &cert {
+            keys.push(&cert.key);
+            keys.push(&cert.signature_key);
+        }
+        // 吊销检查需要在追加 known_hosts 之前完成
+        if let Some(v) = keys.iter().find(|v| self.revoked_keys.iter().any(|r| r == *v)) {
+            return Err(revoked(v))
+        }
+        let path = match &self.known_hosts {
+            None => return Ok(()),
+            Some(path) => path
+        };
+        let known_hosts = KnownHosts::from_path(path)?;
+        let (host, port) = self.known_hosts_host(client)?;
+        let cert = match cert {
+            None => {
+                if self.check_host_key(&known_hosts, path, &host, port, key)? && self.update_host_keys {
+                    client.host_keys_update = Some(HostKeysUpdate {
+                        path: path.to_path_buf(),
+                        host,
+                        port,
+                        hashed: self.hash_known_hosts,
+                        revoked: self.revoked_keys.clone()
+                    })
+                }
+                return Ok(())
+            }
+            Some(v) => v
+        };
+        match known_hosts.check_ca(&host, port, &cert.signature_key) {
+            CheckResult::Ok => {
+                cert.validate(&host)?;
+                for v in [key, &cert.key] {
+                    if known_hosts.check(&host, port, v) == CheckResult::Revoked {
+                        return Err(revoked(v))
+                    }
                 }
+                log::info!("host certificate verification success, key id: {}", cert.key_id);
+                Ok(())
+            }
+            CheckResult::Revoked => Err(revoked(&cert.signature_key)),
+            // 不信任签发证书的 CA 时，按普通的主机密钥校验证书中的密钥
+            _ => {
+                log::info!("certificate authority is not trusted, check the certified host key.");
+                self.check_host_key(&known_hosts, path, &host, port, &cert.key)?;
+                Ok(())
             }
         }
     }
 
-    fn initiate_authentication(&mut self) -> SshResult<()> {
+    /// 主机密钥和 known_hosts 中的记录一致或者刚被追加时返回 true
+    fn check_host_key(&self,
+                      known_hosts: &KnownHosts,
+                      path: &Path,
+                      host: &str,
+                      port: u16,
+                      key: &[u8])
+        -> SshResult<bool>
+    {
+        match known_hosts.check(host, port, key) {
+            CheckResult::Ok => {
+                log::info!("host key verification success.");
+                Ok(true)
+            }
+            CheckResult::Unknown => {
+                log::info!("host [{}] is not found in known_hosts.", host);
+                match self.host_key_policy {
+                    HostKeyPolicy::Strict => {
+                        let fingerprint = known_hosts::fingerprint(key);
+                        log::error!("unknown host key, fingerprint: {}", fingerprint);
+                        Err(SshError::from(SshErrorKind::HostKeyUnknown { fingerprint }))
+                    }
+                    HostKeyPolicy::AcceptNew => {
+                        let line = known_hosts::format_entry(host, port, key, self.hash_known_hosts);
+                        known_hosts::append_entry(path, &line)?;
+                        Ok(true)
+                    }
+                    HostKeyPolicy::AcceptAll => {
+                        log::warn!("accept unknown host key, fingerprint: {}", known_hosts::fingerprint(key));
+                        Ok(false)
+                    }
+                }
+            }
+            CheckResult::Revoked => Err(revoked(key)),
+            CheckResult::Mismatch => Err(mismatch(known_hosts, host, port, key))
+        }
+    }
+
+    /// 查找和写入 known_hosts 时使用的主机名和端口
+    /// 依次使用 set_host_name 设置的主机名、`connect` 传入的主机名和连接的 ip 地址
+    fn known_hosts_host(&self, client: &Client) -> SshResult<(String, u16)> {
+        let peer = client.stream.peer_addr();
+        let connect_host = self.connect_host.as_ref();
+        let host = match (&self.host_name, connect_host, peer) {
+            (Some(v), _, _) => v.to_string(),
+            (None, Some((v, _)), _) => v.to_string(),
+            (None, None, Some(peer)) => peer.ip().to_string(),
+            (None, None, None) => {
+                log::error!("host name is required to verify the host key.");
+                return Err(SshError::from("host name is required to verify the host key."))
+            }
+        };
+        let port = match (connect_host, peer) {
+            (Some((_, port)), _) => *port,
+            (None, Some(peer)) => peer.port(),
+            (None, None) => 22
+        };
+        Ok((host, port))
+    }
+
+    fn initiate_authentication(&mut self, client: &mut Client) -> SshResult<()> {
         let mut data = Data::new();
         data.put_u8(ssh_msg_code::SSH_MSG_SERVICE_REQUEST)
             .put_str(ssh_str::SSH_USERAUTH);
-        let client = client::default()?;
+        log::debug!("request the ssh-userauth service.");
         client.write(data)
     }
 
-    fn authentication(&mut self) -> SshResult<()> {
-        let client = client::default()?;
+    fn authentication(&mut self, client: &mut Client) -> SshResult<()> {
+        // 只接受对已发送请求的回应，例如没有发送认证请求时收到 SUCCESS 说明服务端有问题
+        let mut state = AuthState::ServiceRequest;
+        // 已经发送的修改密码请求中的新密码
+        let mut changed_password = None;
         loop {
             let results = client.read()?;
             for mut result in results {
@@ -216,30 +964,85 @@ impl Session {
                 let message_code = result.get_u8();
                 match message_code {
                     ssh_msg_code::SSH_MSG_SERVICE_ACCEPT => {
-                        let config = config::config();
-                        match config.auth.auth_type {
+                        if state != AuthState::ServiceRequest {
+                            return Err(protocol_violation(client, "unexpected SSH_MSG_SERVICE_ACCEPT"))
+                        }
+                        match client.config.auth.auth_type {
                             // 开始密码验证
-                            AuthType::Password => self.password_authentication()?,
-                            AuthType::PublicKey => self.public_key_authentication()?
+                            AuthType::Password => {
+                                self.password_authentication(client)?;
+                                state = AuthState::PasswordRequest
+                            }
+                            AuthType::PublicKey => {
+                                self.public_key_authentication(client)?;
+                                state = AuthState::PublicKeyQuery
+                            }
                         }
 
                     }
                     ssh_msg_code::SSH_MSG_USERAUTH_FAILURE => {
-                        log::error!("user auth failure.");
-                        return Err(SshError::from("user auth failure, auth type is password."))
+                        if state == AuthState::ServiceRequest {
+                            return Err(protocol_violation(client, "SSH_MSG_USERAUTH_FAILURE before any request"))
+                        }
+                        let method = client.config.auth.auth_type.method();
+                        log::error!("user auth failure, auth type is {}.", method);
+                        return Err(SshError::from(format!("user auth failure, auth type is {}.", method)))
+                    }
+                    /*
+                        byte      SSH_MSG_USERAUTH_PASSWD_CHANGEREQ
+                        string    prompt in ISO-10646 UTF-8 encoding [RFC3629]
+                        string    language tag [RFC3066]
+                    */
+                    ssh_msg_code::SSH_MSG_USERAUTH_PASSWD_CHANGEREQ if state == AuthState::PasswordRequest => {
+                        let prompt = String::from_utf8_lossy(&result.get_u8s()).to_string();
+                        log::info!("password expired: {}", prompt);
+                        let new_password = match &mut self.password_change_callback {
+                            Some(callback) => callback(&prompt),
+                            None => None
+                        };
+                        let new_password = match new_password {
+                            Some(v) => v,
+                            None => {
+                                log::error!("password change required.");
+                                return Err(SshError::from(format!("password change required: {}", prompt)))
+                            }
+                        };
+                        self.password_change(client, &new_password)?;
+                        changed_password = Some(new_password)
                     }
                     ssh_msg_code::SSH_MSG_USERAUTH_PK_OK => {
+                        if state != AuthState::PublicKeyQuery {
+                            return Err(protocol_violation(client, "unexpected SSH_MSG_USERAUTH_PK_OK"))
+                        }
                         log::info!("user auth support this algorithm.");
-                        self.public_key_signature()?
+                        self.public_key_signature(client)?;
+                        state = AuthState::Request
                     }
                     ssh_msg_code::SSH_MSG_USERAUTH_SUCCESS => {
+                        if state != AuthState::Request && state != AuthState::PasswordRequest {
+                            return Err(protocol_violation(client, "SSH_MSG_USERAUTH_SUCCESS without an authentication request"))
+                        }
+                        // 修改成功后新密码就是当前的密码
+                        if let Some(password) = changed_password.take() {
+                            client.config.auth.password = password
+                        }
                         log::info!("user auth successful.");
                         return Ok(())
                     }
+                    /*
+                        byte      SSH_MSG_USERAUTH_BANNER
+                        string    message in ISO-10646 UTF-8 encoding [RFC3629]
+                        string    language tag [RFC3066]
+                    */
+                    ssh_msg_code::SSH_MSG_USERAUTH_BANNER => {
+                        let message = String::from_utf8_lossy(&result.get_u8s()).to_string();
+                        log::info!("user auth banner: {}", message);
+                        if let Some(callback) = &mut self.banner_callback {
+                            callback(&message)
+                        }
+                    }
                     ssh_msg_code::SSH_MSG_GLOBAL_REQUEST => {
-                        let mut data = Data::new();
-                        data.put_u8(ssh_msg_code::SSH_MSG_REQUEST_FAILURE);
-                        client.write(data)?
+                        client.global_request(result)?;
                     }
                     _ => {}
                 }
@@ -247,23 +1050,505 @@ impl Session {
         }
     }
 
-    fn send_version(&mut self) -> SshResult<()> {
-        let client = client::default()?;
-        let config = config::config();
-        client.write_version(format!("{}\r\n", config.version.client_version).as_bytes())?;
-        log::info!("client version: [{}]", config.version.client_version);
+    fn send_version(&mut self, client: &mut Client) -> SshResult<()> {
+        let client_version = client.config.version.client_version.clone();
+        client.write_version(format!("{}\r\n", client_version).as_bytes())?;
+        log::info!("client version: [{}]", client_version);
         Ok(())
     }
 
-    fn receive_version(&mut self) -> SshResult<()> {
-        let client = client::default()?;
-        let vec = client.read_version();
-        let from_utf8 = util::from_utf8(vec)?;
-        let sv = from_utf8.trim();
+    fn receive_version(&mut self, client: &mut Client) -> SshResult<()> {
+        let sv = client.read_version()?;
         log::info!("server version: [{}]", sv);
-        let config = config::config();
-        config.version.server_version = sv.to_string();
+        client.config.version.server_version = sv;
         Ok(())
     }
 }
 
+
+/// `Session::connect` 的地址，和 `ToSocketAddrs` 一样可以是 "host:port"、(host, port)、SocketAddr 等，
+/// 另外取出其中的主机名和端口，校验 known_hosts 时使用
+/// 和 ssh 命令一样，使用连接时的主机名而不是解析出的 ip 地址
+pub trait ConnectAddr: ToSocketAddrs {
+    /// 地址中的主机名和端口，没有时 (例如多个地址) 为 None，这时使用连接的 ip 地址
+    fn host(&self) -> Option<(String, u16)>;
+}
+
+impl ConnectAddr for SocketAddr {
+    fn host(&self) -> Option<(String, u16)> {
+        Some((self.ip().to_string(), self.port()))
+    }
+}
+
+impl ConnectAddr for SocketAddrV4 {
+    fn host(&self) -> Option<(String, u16)> {
+        Some((self.ip().to_string(), self.port()))
+    }
+}
+
+impl ConnectAddr for SocketAddrV6 {
+    fn host(&self) -> Option<(String, u16)> {
+        Some((self.ip().to_string(), self.port()))
+    }
+}
+
+impl ConnectAddr for (IpAddr, u16) {
+    fn host(&self) -> Option<(String, u16)> {
+        Some((self.0.to_string(), self.1))
+    }
+}
+
+impl ConnectAddr for (Ipv4Addr, u16) {
+    fn host(&self) -> Option<(String, u16)> {
+        Some((self.0.to_string(), self.1))
+    }
+}
+
+impl ConnectAddr for (Ipv6Addr, u16) {
+    fn host(&self) -> Option<(String, u16)> {
+        Some((self.0.to_string(), self.1))
+    }
+}
+
+impl ConnectAddr for (&str, u16) {
+    fn host(&self) -> Option<(String, u16)> {
+        Some((self.0.to_string(), self.1))
+    }
+}
+
+impl ConnectAddr for (String, u16) {
+    fn host(&self) -> Option<(String, u16)> {
+        Some((self.0.clone(), self.1))
+    }
+}
+
+impl ConnectAddr for str {
+    fn host(&self) -> Option<(String, u16)> {
+        split_host_port(self)
+    }
+}
+
+impl ConnectAddr for String {
+    fn host(&self) -> Option<(String, u16)> {
+        split_host_port(self)
+    }
+}
+
+impl ConnectAddr for &[SocketAddr] {
+    fn host(&self) -> Option<(String, u16)> {
+        match self {
+            [addr] => addr.host(),
+            _ => None
+        }
+    }
+}
+
+impl<T: ConnectAddr + ?Sized> ConnectAddr for &T {
+    fn host(&self) -> Option<(String, u16)> {
+        (**self).host()
+    }
+}
+
+/// "host:port" 或者 "[ipv6]:port"
+fn split_host_port(addr: &str) -> Option<(String, u16)> {
+    let (host, port) = addr.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let host = host.strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .unwrap_or(host);
+    Some((host.to_string(), port))
+}
+
+
+/// 认证过程中等待服务端回应的请求
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AuthState {
+    /// 已发送 ssh-userauth 服务请求
+    ServiceRequest,
+    /// 已发送不带签名的公钥查询，只能回应 PK_OK 或 FAILURE
+    PublicKeyQuery,
+    /// 已发送密码认证请求，可能收到修改密码的要求
+    PasswordRequest,
+    /// 已发送带签名的公钥认证请求
+    Request,
+}
+
+
+/// 断开连接并返回 ProtocolViolation
+fn protocol_violation(client: &mut Client, message: &str) -> SshError {
+    client.protocol_violation(message)
+}
+
+fn mismatch(known_hosts: &KnownHosts, host: &str, port: u16, key: &[u8]) -> SshError {
+    let actual = known_hosts::fingerprint(key);
+    let expected = known_hosts::key_type(key)
+        .and_then(|t| known_hosts.known_key(host, port, &t).map(known_hosts::fingerprint))
+        .unwrap_or_default();
+    log::error!("host key mismatch, expected: {}, actual: {}", expected, actual);
+    SshError::from(SshErrorKind::HostKeyMismatch { expected, actual })
+}
+
+fn revoked(key: &[u8]) -> SshError {
+    let fingerprint = known_hosts::fingerprint(key);
+    log::error!("host key is revoked, fingerprint: {}", fingerprint);
+    SshError::from(SshErrorKind::HostKeyRevoked { fingerprint })
+}
+
+pub(crate) fn to_names<S: AsRef<str>>(names: &[S]) -> Vec<String> {
+    names.iter().map(|v| v.as_ref().to_string()).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{Ipv6Addr, SocketAddr};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use crate::constant::{algorithms, ssh_msg_code, ssh_str};
+    use crate::data::Data;
+    use crate::known_hosts::{self, HostKeyPolicy, KnownHosts};
+    use crate::config::{AlgorithmList, Config};
+    use crate::error::{SshErrorKind, SshResult};
+    use crate::key_pair::KeyPair;
+    use crate::mock::{self, Peer};
+    use crate::user_info::UserInfo;
+    use super::{ConnectAddr, Session};
+
+    #[test]
+    fn preferred_algorithms_are_per_session() {
+        let mut restricted = Session::new();
+        restricted.set_preferred_ciphers(&[algorithms::ENCRYPTION_AES128_CTR]);
+        restricted.set_preferred_kex(&[algorithms::DH_ECDH_SHA2_NISTP256, algorithms::DH_CURVE25519_SHA256]);
+        let default = Session::new();
+
+        let list = restricted.client_algorithm().unwrap();
+        assert_eq!(list.c_encryption_algorithm.0, vec![algorithms::ENCRYPTION_AES128_CTR]);
+        assert_eq!(list.s_encryption_algorithm.0, vec![algorithms::ENCRYPTION_AES128_CTR]);
+        assert_eq!(list.key_exchange_algorithm.0,
+                   vec![algorithms::DH_ECDH_SHA2_NISTP256, algorithms::DH_CURVE25519_SHA256]);
+
+        // 另一个会话仍然使用默认的算法列表
+        let expected = AlgorithmList::client_algorithm();
+        let list = default.client_algorithm().unwrap();
+        assert_eq!(list.c_encryption_algorithm.0, expected.c_encryption_algorithm.0);
+        assert_eq!(list.key_exchange_algorithm.0, expected.key_exchange_algorithm.0);
+    }
+
+    #[test]
+    fn unsupported_preferred_algorithms() {
+        let mut session = Session::new();
+        session.set_preferred_macs(&["hmac-md5"]);
+        let err = session.client_algorithm().err().unwrap();
+        match err.kind() {
+            SshErrorKind::NegotiationFailed { category, .. } => assert_eq!(category, "mac"),
+            _ => panic!("unexpected error: {}", err)
+        }
+    }
+
+    #[test]
+    fn connect_addr_host() {
+        assert_eq!("example.com:2222".host(), Some(("example.com".to_string(), 2222)));
+        assert_eq!("[::1]:22".to_string().host(), Some(("::1".to_string(), 22)));
+        assert_eq!(("example.com", 22).host(), Some(("example.com".to_string(), 22)));
+        assert_eq!(("example.com".to_string(), 22).host(), Some(("example.com".to_string(), 22)));
+        assert_eq!((Ipv6Addr::LOCALHOST, 22).host(), Some(("::1".to_string(), 22)));
+        let addr: SocketAddr = "127.0.0.1:2222".parse().unwrap();
+        assert_eq!(addr.host(), Some(("127.0.0.1".to_string(), 2222)));
+        assert_eq!([addr, addr].as_slice().host(), None);
+        assert_eq!("example.com".host(), None);
+    }
+
+    #[test]
+    fn known_hosts_uses_connect_host() {
+        let (client, _server) = mock::connection();
+        let mut session = Session::new();
+        // connect_io 没有对端地址，也没有主机名
+        assert!(session.known_hosts_host(&client).is_err());
+
+        session.connect_host = Some(("example.com".to_string(), 2222));
+        assert_eq!(session.known_hosts_host(&client).unwrap(), ("example.com".to_string(), 2222));
+
+        // set_host_name 优先，端口仍然是连接的端口
+        session.set_host_name("alias.example.com");
+        assert_eq!(session.known_hosts_host(&client).unwrap(), ("alias.example.com".to_string(), 2222));
+    }
+
+    /// 校验 example.com:22 发送的主机密钥，known_hosts 不为 None 时写入临时文件
+    fn verify(session: &mut Session, known_hosts: Option<&str>, host_key: &str) -> SshResult<()> {
+        static FILE_NO: AtomicUsize = AtomicUsize::new(0);
+        let (mut client, _server) = mock::connection();
+        client.host_key = Some(mock::blob(host_key));
+        session.connect_host = Some(("example.com".to_string(), 22));
+        let path = std::env::temp_dir().join(format!("ssh-rs-known-hosts-{}-{}",
+                                                     std::process::id(),
+                                                     FILE_NO.fetch_add(1, Ordering::Relaxed)));
+        if let Some(s) = known_hosts {
+            std::fs::write(&path, s).unwrap();
+            session.set_known_hosts(&path);
+        }
+        let result = session.verify_host_key(&mut client);
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    fn revoked(key: &str) -> SshErrorKind {
+        SshErrorKind::HostKeyRevoked { fingerprint: known_hosts::fingerprint(&mock::blob(key)) }
+    }
+
+    #[test]
+    fn revoked_keys_are_rejected() {
+        let trusted = format!("@cert-authority * ssh-ed25519 {}\nexample.com ssh-ed25519 {}",
+                              mock::CA_KEY, mock::HOST_KEY);
+        verify(&mut Session::new(), Some(&trusted), mock::HOST_KEY).unwrap();
+        verify(&mut Session::new(), Some(&trusted), mock::HOST_CERT).unwrap();
+        // 没有设置 known_hosts 或者 known_hosts 中有一致的记录时也拒绝
+        for known_hosts in [None, Some(trusted.as_str())] {
+            let mut session = Session::new();
+            session.revoke_host_key(mock::blob(mock::HOST_KEY));
+            assert_eq!(verify(&mut session, known_hosts, mock::HOST_KEY).unwrap_err().kind(), &revoked(mock::HOST_KEY));
+            // 证书中被签名的密钥
+            assert_eq!(verify(&mut session, known_hosts, mock::HOST_CERT).unwrap_err().kind(), &revoked(mock::HOST_KEY));
+            // 签发证书的 CA
+            session.set_revoked_keys(vec![mock::blob(mock::CA_KEY)]);
+            assert_eq!(verify(&mut session, known_hosts, mock::HOST_CERT).unwrap_err().kind(), &revoked(mock::CA_KEY));
+            // 证书本身
+            session.set_revoked_keys(vec![mock::blob(mock::HOST_CERT)]);
+            assert_eq!(verify(&mut session, known_hosts, mock::HOST_CERT).unwrap_err().kind(), &revoked(mock::HOST_CERT));
+        }
+    }
+
+    #[test]
+    fn certificate_revoked_in_known_hosts() {
+        let ca = format!("@cert-authority * ssh-ed25519 {}\n", mock::CA_KEY);
+        let key = format!("{}@revoked example.com ssh-ed25519 {}", ca, mock::HOST_KEY);
+        assert_eq!(verify(&mut Session::new(), Some(&key), mock::HOST_CERT).unwrap_err().kind(), &revoked(mock::HOST_KEY));
+        let ca_revoked = format!("{}@revoked * ssh-ed25519 {}", ca, mock::CA_KEY);
+        assert_eq!(verify(&mut Session::new(), Some(&ca_revoked), mock::HOST_CERT).unwrap_err().kind(), &revoked(mock::CA_KEY));
+        // CA 可信时仍然校验证书的有效期
+        assert!(matches!(verify(&mut Session::new(), Some(&ca), mock::EXPIRED_HOST_CERT).unwrap_err().kind(),
+                         SshErrorKind::HostCertificateExpired { .. }));
+    }
+
+    /// 服务端依次发送 messages 中的消息，返回认证的结果和 client 发送的消息编号
+    fn authenticate(user_info: UserInfo, messages: &[u8]) -> (SshResult<()>, Vec<u8>) {
+        let (mut client, server) = mock::client();
+        client.config = Config::new(user_info);
+        for message_code in messages {
+            let mut data = Data::new();
+            data.put_u8(*message_code);
+            if *message_code == ssh_msg_code::SSH_MSG_SERVICE_ACCEPT {
+                data.put_str(ssh_str::SSH_USERAUTH);
+            }
+            server.send(data)
+        }
+        let result = Session::new().authentication(&mut client);
+        let sent = server.received().iter().filter_map(|v| v.first().copied()).collect();
+        (result, sent)
+    }
+
+    fn is_protocol_violation(result: SshResult<()>) -> bool {
+        match result {
+            Err(e) => matches!(e.kind(), SshErrorKind::ProtocolViolation { .. }),
+            Ok(_) => false
+        }
+    }
+
+    fn password() -> UserInfo {
+        UserInfo::from_password("user", "password")
+    }
+
+    #[test]
+    fn password_success() {
+        let (result, sent) = authenticate(password(),
+                                          &[ssh_msg_code::SSH_MSG_SERVICE_ACCEPT, ssh_msg_code::SSH_MSG_USERAUTH_SUCCESS]);
+        assert!(result.is_ok());
+        assert_eq!(sent, [ssh_msg_code::SSH_MSG_USERAUTH_REQUEST]);
+    }
+
+    #[test]
+    fn success_before_service_accept_is_a_protocol_violation() {
+        let (result, sent) = authenticate(password(), &[ssh_msg_code::SSH_MSG_USERAUTH_SUCCESS]);
+        assert!(is_protocol_violation(result));
+        assert_eq!(sent, [ssh_msg_code::SSH_MSG_DISCONNECT]);
+    }
+
+    #[test]
+    fn success_for_public_key_query_is_a_protocol_violation() {
+        // 只发送了不带签名的公钥查询，服务端不应该直接认证成功
+        let user_info = UserInfo::from_key_pair("user", KeyPair::new());
+        let (result, sent) = authenticate(user_info,
+                                          &[ssh_msg_code::SSH_MSG_SERVICE_ACCEPT, ssh_msg_code::SSH_MSG_USERAUTH_SUCCESS]);
+        assert!(is_protocol_violation(result));
+        assert_eq!(sent, [ssh_msg_code::SSH_MSG_USERAUTH_REQUEST, ssh_msg_code::SSH_MSG_DISCONNECT]);
+    }
+
+    #[test]
+    fn failure_before_request_is_a_protocol_violation() {
+        let (result, _) = authenticate(password(), &[ssh_msg_code::SSH_MSG_USERAUTH_FAILURE]);
+        assert!(is_protocol_violation(result));
+    }
+
+    #[test]
+    fn unknown_host_key_is_rejected_by_default() {
+        let mut key = Data::new();
+        key.put_str(algorithms::PUBLIC_KEY_ED25519).put_u8s(&[7; 32]);
+        let known_hosts = KnownHosts::new();
+        let path = Path::new("known_hosts");
+        let mut session = Session::new();
+        assert_eq!(session.host_key_policy, HostKeyPolicy::Strict);
+        let err = session.check_host_key(&known_hosts, path, "example.com", 22, &key).err().unwrap();
+        assert!(matches!(err.kind(), SshErrorKind::HostKeyUnknown { .. }));
+
+        session.set_host_key_policy(HostKeyPolicy::AcceptAll);
+        assert!(!session.check_host_key(&known_hosts, path, "example.com", 22, &key).unwrap());
+    }
+
+    #[test]
+    fn failure_names_the_method_tried() {
+        let messages = [ssh_msg_code::SSH_MSG_SERVICE_ACCEPT, ssh_msg_code::SSH_MSG_USERAUTH_FAILURE];
+        let (result, _) = authenticate(password(), &messages);
+        assert_eq!(result.unwrap_err().to_string(), "user auth failure, auth type is password.");
+        let user_info = UserInfo::from_key_pair("user", KeyPair::new());
+        let (result, _) = authenticate(user_info, &messages);
+        assert_eq!(result.unwrap_err().to_string(), "user auth failure, auth type is publickey.");
+    }
+
+    /// 服务端执行一条命令：打开通道、回应 exec 请求、发送输出和退出状态后关闭通道
+    /// exit 为 exit-status 或 exit-signal 请求中 want reply 之后的数据
+    fn serve_exec(server: &mock::MockServer, exit_request: &str, exit: Data) {
+        let mut open = server.recv();
+        assert_eq!(open.get_u8(), ssh_msg_code::SSH_MSG_CHANNEL_OPEN);
+        open.get_u8s();
+        let client_channel = open.get_u32();
+        let mut confirmation = Data::new();
+        confirmation.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_OPEN_CONFIRMATION)
+            .put_u32(client_channel)
+            .put_u32(9)
+            .put_u32(1 << 20)
+            .put_u32(32768);
+        server.send(confirmation);
+        assert_eq!(server.recv().first(), Some(&ssh_msg_code::SSH_MSG_CHANNEL_REQUEST));
+        let mut stdout = Data::new();
+        stdout.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA).put_u32(client_channel).put_str("out");
+        server.send(stdout);
+        let mut stderr = Data::new();
+        stderr.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_EXTENDED_DATA).put_u32(client_channel).put_u32(1).put_str("err");
+        server.send(stderr);
+        let mut request = Data::new();
+        request.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
+            .put_u32(client_channel)
+            .put_str(exit_request)
+            .put_u8(false as u8);
+        request.extend(exit.to_vec());
+        server.send(request);
+        for message_code in [ssh_msg_code::SSH_MSG_CHANNEL_EOF, ssh_msg_code::SSH_MSG_CHANNEL_CLOSE] {
+            let mut data = Data::new();
+            data.put_u8(message_code).put_u32(client_channel);
+            server.send(data);
+        }
+        assert_eq!(server.recv().first(), Some(&ssh_msg_code::SSH_MSG_CHANNEL_CLOSE));
+    }
+
+    #[test]
+    fn exec_can_be_called_repeatedly() {
+        let (client, server) = mock::client();
+        let mut session = Session::new();
+        session.client = Some(Arc::new(Mutex::new(client)));
+        let handle = {
+            let server = server.clone();
+            thread::spawn(move || {
+                let mut status = Data::new();
+                status.put_u32(1);
+                serve_exec(&server, ssh_str::EXIT_STATUS, status);
+                let mut signal = Data::new();
+                signal.put_str("KILL").put_u8(false as u8).put_str("killed").put_str("");
+                serve_exec(&server, ssh_str::EXIT_SIGNAL, signal);
+            })
+        };
+        let result = session.exec("false").unwrap();
+        assert_eq!(result.stdout, b"out");
+        assert_eq!(result.stderr, b"err");
+        assert_eq!(result.exit_status, Some(1));
+        assert_eq!(result.exit_signal, None);
+        assert!(!result.success());
+        let result = session.exec("sleep 100").unwrap();
+        assert_eq!(result.exit_status, None);
+        assert_eq!(result.exit_signal.unwrap().signal, "KILL");
+        handle.join().unwrap();
+        assert!(session.client().unwrap().channels.is_empty());
+    }
+
+    #[test]
+    fn via_runs_the_handshake_over_direct_tcpip() {
+        let (jump_io, jump_server) = mock::pipe();
+        let (mut tunnel, target_server) = mock::pipe();
+        let stop = Arc::new(AtomicBool::new(false));
+        let target = thread::spawn(move || {
+            let mut peer = Peer::new(target_server, 2);
+            assert!(peer.handshake());
+            peer.accept();
+            peer.host_key()
+        });
+        let jump = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut peer = Peer::new(jump_server, 1);
+                assert!(peer.handshake());
+                peer.accept();
+                let mut open = peer.recv_message(ssh_msg_code::SSH_MSG_CHANNEL_OPEN);
+                open.get_u8();
+                assert_eq!(open.get_u8s(), ssh_str::DIRECT_TCPIP.as_bytes());
+                let client_channel = open.get_u32();
+                open.get_u32();
+                open.get_u32();
+                let host = String::from_utf8(open.get_u8s()).unwrap();
+                let port = open.get_u32();
+                let mut confirmation = Data::new();
+                confirmation.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_OPEN_CONFIRMATION)
+                    .put_u32(client_channel)
+                    .put_u32(9)
+                    .put_u32(1 << 20)
+                    .put_u32(32768);
+                peer.send(confirmation);
+                // 在 direct-tcpip 通道和目标主机之间转发数据
+                let mut buf = [0; 16384];
+                while !stop.load(Ordering::Relaxed) {
+                    while let Some(mut data) = peer.try_recv() {
+                        if data.get_u8() == ssh_msg_code::SSH_MSG_CHANNEL_DATA {
+                            data.get_u32();
+                            tunnel.write_all(&data.get_u8s()).unwrap();
+                        }
+                    }
+                    match tunnel.read(&mut buf) {
+                        Ok(len) => {
+                            let mut data = Data::new();
+                            data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
+                                .put_u32(client_channel)
+                                .put_u8s(&buf[..len]);
+                            peer.send(data)
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(1))
+                    }
+                }
+                (host, port, peer.host_key())
+            })
+        };
+        let mut jump_session = Session::new();
+        jump_session.set_user_and_password("jump", "password");
+        jump_session.connect_io(jump_io).unwrap();
+        let mut session = Session::new();
+        session.set_user_and_password("target", "password");
+        session.via(&mut jump_session, "10.0.0.2", 2222).unwrap();
+        let target_key = target.join().unwrap();
+        stop.store(true, Ordering::Relaxed);
+        let (host, port, jump_key) = jump.join().unwrap();
+        assert_eq!((host.as_str(), port), ("10.0.0.2", 2222));
+        assert_eq!(session.connect_host, Some(("10.0.0.2".to_string(), 2222)));
+        assert_eq!(session.client().unwrap().host_key, Some(target_key));
+        assert_eq!(jump_session.client().unwrap().host_key, Some(jump_key));
+    }
+}
@@ -0,0 +1,304 @@
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use crate::channel_direct_tcpip::ChannelDirectTcpip;
+use crate::channel_exec::ExecResult;
+use crate::error::{SshError, SshResult};
+use crate::session::{ConnectAddr, Session};
+
+
+/// 异步使用的 session，不依赖具体的运行时
+/// 密钥交换、认证和打开通道等操作在单独的线程中执行，完成之后唤醒等待的任务，不会阻塞运行时的线程
+/// 同一个 session 的操作依次执行
+pub struct AsyncSession {
+    session: Arc<Mutex<Session>>
+}
+
+impl AsyncSession {
+    /// 使用非阻塞模式连接，之后的读写见 `AsyncDirectTcpip`
+    pub async fn connect<A>(mut session: Session, addr: A) -> SshResult<AsyncSession>
+    where
+        A: ConnectAddr + Send + 'static
+    {
+        session.set_nonblocking(true);
+        let session = blocking(move || {
+            session.connect(addr)?;
+            Ok(session)
+        }).await?;
+        Ok(AsyncSession::from(session))
+    }
+
+    /// 在单独的线程中使用 session 执行 f
+    /// 返回的 future 被丢弃时 f 仍会执行完
+    pub fn run<F, T>(&self, f: F) -> impl Future<Output = SshResult<T>>
+    where
+        F: FnOnce(&mut Session) -> SshResult<T> + Send + 'static,
+        T: Send + 'static
+    {
+        let session = self.session.clone();
+        blocking(move || {
+            let mut session = session.lock()
+                .map_err(|_| SshError::from("session is poisoned."))?;
+            f(&mut session)
+        })
+    }
+
+    /// 见 `Session::exec`
+    pub async fn exec(&self, command: &str) -> SshResult<ExecResult> {
+        let command = command.to_string();
+        self.run(move |session| session.exec(&command)).await
+    }
+
+    /// 见 `Session::open_direct_tcpip`
+    pub async fn open_direct_tcpip(&self, host: &str, port: u32) -> SshResult<AsyncDirectTcpip> {
+        let host = host.to_string();
+        let channel = self.run(move |session| session.open_direct_tcpip(&host, port)).await?;
+        Ok(AsyncDirectTcpip { channel: Some(channel) })
+    }
+
+    /// 等待之前的操作完成之后关闭 session
+    pub async fn close(self) -> SshResult<()> {
+        blocking(move || {
+            let session = self.session.lock()
+                .map_err(|_| SshError::from("session is poisoned."))?;
+            drop(session);
+            match Arc::try_unwrap(self.session) {
+                Ok(session) => session.into_inner()
+                    .map_err(|_| SshError::from("session is poisoned."))?
+                    .close(),
+                Err(_) => Err(SshError::from("session is still in use."))
+            }
+        }).await
+    }
+}
+
+impl From<Session> for AsyncSession {
+    /// session 需要已经设置为非阻塞模式，见 `Session::set_nonblocking`
+    fn from(session: Session) -> Self {
+        AsyncSession {
+            session: Arc::new(Mutex::new(session))
+        }
+    }
+}
+
+
+/// 异步读写的 direct-tcpip 通道
+/// `poll_read`、`poll_write`、`poll_flush` 和 `poll_close` 与 `AsyncRead`/`AsyncWrite` 的签名相同，
+/// 没有数据可读时唤醒任务后返回 `Poll::Pending`，下一次 poll 时再读取连接
+pub struct AsyncDirectTcpip {
+    channel: Option<ChannelDirectTcpip>
+}
+
+impl AsyncDirectTcpip {
+    /// 客户端通道编号，见 `ChannelDirectTcpip::id`
+    pub fn id(&self) -> Option<u32> {
+        self.channel.as_ref().map(|v| v.id())
+    }
+
+    /// 服务端发送 EOF 或关闭通道之后返回 0
+    pub fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let channel = match &mut self.get_mut().channel {
+            Some(v) => v,
+            None => return Poll::Ready(Ok(0))
+        };
+        match Read::read(channel, buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            v => Poll::Ready(v)
+        }
+    }
+
+    /// 远程窗口用完时在当前线程等待服务端调整窗口
+    pub fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().channel {
+            Some(channel) => Poll::Ready(Write::write(channel, buf)),
+            None => Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe)))
+        }
+    }
+
+    /// 写入时已经发送，不需要 flush
+    pub fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// 关闭通道，之后的读取返回 0，写入返回 `BrokenPipe`
+    pub fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut().channel.take() {
+            Some(channel) => Poll::Ready(channel.close().map(|_| ()).map_err(crate::error::to_io_error)),
+            None => Poll::Ready(Ok(()))
+        }
+    }
+
+    /// 见 `poll_read`
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_read(cx, buf)).await
+    }
+
+    /// 见 `poll_write`
+    pub async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match &mut self.channel {
+            Some(channel) => channel.write_all(buf),
+            None => Err(io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+    }
+
+    /// 见 `poll_close`
+    pub async fn close(&mut self) -> io::Result<()> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_close(cx)).await
+    }
+}
+
+
+/// 线程中执行的操作的结果，和还没有完成时等待的任务
+struct Shared<T> {
+    result: Option<SshResult<T>>,
+    waker: Option<Waker>
+}
+
+/// 在单独的线程中执行 f 的 future
+struct Blocking<T> {
+    shared: Arc<Mutex<Shared<T>>>
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = SshResult<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn blocking<F, T>(f: F) -> Blocking<T>
+where
+    F: FnOnce() -> SshResult<T> + Send + 'static,
+    T: Send + 'static
+{
+    let shared = Arc::new(Mutex::new(Shared { result: None, waker: None }));
+    let done = shared.clone();
+    thread::spawn(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(f))
+            .unwrap_or_else(|_| Err(SshError::from("async session operation panicked.")));
+        let mut shared = done.lock().unwrap();
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake()
+        }
+    });
+    Blocking { shared }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread;
+    use crate::constant::{ssh_msg_code, ssh_str};
+    use crate::data::Data;
+    use crate::mock::{self, Peer};
+    use crate::session::Session;
+    use super::AsyncSession;
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark()
+        }
+    }
+
+    /// 测试使用的最简单的执行器，没有完成时等待唤醒
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            if let Poll::Ready(v) = future.as_mut().poll(&mut cx) {
+                return v
+            }
+            thread::park()
+        }
+    }
+
+    #[test]
+    fn run_wakes_the_task_when_done() {
+        let session = AsyncSession::from(Session::new());
+        let (tx, rx) = mpsc::channel::<u32>();
+        let mut future = pin!(session.run(move |_| Ok(rx.recv().unwrap())));
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        assert!(future.as_mut().poll(&mut Context::from_waker(&waker)).is_pending());
+        tx.send(7).unwrap();
+        assert_eq!(block_on(future).unwrap(), 7);
+    }
+
+    #[test]
+    fn panic_in_run_is_an_error() {
+        let session = AsyncSession::from(Session::new());
+        assert!(block_on(session.run(|_| -> crate::SshResult<()> { panic!("boom") })).is_err());
+        // session 可能只修改了一部分，之后的操作也返回错误
+        assert!(block_on(session.run(|_| Ok(1))).is_err());
+    }
+
+    #[test]
+    fn direct_tcpip_over_async_session() {
+        let (io, server) = mock::pipe();
+        let handle = thread::spawn(move || {
+            let mut peer = Peer::new(server, 1);
+            assert!(peer.handshake());
+            peer.accept();
+            let mut open = peer.recv_message(ssh_msg_code::SSH_MSG_CHANNEL_OPEN);
+            open.get_u8();
+            assert_eq!(open.get_u8s(), ssh_str::DIRECT_TCPIP.as_bytes());
+            let client_channel = open.get_u32();
+            let mut confirmation = Data::new();
+            confirmation.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_OPEN_CONFIRMATION)
+                .put_u32(client_channel)
+                .put_u32(5)
+                .put_u32(1 << 20)
+                .put_u32(32768);
+            peer.send(confirmation);
+            let mut data = peer.recv_message(ssh_msg_code::SSH_MSG_CHANNEL_DATA);
+            data.get_u8();
+            data.get_u32();
+            let mut reply = Data::new();
+            reply.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
+                .put_u32(client_channel)
+                .put_u8s(&[b"re: ".as_slice(), &data.get_u8s()].concat());
+            peer.send(reply);
+            peer.recv_message(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE);
+            let mut close = Data::new();
+            close.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_CLOSE)
+                .put_u32(client_channel);
+            peer.send(close);
+        });
+        let mut session = Session::new();
+        session.set_user_and_password("user", "password");
+        let session = AsyncSession::from(session);
+        block_on(session.run(move |session| session.connect_io(io))).unwrap();
+        let mut tcpip = block_on(session.open_direct_tcpip("10.0.0.2", 80)).unwrap();
+        block_on(tcpip.write_all(b"ping")).unwrap();
+        let mut buf = [0; 16];
+        let len = block_on(tcpip.read(&mut buf)).unwrap();
+        assert_eq!(&buf[..len], b"re: ping");
+        block_on(tcpip.close()).unwrap();
+        assert_eq!(block_on(tcpip.read(&mut buf)).unwrap(), 0);
+        handle.join().unwrap();
+        block_on(session.close()).unwrap();
+    }
+}
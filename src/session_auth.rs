@@ -1,22 +1,23 @@
 use std::path::Path;
-use crate::{client, config, Session, SshResult};
-use crate::constant::{ssh_msg_code, ssh_str};
+use crate::{Session, SshError, SshResult};
+use crate::client::Client;
+use crate::constant::{algorithms, ssh_msg_code, ssh_str};
 use crate::data::Data;
 use crate::key_pair::{KeyPair, KeyPairType};
 use crate::user_info::UserInfo;
 
 impl Session {
 
-    pub fn auth_user_info(&self, user_info: UserInfo) {
-        config::init(user_info);
+    pub fn auth_user_info(&mut self, user_info: UserInfo) {
+        self.user_info = Some(user_info)
     }
 
-    pub fn set_user_and_password<U: ToString, P: ToString>(&self, username: U, password: P) {
+    pub fn set_user_and_password<U: ToString, P: ToString>(&mut self, username: U, password: P) {
         let user_info = UserInfo::from_password(username.to_string(), password.to_string());
         self.auth_user_info(user_info);
     }
 
-    pub fn set_user_and_key_pair<U: ToString, K: ToString>(&self, username: U, key_str: K, key_type: KeyPairType) -> SshResult<()> {
+    pub fn set_user_and_key_pair<U: ToString, K: ToString>(&mut self, username: U, key_str: K, key_type: KeyPairType) -> SshResult<()> {
         let pair = KeyPair::from_str(key_str.to_string().as_str(), key_type)?;
         let user_info = UserInfo::from_key_pair(username, pair);
         self.auth_user_info(user_info);
@@ -24,16 +25,16 @@ impl Session {
     }
 
     pub fn set_user_and_key_pair_path<U: ToString, P: AsRef<Path>>
-    (&self, username: U, key_path: P, key_type: KeyPairType) -> SshResult<()> {
+    (&mut self, username: U, key_path: P, key_type: KeyPairType) -> SshResult<()> {
         let pair = KeyPair::from_path(key_path, key_type)?;
         let user_info = UserInfo::from_key_pair(username.to_string(), pair);
         self.auth_user_info(user_info);
         Ok(())
     }
 
-    pub(crate) fn password_authentication(&self) -> SshResult<()> {
+    pub(crate) fn password_authentication(&self, client: &mut Client) -> SshResult<()> {
         log::info!("password authentication.");
-        let config = config::config();
+        let config = &client.config;
         let mut data = Data::new();
         data.put_u8(ssh_msg_code::SSH_MSG_USERAUTH_REQUEST)
             .put_str(config.auth.username.as_str())
@@ -41,39 +42,82 @@ impl Session {
             .put_str(ssh_str::PASSWORD)
             .put_u8(false as u8)
             .put_str(config.auth.password.as_str());
-        let client = client::default()?;
         client.write(data)
     }
 
-    pub(crate) fn public_key_authentication(&self) -> SshResult<()> {
+    /*
+        byte      SSH_MSG_USERAUTH_REQUEST
+        string    user name
+        string    service name
+        string    "password"
+        boolean   TRUE
+        string    plaintext old password in ISO-10646 UTF-8 encoding [RFC3629]
+        string    plaintext new password in ISO-10646 UTF-8 encoding [RFC3629]
+    */
+    /// 服务端要求修改已过期的密码，使用旧密码和新密码重新认证
+    pub(crate) fn password_change(&self, client: &mut Client, new_password: &str) -> SshResult<()> {
+        log::info!("password change request.");
+        let config = &client.config;
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_USERAUTH_REQUEST)
+            .put_str(config.auth.username.as_str())
+            .put_str(ssh_str::SSH_CONNECTION)
+            .put_str(ssh_str::PASSWORD)
+            .put_u8(true as u8)
+            .put_str(config.auth.password.as_str())
+            .put_str(new_password);
+        client.write(data)
+    }
+
+    pub(crate) fn public_key_authentication(&self, client: &mut Client) -> SshResult<()> {
         log::info!("public key authentication.");
 
-        let config = config::config();
+        let config = &client.config;
         let mut data = Data::new();
         data.put_u8(ssh_msg_code::SSH_MSG_USERAUTH_REQUEST)
             .put_str(config.auth.username.as_str())
             .put_str(ssh_str::SSH_CONNECTION)
             .put_str(ssh_str::PUBLIC_KEY)
             .put_u8(false as u8)
-            .put_str(config.auth.key_pair.key_type.as_str())
+            .put_str(signature_algorithm(client, &config.auth.key_pair.key_type))
             .put_u8s(config.auth.key_pair.blob.as_slice());
-        let client = client::default()?;
         client.write(data)
     }
 
-    pub(crate) fn public_key_signature(&self) -> SshResult<()> {
-        let config = config::config();
+    pub(crate) fn public_key_signature(&self, client: &mut Client) -> SshResult<()> {
+        let config = &client.config;
+        let algorithm = signature_algorithm(client, &config.auth.key_pair.key_type);
         let mut data = Data::new();
         data.put_u8(ssh_msg_code::SSH_MSG_USERAUTH_REQUEST)
             .put_str(config.auth.username.as_str())
             .put_str(ssh_str::SSH_CONNECTION)
             .put_str(ssh_str::PUBLIC_KEY)
             .put_u8(true as u8)
-            .put_str(config.auth.key_pair.key_type.as_str())
+            .put_str(algorithm)
             .put_u8s(config.auth.key_pair.blob.as_slice());
-        let signature = config.auth.key_pair.signature(data.as_slice());
+        let session_id = match &client.session_id {
+            Some(v) => v,
+            None => return Err(SshError::from("session id not generated."))
+        };
+        let signature = config.auth.key_pair.signature(session_id, data.as_slice(), algorithm);
         data.put_u8s(&signature);
-        let client = client::default()?;
         client.write(data)
     }
 }
+
+
+/// 密钥的签名算法，ssh-rsa 密钥按服务端 server-sig-algs 中的 rsa-sha2-512、rsa-sha2-256 的顺序选择
+/// 服务端没有发送 ext-info 时不知道它支持哪些算法，和 OpenSSH 一样使用 ssh-rsa (sha1)
+fn signature_algorithm<'a>(client: &Client, key_type: &'a str) -> &'a str {
+    if key_type != algorithms::PUBLIC_KEY_RSA {
+        return key_type
+    }
+    let server_sig_algs = match &client.server_sig_algs {
+        Some(v) => v,
+        None => return algorithms::PUBLIC_KEY_RSA
+    };
+    [algorithms::PUBLIC_KEY_RSA_SHA2_512, algorithms::PUBLIC_KEY_RSA_SHA2_256]
+        .into_iter()
+        .find(|v| server_sig_algs.iter().any(|s| s == v))
+        .unwrap_or(algorithms::PUBLIC_KEY_RSA)
+}
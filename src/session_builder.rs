@@ -0,0 +1,310 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::error::{SshError, SshResult};
+use crate::key_pair::{KeyPair, KeyPairType};
+use crate::{Session, UserInfo};
+use crate::session::{to_names, BannerCallback, PasswordChangeCallback};
+
+
+/// 默认端口
+const DEFAULT_PORT: u16 = 22;
+
+
+enum PrivateKey {
+    Str(String),
+    Path(PathBuf)
+}
+
+
+/// Session 构建器
+/// 所有配置在 `connect` 时才生效，`connect` 一次完成 TCP 连接、握手和认证
+pub struct SessionBuilder {
+    host: Option<String>,
+    port: u16,
+    username: Option<String>,
+    password: Option<String>,
+    private_key: Option<(PrivateKey, KeyPairType)>,
+    timeout: Option<Duration>,
+    known_hosts: Option<PathBuf>,
+    nonblocking: bool,
+    poll_interval: Option<Duration>,
+    rekey_limit: Option<(u64, Option<Duration>)>,
+    window_size: Option<u32>,
+    max_packet_size: Option<u32>,
+    banner_callback: Option<BannerCallback>,
+    password_change_callback: Option<PasswordChangeCallback>,
+    env: Vec<(String, String)>,
+    agent_forwarding: bool,
+    preferred_ciphers: Option<Vec<String>>,
+    preferred_kex: Option<Vec<String>>,
+    preferred_macs: Option<Vec<String>>
+}
+
+impl SessionBuilder {
+    pub fn new() -> Self {
+        SessionBuilder {
+            host: None,
+            port: DEFAULT_PORT,
+            username: None,
+            password: None,
+            private_key: None,
+            timeout: None,
+            known_hosts: None,
+            nonblocking: true,
+            poll_interval: None,
+            rekey_limit: None,
+            window_size: None,
+            max_packet_size: None,
+            banner_callback: None,
+            password_change_callback: None,
+            env: vec![],
+            agent_forwarding: false,
+            preferred_ciphers: None,
+            preferred_kex: None,
+            preferred_macs: None
+        }
+    }
+
+    pub fn host<S: ToString>(mut self, host: S) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    /// 默认 22
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn username<S: ToString>(mut self, username: S) -> Self {
+        self.username = Some(username.to_string());
+        self
+    }
+
+    pub fn password<S: ToString>(mut self, password: S) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// 私钥字符串，同时设置了密码时使用私钥认证
+    pub fn private_key<S: ToString>(mut self, key: S, key_type: KeyPairType) -> Self {
+        self.private_key = Some((PrivateKey::Str(key.to_string()), key_type));
+        self
+    }
+
+    /// 私钥文件路径，同时设置了密码时使用私钥认证
+    pub fn private_key_path<P: AsRef<Path>>(mut self, path: P, key_type: KeyPairType) -> Self {
+        self.private_key = Some((PrivateKey::Path(path.as_ref().to_path_buf()), key_type));
+        self
+    }
+
+    /// TCP 连接和读取数据的超时时间
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// 使用 known_hosts 校验主机密钥，查找时使用 host 设置的主机名
+    pub fn known_hosts<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.known_hosts = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// TcpStream 是否使用非阻塞模式，默认 true，见 `Session::set_nonblocking`
+    pub fn nonblocking(mut self, nonblocking: bool) -> Self {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// 没有数据时两次读取之间等待的时间，见 `Session::set_poll_interval`
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = Some(interval);
+        self
+    }
+
+    /// 重新协商密钥的字节数和时间限制，见 `Session::set_rekey_limit`
+    pub fn rekey_limit(mut self, bytes: u64, interval: Option<Duration>) -> Self {
+        self.rekey_limit = Some((bytes, interval));
+        self
+    }
+
+    /// 通道的本地窗口大小，见 `Session::set_window_size`
+    pub fn window_size(mut self, size: u32) -> Self {
+        self.window_size = Some(size);
+        self
+    }
+
+    /// 通道的最大数据包大小，见 `Session::set_max_packet_size`
+    pub fn max_packet_size(mut self, size: u32) -> Self {
+        self.max_packet_size = Some(size);
+        self
+    }
+
+    /// 认证横幅回调，见 `Session::set_banner_callback`
+    pub fn banner_callback<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&str) + Send + 'static
+    {
+        self.banner_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// 修改过期密码的回调，见 `Session::set_password_change_callback`
+    pub fn password_change_callback<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&str) -> Option<String> + Send + 'static
+    {
+        self.password_change_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// exec 和 shell 通道的环境变量，可以多次调用，见 `Session::set_env`
+    pub fn env<N: ToString, V: ToString>(mut self, name: N, value: V) -> Self {
+        self.env.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// 在 exec 和 shell 通道上请求 agent 转发，见 `Session::enable_agent_forwarding`
+    pub fn agent_forwarding(mut self, b: bool) -> Self {
+        self.agent_forwarding = b;
+        self
+    }
+
+    /// 只协商给定的加密算法，见 `Session::set_preferred_ciphers`
+    pub fn preferred_ciphers<S: AsRef<str>>(mut self, ciphers: &[S]) -> Self {
+        self.preferred_ciphers = Some(to_names(ciphers));
+        self
+    }
+
+    /// 只协商给定的密钥交换算法，见 `Session::set_preferred_kex`
+    pub fn preferred_kex<S: AsRef<str>>(mut self, kex: &[S]) -> Self {
+        self.preferred_kex = Some(to_names(kex));
+        self
+    }
+
+    /// 只协商给定的 mac 算法，见 `Session::set_preferred_macs`
+    pub fn preferred_macs<S: AsRef<str>>(mut self, macs: &[S]) -> Self {
+        self.preferred_macs = Some(to_names(macs));
+        self
+    }
+
+    pub fn connect(self) -> SshResult<Session> {
+        let port = self.port;
+        let timeout = self.timeout;
+        let (mut session, host) = self.build()?;
+        log::info!("connect to [{}:{}].", host, port);
+        match timeout {
+            Some(timeout) => session.connect_with_timeout((host.as_str(), port), timeout)?,
+            None => session.connect((host.as_str(), port))?
+        }
+        Ok(session)
+    }
+
+    /// 还没有连接的 Session，全部配置都保存在这个 Session 中，返回的主机名用于连接
+    fn build(self) -> SshResult<(Session, String)> {
+        let host = match self.host {
+            Some(v) => v,
+            None => return Err(SshError::from("host is required."))
+        };
+        let username = match self.username {
+            Some(v) => v,
+            None => return Err(SshError::from("username is required."))
+        };
+        let user_info = match (self.private_key, self.password) {
+            (Some((PrivateKey::Str(key), key_type)), _) => {
+                UserInfo::from_key_pair(username, KeyPair::from_str(&key, key_type)?)
+            }
+            (Some((PrivateKey::Path(path), key_type)), _) => {
+                UserInfo::from_key_pair(username, KeyPair::from_path(path, key_type)?)
+            }
+            (None, Some(password)) => UserInfo::from_password(username, password),
+            (None, None) => return Err(SshError::from("password or private key is required."))
+        };
+        let mut session = Session::new();
+        session.user_info = Some(user_info);
+        if let Some(timeout) = self.timeout {
+            session.timeout = timeout;
+        }
+        session.preferred_ciphers = self.preferred_ciphers;
+        session.preferred_kex = self.preferred_kex;
+        session.preferred_macs = self.preferred_macs;
+        if let Some(path) = self.known_hosts {
+            session.set_known_hosts(path);
+        }
+        session.set_host_name(host.as_str());
+        session.set_nonblocking(self.nonblocking);
+        if let Some(interval) = self.poll_interval {
+            session.set_poll_interval(interval);
+        }
+        if let Some((bytes, interval)) = self.rekey_limit {
+            session.set_rekey_limit(bytes, interval);
+        }
+        // 两个值一起校验，与设置的顺序无关
+        let window_size = self.window_size.unwrap_or(session.window_size);
+        let max_packet_size = self.max_packet_size.unwrap_or(session.max_packet_size);
+        session.set_channel_sizes(window_size, max_packet_size)?;
+        session.banner_callback = self.banner_callback;
+        session.password_change_callback = self.password_change_callback;
+        for (name, value) in self.env {
+            session.set_env(name, value);
+        }
+        session.enable_agent_forwarding(self.agent_forwarding);
+        Ok((session, host))
+    }
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        SessionBuilder::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::constant::algorithms;
+    use crate::user_info::AuthType;
+    use super::SessionBuilder;
+
+    #[test]
+    fn build_keeps_settings_in_session() {
+        let (first, host) = SessionBuilder::new()
+            .host("example.com")
+            .username("alice")
+            .password("secret")
+            .timeout(Duration::from_millis(1500))
+            .preferred_ciphers(&[algorithms::ENCRYPTION_AES128_CTR])
+            .build()
+            .unwrap();
+        let (second, _) = SessionBuilder::new()
+            .host("example.org")
+            .username("bob")
+            .password("other")
+            .build()
+            .unwrap();
+        assert_eq!(host, "example.com");
+        assert_eq!(first.host_name.as_deref(), Some("example.com"));
+
+        let user_info = first.user_info.as_ref().unwrap();
+        assert_eq!(user_info.username, "alice");
+        assert_eq!(user_info.password, "secret");
+        assert!(matches!(user_info.auth_type, AuthType::Password));
+        assert_eq!(first.timeout, Duration::from_millis(1500));
+        assert_eq!(first.preferred_ciphers, Some(vec![algorithms::ENCRYPTION_AES128_CTR.to_string()]));
+
+        // 第二个会话不受第一个会话的配置影响
+        let user_info = second.user_info.as_ref().unwrap();
+        assert_eq!(user_info.username, "bob");
+        assert_eq!(user_info.password, "other");
+        assert_ne!(second.timeout, first.timeout);
+        assert_eq!(second.preferred_ciphers, None);
+    }
+
+    #[test]
+    fn build_requires_host_and_credentials() {
+        assert!(SessionBuilder::new().username("alice").password("secret").build().is_err());
+        assert!(SessionBuilder::new().host("example.com").password("secret").build().is_err());
+        assert!(SessionBuilder::new().host("example.com").username("alice").build().is_err());
+    }
+}
@@ -1,11 +1,10 @@
 
-pub use log;
-
-
 use log::{LevelFilter, Log, Metadata, Record};
 
 pub(crate) static SLOG: Slog = Slog;
 
+/// `Session::is_enable_log` 使用的简单日志记录器，输出到标准错误，不影响标准输出中的命令输出
+/// 库本身只通过 `log` 记录日志，使用 env_logger 等其它实现时不需要它
 pub struct Slog;
 
 
@@ -34,7 +33,7 @@ impl Log for Slog {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            println!("[SSH]-[{}]: {}", record.level(), record.args());
+            eprintln!("[SSH]-[{}]: {}", record.level(), record.args());
         }
     }
 
@@ -1,23 +1,36 @@
 use std::cell::RefCell;
 use std::time::{Duration, SystemTime};
-use crate::{slog::log, SshError, SshResult};
+use crate::{SshError, SshResult};
 use crate::error::SshErrorKind;
 
 
-pub(crate) static mut TIMEOUT: u64 = 30;
-
-pub(crate) struct Timeout(RefCell<SystemTime>);
+/// 每个连接各自的超时时间，见 `Session::set_timeout`
+pub(crate) struct Timeout {
+    duration: Duration,
+    deadline: RefCell<SystemTime>
+}
 
 impl Timeout {
-    pub(crate) fn new() -> Self {
-        let time = SystemTime::now();
-        let time = unsafe { time + Duration::from_secs(TIMEOUT) };
-        Timeout(RefCell::new(time))
+    pub(crate) fn new(duration: Duration) -> Self {
+        Timeout {
+            duration,
+            deadline: RefCell::new(SystemTime::now() + duration)
+        }
+    }
+
+    pub(crate) fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// 修改超时时间，从现在开始重新计时
+    pub(crate) fn set_duration(&mut self, duration: Duration) {
+        self.duration = duration;
+        self.renew()
     }
 
     pub(crate) fn is_timeout(&self) -> SshResult<()> {
         let time = SystemTime::now();
-        if time > *self.0.borrow() {
+        if time > *self.deadline.borrow() {
             log::error!("time out.");
             return Err(SshError::from(SshErrorKind::Timeout))
         }
@@ -25,7 +38,7 @@ impl Timeout {
     }
 
     pub(crate) fn renew(&self) {
-        let mut ref_mut = self.0.borrow_mut();
-        *ref_mut = unsafe { SystemTime::now() + Duration::from_secs(TIMEOUT) };
+        let mut ref_mut = self.deadline.borrow_mut();
+        *ref_mut = SystemTime::now() + self.duration;
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,176 @@
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+use crate::client::Client;
+use crate::window_size::WindowSize;
+
+
+/// scp 传输的选项
+#[derive(Debug, Clone)]
+pub struct TransferOptions {
+    stall_watchdog: Option<Duration>
+}
+
+impl TransferOptions {
+    pub fn new() -> Self {
+        TransferOptions {
+            stall_watchdog: None
+        }
+    }
+
+    /// 超过 duration 没有收发任何数据时终止传输，
+    /// 返回带有诊断快照的 `SshErrorKind::TransferStalled`，默认不检查
+    pub fn stall_watchdog(mut self, duration: Duration) -> Self {
+        self.stall_watchdog = Some(duration);
+        self
+    }
+
+    pub(crate) fn watchdog(&self) -> Option<Watchdog> {
+        self.stall_watchdog.map(Watchdog::new)
+    }
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        TransferOptions::new()
+    }
+}
+
+
+/// 传输停滞时的诊断快照
+/// Display 为多行文本，to_json 为单行 json，都可以直接附到问题报告中
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferSnapshot {
+    /// 距离上一次收发数据的时间
+    pub stalled_for: Duration,
+    /// 累计收到的数据字节数
+    pub bytes_in: u64,
+    /// 累计发送的数据字节数
+    pub bytes_out: u64,
+    /// 本地窗口大小 (服务端还可以发送的字节数)
+    pub local_window_size: u32,
+    /// 远程窗口大小 (客户端还可以发送的字节数)
+    pub remote_window_size: u32,
+    /// 本通道队列中还没有处理的消息数量
+    pub queue_depth: usize,
+    /// 其它通道队列中还没有处理的消息数量
+    pub other_queue_depth: usize,
+    /// 还没有返回给 poll_event 的事件数量
+    pub pending_events: usize,
+    /// 最近收到的消息编号，从旧到新
+    pub recent_messages: Vec<u8>,
+    /// 连接上的错误 (SO_ERROR)
+    pub socket_error: Option<String>,
+}
+
+impl TransferSnapshot {
+    pub(crate) fn capture(ws: &WindowSize, client: &mut Client, stalled_for: Duration) -> Self {
+        let queue_depth = client.channels.get(&ws.client_channel)
+            .map_or(0, |v| v.len());
+        let other_queue_depth = client.channels.iter()
+            .filter(|(cc, _)| **cc != ws.client_channel)
+            .map(|(_, v)| v.len())
+            .sum();
+        let socket_error = match client.stream.take_error() {
+            Ok(v) => v.map(|e| e.to_string()),
+            Err(e) => Some(e.to_string())
+        };
+        TransferSnapshot {
+            stalled_for,
+            bytes_in: ws.bytes_in,
+            bytes_out: ws.bytes_out,
+            local_window_size: ws.local_window_size(),
+            remote_window_size: ws.remote_window_size(),
+            queue_depth,
+            other_queue_depth,
+            pending_events: client.events.len(),
+            recent_messages: client.recent_messages.iter().copied().collect(),
+            socket_error,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let recent_messages = self.recent_messages.iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        let socket_error = match &self.socket_error {
+            Some(v) => json_string(v),
+            None => "null".to_string()
+        };
+        format!("{{\"stalled_for_ms\":{},\"bytes_in\":{},\"bytes_out\":{},\
+                 \"local_window_size\":{},\"remote_window_size\":{},\
+                 \"queue_depth\":{},\"other_queue_depth\":{},\"pending_events\":{},\
+                 \"recent_messages\":[{}],\"socket_error\":{}}}",
+                self.stalled_for.as_millis(),
+                self.bytes_in,
+                self.bytes_out,
+                self.local_window_size,
+                self.remote_window_size,
+                self.queue_depth,
+                self.other_queue_depth,
+                self.pending_events,
+                recent_messages,
+                socket_error)
+    }
+}
+
+impl Display for TransferSnapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "stalled for: {:?}", self.stalled_for)?;
+        writeln!(f, "bytes: in {} out {}", self.bytes_in, self.bytes_out)?;
+        writeln!(f, "window: local {} remote {}", self.local_window_size, self.remote_window_size)?;
+        writeln!(f, "queue: channel {} other channels {} events {}",
+                 self.queue_depth, self.other_queue_depth, self.pending_events)?;
+        writeln!(f, "recent messages: {:?}", self.recent_messages)?;
+        match &self.socket_error {
+            Some(v) => write!(f, "socket error: {}", v),
+            None => write!(f, "socket error: none")
+        }
+    }
+}
+
+fn json_string(v: &str) -> String {
+    let mut s = String::from("\"");
+    for c in v.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            c if (c as u32) < 0x20 => s.push_str(&format!("\\u{:04x}", c as u32)),
+            c => s.push(c)
+        }
+    }
+    s.push('"');
+    s
+}
+
+
+/// 记录最后一次收发数据的时间
+pub(crate) struct Watchdog {
+    limit: Duration,
+    last_progress: Instant,
+    last_bytes: u64,
+}
+
+impl Watchdog {
+    fn new(limit: Duration) -> Self {
+        Watchdog {
+            limit,
+            last_progress: Instant::now(),
+            last_bytes: 0,
+        }
+    }
+
+    /// bytes 为累计收发的字节数，超过时限没有变化时返回停滞的时长
+    pub(crate) fn check(&mut self, bytes: u64) -> Option<Duration> {
+        if bytes != self.last_bytes {
+            self.last_bytes = bytes;
+            self.last_progress = Instant::now();
+            return None
+        }
+        let elapsed = self.last_progress.elapsed();
+        if elapsed >= self.limit {
+            return Some(elapsed)
+        }
+        None
+    }
+}
@@ -0,0 +1,125 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+
+
+/// 承载 ssh 数据的字节流
+/// 默认是 TcpStream，也可以是代理、隧道等任意可读写的流
+pub(crate) trait Transport: Read + Write + Send {
+    fn shutdown(&mut self) -> io::Result<()>;
+    /// 对端地址，查找 known_hosts 时使用
+    fn peer_addr(&self) -> Option<SocketAddr>;
+    /// 连接上的错误 (SO_ERROR)，用于诊断
+    fn take_error(&self) -> io::Result<Option<io::Error>>;
+    /// TCP_NODELAY
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()>;
+    /// SO_RCVBUF
+    fn set_recv_buffer_size(&self, size: usize) -> io::Result<()>;
+    /// SO_SNDBUF
+    fn set_send_buffer_size(&self, size: usize) -> io::Result<()>;
+}
+
+impl Transport for TcpStream {
+    fn shutdown(&mut self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        TcpStream::take_error(self)
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        TcpStream::set_nodelay(self, nodelay)
+    }
+
+    #[cfg(unix)]
+    fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        set_buffer_size(self, libc::SO_RCVBUF, size)
+    }
+
+    #[cfg(unix)]
+    fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+        set_buffer_size(self, libc::SO_SNDBUF, size)
+    }
+
+    #[cfg(not(unix))]
+    fn set_recv_buffer_size(&self, _: usize) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "socket buffer size is only supported on unix"))
+    }
+
+    #[cfg(not(unix))]
+    fn set_send_buffer_size(&self, _: usize) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "socket buffer size is only supported on unix"))
+    }
+}
+
+/// std 没有提供缓冲区大小的设置，直接调用 setsockopt
+#[cfg(unix)]
+fn set_buffer_size(stream: &TcpStream, option: libc::c_int, size: usize) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let value = match libc::c_int::try_from(size) {
+        Ok(v) => v,
+        Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "socket buffer size is too large"))
+    };
+    let ret = unsafe {
+        libc::setsockopt(stream.as_raw_fd(),
+                         libc::SOL_SOCKET,
+                         option,
+                         &value as *const libc::c_int as *const libc::c_void,
+                         std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+
+/// 任意 Read + Write 的流
+/// 没有对端地址和连接错误，关闭时只 flush，socket 选项被忽略
+pub(crate) struct IoTransport<T>(pub(crate) T);
+
+impl<T: Read> Read for IoTransport<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Write> Write for IoTransport<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<T: Read + Write + Send> Transport for IoTransport<T> {
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        Ok(None)
+    }
+
+    fn set_nodelay(&self, _: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_recv_buffer_size(&self, _: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_send_buffer_size(&self, _: usize) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
+use crate::constant::ssh_str;
 use crate::key_pair::KeyPair;
 
+#[derive(Clone)]
 pub struct UserInfo {
     pub(crate) auth_type: AuthType,
     pub(crate) username: String,
@@ -30,7 +32,18 @@ impl UserInfo {
 }
 
 
+#[derive(Clone, Copy)]
 pub enum AuthType {
     Password,
     PublicKey
 }
+
+impl AuthType {
+    /// 认证请求中的方法名称
+    pub(crate) fn method(self) -> &'static str {
+        match self {
+            AuthType::Password => ssh_str::PASSWORD,
+            AuthType::PublicKey => ssh_str::PUBLIC_KEY
+        }
+    }
+}
@@ -3,7 +3,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use rand::Rng;
 use rand::rngs::OsRng;
 use crate::error::{SshError, SshResult};
-use crate::slog::log;
+use crate::limits;
 
 
 pub(crate) fn from_utf8(v: Vec<u8>) -> SshResult<String> {
@@ -30,11 +30,19 @@ pub(crate) fn sys_time_to_secs(time: SystemTime) -> SshResult<u64> {
 
 // 十六位随机数
 pub(crate) fn cookie() -> Vec<u8> {
-    let cookie: [u8; 16] = OsRng.gen();
+    let cookie: [u8; limits::KEX_COOKIE_LEN] = OsRng.gen();
     cookie.to_vec()
 }
 
 
+// 数据包的随机填充
+pub(crate) fn random_padding(len: usize) -> Vec<u8> {
+    let mut v = vec![0_u8; len];
+    OsRng.fill(v.as_mut_slice());
+    v
+}
+
+
 #[allow(dead_code)]
 pub(crate) fn vec_u8_to_string(v: Vec<u8>, pat: &str) -> SshResult<Vec<String>> {
     let result = from_utf8(v)?;
@@ -1,11 +1,10 @@
-use std::io::{Read, Write};
-use crate::algorithm::encryption;
 use crate::client::Client;
-use crate::constant::size::LOCAL_WINDOW_SIZE;
-use crate::constant::{size, ssh_msg_code};
+use crate::limits;
+use crate::constant::ssh_msg_code;
 use crate::error::SshResult;
 use crate::data::Data;
-use crate::packet::Packet;
+use crate::error::SshErrorKind;
+use crate::transfer::{TransferSnapshot, Watchdog};
 use crate::SshError;
 
 pub struct WindowSize {
@@ -18,22 +17,55 @@ pub struct WindowSize {
     /// 远程最大窗口大小
     remote_max_window_size : u32,
     /// 远程窗口大小
-    remote_window_size : u32
+    remote_window_size : u32,
+    /// 远程的最大数据包大小，为 0 时使用 MAX_PACKET_SIZE
+    remote_max_packet_size: u32,
+    /// 累计收到的数据字节数
+    pub(crate) bytes_in: u64,
+    /// 累计发送的数据字节数
+    pub(crate) bytes_out: u64,
+    /// 传输的停滞检测，等待窗口调整时也需要检查
+    pub(crate) watchdog: Option<Watchdog>
 }
 
 impl WindowSize {
 
-    pub(crate) fn new() -> Self {
+    /// 打开通道时告诉服务端的初始窗口大小，见 `Session::set_window_size`
+    pub(crate) fn with_local_window_size(size: u32) -> Self {
         WindowSize {
             server_channel: 0,
             client_channel: 0,
-            local_max_window_size: LOCAL_WINDOW_SIZE,
-            local_window_size: LOCAL_WINDOW_SIZE,
+            local_max_window_size: size,
+            local_window_size: size,
             remote_max_window_size: 0,
-            remote_window_size: 0
+            remote_window_size: 0,
+            remote_max_packet_size: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            watchdog: None
         }
     }
 
+    pub(crate) fn local_window_size(&self) -> u32 {
+        self.local_window_size
+    }
+
+    pub(crate) fn remote_window_size(&self) -> u32 {
+        self.remote_window_size
+    }
+
+    /// 开启停滞检测并且超过时限没有收发数据时返回 TransferStalled
+    pub(crate) fn check_stall(&mut self, client: &mut Client) -> SshResult<()> {
+        let bytes = self.bytes_in + self.bytes_out;
+        let stalled_for = match self.watchdog.as_mut().and_then(|v| v.check(bytes)) {
+            Some(v) => v,
+            None => return Ok(())
+        };
+        let snapshot = TransferSnapshot::capture(self, client, stalled_for);
+        log::error!("transfer stalled, diagnostic snapshot:\n{}", snapshot);
+        Err(SshError::from(SshErrorKind::TransferStalled { snapshot: Box::new(snapshot) }))
+    }
+
     fn get_size(&self, data: &[u8]) -> Option<u32> {
         let mc = &data[0];
         match *mc {
@@ -62,60 +94,56 @@ impl WindowSize {
 
 impl WindowSize {
 
-    pub fn process_remote_window_size(&mut self, data: &[u8], client: &mut Client) -> SshResult<()> {
-        if self.remote_window_size == 0 {
-            return Ok(());
-        }
-        let used = self.remote_max_window_size - self.remote_window_size;
-        let size = match self.get_size(data) {
-            None => return Ok(()),
-            Some(size) => size
+    /// 一个数据包最多可以携带的数据字节数
+    /// 不超过远程的最大数据包大小 (去掉 header_len 长度的消息头) 和当前的远程窗口
+    pub(crate) fn sendable(&self, header_len: usize) -> usize {
+        let max_packet_size = match self.remote_max_packet_size {
+            0 => limits::MAX_PACKET_SIZE,
+            v => v as usize
         };
-        self.sub_remote_window_size(size);
-        if used > 0 && self.remote_max_window_size / used <= 20 {
-            let mut result = vec![0; size::BUF_SIZE as usize];
-            loop {
-                match client.stream.read(&mut result) {
-                    Ok(len) => {
-                        result.truncate(len);
-                        break
-                    }
-                    Err(e) => {
-                        if Client::is_would_block(&e) {
-                           continue
-                        }
-                        return Err(SshError::from(e))
-                    }
-                };
-            }
+        let max_data_len = max_packet_size.saturating_sub(header_len).max(1);
+        max_data_len.min(self.remote_window_size as usize)
+    }
 
-            client.sequence.server_auto_increment();
-            let result = encryption::get()
-                .decrypt(client.sequence.server_sequence_num, &mut result)?;
-            let mut data = Packet::from(result).unpacking();
-            let mc = data.get_u8();
-            if ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST == mc {
-                // 接收方 通道编号 暂不处理
-                data.get_u32();
-                // 远程客户端调整的窗口大小
-                let size = data.get_u32();
-                self.add_remote_window_size(size);
-                return Ok(())
+    /// 远程窗口为 0 时等待服务端的 WINDOW_ADJUST
+    pub(crate) fn wait_remote_window(&mut self, client: &mut Client) -> SshResult<()> {
+        while self.remote_window_size == 0 {
+            match client.read_window_adjust(self.client_channel)? {
+                // 没有数据时读取中已经等待过 poll_interval
+                0 => self.check_stall(client)?,
+                size => {
+                    self.add_remote_window_size(size);
+                    self.add_remote_max_window_size(size);
+                }
             }
         }
-        return Ok(())
+        Ok(())
+    }
+
+    /// 发送了 size 字节的数据
+    pub(crate) fn consume_remote_window(&mut self, size: u32, client: &mut Client) {
+        self.bytes_out += size as u64;
+        if let Some(record) = client.records.get_mut(&self.client_channel) {
+            record.bytes_out += size as u64
+        }
+        self.sub_remote_window_size(size);
     }
 
     pub fn sub_remote_window_size(&mut self, rws: u32) {
-        self.remote_window_size = self.remote_window_size - rws;
+        self.remote_window_size = self.remote_window_size.saturating_sub(rws);
     }
 
+    // 窗口最大为 2^32 - 1 (RFC 4254 5.2)
     pub fn add_remote_window_size(&mut self, rws: u32) {
-        self.remote_window_size = self.remote_window_size + rws;
+        self.remote_window_size = self.remote_window_size.saturating_add(rws);
     }
 
     pub fn add_remote_max_window_size(&mut self, rws: u32) {
-        self.remote_max_window_size = self.remote_max_window_size + rws;
+        self.remote_max_window_size = self.remote_max_window_size.saturating_add(rws);
+    }
+
+    pub(crate) fn set_remote_max_packet_size(&mut self, size: u32) {
+        self.remote_max_packet_size = size;
     }
 }
 
@@ -126,40 +154,151 @@ impl WindowSize {
             None => return Ok(()),
             Some(size) => size
         };
+        self.bytes_in += size as u64;
+        if size > self.local_window_size {
+            log::warn!("server sent {} bytes beyond the channel window.", size - self.local_window_size);
+        }
         self.sub_local_window_size(size);
         let used = self.local_max_window_size - self.local_window_size;
-        if used <= 0 {
+        if used == 0 {
             return Ok(())
         }
         if (self.local_max_window_size / used) > 20 {
             return Ok(());
         }
+        // 重新协商密钥期间不能发送 WINDOW_ADJUST，留到之后收到数据时再调整
+        if client.rekeying {
+            return Ok(())
+        }
 
         let mut data = Data::new();
         data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST)
             .put_u32(self.server_channel)
             .put_u32(used);
-
-        let buf = client.get_encryption_data(data)?;
-
-        client.sequence.client_auto_increment();
-
-        if let Err(e) = client.stream.write(&buf) {
-            return Err(SshError::from(e))
-        }
-        if let Err(e) = client.stream.flush() {
-            return Err(SshError::from(e))
-        }
+        client.write(data)?;
         self.add_local_window_size(used);
-        return Ok(());
+        Ok(())
     }
 
     pub fn sub_local_window_size(&mut self, lws: u32) {
-        self.local_window_size = self.local_window_size - lws;
+        self.local_window_size = self.local_window_size.saturating_sub(lws);
     }
 
     pub fn add_local_window_size(&mut self, lws: u32) {
-        self.local_window_size = self.local_window_size + lws;
+        self.local_window_size = self.local_window_size.saturating_add(lws);
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use crate::constant::ssh_msg_code;
+    use crate::data::Data;
+    use crate::{limits, mock};
+    use super::WindowSize;
+
+    fn window() -> WindowSize {
+        let mut window = WindowSize::with_local_window_size(limits::LOCAL_WINDOW_SIZE);
+        window.client_channel = 1;
+        window.server_channel = 7;
+        window
     }
 
+    #[test]
+    fn sendable_is_limited_by_packet_size_and_window() {
+        let mut window = window();
+        assert_eq!(window.sendable(9), 0);
+        window.add_remote_window_size(u32::MAX);
+        assert_eq!(window.sendable(9), limits::MAX_PACKET_SIZE - 9);
+        window.set_remote_max_packet_size(1000);
+        assert_eq!(window.sendable(13), 987);
+        // 消息头比最大数据包还大时每个数据包至少发送 1 字节
+        window.set_remote_max_packet_size(5);
+        assert_eq!(window.sendable(9), 1);
+        window.set_remote_max_packet_size(1000);
+        window.sub_remote_window_size(u32::MAX - 100);
+        assert_eq!(window.sendable(9), 100);
+    }
+
+    #[test]
+    fn remote_window_saturates() {
+        let (mut client, _server) = mock::client();
+        client.register_channel(1);
+        let mut window = window();
+        window.add_remote_window_size(u32::MAX);
+        window.add_remote_window_size(1);
+        assert_eq!(window.remote_window_size(), u32::MAX);
+        window.consume_remote_window(u32::MAX - 10, &mut client);
+        window.consume_remote_window(100, &mut client);
+        assert_eq!(window.remote_window_size(), 0);
+        assert_eq!(window.bytes_out, u32::MAX as u64 + 90);
+        assert_eq!(client.records[&1].bytes_out, u32::MAX as u64 + 90);
+    }
+
+    #[test]
+    fn data_larger_than_remote_window_waits_for_adjust() {
+        let (mut client, server) = mock::client();
+        client.register_channel(1);
+        let mut window = window();
+        window.set_remote_max_packet_size(32768);
+        window.add_remote_window_size(1000);
+        let adjusts = server.clone();
+        let handle = thread::spawn(move || {
+            for _ in 0..2 {
+                thread::sleep(Duration::from_millis(50));
+                let mut data = Data::new();
+                data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST)
+                    .put_u32(1)
+                    .put_u32(1000);
+                adjusts.send(data);
+            }
+        });
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
+            .put_u32(7)
+            .put_u8s(&[1; 2500]);
+        client.write_data(data, Some(&mut window)).unwrap();
+        handle.join().unwrap();
+
+        let sizes = server.received().into_iter().map(|mut v| {
+            assert_eq!(v.get_u8(), ssh_msg_code::SSH_MSG_CHANNEL_DATA);
+            assert_eq!(v.get_u32(), 7);
+            v.get_u8s().len()
+        }).collect::<Vec<usize>>();
+        assert_eq!(sizes, [1000, 1000, 500]);
+        assert_eq!(window.remote_window_size(), 500);
+        assert_eq!(window.bytes_out, 2500);
+    }
+
+    #[test]
+    fn local_window_is_adjusted() {
+        let (mut client, server) = mock::client();
+        client.register_channel(1);
+        let mut window = window();
+        let total = 3 * limits::LOCAL_WINDOW_SIZE as usize;
+        let mut received = 0;
+        while received < total {
+            let mut data = Data::new();
+            data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_DATA)
+                .put_u32(1)
+                .put_u8s(&[0; 32000]);
+            server.send(data);
+            for mut v in client.read_data(&mut window).unwrap() {
+                v.get_u8();
+                v.get_u32();
+                received += v.get_u8s().len();
+            }
+        }
+        let adjusted = server.received().into_iter().map(|mut v| {
+            assert_eq!(v.get_u8(), ssh_msg_code::SSH_MSG_CHANNEL_WINDOW_ADJUST);
+            assert_eq!(v.get_u32(), 7);
+            v.get_u32() as usize
+        }).sum::<usize>();
+        assert!(adjusted > 0);
+        assert_eq!(window.local_window_size() as usize, limits::LOCAL_WINDOW_SIZE as usize - (received - adjusted));
+        assert_eq!(window.bytes_in, received as u64);
+    }
 }
@@ -0,0 +1,333 @@
+//! X11 转发 (RFC 4254 6.3)
+//!
+//! 开启后在 exec 和 shell 通道上发送 x11-req，其中是随机生成的假 cookie，
+//! 服务端把它写入远程的 xauth，远程的 X 客户端连接 DISPLAY 时服务端打开 x11 通道。
+//! 每个 x11 通道单独连接一次本地的 X server (DISPLAY)，数据的转发见 forwarded_channel。
+//!
+//! 通道的第一段数据是 X11 的连接建立请求，校验其中的假 cookie 并替换为本地 xauth 中真正的 cookie，
+//! 服务端拿不到真正的 cookie。本地没有 cookie 时 (X server 没有开启访问控制) 去掉请求中的认证信息。
+//! 没有请求过转发时服务端打开的 x11 通道会被拒绝
+
+use std::io;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::Command;
+use rand::Rng;
+use rand::rngs::OsRng;
+use crate::algorithm::backend;
+use crate::client::Client;
+use crate::constant::{ssh_msg_code, ssh_str};
+use crate::data::Data;
+use crate::error::{SshError, SshResult};
+use crate::forwarded_channel::{ForwardedChannel, LocalStream};
+
+
+/// 本地的 X server
+const DISPLAY: &str = "DISPLAY";
+
+const MIT_MAGIC_COOKIE: &str = "MIT-MAGIC-COOKIE-1";
+
+/// 假 cookie 的长度，与 MIT-MAGIC-COOKIE-1 相同
+const COOKIE_LEN: usize = 16;
+
+/// X11 连接建立请求的固定部分
+const SETUP_HEADER_LEN: usize = 12;
+
+/// DISPLAY 为 :N 时 X server 的 unix socket
+const X11_UNIX_DIR: &str = "/tmp/.X11-unix";
+
+/// DISPLAY 为 host:N 时的 tcp 端口为 6000 + N
+const X11_BASE_PORT: u16 = 6000;
+
+
+/// 本地的 X server 地址
+enum DisplayAddr {
+    Unix(PathBuf),
+    Tcp(String, u16),
+}
+
+/// 解析后的 DISPLAY
+struct Display {
+    addr: DisplayAddr,
+    screen: u32,
+}
+
+impl Display {
+    /// 支持 :N、unix:N、host:N，都可以带 .screen，
+    /// 以及 XQuartz 的 /path/to/socket:N
+    fn parse(display: &str) -> Option<Display> {
+        let (host, number) = display.rsplit_once(':')?;
+        let (number, screen) = match number.split_once('.') {
+            Some((n, s)) => (n, s.parse().ok()?),
+            None => (number, 0)
+        };
+        let number: u16 = number.parse().ok()?;
+        let addr = match host {
+            "" | "unix" => DisplayAddr::Unix(PathBuf::from(format!("{}/X{}", X11_UNIX_DIR, number))),
+            _ if host.starts_with('/') => {
+                // XQuartz 的 socket 文件名本身带着 :N
+                let path = PathBuf::from(format!("{}:{}", host, number));
+                match path.exists() {
+                    true => DisplayAddr::Unix(path),
+                    false => DisplayAddr::Unix(PathBuf::from(host))
+                }
+            }
+            _ => DisplayAddr::Tcp(host.to_string(), X11_BASE_PORT.checked_add(number)?)
+        };
+        Some(Display { addr, screen })
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn LocalStream>> {
+        match &self.addr {
+            DisplayAddr::Tcp(host, port) => {
+                let stream = TcpStream::connect((host.as_str(), *port))?;
+                stream.set_nonblocking(true)?;
+                stream.set_nodelay(true)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            DisplayAddr::Unix(path) => {
+                let stream = std::os::unix::net::UnixStream::connect(path)?;
+                stream.set_nonblocking(true)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(not(unix))]
+            DisplayAddr::Unix(_) => {
+                Err(io::Error::new(io::ErrorKind::Unsupported, "unix display is only supported on unix"))
+            }
+        }
+    }
+}
+
+
+/// 请求过 X11 转发之后的状态，同一个 session 的所有通道使用同一个假 cookie
+pub(crate) struct X11Forwarding {
+    display: Display,
+    /// x11-req 中发送给服务端的 cookie
+    fake_cookie: Vec<u8>,
+    /// 本地 xauth 中的 MIT-MAGIC-COOKIE-1
+    real_cookie: Option<Vec<u8>>,
+    /// 只接受一个 x11 通道
+    single_connection: bool,
+    /// 已经接受过 x11 通道
+    accepted: bool,
+}
+
+impl X11Forwarding {
+    /// 没有设置 DISPLAY 或者无法解析时返回 None
+    fn from_env() -> Option<X11Forwarding> {
+        let name = match std::env::var(DISPLAY) {
+            Ok(v) => v,
+            Err(_) => {
+                log::error!("DISPLAY is not set, skip x11 forwarding.");
+                return None
+            }
+        };
+        let display = match Display::parse(&name) {
+            Some(v) => v,
+            None => {
+                log::error!("invalid DISPLAY [{}], skip x11 forwarding.", name);
+                return None
+            }
+        };
+        let real_cookie = xauth_cookie(&name);
+        if real_cookie.is_none() {
+            log::info!("no xauth cookie for display [{}], x11 connections are forwarded without authentication.", name);
+        }
+        let fake_cookie: [u8; COOKIE_LEN] = OsRng.gen();
+        Some(X11Forwarding {
+            display,
+            fake_cookie: fake_cookie.to_vec(),
+            real_cookie,
+            single_connection: false,
+            accepted: false,
+        })
+    }
+}
+
+/// `xauth list DISPLAY` 中的 MIT-MAGIC-COOKIE-1，没有安装 xauth 或没有记录时返回 None
+fn xauth_cookie(display: &str) -> Option<Vec<u8>> {
+    let output = match Command::new("xauth").arg("list").arg(display).output() {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("run xauth error: {}", e);
+            return None
+        }
+    };
+    // 每一行: 显示名 协议 十六进制的 cookie
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [_, MIT_MAGIC_COOKIE, cookie] => from_hex(cookie),
+                _ => None
+            }
+        })
+        .next()
+}
+
+fn from_hex(v: &str) -> Option<Vec<u8>> {
+    if !v.len().is_multiple_of(2) {
+        return None
+    }
+    (0..v.len()).step_by(2)
+        .map(|i| u8::from_str_radix(v.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn to_hex(v: &[u8]) -> String {
+    v.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+/// 等待 x11 通道上完整的连接建立请求
+pub(crate) struct X11Setup {
+    fake_cookie: Vec<u8>,
+    real_cookie: Option<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+impl X11Setup {
+    /*
+        1     byte order: 'B' (大端) 或 'l' (小端)
+        1     unused
+        2     protocol-major-version
+        2     protocol-minor-version
+        2     n, authorization-protocol-name 的长度
+        2     d, authorization-protocol-data 的长度
+        2     unused
+        n+p   authorization-protocol-name，补齐到 4 的倍数
+        d+q   authorization-protocol-data，补齐到 4 的倍数
+    */
+    /// 请求还没有收完时返回 None，收完后返回替换了 cookie 的请求和之后已经收到的数据
+    /// cookie 不是 x11-req 中发送的假 cookie 时返回错误，通道随之关闭
+    pub(crate) fn receive(&mut self, data: &[u8]) -> SshResult<Option<Vec<u8>>> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() < SETUP_HEADER_LEN {
+            return Ok(None)
+        }
+        let big_endian = match self.buf[0] {
+            b'B' => true,
+            b'l' => false,
+            _ => return Err(SshError::from("invalid x11 byte order."))
+        };
+        let read_u16 = |v: &[u8]| match big_endian {
+            true => u16::from_be_bytes([v[0], v[1]]),
+            false => u16::from_le_bytes([v[0], v[1]])
+        } as usize;
+        let name_len = read_u16(&self.buf[6..8]);
+        let data_len = read_u16(&self.buf[8..10]);
+        let name_end = SETUP_HEADER_LEN + pad4(name_len);
+        let data_end = name_end + pad4(data_len);
+        if self.buf.len() < data_end {
+            return Ok(None)
+        }
+        let name = &self.buf[SETUP_HEADER_LEN..SETUP_HEADER_LEN + name_len];
+        let cookie = &self.buf[name_end..name_end + data_len];
+        if name != MIT_MAGIC_COOKIE.as_bytes()
+            || cookie.len() != self.fake_cookie.len()
+            || !backend::constant_time_eq(cookie, &self.fake_cookie) {
+            return Err(SshError::from("x11 connection with a wrong cookie."))
+        }
+        let (name, cookie): (&[u8], &[u8]) = match &self.real_cookie {
+            Some(v) => (MIT_MAGIC_COOKIE.as_bytes(), v),
+            None => (&[], &[])
+        };
+        let write_u16 = |v: usize| match big_endian {
+            true => (v as u16).to_be_bytes(),
+            false => (v as u16).to_le_bytes()
+        };
+        let mut setup = self.buf[..6].to_vec();
+        setup.extend_from_slice(&write_u16(name.len()));
+        setup.extend_from_slice(&write_u16(cookie.len()));
+        setup.extend_from_slice(&[0, 0]);
+        setup.extend_from_slice(name);
+        setup.resize(SETUP_HEADER_LEN + pad4(name.len()), 0);
+        setup.extend_from_slice(cookie);
+        setup.resize(SETUP_HEADER_LEN + pad4(name.len()) + pad4(cookie.len()), 0);
+        setup.extend_from_slice(&self.buf[data_end..]);
+        Ok(Some(setup))
+    }
+}
+
+fn pad4(len: usize) -> usize {
+    len.div_ceil(4) * 4
+}
+
+
+impl Client {
+    /*
+        byte      SSH_MSG_CHANNEL_REQUEST
+        uint32    recipient channel
+        string    "x11-req"
+        boolean   want reply
+        boolean   single connection
+        string    x11 authentication protocol
+        string    x11 authentication cookie
+        uint32    x11 screen number
+    */
+    /// 第一次请求时读取 DISPLAY 和本地的 cookie，之后的通道使用同一个假 cookie
+    /// 没有设置 DISPLAY 时不发送请求
+    pub(crate) fn send_x11_request(&mut self, server_channel: u32, single_connection: bool) -> SshResult<()> {
+        if self.x11_forwarding.is_none() {
+            self.x11_forwarding = X11Forwarding::from_env();
+        }
+        let x11 = match &mut self.x11_forwarding {
+            Some(v) => v,
+            None => return Ok(())
+        };
+        x11.single_connection = single_connection;
+        let mut data = Data::new();
+        data.put_u8(ssh_msg_code::SSH_MSG_CHANNEL_REQUEST)
+            .put_u32(server_channel)
+            .put_str(ssh_str::X11_REQ)
+            .put_u8(false as u8)
+            .put_u8(single_connection as u8)
+            .put_str(MIT_MAGIC_COOKIE)
+            .put_str(&to_hex(&x11.fake_cookie))
+            .put_u32(x11.display.screen);
+        self.write(data)
+    }
+
+    /*
+        byte      SSH_MSG_CHANNEL_OPEN
+        string    "x11"
+        uint32    sender channel
+        uint32    initial window size
+        uint32    maximum packet size
+        string    originator address
+        uint32    originator port
+    */
+    /// 只有请求过 X11 转发时才接受，single connection 时只接受第一个，
+    /// 连接不上本地的 X server 时回应 SSH_OPEN_CONNECT_FAILED
+    pub(crate) fn accept_x11_channel(&mut self, sender_channel: u32, window: u32, max_packet_size: u32) -> SshResult<()> {
+        let x11 = match &mut self.x11_forwarding {
+            Some(v) if !(v.single_connection && v.accepted) => v,
+            _ => {
+                log::info!("reject x11 channel, x11 forwarding was not requested.");
+                return self.send_open_failure(sender_channel,
+                                              ssh_msg_code::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+                                              "x11 forwarding not requested")
+            }
+        };
+        let stream = match x11.display.connect() {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("connect x11 display error: {}", e);
+                return self.send_open_failure(sender_channel,
+                                              ssh_msg_code::SSH_OPEN_CONNECT_FAILED,
+                                              "cannot connect to the local x11 display")
+            }
+        };
+        x11.accepted = true;
+        let setup = X11Setup {
+            fake_cookie: x11.fake_cookie.clone(),
+            real_cookie: x11.real_cookie.clone(),
+            buf: vec![],
+        };
+        let channel = ForwardedChannel::new("x11", sender_channel, window, max_packet_size, stream)
+            .with_x11_setup(setup);
+        self.open_forwarded(channel)
+    }
+}